@@ -4,7 +4,7 @@ use grabapl::operation::signature::parameter::OperationParameter;
 use grabapl::operation::signature::parameterbuilder::OperationParameterBuilder;
 use grabapl::operation::user_defined::{
     AbstractNodeId, AbstractOperationArgument, Instruction, OpLikeInstruction, QueryInstructions,
-    UserDefinedOperation,
+    QueryLikeInstruction, UserDefinedOperation,
 };
 use grabapl::prelude::*;
 use grabapl::util::bimap::BiMap;
@@ -32,7 +32,7 @@ fn mk_builtin_query(
         &<BuiltinQuery as grabapl::operation::query::BuiltinQuery>::parameter(&query),
     )
     .unwrap();
-    Instruction::BuiltinQuery(query, arg, instructions)
+    Instruction::QueryLike(QueryLikeInstruction::Builtin(query), arg, instructions)
 }
 
 // Note: assumes the subst markers are 0..n
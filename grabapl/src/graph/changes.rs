@@ -0,0 +1,76 @@
+//! Change tracking for keeping derived views of a [`Graph`] up to date incrementally.
+
+use crate::graph::{Graph, NodeKey};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A modification of a graph, without the modified values.
+///
+/// Node additions and removals are recorded in the order they happened, since that order
+/// determines the order of the graph's nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum GraphChange {
+    AddedNode(NodeKey),
+    RemovedNode(NodeKey),
+    ChangedNode(NodeKey),
+    /// The edge was added, removed, or its attribute may have been replaced.
+    ChangedEdge(NodeKey, NodeKey),
+}
+
+/// The changes of a graph since the last call to [`Graph::take_changes`].
+#[derive(Debug)]
+pub(crate) struct ChangeLog {
+    /// Identifies the tracking session, such that a consumer can detect if another consumer
+    /// restarted tracking in the meantime.
+    id: u64,
+    changes: Vec<GraphChange>,
+}
+
+static NEXT_CHANGE_LOG_ID: AtomicU64 = AtomicU64::new(0);
+
+fn next_change_log_id() -> u64 {
+    NEXT_CHANGE_LOG_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+impl Clone for ChangeLog {
+    /// A clone of a graph is a different graph, so its changes belong to a new tracking session
+    /// that no consumer knows about.
+    fn clone(&self) -> Self {
+        ChangeLog {
+            id: next_change_log_id(),
+            changes: Vec::new(),
+        }
+    }
+}
+
+impl<NodeAttr, EdgeAttr> Graph<NodeAttr, EdgeAttr> {
+    /// Starts recording changes, discarding any previously recorded changes.
+    ///
+    /// Returns the id of the new tracking session.
+    pub(crate) fn track_changes(&mut self) -> u64 {
+        let id = next_change_log_id();
+        self.change_log = Some(ChangeLog {
+            id,
+            changes: Vec::new(),
+        });
+        id
+    }
+
+    pub(crate) fn stop_tracking_changes(&mut self) {
+        self.change_log = None;
+    }
+
+    /// Returns the changes recorded since tracking session `id` started or since the last call,
+    /// or `None` if that session is no longer active.
+    pub(crate) fn take_changes(&mut self, id: u64) -> Option<Vec<GraphChange>> {
+        match &mut self.change_log {
+            Some(log) if log.id == id => Some(std::mem::take(&mut log.changes)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn record_change(&mut self, change: GraphChange) {
+        if let Some(log) = &mut self.change_log {
+            log.changes.push(change);
+        }
+    }
+}
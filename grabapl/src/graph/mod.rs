@@ -8,7 +8,13 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 use std::hash::RandomState;
 
+mod changes;
 pub mod dot;
+mod undo;
+
+pub(crate) use changes::GraphChange;
+use changes::ChangeLog;
+use undo::UndoLog;
 
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -97,7 +103,7 @@ pub enum EdgeInsertionOrder {
 }
 
 /// A graph with ordered edges and arbitrary associated edge and node data.
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Graph<NodeAttr, EdgeAttr> {
     #[cfg_attr(
@@ -107,6 +113,25 @@ pub struct Graph<NodeAttr, EdgeAttr> {
     pub(crate) graph: DiGraphMap<NodeKey, EdgeAttribute<EdgeAttr>, RandomState>,
     pub(crate) max_node_key: NodeKey,
     pub(crate) node_attr_map: HashMap<NodeKey, NodeAttribute<NodeAttr>>,
+    /// Records modifications while a transaction is active.
+    #[cfg_attr(feature = "serde", serde(skip, default = "Option::default"))]
+    undo_log: Option<UndoLog<NodeAttr, EdgeAttr>>,
+    /// Records changes while derived views of this graph are kept up to date.
+    #[cfg_attr(feature = "serde", serde(skip, default = "Option::default"))]
+    change_log: Option<ChangeLog>,
+}
+
+impl<NodeAttr: Clone, EdgeAttr: Clone> Clone for Graph<NodeAttr, EdgeAttr> {
+    /// A clone is not part of the original's transaction, so it does not record modifications.
+    fn clone(&self) -> Self {
+        Graph {
+            graph: self.graph.clone(),
+            max_node_key: self.max_node_key,
+            node_attr_map: self.node_attr_map.clone(),
+            undo_log: None,
+            change_log: self.change_log.clone(),
+        }
+    }
 }
 
 impl<NodeAttr, EdgeAttr> Default for Graph<NodeAttr, EdgeAttr> {
@@ -121,6 +146,8 @@ impl<NodeAttr, EdgeAttr> Graph<NodeAttr, EdgeAttr> {
             graph: GraphMap::new(),
             max_node_key: 0.into(),
             node_attr_map: HashMap::new(),
+            undo_log: None,
+            change_log: None,
         }
     }
 
@@ -134,6 +161,10 @@ impl<NodeAttr, EdgeAttr> Graph<NodeAttr, EdgeAttr> {
         self.node_attr_map
             .insert(node_key, NodeAttribute::new(node_attr));
         self.max_node_key += 1.into();
+        if let Some(log) = &mut self.undo_log {
+            log.record_added_node(node_key);
+        }
+        self.record_change(GraphChange::AddedNode(node_key));
         node_key
     }
 
@@ -169,6 +200,14 @@ impl<NodeAttr, EdgeAttr> Graph<NodeAttr, EdgeAttr> {
         })
     }
 
+    pub fn node_count(&self) -> usize {
+        self.graph.node_count()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.graph.edge_count()
+    }
+
     fn extremum_out_edge_order_key(
         &self,
         source: NodeKey,
@@ -229,6 +268,13 @@ impl<NodeAttr, EdgeAttr> Graph<NodeAttr, EdgeAttr> {
             target,
             EdgeAttribute::new(edge_attr, new_out_order, new_in_order),
         );
+        if let Some(log) = &mut self.undo_log {
+            match &old_attr {
+                Some(old) => log.record_changed_edge(source, target, old),
+                None => log.record_added_edge(source, target),
+            }
+        }
+        self.record_change(GraphChange::ChangedEdge(source, target));
         old_attr.map(|attr| attr.edge_attr)
     }
 
@@ -241,6 +287,25 @@ impl<NodeAttr, EdgeAttr> Graph<NodeAttr, EdgeAttr> {
         neighbors.into_iter().map(|(_, target, _)| target).collect()
     }
 
+    /// Returns the index of the edge `source -> target` among the outgoing edges of `source` in
+    /// edge order, together with the number of outgoing edges of `source`.
+    pub(crate) fn out_edge_position(
+        &self,
+        source: NodeKey,
+        target: NodeKey,
+    ) -> Option<(usize, usize)> {
+        let order = self.graph.edge_weight(source, target)?.source_out_order;
+        let mut index = 0;
+        let mut count = 0;
+        for (_, other_target, edge_attr) in self.graph.edges_directed(source, Direction::Outgoing) {
+            count += 1;
+            if (edge_attr.source_out_order, other_target) < (order, target) {
+                index += 1;
+            }
+        }
+        Some((index, count))
+    }
+
     #[allow(unused)]
     fn neighbors_in_ordered(&self, target: NodeKey) -> Vec<NodeKey> {
         let mut neighbors = self
@@ -279,7 +344,21 @@ impl<NodeAttr, EdgeAttr> Graph<NodeAttr, EdgeAttr> {
 
     pub fn remove_node(&mut self, node_key: NodeKey) -> Option<NodeAttr> {
         if let Some(node_attr) = self.node_attr_map.remove(&node_key) {
+            if let Some(log) = &mut self.undo_log {
+                let outgoing = self.graph.edges_directed(node_key, Direction::Outgoing);
+                // self-loops are already contained in the outgoing edges
+                let incoming = self
+                    .graph
+                    .edges_directed(node_key, Direction::Incoming)
+                    .filter(|(src, target, _)| src != target);
+                let edges = outgoing
+                    .chain(incoming)
+                    .map(|(src, target, attr)| (src, target, log.clone_edge_attribute(attr)))
+                    .collect::<Vec<_>>();
+                log.record_removed_node(node_key, &node_attr, edges);
+            }
             self.graph.remove_node(node_key);
+            self.record_change(GraphChange::RemovedNode(node_key));
             Some(node_attr.node_attr)
         } else {
             None
@@ -291,9 +370,12 @@ impl<NodeAttr, EdgeAttr> Graph<NodeAttr, EdgeAttr> {
     }
 
     pub fn remove_edge(&mut self, (src, target): EdgeKey) -> Option<EdgeAttr> {
-        self.graph
-            .remove_edge(src, target)
-            .map(|attr| attr.edge_attr)
+        let removed = self.graph.remove_edge(src, target)?;
+        if let Some(log) = &mut self.undo_log {
+            log.record_changed_edge(src, target, &removed);
+        }
+        self.record_change(GraphChange::ChangedEdge(src, target));
+        Some(removed.edge_attr)
     }
 
     pub fn get_edge_attr(&self, (src, target): EdgeKey) -> Option<&EdgeAttr> {
@@ -303,9 +385,15 @@ impl<NodeAttr, EdgeAttr> Graph<NodeAttr, EdgeAttr> {
     }
 
     pub fn get_mut_edge_attr(&mut self, (src, target): EdgeKey) -> Option<&mut EdgeAttr> {
-        self.graph
-            .edge_weight_mut(src, target)
-            .map(|attr| &mut attr.edge_attr)
+        if self.graph.contains_edge(src, target) {
+            self.record_change(GraphChange::ChangedEdge(src, target));
+        }
+        let attr = self.graph.edge_weight_mut(src, target)?;
+        if let Some(log) = &mut self.undo_log {
+            // the caller may modify the attribute
+            log.record_changed_edge(src, target, attr);
+        }
+        Some(&mut attr.edge_attr)
     }
 
     pub fn get_node_attr(&self, node_key: NodeKey) -> Option<&NodeAttr> {
@@ -315,15 +403,25 @@ impl<NodeAttr, EdgeAttr> Graph<NodeAttr, EdgeAttr> {
     }
 
     pub fn get_mut_node_attr(&mut self, node_key: NodeKey) -> Option<&mut NodeAttr> {
-        self.node_attr_map
-            .get_mut(&node_key)
-            .map(|attr| &mut attr.node_attr)
+        if self.node_attr_map.contains_key(&node_key) {
+            self.record_change(GraphChange::ChangedNode(node_key));
+        }
+        let attr = self.node_attr_map.get_mut(&node_key)?;
+        if let Some(log) = &mut self.undo_log {
+            // the caller may modify the attribute
+            log.record_changed_node(node_key, &attr.node_attr);
+        }
+        Some(&mut attr.node_attr)
     }
 
     /// Sets the node attribute for the given node key that already exists in the graph.
     pub fn set_node_attr(&mut self, node_key: NodeKey, node_attr: NodeAttr) -> Option<NodeAttr> {
         if let Some(attr) = self.node_attr_map.get_mut(&node_key) {
             let old_attr = std::mem::replace(&mut attr.node_attr, node_attr);
+            if let Some(log) = &mut self.undo_log {
+                log.record_changed_node(node_key, &old_attr);
+            }
+            self.record_change(GraphChange::ChangedNode(node_key));
             Some(old_attr)
         } else {
             None
@@ -337,7 +435,11 @@ impl<NodeAttr, EdgeAttr> Graph<NodeAttr, EdgeAttr> {
         edge_attr: EdgeAttr,
     ) -> Option<EdgeAttr> {
         if let Some(ea) = self.graph.edge_weight_mut(src, target) {
+            if let Some(log) = &mut self.undo_log {
+                log.record_changed_edge(src, target, ea);
+            }
             let old_attr = std::mem::replace(&mut ea.edge_attr, edge_attr);
+            self.record_change(GraphChange::ChangedEdge(src, target));
             Some(old_attr)
         } else {
             None
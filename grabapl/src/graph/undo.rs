@@ -0,0 +1,151 @@
+//! Undo log for transactional modifications of a [`Graph`].
+
+use crate::graph::{EdgeAttribute, Graph, GraphChange, NodeAttribute, NodeKey};
+
+/// A single recorded modification, holding everything that is needed to revert it.
+#[derive(Debug)]
+enum UndoEntry<NodeAttr, EdgeAttr> {
+    AddedNode(NodeKey),
+    RemovedNode {
+        node_key: NodeKey,
+        attr: NodeAttribute<NodeAttr>,
+        /// All edges incident to the removed node, including their edge orders.
+        edges: Vec<(NodeKey, NodeKey, EdgeAttribute<EdgeAttr>)>,
+    },
+    ChangedNode(NodeKey, NodeAttr),
+    AddedEdge(NodeKey, NodeKey),
+    /// The edge was removed or its attribute was replaced.
+    ChangedEdge(NodeKey, NodeKey, EdgeAttribute<EdgeAttr>),
+}
+
+/// Records the modifications of a graph since the start of a transaction.
+///
+/// See [`Graph::begin_transaction`]. Clones of a graph do not share its undo log.
+#[derive(Debug)]
+pub(crate) struct UndoLog<NodeAttr, EdgeAttr> {
+    max_node_key: NodeKey,
+    entries: Vec<UndoEntry<NodeAttr, EdgeAttr>>,
+    // stored so that recording does not need `Clone` bounds on every graph method
+    clone_node_attr: fn(&NodeAttr) -> NodeAttr,
+    clone_edge_attr: fn(&EdgeAttr) -> EdgeAttr,
+}
+
+impl<NodeAttr, EdgeAttr> UndoLog<NodeAttr, EdgeAttr> {
+    pub(crate) fn record_added_node(&mut self, node_key: NodeKey) {
+        self.entries.push(UndoEntry::AddedNode(node_key));
+    }
+
+    pub(crate) fn record_removed_node(
+        &mut self,
+        node_key: NodeKey,
+        attr: &NodeAttribute<NodeAttr>,
+        edges: impl IntoIterator<Item = (NodeKey, NodeKey, EdgeAttribute<EdgeAttr>)>,
+    ) {
+        let attr = NodeAttribute::new((self.clone_node_attr)(&attr.node_attr));
+        self.entries.push(UndoEntry::RemovedNode {
+            node_key,
+            attr,
+            edges: edges.into_iter().collect(),
+        });
+    }
+
+    pub(crate) fn record_changed_node(&mut self, node_key: NodeKey, old: &NodeAttr) {
+        let old = (self.clone_node_attr)(old);
+        self.entries.push(UndoEntry::ChangedNode(node_key, old));
+    }
+
+    pub(crate) fn record_added_edge(&mut self, source: NodeKey, target: NodeKey) {
+        self.entries.push(UndoEntry::AddedEdge(source, target));
+    }
+
+    pub(crate) fn record_changed_edge(
+        &mut self,
+        source: NodeKey,
+        target: NodeKey,
+        old: &EdgeAttribute<EdgeAttr>,
+    ) {
+        let old = self.clone_edge_attribute(old);
+        self.entries
+            .push(UndoEntry::ChangedEdge(source, target, old));
+    }
+
+    pub(crate) fn clone_edge_attribute(
+        &self,
+        attr: &EdgeAttribute<EdgeAttr>,
+    ) -> EdgeAttribute<EdgeAttr> {
+        attr.with((self.clone_edge_attr)(&attr.edge_attr))
+    }
+}
+
+impl<NodeAttr: Clone, EdgeAttr: Clone> Graph<NodeAttr, EdgeAttr> {
+    /// Starts recording all modifications of this graph, such that they can be reverted with
+    /// [`Graph::rollback_transaction`].
+    ///
+    /// Transactions do not nest: starting a transaction while one is active discards the active
+    /// transaction's undo log.
+    pub fn begin_transaction(&mut self) {
+        self.undo_log = Some(UndoLog {
+            max_node_key: self.max_node_key,
+            entries: Vec::new(),
+            clone_node_attr: NodeAttr::clone,
+            clone_edge_attr: EdgeAttr::clone,
+        });
+    }
+
+    /// Returns true if a transaction is active.
+    pub fn in_transaction(&self) -> bool {
+        self.undo_log.is_some()
+    }
+
+    /// Keeps all modifications since [`Graph::begin_transaction`] and stops recording.
+    pub fn commit_transaction(&mut self) {
+        self.undo_log = None;
+    }
+
+    /// Reverts all modifications since [`Graph::begin_transaction`], including the allocation
+    /// of node keys, and stops recording.
+    ///
+    /// Does nothing if no transaction is active.
+    pub fn rollback_transaction(&mut self) {
+        let Some(log) = self.undo_log.take() else {
+            return;
+        };
+        for entry in log.entries.into_iter().rev() {
+            match entry {
+                UndoEntry::AddedNode(node_key) => {
+                    self.node_attr_map.remove(&node_key);
+                    self.graph.remove_node(node_key);
+                    self.record_change(GraphChange::RemovedNode(node_key));
+                }
+                UndoEntry::RemovedNode {
+                    node_key,
+                    attr,
+                    edges,
+                } => {
+                    self.graph.add_node(node_key);
+                    self.node_attr_map.insert(node_key, attr);
+                    self.record_change(GraphChange::AddedNode(node_key));
+                    for (source, target, attr) in edges {
+                        self.graph.add_edge(source, target, attr);
+                        self.record_change(GraphChange::ChangedEdge(source, target));
+                    }
+                }
+                UndoEntry::ChangedNode(node_key, old) => {
+                    if let Some(attr) = self.node_attr_map.get_mut(&node_key) {
+                        attr.node_attr = old;
+                        self.record_change(GraphChange::ChangedNode(node_key));
+                    }
+                }
+                UndoEntry::AddedEdge(source, target) => {
+                    self.graph.remove_edge(source, target);
+                    self.record_change(GraphChange::ChangedEdge(source, target));
+                }
+                UndoEntry::ChangedEdge(source, target, old) => {
+                    self.graph.add_edge(source, target, old);
+                    self.record_change(GraphChange::ChangedEdge(source, target));
+                }
+            }
+        }
+        self.max_node_key = log.max_node_key;
+    }
+}
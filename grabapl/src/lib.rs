@@ -39,14 +39,20 @@ interned_string_newtype!(SubstMarker);
 pub mod prelude {
     pub use super::SubstMarker;
     pub use crate::graph::{Graph, NodeKey};
-    pub use crate::operation::builder::{BuilderOpLike, OperationBuilder};
+    pub use crate::operation::builder::{BuilderOpLike, BuilderQueryLike, OperationBuilder};
     pub use crate::operation::builtin::LibBuiltinOperation;
+    pub use crate::operation::execution::ExecutionLimits;
+    pub use crate::operation::query::{
+        NegativeShapeCondition, NegativeShapeNode, ShapeEdgeOrder, ShapePath,
+    };
+    pub use crate::operation::session::Session;
     pub use crate::operation::signature::OperationSignature;
     pub use crate::operation::signature::parameter::{GraphWithSubstitution, OperationParameter};
     pub use crate::operation::signature::parameterbuilder::OperationParameterBuilder;
     pub use crate::operation::user_defined::{AbstractNodeId, UserDefinedOperation};
     pub use crate::operation::{
         BuiltinOperation, Operation, OperationContext, OperationId, run_from_concrete,
+        run_from_concrete_transactional, run_from_concrete_with_limits,
     };
     pub use crate::semantics::{
         AbstractGraph, AbstractJoin, AbstractMatcher, ConcreteGraph, ConcreteToAbstract, Semantics,
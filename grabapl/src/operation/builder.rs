@@ -60,7 +60,7 @@
 
 use crate::operation::builtin::LibBuiltinOperation;
 use crate::operation::marker::Marker;
-use crate::operation::query::{BuiltinQuery, ShapeNodeIdentifier};
+use crate::operation::query::{BuiltinQuery, QueryRefinement, ShapeNodeIdentifier};
 use crate::operation::signature::parameter::{
     AbstractOperationOutput, AbstractOutputNodeMarker, GraphWithSubstitution, OperationParameter,
     ParameterSubstitution,
@@ -72,7 +72,7 @@ use crate::operation::user_defined::{
 };
 use crate::operation::{Operation, OperationError, OperationResult, get_substitution};
 use crate::prelude::*;
-use crate::semantics::AbstractGraph;
+use crate::semantics::{AbstractGraph, AbstractMatcher};
 use crate::util::bimap::BiMap;
 use crate::util::log;
 use crate::{NodeKey, Semantics, SubstMarker};
@@ -83,7 +83,7 @@ use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use thiserror::Error;
 
-mod programming_by_demonstration;
+pub mod programming_by_demonstration;
 pub mod stack_based_builder;
 /*
 General overview:
@@ -128,6 +128,14 @@ impl<'a, S: Semantics> AbstractOperation<'a, S> {
             AbstractOperation::Partial(sig) => Ok(sig.output.apply_abstract(g)),
         }
     }
+
+    fn is_query(&self) -> bool {
+        match self {
+            AbstractOperation::Op(Operation::Custom(op)) => op.signature.is_query,
+            AbstractOperation::Op(_) => false,
+            AbstractOperation::Partial(sig) => sig.is_query,
+        }
+    }
 }
 
 pub enum BuilderOpLike<S: Semantics> {
@@ -141,16 +149,26 @@ impl<S: Semantics> BuilderOpLike<S> {
     fn as_abstract_operation<'a>(
         &'a self,
         op_ctx: &'a OperationContext<S>,
+        declared_signatures: &'a HashMap<OperationId, OperationSignature<S>>,
         partial_self_signature: &'a OperationSignature<S>,
     ) -> Result<AbstractOperation<'a, S>, OperationBuilderError> {
         let op = match self {
             BuilderOpLike::Builtin(op) => AbstractOperation::Op(Operation::Builtin(op)),
             BuilderOpLike::LibBuiltin(op) => AbstractOperation::Op(Operation::LibBuiltin(op)),
+            BuilderOpLike::FromOperationId(id) if declared_signatures.contains_key(id) => {
+                // operations that are built at the same time are only known by their signature
+                AbstractOperation::Partial(&declared_signatures[id])
+            }
             BuilderOpLike::FromOperationId(id) => {
-                let op = op_ctx
-                    .get(*id)
-                    .ok_or(OperationBuilderError::NotFoundOperationId(*id))?;
-                AbstractOperation::Op(op)
+                if let Some(signature) = op_ctx.declared_signature(*id) {
+                    // defined in a different context that is linked later
+                    AbstractOperation::Partial(signature)
+                } else {
+                    let op = op_ctx
+                        .get(*id)
+                        .ok_or(OperationBuilderError::NotFoundOperationId(*id))?;
+                    AbstractOperation::Op(op)
+                }
             }
             BuilderOpLike::Recurse => AbstractOperation::Partial(partial_self_signature),
         };
@@ -178,6 +196,25 @@ impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for Build
     }
 }
 
+/// The condition of a query started with [`OperationBuilder::start_query_like`].
+pub enum BuilderQueryLike<S: Semantics> {
+    Builtin(S::BuiltinQuery),
+    /// A user defined query, see [`OperationBuilder::expect_self_query`].
+    FromOperationId(OperationId),
+    /// A recursive call to the query being built.
+    Recurse,
+}
+
+impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for BuilderQueryLike<S> {
+    fn clone(&self) -> Self {
+        match self {
+            BuilderQueryLike::Builtin(query) => BuilderQueryLike::Builtin(query.clone()),
+            BuilderQueryLike::FromOperationId(id) => BuilderQueryLike::FromOperationId(*id),
+            BuilderQueryLike::Recurse => BuilderQueryLike::Recurse,
+        }
+    }
+}
+
 // TODO: rename to BuilderMessage? since Instruction is already used in the user-defined operation context.
 #[derive(derive_more::Debug)]
 pub enum BuilderInstruction<S: Semantics> {
@@ -188,7 +225,7 @@ pub enum BuilderInstruction<S: Semantics> {
     #[debug("ExpectParameterEdge({_0:?}, {_1:?}, ???)")]
     ExpectParameterEdge(SubstMarker, SubstMarker, S::EdgeAbstract),
     #[debug("StartQuery(???, args: {_1:?})")]
-    StartQuery(S::BuiltinQuery, Vec<AbstractNodeId>),
+    StartQuery(BuilderQueryLike<S>, Vec<AbstractNodeId>),
     #[debug("EnterTrueBranch")]
     EnterTrueBranch,
     #[debug("EnterFalseBranch")]
@@ -205,8 +242,23 @@ pub enum BuilderInstruction<S: Semantics> {
     ExpectShapeNode(AbstractOutputNodeMarker, S::NodeAbstract),
     #[debug("ExpectShapeNodeChange({_0:?}, ???)")]
     ExpectShapeNodeChange(AbstractNodeId, S::NodeAbstract),
-    #[debug("ExpectShapeEdge({_0:?}, {_1:?}, ???)")]
-    ExpectShapeEdge(AbstractNodeId, AbstractNodeId, S::EdgeAbstract),
+    #[debug("ExpectShapeEdge({_0:?}, {_1:?}, ???, {_3:?})")]
+    ExpectShapeEdge(
+        AbstractNodeId,
+        AbstractNodeId,
+        S::EdgeAbstract,
+        Option<ShapeEdgeOrder<AbstractNodeId>>,
+    ),
+    #[debug("ExpectNoShapePattern(???)")]
+    ExpectNoShapePattern(NegativeShapeCondition<S, AbstractNodeId>),
+    #[debug("ExpectShapePath({:?}, {:?}, ???)", _0.source, _0.target)]
+    ExpectShapePath(ShapePath<S, AbstractNodeId>),
+    #[debug("StartShapeMatch({_0:?})")]
+    StartShapeMatch(AbstractOperationResultMarker),
+    #[debug("StartMatchArm")]
+    StartMatchArm,
+    #[debug("EnterDefaultArm")]
+    EnterDefaultArm,
     #[debug("SkipMarker({_0:?})")]
     SkipMarker(Marker),
     #[debug("SkipAllMarkers")]
@@ -233,6 +285,8 @@ pub enum BuilderInstruction<S: Semantics> {
     RenameNode(AbstractNodeId, NamedMarker),
     Finalize,
     /// Asserts that the current operation will return a node with the given abstract value and name.
+    #[debug("DeclareOperation({_0:?}, ???)")]
+    DeclareOperation(OperationId, Box<OperationSignature<S>>),
     #[debug("SelfReturnNode({_0:?}, ???)")]
     SelfReturnNode(AbstractOutputNodeMarker, S::NodeAbstract),
     /// Asserts that the current operation will return an edge with the given abstract value.
@@ -249,6 +303,12 @@ pub enum BuilderInstruction<S: Semantics> {
     /// Add the current operation's frame to the operation trace
     #[debug("Trace")]
     Trace,
+    /// Asserts that the current operation is a query.
+    #[debug("ExpectSelfQuery")]
+    ExpectSelfQuery,
+    /// Yield the result of the current query. Must be the last instruction on its path.
+    #[debug("YieldQueryResult({_0})")]
+    YieldQueryResult(bool),
 }
 
 impl<S: Semantics> BuilderInstruction<S> {
@@ -260,6 +320,8 @@ impl<S: Semantics> BuilderInstruction<S> {
             self,
             EnterTrueBranch
                 | EnterFalseBranch
+                | StartMatchArm
+                | EnterDefaultArm
                 | EndQuery
                 | ReturnNode(..)
                 | ReturnEdge(..)
@@ -284,9 +346,14 @@ impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for Build
             EndQuery => EndQuery,
             ExpectShapeNode(marker, node) => ExpectShapeNode(*marker, node.clone()),
             ExpectShapeNodeChange(aid, node) => ExpectShapeNodeChange(*aid, node.clone()),
-            ExpectShapeEdge(source, target, edge) => {
-                ExpectShapeEdge(*source, *target, edge.clone())
+            ExpectShapeEdge(source, target, edge, order) => {
+                ExpectShapeEdge(*source, *target, edge.clone(), *order)
             }
+            ExpectNoShapePattern(condition) => ExpectNoShapePattern(condition.clone()),
+            ExpectShapePath(path) => ExpectShapePath(path.clone()),
+            StartShapeMatch(op_marker) => StartShapeMatch(*op_marker),
+            StartMatchArm => StartMatchArm,
+            EnterDefaultArm => EnterDefaultArm,
             SkipMarker(marker) => SkipMarker(*marker),
             SkipAllMarkers => SkipAllMarkers,
             AddNamedOperation(name, op, args) => AddNamedOperation(*name, op.clone(), args.clone()),
@@ -296,10 +363,13 @@ impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for Build
             ReturnEdge(src, dst, edge) => ReturnEdge(*src, *dst, edge.clone()),
             RenameNode(old_aid, new_name) => RenameNode(*old_aid, *new_name),
             Finalize => Finalize,
+            DeclareOperation(op_id, signature) => DeclareOperation(*op_id, signature.clone()),
             SelfReturnNode(marker, node) => SelfReturnNode(*marker, node.clone()),
             SelfReturnEdge(src, dst, edge) => SelfReturnEdge(*src, *dst, edge.clone()),
             Diverge(msg) => Diverge(msg.clone()),
             Trace => Trace,
+            ExpectSelfQuery => ExpectSelfQuery,
+            YieldQueryResult(value) => YieldQueryResult(*value),
         }
     }
 }
@@ -318,6 +388,8 @@ pub enum OperationBuilderError {
     ExpectedOperationOrQuery,
     #[error("Already visited the {0} branch of the active query")]
     AlreadyVisitedBranch(bool),
+    #[error("Already visited the default arm of the active shape match")]
+    AlreadyVisitedDefaultArm,
     #[error("Could not find abstract node id: {0:?}")]
     NotFoundAid(AbstractNodeId),
     #[error("AID {0:?} already exists")]
@@ -369,6 +441,10 @@ pub enum OperationBuilderError {
     ShapeEdgeTargetNotFound,
     #[error("Shape edge source node not found")]
     ShapeEdgeSourceNotFound,
+    #[error("Shape edge order refers to edge {0:?}->{1:?}, which is not part of the shape query")]
+    ShapeEdgeOrderSiblingNotFound(AbstractNodeId, AbstractNodeId),
+    #[error("Negative shape condition refers to unknown forbidden node {0}")]
+    NegativeShapeConditionNodeNotFound(usize),
     #[error(
         "Cannot rename parameter node {0:?}, only new nodes from operation calls can be renamed"
     )]
@@ -382,8 +458,26 @@ pub enum OperationBuilderError {
     // TODO: maybe have enum variants for these
     #[error("{0}")]
     Oneoff(&'static str),
+    #[error("Operation does not conform to its declared signature: {0}")]
+    DeclaredSignatureMismatch(&'static str),
     #[error("Shape node already exists: {}", _0.0)]
     ShapeNodeAlreadyExists(ShapeNodeIdentifier),
+    #[error("Operation {0} is not a query")]
+    NotAQuery(OperationId),
+    #[error("Only queries can yield a result")]
+    YieldOutsideQuery,
+    #[error("Unreachable instruction after yielding the query result")]
+    InstructionAfterYield,
+    #[error("Either all or none of the branches must yield the query result")]
+    YieldInSomeBranches,
+    #[error("Query does not yield a result on every path")]
+    MissingQueryResult,
+    #[error("Queries cannot create or delete nodes or edges")]
+    QueryWithStructuralChanges,
+    #[error("Query refinement of node {0:?} is not a subtype of its current type")]
+    InvalidNodeRefinement(SubstMarker),
+    #[error("Query refinement of edge {0:?}->{1:?} is not a subtype of its current type")]
+    InvalidEdgeRefinement(SubstMarker, SubstMarker),
 }
 
 // type alias to switch between implementations globally
@@ -417,12 +511,22 @@ pub enum QueryPath {
     Query(String),
     TrueBranch,
     FalseBranch,
+    MatchArm(usize),
+    DefaultArm,
+}
+
+/// A [`QueryRefinement`] whose markers were resolved to the node keys of an [`IntermediateState`].
+struct ResolvedQueryRefinement<S: Semantics> {
+    nodes: Vec<(NodeKey, S::NodeAbstract)>,
+    edges: Vec<((NodeKey, NodeKey), S::EdgeAbstract)>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 struct IntermediateStateAbstractOutputResult {
     new_aids: Vec<AbstractNodeId>,
     removed_aids: Vec<AbstractNodeId>,
+    /// Whether any nodes or edges were added or removed, including ones that are not in scope.
+    has_structural_changes: bool,
 }
 
 // TODO: Store more information like:
@@ -447,6 +551,10 @@ pub struct IntermediateState<S: Semantics> {
     pub node_may_be_written_to: HashMap<AbstractNodeId, S::NodeAbstract>,
     /// The most generic abstract type that may be written to each edge, if any.
     pub edge_may_be_written_to: HashMap<(AbstractNodeId, AbstractNodeId), S::EdgeAbstract>,
+    /// Nodes that are overwritten on every path to this state.
+    pub node_must_be_written_to: HashSet<AbstractNodeId>,
+    /// Edges that are overwritten on every path to this state.
+    pub edge_must_be_written_to: HashSet<(AbstractNodeId, AbstractNodeId)>,
 
     /// Edges that may have been deleted at some point.
     /// This is necessary to track, because this may be an edge that is _not_ in our scope right now.
@@ -459,6 +567,8 @@ pub struct IntermediateState<S: Semantics> {
     pub op_marker_counter: u64,
 
     pub has_diverged: bool,
+    /// Whether the query result was yielded on every path to this state.
+    pub has_yielded: bool,
 }
 
 // TODO: unfortunately, we cannot derive Clone, since it implies a `S: Clone` bound.
@@ -472,10 +582,13 @@ impl<S: Semantics> Clone for IntermediateState<S> {
             edge_may_originate_from_shape_query: self.edge_may_originate_from_shape_query.clone(),
             node_may_be_written_to: self.node_may_be_written_to.clone(),
             edge_may_be_written_to: self.edge_may_be_written_to.clone(),
+            node_must_be_written_to: self.node_must_be_written_to.clone(),
+            edge_must_be_written_to: self.edge_must_be_written_to.clone(),
             edges_maybe_deleted: self.edges_maybe_deleted.clone(),
             query_path: self.query_path.clone(),
             op_marker_counter: self.op_marker_counter,
             has_diverged: self.has_diverged,
+            has_yielded: self.has_yielded,
         }
     }
 }
@@ -489,10 +602,13 @@ impl<S: Semantics> IntermediateState<S> {
             edge_may_originate_from_shape_query: HashSet::new(),
             node_may_be_written_to: HashMap::new(),
             edge_may_be_written_to: HashMap::new(),
+            node_must_be_written_to: HashSet::new(),
+            edge_must_be_written_to: HashSet::new(),
             edges_maybe_deleted: HashSet::new(),
             query_path: Vec::new(),
             op_marker_counter: 50000,
             has_diverged: false,
+            has_yielded: false,
         }
     }
 
@@ -513,10 +629,13 @@ impl<S: Semantics> IntermediateState<S> {
             edge_may_originate_from_shape_query: HashSet::new(),
             node_may_be_written_to: HashMap::new(),
             edge_may_be_written_to: HashMap::new(),
+            node_must_be_written_to: HashSet::new(),
+            edge_must_be_written_to: HashSet::new(),
             edges_maybe_deleted: HashSet::new(),
             query_path: Vec::new(),
             op_marker_counter: 50000,
             has_diverged: false,
+            has_yielded: false,
         }
     }
 
@@ -657,6 +776,18 @@ impl<S: Semantics> IntermediateState<S> {
                 ((new_src, new_dst), edge_av.clone())
             })
             .collect();
+        if self.node_must_be_written_to.remove(&old_aid) {
+            self.node_must_be_written_to.insert(new_aid);
+        }
+        self.edge_must_be_written_to = self
+            .edge_must_be_written_to
+            .iter()
+            .map(|&(src, dst)| {
+                let new_src = if src == old_aid { new_aid } else { src };
+                let new_dst = if dst == old_aid { new_aid } else { dst };
+                (new_src, new_dst)
+            })
+            .collect();
 
         Ok(())
     }
@@ -702,17 +833,82 @@ impl<S: Semantics> IntermediateState<S> {
         Ok((abstract_arg, output))
     }
 
+    /// Returns the query's argument as well as its refinements for the taken and not taken branches.
     fn interpret_builtin_query(
         &mut self,
         query: &S::BuiltinQuery,
         args: Vec<AbstractNodeId>,
-    ) -> Result<AbstractOperationArgument, OperationBuilderError> {
+    ) -> Result<
+        (
+            AbstractOperationArgument,
+            ResolvedQueryRefinement<S>,
+            ResolvedQueryRefinement<S>,
+        ),
+        OperationBuilderError,
+    > {
         let param = query.parameter();
         let (subst, abstract_arg) = self.get_substitution(&param, args)?;
         // now apply the query and store result
         let mut gws = GraphWithSubstitution::new(&mut self.graph, &subst);
         query.apply_abstract(&mut gws);
-        Ok(abstract_arg)
+        let taken = query.apply_abstract_taken(&gws);
+        let not_taken = query.apply_abstract_not_taken(&gws);
+        let taken = self.resolve_query_refinement(&subst, taken)?;
+        let not_taken = self.resolve_query_refinement(&subst, not_taken)?;
+        Ok((abstract_arg, taken, not_taken))
+    }
+
+    /// Resolves the refinement's markers to our node keys and checks that it only narrows types.
+    fn resolve_query_refinement(
+        &self,
+        subst: &ParameterSubstitution,
+        refinement: QueryRefinement<S>,
+    ) -> Result<ResolvedQueryRefinement<S>, OperationBuilderError> {
+        let key_of = |marker: &SubstMarker| {
+            subst
+                .mapping
+                .get(marker)
+                .copied()
+                .ok_or(OperationBuilderError::NotFoundSubstMarker(*marker))
+        };
+        let mut resolved = ResolvedQueryRefinement {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        };
+        for (marker, av) in refinement.nodes {
+            let key = key_of(&marker)?;
+            let current = self
+                .graph
+                .get_node_attr(key)
+                .ok_or(OperationBuilderError::NotFoundSubstMarker(marker))?;
+            if !S::NodeMatcher::matches(&av, current) {
+                bail!(OperationBuilderError::InvalidNodeRefinement(marker));
+            }
+            resolved.nodes.push((key, av));
+        }
+        for ((src, dst), av) in refinement.edges {
+            let edge_key = (key_of(&src)?, key_of(&dst)?);
+            let Some(current) = self.graph.get_edge_attr(edge_key) else {
+                bail!(OperationBuilderError::InvalidEdgeRefinement(src, dst));
+            };
+            if !S::EdgeMatcher::matches(&av, current) {
+                bail!(OperationBuilderError::InvalidEdgeRefinement(src, dst));
+            }
+            resolved.edges.push((edge_key, av));
+        }
+        Ok(resolved)
+    }
+
+    /// Narrows the types of this state's nodes and edges.
+    ///
+    /// Unlike operations, this does not count as a write, since the values themselves do not change.
+    fn apply_query_refinement(&mut self, refinement: &ResolvedQueryRefinement<S>) {
+        for (key, av) in &refinement.nodes {
+            self.graph.set_node_attr(*key, av.clone());
+        }
+        for (edge_key, av) in &refinement.edges {
+            self.graph.set_edge_attr(*edge_key, av.clone());
+        }
     }
 
     /// Returns the newly added AIDs
@@ -721,6 +917,10 @@ impl<S: Semantics> IntermediateState<S> {
         marker: Option<AbstractOperationResultMarker>,
         operation_output: AbstractOperationOutput<S>,
     ) -> Result<IntermediateStateAbstractOutputResult, OperationBuilderError> {
+        let has_structural_changes = !operation_output.new_nodes.is_empty()
+            || !operation_output.removed_nodes.is_empty()
+            || !operation_output.new_edges.is_empty()
+            || !operation_output.removed_edges.is_empty();
         // go over new nodes
         let mut new_aids = Vec::new();
         for (node_marker, node_key) in operation_output.new_nodes {
@@ -770,6 +970,9 @@ impl<S: Semantics> IntermediateState<S> {
             // See `maybe_set_node_value`.
             // This structure is useful because operations (i.e., builtins) that know for a fact that
             // they write a specific abstract value can use this to be more precise.
+            if operation_output.must_changed_nodes.contains(&key) {
+                self.node_must_be_written_to.insert(aid);
+            }
             self.node_may_be_written_to.insert(aid, node_abstract);
         }
         for ((source, target), edge_abstract) in operation_output.changed_abstract_values_edges {
@@ -785,6 +988,12 @@ impl<S: Semantics> IntermediateState<S> {
                 log::warn!("internal error: changed edge target not found in mapping");
                 continue;
             };
+            if operation_output
+                .must_changed_edges
+                .contains(&(source, target))
+            {
+                self.edge_must_be_written_to.insert((source_aid, target_aid));
+            }
             self.edge_may_be_written_to
                 .insert((source_aid, target_aid), edge_abstract);
         }
@@ -813,6 +1022,7 @@ impl<S: Semantics> IntermediateState<S> {
         Ok(IntermediateStateAbstractOutputResult {
             new_aids,
             removed_aids,
+            has_structural_changes,
         })
     }
 
@@ -1129,6 +1339,7 @@ fn merge_states_result<S: Semantics>(
     }
 
     let mut new_state = IntermediateState::new();
+    new_state.has_yielded = state_true.has_yielded && state_false.has_yielded;
 
     let mut common_aids = HashSet::new();
     // First, collect all AIDs that are present in both states.
@@ -1197,6 +1408,12 @@ fn merge_states_result<S: Semantics>(
         if let Some(merged_av) = merged_written_av {
             new_state.node_may_be_written_to.insert(aid, merged_av);
         }
+        // a node is only overwritten for sure if it is overwritten in both branches
+        if state_true.node_must_be_written_to.contains(&aid)
+            && state_false.node_must_be_written_to.contains(&aid)
+        {
+            new_state.node_must_be_written_to.insert(aid);
+        }
     }
 
     // Now we merge the edges.
@@ -1294,6 +1511,11 @@ fn merge_states_result<S: Semantics>(
                 .edge_may_be_written_to
                 .insert((*from_aid, *to_aid), merged_av);
         }
+        if state_true.edge_must_be_written_to.contains(&edge)
+            && state_false.edge_must_be_written_to.contains(&edge)
+        {
+            new_state.edge_must_be_written_to.insert(edge);
+        }
     }
 
     // merge the 'maybe deleted edges' sets
@@ -1327,3 +1549,55 @@ fn merge_states_result<S: Semantics>(
             .collect(),
     }
 }
+
+/// Checks that either all or none of the non-diverged states have yielded a query result.
+///
+/// Since nothing may follow a yield, a merged state where only some branches yielded would leave
+/// the remaining instructions unreachable on some paths.
+fn check_branches_agree_on_yield<S: Semantics>(
+    states: &[&IntermediateState<S>],
+) -> Result<(), OperationBuilderError> {
+    let mut live_states = states.iter().filter(|state| !state.has_diverged);
+    let Some(first) = live_states.next() else {
+        return Ok(());
+    };
+    if live_states.any(|state| state.has_yielded != first.has_yielded) {
+        bail!(OperationBuilderError::YieldInSomeBranches);
+    }
+    Ok(())
+}
+
+/// Merges any number of states, e.g., the states at the end of all arms of a shape match, by
+/// merging them one after the other with [`merge_states_result`].
+///
+/// Returns the merged state and, for every given state, the AIDs that are not present in the
+/// merged state.
+fn merge_many_states_result<S: Semantics>(
+    states: &[&IntermediateState<S>],
+) -> (IntermediateState<S>, Vec<HashSet<AbstractNodeId>>) {
+    let (first, rest) = states
+        .split_first()
+        .expect("internal error: need at least one state to merge");
+    let merged_state = rest.iter().fold((*first).clone(), |merged, state| {
+        merge_states_result(&merged, state).merged_state
+    });
+
+    let merged_aids = merged_state
+        .node_keys_to_aid
+        .right_values()
+        .cloned()
+        .collect::<HashSet<_>>();
+    let missing = states
+        .iter()
+        .map(|state| {
+            state
+                .node_keys_to_aid
+                .right_values()
+                .cloned()
+                // like in merge_states_result, a diverged state is missing everything
+                .filter(|aid| state.has_diverged || !merged_aids.contains(aid))
+                .collect()
+        })
+        .collect();
+    (merged_state, missing)
+}
@@ -1,18 +1,133 @@
 /*!
-Not implemented :(
-My idea to implement this is as follows:
+Programming by demonstration: running concrete examples alongside an [`OperationBuilder`].
 
-The user can specify a set of concrete examples.
+The user can specify a set of concrete examples via [`OperationBuilder::add_example`].
 A single such example consists of a concrete graph, that must match the operation's signature, but
 could have more nodes as well (to account for shape queries!)
 
-When stepping through the abstract operation, the example is ran through each instruction as well.
-However, since some branches might not be entered for the example, that case needs to be handled.
-For example, you might store an Option<ExecutedExample> and set it to none when entering a branch that
-concretely is not entered for that example.
+After every builder message, the operation built so far is run on every example, up to the
+current instruction. Since some branches might not be entered for an example, every example
+keeps track of whether it dynamically reaches the current instruction, and what its concrete graph
+looks like there. See [`ExampleRun`].
 
-We might even run the entire set of examples simultaneously, keep track of which dynamically reach the current
-instruction, and whenever that set is empty, we can tell the user somehow and they can then add more
-examples to the operation.
+Whenever no example reaches the current instruction, the user can be told so via
+[`OperationBuilder::no_example_reaches_current_instruction`], and they can then add more examples
+to the operation. Examples that do not match the operation's parameter are not run at all, and
+are reported separately via [`OperationBuilder::examples_not_matching_parameter`].
 
+[`OperationBuilder`]: crate::operation::builder::OperationBuilder
+[`OperationBuilder::add_example`]: crate::operation::builder::OperationBuilder::add_example
+[`OperationBuilder::no_example_reaches_current_instruction`]: crate::operation::builder::OperationBuilder::no_example_reaches_current_instruction
+[`OperationBuilder::examples_not_matching_parameter`]: crate::operation::builder::OperationBuilder::examples_not_matching_parameter
 */
+
+use crate::operation::marker::MarkerSet;
+use crate::operation::trace::{Trace, TraceFrame};
+use crate::operation::{OperationError, concrete_substitution, run_from_concrete_with_marker_set};
+use crate::prelude::*;
+use crate::semantics::{AbstractionCache, ConcreteGraph};
+use crate::{NodeKey, Semantics};
+use error_stack::Report;
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+/// A concrete example for the operation being built.
+pub struct DemonstrationExample<S: Semantics> {
+    /// The graph the operation is run on. Every run starts from a fresh copy.
+    pub graph: ConcreteGraph<S>,
+    /// The nodes of `graph` that are passed as the operation's explicit parameters.
+    pub inputs: Vec<NodeKey>,
+}
+
+impl<S: Semantics> Clone for DemonstrationExample<S> {
+    fn clone(&self) -> Self {
+        DemonstrationExample {
+            graph: self.graph.clone(),
+            inputs: self.inputs.clone(),
+        }
+    }
+}
+
+/// The result of running a [`DemonstrationExample`] up to the builder's current instruction.
+pub struct ExampleRun<S: Semantics> {
+    /// Whether the example's graph and inputs match the operation's parameter.
+    ///
+    /// Examples that do not match are not run, and `error` explains why they do not match.
+    pub matches_parameter: bool,
+    /// The runtime state of the example every time it reached the current instruction, in execution order.
+    ///
+    /// With recursion, the current instruction may be reached more than once.
+    /// If the example failed, this contains the states reached before the failure.
+    pub reached: Vec<TraceFrame<S>>,
+    /// The error that aborted the example, e.g., a crash or an exceeded execution limit.
+    pub error: Option<Report<OperationError>>,
+}
+
+impl<S: Semantics> ExampleRun<S> {
+    /// Returns true if the example reached the current instruction at least once.
+    pub fn reaches_current_instruction(&self) -> bool {
+        !self.reached.is_empty()
+    }
+}
+
+/// Runs every example with the given operation, whose only trace instruction must be at the
+/// builder's current instruction.
+///
+/// Frames traced by other operations, e.g., by a callee's own trace instruction, are ignored.
+pub(super) fn run_examples<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>>(
+    op_ctx: &OperationContext<S>,
+    self_op_id: OperationId,
+    traced_op: UserDefinedOperation<S>,
+    examples: &[DemonstrationExample<S>],
+    limits: ExecutionLimits,
+) -> Vec<ExampleRun<S>> {
+    // recursive calls must see the partial operation as well
+    let mut op_ctx = op_ctx.clone();
+    op_ctx.add_custom_operation(self_op_id, traced_op);
+
+    examples
+        .iter()
+        .map(|example| {
+            let mut g = example.graph.clone();
+            let mut abstraction = AbstractionCache::new();
+            let subst = concrete_substitution(
+                &mut g,
+                &mut abstraction,
+                &op_ctx,
+                self_op_id,
+                &example.inputs,
+            );
+            abstraction.clear(&mut g);
+            if let Err(err) = subst {
+                return ExampleRun {
+                    matches_parameter: false,
+                    reached: Vec::new(),
+                    error: Some(err),
+                };
+            }
+
+            let trace = RefCell::new(Trace::new());
+            let res = run_from_concrete_with_marker_set(
+                &mut g,
+                &op_ctx,
+                self_op_id,
+                &example.inputs,
+                limits,
+                &RefCell::new(MarkerSet::new()),
+                &trace,
+                &HashSet::new(),
+            );
+            let reached = trace
+                .into_inner()
+                .frames
+                .into_iter()
+                .filter(|frame| frame.op_id == self_op_id)
+                .collect();
+            ExampleRun {
+                matches_parameter: true,
+                reached,
+                error: res.err(),
+            }
+        })
+        .collect()
+}
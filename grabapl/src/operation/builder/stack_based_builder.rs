@@ -1,24 +1,27 @@
 use crate::operation::builder::{
-    BuilderInstruction, BuilderOpLike, IntermediateState, OperationBuilderError, QueryPath,
-    merge_states_result,
+    BuilderInstruction, BuilderOpLike, BuilderQueryLike, IntermediateState, OperationBuilderError,
+    QueryPath, check_branches_agree_on_yield, merge_many_states_result, merge_states_result,
 };
 use crate::operation::signature::parameter::{AbstractOutputNodeMarker, OperationParameter};
 use crate::operation::signature::parameterbuilder::OperationParameterBuilder;
 use crate::operation::user_defined::{
     AbstractNodeId, AbstractOperationArgument, AbstractOperationResultMarker,
     AbstractUserDefinedOperationOutput, Instruction, InstructionWithResultMarker, NamedMarker,
-    QueryInstructions, UserDefinedOperation,
+    QueryBranch, QueryInstructions, QueryLikeInstruction, ShapeMatchArm, UserDefinedOperation,
 };
 use crate::prelude::*;
 use crate::{NodeKey, Semantics, SubstMarker};
 use derive_more::From;
 use derive_more::with_trait::TryInto;
 use error_stack::{ResultExt, bail, report};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::marker::PhantomData;
 
 use crate::operation::OperationContext;
+use crate::operation::builder::programming_by_demonstration::{
+    self, DemonstrationExample, ExampleRun,
+};
 use crate::operation::marker::{Marker, SkipMarkers};
 use crate::operation::query::{GraphShapeQuery, ShapeNodeIdentifier};
 use crate::operation::signature::{
@@ -158,6 +161,10 @@ impl<S: Semantics> CollectingInstructionsFrame<S> {
         let this: &mut CollectingInstructionsFrame<S> = builder.stack.expect_mut();
 
         let instruction = instruction_opt.take().unwrap();
+        if this.current_state.has_yielded && !instruction.can_break_body() {
+            let _ = instruction_opt.insert(instruction);
+            bail!(OperationBuilderError::InstructionAfterYield);
+        }
         match instruction {
             // We handle these ourselves
             BI::AddOperation(builder_op_like, args) => {
@@ -187,7 +194,7 @@ impl<S: Semantics> CollectingInstructionsFrame<S> {
             }
             BI::StartQuery(..) => {
                 let (query_frame, branches_frame) =
-                    QueryFrame::new(&this.current_state, instruction)?;
+                    QueryFrame::new(&builder.data, &this.current_state, instruction)?;
 
                 builder.push_frame(query_frame);
                 builder.push_frame(branches_frame);
@@ -226,6 +233,11 @@ impl<S: Semantics> CollectingInstructionsFrame<S> {
                 // push it onto the stack
                 builder.push_frame(shape_query_frame);
             }
+            BI::StartShapeMatch(op_result_marker) => {
+                let match_frame =
+                    ShapeMatchFrame::new(op_result_marker, this.current_state.clone());
+                builder.push_frame(match_frame);
+            }
             BI::Diverge(msg) => {
                 this.current_state.diverge();
                 this.instructions.push((
@@ -238,6 +250,14 @@ impl<S: Semantics> CollectingInstructionsFrame<S> {
             BI::Trace => {
                 this.instructions.push((None, Instruction::Trace));
             }
+            BI::YieldQueryResult(value) => {
+                if !builder.data.is_query {
+                    bail!(OperationBuilderError::YieldOutsideQuery);
+                }
+                this.current_state.has_yielded = true;
+                this.instructions
+                    .push((None, Instruction::YieldQueryResult(value)));
+            }
             _ => {
                 bail_unexpected_instruction!(
                     instruction,
@@ -258,11 +278,23 @@ impl<S: Semantics> CollectingInstructionsFrame<S> {
         op_like: BuilderOpLike<S>,
         args: Vec<AbstractNodeId>,
     ) -> Result<Vec<AbstractNodeId>, OperationBuilderError> {
-        let op = op_like
-            .as_abstract_operation(builder_data.op_ctx, &builder_data.expected_self_signature)?;
+        // if we declared our own signature, recursive calls must rely on it as well
+        let self_signature = builder_data
+            .declared_signatures
+            .get(&builder_data.self_op_id)
+            .unwrap_or(&builder_data.expected_self_signature);
+        let op = op_like.as_abstract_operation(
+            builder_data.op_ctx,
+            &builder_data.declared_signatures,
+            self_signature,
+        )?;
         let (abstract_arg, output_res) =
             self.current_state
                 .interpret_op(builder_data.op_ctx, output_name, op, args)?;
+        // queries must leave the graph's structure untouched, even outside the caller's view
+        if builder_data.is_query && output_res.has_structural_changes {
+            bail!(OperationBuilderError::QueryWithStructuralChanges);
+        }
 
         let op_like_instr = op_like.into_op_like_instruction(builder_data.self_op_id);
 
@@ -394,6 +426,7 @@ impl<S: Semantics> BranchesFrame<S> {
             .as_ref()
             .map(|cif| &cif.current_state)
             .unwrap_or(default_false_state);
+        check_branches_agree_on_yield(&[true_branch_state_ref, false_branch_state_ref])?;
         let merge_result = merge_states_result(true_branch_state_ref, false_branch_state_ref);
 
         // take into account the missing AIDs from the branches, and insert ForgetAid instructions
@@ -422,9 +455,12 @@ impl<S: Semantics> BranchesFrame<S> {
 }
 
 struct QueryFrame<S: Semantics> {
-    query: S::BuiltinQuery,
+    query: QueryLikeInstruction<S>,
     abstract_arg: AbstractOperationArgument,
     before_branches_state: IntermediateState<S>,
+    /// The states at the start of the branches, which may be refined by the query.
+    initial_true_branch_state: IntermediateState<S>,
+    initial_false_branch_state: IntermediateState<S>,
 }
 
 impl<S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> Clone for QueryFrame<S> {
@@ -433,33 +469,73 @@ impl<S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> Clone for Query
             query: self.query.clone(),
             abstract_arg: self.abstract_arg.clone(),
             before_branches_state: self.before_branches_state.clone(),
+            initial_true_branch_state: self.initial_true_branch_state.clone(),
+            initial_false_branch_state: self.initial_false_branch_state.clone(),
         }
     }
 }
 
 impl<S: Semantics> QueryFrame<S> {
     pub fn new(
+        builder_data: &BuilderData<S>,
         outer_state: &IntermediateState<S>,
         instruction: BuilderInstruction<S>,
     ) -> Result<(Self, BranchesFrame<S>), OperationBuilderError> {
         use BuilderInstruction as BI;
 
         match instruction {
-            BI::StartQuery(query, args) => {
+            BI::StartQuery(query_like, args) => {
                 let mut before_branches_state = outer_state.clone();
                 // TODO: decide if queries should be allowed to modify the state.
-                //  (maybe they should even be allowed to provide different states on true and false?)
-                let abstract_arg = before_branches_state.interpret_builtin_query(&query, args)?;
+                let mut refinements = None;
+                let (query, abstract_arg) = match query_like {
+                    BuilderQueryLike::Builtin(query) => {
+                        let (abstract_arg, taken, not_taken) =
+                            before_branches_state.interpret_builtin_query(&query, args)?;
+                        refinements = Some((taken, not_taken));
+                        (QueryLikeInstruction::Builtin(query), abstract_arg)
+                    }
+                    BuilderQueryLike::FromOperationId(op_id) => {
+                        let abstract_arg = Self::interpret_user_defined_query(
+                            builder_data,
+                            &mut before_branches_state,
+                            BuilderOpLike::FromOperationId(op_id),
+                            op_id,
+                            args,
+                        )?;
+                        (QueryLikeInstruction::Operation(op_id), abstract_arg)
+                    }
+                    BuilderQueryLike::Recurse => {
+                        let op_id = builder_data.self_op_id;
+                        let abstract_arg = Self::interpret_user_defined_query(
+                            builder_data,
+                            &mut before_branches_state,
+                            BuilderOpLike::Recurse,
+                            op_id,
+                            args,
+                        )?;
+                        (QueryLikeInstruction::Operation(op_id), abstract_arg)
+                    }
+                };
+
+                let mut initial_true_branch_state = before_branches_state.clone();
+                let mut initial_false_branch_state = before_branches_state.clone();
+                if let Some((taken, not_taken)) = refinements {
+                    initial_true_branch_state.apply_query_refinement(&taken);
+                    initial_false_branch_state.apply_query_refinement(&not_taken);
+                }
 
                 let frame = QueryFrame {
                     query,
                     abstract_arg,
                     before_branches_state,
+                    initial_true_branch_state,
+                    initial_false_branch_state,
                 };
 
                 let branches_frame = BranchesFrame::new(
-                    frame.before_branches_state.clone(),
-                    frame.before_branches_state.clone(),
+                    frame.initial_true_branch_state.clone(),
+                    frame.initial_false_branch_state.clone(),
                 );
 
                 Ok((frame, branches_frame))
@@ -469,6 +545,31 @@ impl<S: Semantics> QueryFrame<S> {
         }
     }
 
+    /// Interprets a call to a user defined query, checking that the callee is a query.
+    fn interpret_user_defined_query(
+        builder_data: &BuilderData<S>,
+        state: &mut IntermediateState<S>,
+        op_like: BuilderOpLike<S>,
+        op_id: OperationId,
+        args: Vec<AbstractNodeId>,
+    ) -> Result<AbstractOperationArgument, OperationBuilderError> {
+        let self_signature = builder_data
+            .declared_signatures
+            .get(&builder_data.self_op_id)
+            .unwrap_or(&builder_data.expected_self_signature);
+        let op = op_like.as_abstract_operation(
+            builder_data.op_ctx,
+            &builder_data.declared_signatures,
+            self_signature,
+        )?;
+        if !op.is_query() {
+            bail!(OperationBuilderError::NotAQuery(op_id));
+        }
+        // queries cannot have structural changes, so there are no new or removed AIDs to track
+        let (abstract_arg, _) = state.interpret_op(builder_data.op_ctx, None, op, args)?;
+        Ok(abstract_arg)
+    }
+
     pub fn consume(
         builder: &mut Builder<S>,
         instruction_opt: &mut Option<BuilderInstruction<S>>,
@@ -499,8 +600,8 @@ impl<S: Semantics> QueryFrame<S> {
 
         let (merged_branch, query_instructions) = branches_frame
             .into_merged_state_and_query_instructions(
-                &self.before_branches_state,
-                &self.before_branches_state,
+                &self.initial_true_branch_state,
+                &self.initial_false_branch_state,
             )?;
 
         let outer_frame: &mut CollectingInstructionsFrame<S> = builder.stack.expect_mut();
@@ -508,7 +609,7 @@ impl<S: Semantics> QueryFrame<S> {
         // push ourselves as instruction
         outer_frame.instructions.push((
             None,
-            Instruction::BuiltinQuery(self.query, self.abstract_arg, query_instructions),
+            Instruction::QueryLike(self.query, self.abstract_arg, query_instructions),
         ));
 
         Ok(())
@@ -575,6 +676,7 @@ impl<S: Semantics> ReturnFrame<S> {
         let mut signature =
             OperationSignature::empty_new("some_name", data.built.parameter.clone().unwrap());
         populate_signature_changes(&mut signature, &cif.current_state);
+        signature.is_query = data.is_query;
 
         ReturnFrame {
             instr_frame: cif,
@@ -774,6 +876,14 @@ struct BuildingShapeQueryFrame<S: Semantics> {
     /// This is simultaneously the expected graph of the shape query.
     true_branch_state: IntermediateState<S>,
     skip_markers: SkipMarkers,
+    /// Edge order constraints, in terms of the keys of `true_branch_state`.
+    edge_orders: Vec<(NodeKey, NodeKey, ShapeEdgeOrder<NodeKey>)>,
+    /// Negative conditions, in terms of the keys of `true_branch_state`.
+    negative_conditions: Vec<NegativeShapeCondition<S, NodeKey>>,
+    /// Paths, in terms of the keys of `true_branch_state`.
+    paths: Vec<ShapePath<S, NodeKey>>,
+    /// Whether this is the query of an arm of the [`ShapeMatchFrame`] below.
+    match_arm: bool,
 }
 
 impl<S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> Clone
@@ -788,6 +898,10 @@ impl<S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> Clone
             initial_state: self.initial_state.clone(),
             true_branch_state: self.true_branch_state.clone(),
             skip_markers: self.skip_markers.clone(),
+            edge_orders: self.edge_orders.clone(),
+            negative_conditions: self.negative_conditions.clone(),
+            paths: self.paths.clone(),
+            match_arm: self.match_arm,
         }
     }
 }
@@ -807,6 +921,24 @@ impl<S: Semantics> BuildingShapeQueryFrame<S> {
             initial_state,
             true_branch_state,
             skip_markers: SkipMarkers::none(), // we don't skip any markers by default
+            edge_orders: Vec::new(),
+            negative_conditions: Vec::new(),
+            paths: Vec::new(),
+            match_arm: false,
+        }
+    }
+
+    /// A frame for the query of an arm of the [`ShapeMatchFrame`] on top of the stack.
+    ///
+    /// Unlike shape queries, the query of an arm ends with the first instruction that does not
+    /// belong to it, which starts the arm's body.
+    pub fn new_match_arm(
+        query_marker: AbstractOperationResultMarker,
+        initial_state: IntermediateState<S>,
+    ) -> Self {
+        BuildingShapeQueryFrame {
+            match_arm: true,
+            ..BuildingShapeQueryFrame::new(query_marker, initial_state)
         }
     }
 
@@ -835,8 +967,48 @@ impl<S: Semantics> BuildingShapeQueryFrame<S> {
             BI::ExpectShapeNodeChange(aid, new_av) => {
                 this.true_branch_state.set_node_av(aid, new_av)?;
             }
-            BI::ExpectShapeEdge(src, dst, edge) => {
+            BI::ExpectShapeEdge(src, dst, edge, order) => {
                 this.true_branch_state.add_edge(src, dst, edge, true)?;
+                if let Some(order) = order {
+                    let state = &this.true_branch_state;
+                    let src_key = state.get_key_from_aid(&src)?;
+                    let dst_key = state.get_key_from_aid(&dst)?;
+                    let order = order.map_sibling(|sibling| {
+                        let sibling_key = state.get_key_from_aid(&sibling)?;
+                        if sibling == dst
+                            || state.graph.get_edge_attr((src_key, sibling_key)).is_none()
+                        {
+                            bail!(OperationBuilderError::ShapeEdgeOrderSiblingNotFound(
+                                src, sibling
+                            ));
+                        }
+                        Ok(sibling_key)
+                    })?;
+                    this.edge_orders.push((src_key, dst_key, order));
+                }
+            }
+            BI::ExpectNoShapePattern(condition) => {
+                let state = &this.true_branch_state;
+                let forbidden_count = condition.forbidden_nodes.len();
+                for (src, dst, _) in &condition.edges {
+                    for node in [src, dst] {
+                        if let NegativeShapeNode::Forbidden(index) = node
+                            && *index >= forbidden_count
+                        {
+                            bail!(OperationBuilderError::NegativeShapeConditionNodeNotFound(
+                                *index
+                            ));
+                        }
+                    }
+                }
+                let condition = condition.map_existing(|aid| state.get_key_from_aid(&aid))?;
+                this.negative_conditions.push(condition);
+            }
+            BI::ExpectShapePath(path) => {
+                // The path's nodes are not known statically, so it does not change the state.
+                let state = &this.true_branch_state;
+                let path = path.map_nodes(|aid| state.get_key_from_aid(&aid))?;
+                this.paths.push(path);
             }
             BI::SkipMarker(marker) => {
                 this.skip_markers.skip(marker);
@@ -844,6 +1016,14 @@ impl<S: Semantics> BuildingShapeQueryFrame<S> {
             BI::SkipAllMarkers => {
                 this.skip_markers.skip_all();
             }
+            instruction if this.match_arm => {
+                // The arm's body starts, and needs to consume this instruction
+                let _ = instruction_opt.insert(instruction);
+
+                let this: BuildingShapeQueryFrame<S> = builder.stack.expect_pop();
+                let (query, arm_state) = this.into_query();
+                ShapeMatchFrame::enter_arm(builder, query, arm_state);
+            }
             instruction if instruction.can_break_body() => {
                 // Advance to BuiltShapeQueryFrame
                 // it needs to consume this instruction
@@ -874,25 +1054,224 @@ impl<S: Semantics> BuildingShapeQueryFrame<S> {
 
         // TODO: check validity, i.e., no free floating shape nodes, etc.
 
+        let query_marker = self.query_marker;
+        let abstract_arg = self.abstract_arg.clone();
+        let initial_state = self.initial_state.clone();
+        let (query, true_branch_state) = self.into_query();
+
+        let built_frame = BuiltShapeQueryFrame::new(
+            query_marker,
+            query,
+            abstract_arg,
+            initial_state.clone(),
+            true_branch_state.clone(),
+        );
+
+        let branches_frame = BranchesFrame::new(true_branch_state, initial_state);
+
+        Ok((built_frame, branches_frame))
+    }
+
+    /// Returns the built shape query, and the state if it matches.
+    fn into_query(self) -> (GraphShapeQuery<S>, IntermediateState<S>) {
         let query = GraphShapeQuery::new(
             self.parameter,
             self.true_branch_state.graph.clone(),
             self.gsq_node_keys_to_shape_idents,
         )
-        .with_skip_markers(self.skip_markers);
+        .with_skip_markers(self.skip_markers)
+        .with_edge_orders(self.edge_orders)
+        .with_negative_conditions(self.negative_conditions)
+        .with_paths(self.paths);
+        (query, self.true_branch_state)
+    }
+}
 
-        let built_frame = BuiltShapeQueryFrame::new(
-            self.query_marker,
+/// An arm of a [`ShapeMatchFrame`] whose query has been built.
+struct ShapeMatchArmFrame<S: Semantics> {
+    query: GraphShapeQuery<S>,
+    /// The state at the start of the arm's body, i.e., if the arm's query matches.
+    initial_state: IntermediateState<S>,
+    /// The arm's body, once it is finished.
+    body: Option<CollectingInstructionsFrame<S>>,
+}
+
+impl<S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> Clone for ShapeMatchArmFrame<S> {
+    fn clone(&self) -> Self {
+        ShapeMatchArmFrame {
+            query: self.query.clone(),
+            initial_state: self.initial_state.clone(),
+            body: self.body.clone(),
+        }
+    }
+}
+
+/// This frame is used to build a shape match, from the StartShapeMatch instruction to its EndQuery.
+///
+/// Every StartMatchArm instruction pushes a [`BuildingShapeQueryFrame`] for the arm's query,
+/// which in turn pushes a [`CollectingInstructionsFrame`] for the arm's body. The default arm's
+/// body starts with EnterDefaultArm and must come last.
+struct ShapeMatchFrame<S: Semantics> {
+    query_marker: AbstractOperationResultMarker,
+    /// The argument shared by all arms' shape queries.
+    abstract_arg: AbstractOperationArgument,
+    /// The state before the match, which is also the state at the start of the default arm.
+    initial_state: IntermediateState<S>,
+    arms: Vec<ShapeMatchArmFrame<S>>,
+    default_arm: Option<CollectingInstructionsFrame<S>>,
+    /// The arm whose body is currently being built, with `QueryBranch::NotTaken` for the default arm.
+    currently_entered_arm: Option<QueryBranch>,
+}
+
+impl<S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> Clone for ShapeMatchFrame<S> {
+    fn clone(&self) -> Self {
+        ShapeMatchFrame {
+            query_marker: self.query_marker,
+            abstract_arg: self.abstract_arg.clone(),
+            initial_state: self.initial_state.clone(),
+            arms: self.arms.clone(),
+            default_arm: self.default_arm.clone(),
+            currently_entered_arm: self.currently_entered_arm,
+        }
+    }
+}
+
+impl<S: Semantics> ShapeMatchFrame<S> {
+    pub fn new(
+        query_marker: AbstractOperationResultMarker,
+        initial_state: IntermediateState<S>,
+    ) -> Self {
+        let (_, abstract_arg) = initial_state.as_param_for_shape_query();
+        ShapeMatchFrame {
+            query_marker,
+            abstract_arg,
+            initial_state,
+            arms: vec![],
+            default_arm: None,
+            currently_entered_arm: None,
+        }
+    }
+
+    /// Adds an arm with the given query to the shape match frame on top of the stack, and starts
+    /// building its body.
+    fn enter_arm(builder: &mut Builder<S>, query: GraphShapeQuery<S>, state: IntermediateState<S>) {
+        let this: &mut ShapeMatchFrame<S> = builder.stack.expect_mut();
+        this.currently_entered_arm = Some(QueryBranch::Arm(this.arms.len()));
+        this.arms.push(ShapeMatchArmFrame {
             query,
-            self.abstract_arg,
-            self.initial_state.clone(),
-            self.true_branch_state.clone(),
-        );
+            initial_state: state.clone(),
+            body: None,
+        });
+        builder.push_frame(CollectingInstructionsFrame::from_state(state));
+    }
 
-        let branches_frame =
-            BranchesFrame::new(self.true_branch_state.clone(), self.initial_state.clone());
+    pub fn consume(
+        builder: &mut Builder<S>,
+        instruction_opt: &mut Option<BuilderInstruction<S>>,
+    ) -> Result<(), OperationBuilderError> {
+        use BuilderInstruction as BI;
 
-        Ok((built_frame, branches_frame))
+        let this: &mut ShapeMatchFrame<S> = builder.stack.expect_mut();
+
+        if let Some(arm) = this.currently_entered_arm
+            && builder
+                .return_stack
+                .top_is::<CollectingInstructionsFrame<S>>()
+        {
+            let body: CollectingInstructionsFrame<S> = builder.return_stack.expect_pop();
+            match arm {
+                QueryBranch::Arm(index) => this.arms[index].body = Some(body),
+                _ => this.default_arm = Some(body),
+            }
+            this.currently_entered_arm = None;
+        }
+
+        let instruction = instruction_opt.take().unwrap();
+        match instruction {
+            BI::StartMatchArm => {
+                if this.default_arm.is_some() {
+                    bail!(OperationBuilderError::Oneoff(
+                        "match arms must come before the default arm"
+                    ));
+                }
+                let arm_frame = BuildingShapeQueryFrame::new_match_arm(
+                    this.query_marker,
+                    this.initial_state.clone(),
+                );
+                builder.push_frame(arm_frame);
+            }
+            BI::EnterDefaultArm => {
+                if this.default_arm.is_some() {
+                    bail!(OperationBuilderError::AlreadyVisitedDefaultArm);
+                }
+                let default_frame =
+                    CollectingInstructionsFrame::from_state(this.initial_state.clone());
+                this.currently_entered_arm = Some(QueryBranch::NotTaken);
+                builder.push_frame(default_frame);
+            }
+            BI::EndQuery | BI::Finalize => {
+                let this: ShapeMatchFrame<S> = builder.stack.expect_pop();
+                this.handle_match_end(builder)?;
+            }
+            _ => {
+                bail_unexpected_instruction!(instruction, instruction_opt, "ShapeMatchFrame");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_match_end(self, builder: &mut Builder<S>) -> Result<(), OperationBuilderError> {
+        // merge the final states of all arms, including the default arm
+        let (merged_state, missing) = {
+            let mut states = self
+                .arms
+                .iter()
+                .map(|arm| {
+                    arm.body
+                        .as_ref()
+                        .map(|body| &body.current_state)
+                        .unwrap_or(&arm.initial_state)
+                })
+                .collect::<Vec<_>>();
+            states.push(
+                self.default_arm
+                    .as_ref()
+                    .map(|body| &body.current_state)
+                    .unwrap_or(&self.initial_state),
+            );
+            check_branches_agree_on_yield(&states)?;
+            merge_many_states_result(&states)
+        };
+
+        // every arm forgets the AIDs that are missing from the merged state
+        let mut missing = missing.into_iter();
+        let mut instructions_with_forgotten_aids =
+            |body: Option<CollectingInstructionsFrame<S>>| {
+                let mut instructions = body.map(|body| body.instructions).unwrap_or_default();
+                for aid in missing.next().unwrap() {
+                    instructions.push((None, Instruction::ForgetAid { aid }));
+                }
+                instructions
+            };
+        let arms = self
+            .arms
+            .into_iter()
+            .map(|arm| ShapeMatchArm {
+                query: arm.query,
+                instructions: instructions_with_forgotten_aids(arm.body),
+            })
+            .collect();
+        let default_instructions = instructions_with_forgotten_aids(self.default_arm);
+
+        let outer_frame: &mut CollectingInstructionsFrame<S> = builder.stack.expect_mut();
+        outer_frame.current_state = merged_state;
+        outer_frame.instructions.push((
+            Some(self.query_marker),
+            Instruction::ShapeMatch(arms, self.abstract_arg, default_instructions),
+        ));
+
+        Ok(())
     }
 }
 
@@ -987,6 +1366,7 @@ enum Frame<S: Semantics> {
     Branches(BranchesFrame<S>),
     BuildingShapeQuery(BuildingShapeQueryFrame<S>),
     BuiltShapeQuery(BuiltShapeQueryFrame<S>),
+    ShapeMatch(ShapeMatchFrame<S>),
     Return(ReturnFrame<S>),
     WrapperReturn(WrapperReturnFrame<S>),
 }
@@ -1000,6 +1380,7 @@ impl<S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> Clone for Frame
             Frame::Branches(frame) => Frame::Branches(frame.clone()),
             Frame::BuildingShapeQuery(frame) => Frame::BuildingShapeQuery(frame.clone()),
             Frame::BuiltShapeQuery(frame) => Frame::BuiltShapeQuery(frame.clone()),
+            Frame::ShapeMatch(frame) => Frame::ShapeMatch(frame.clone()),
             Frame::Return(frame) => Frame::Return(frame.clone()),
             Frame::WrapperReturn(frame) => Frame::WrapperReturn(frame.clone()),
         }
@@ -1110,6 +1491,17 @@ impl<S: Semantics> FrameStack<S> {
                         built_shape_query_frame.query_marker
                     )));
                 }
+                Frame::ShapeMatch(shape_match_frame) => {
+                    path.push(QueryPath::Query(format!(
+                        "{:?}",
+                        shape_match_frame.query_marker
+                    )));
+                    match shape_match_frame.currently_entered_arm {
+                        Some(QueryBranch::Arm(index)) => path.push(QueryPath::MatchArm(index)),
+                        Some(_) => path.push(QueryPath::DefaultArm),
+                        None => {}
+                    }
+                }
                 _ => {}
             }
         }
@@ -1145,6 +1537,11 @@ struct BuilderData<'a, S: Semantics> {
     /// How we expect our signature to look like
     /// Includes changes asserted by the user via e.g. SelfReturnNode
     expected_self_signature: OperationSignature<S>,
+    /// Signatures of operations that are built at the same time, e.g., mutually recursive ones.
+    /// Calls to these operations are checked against the declared signature.
+    declared_signatures: HashMap<OperationId, OperationSignature<S>>,
+    /// Whether the operation being built is a query, see [`BuilderInstruction::ExpectSelfQuery`].
+    is_query: bool,
 }
 
 impl<'a, S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> Clone for BuilderData<'a, S> {
@@ -1155,6 +1552,8 @@ impl<'a, S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> Clone for B
             self_op_id: self.self_op_id,
             partial_self_op: self.partial_self_op.clone(),
             expected_self_signature: self.expected_self_signature.clone(),
+            declared_signatures: self.declared_signatures.clone(),
+            is_query: self.is_query,
         }
     }
 }
@@ -1170,6 +1569,8 @@ impl<'a, S: Semantics> BuilderData<'a, S> {
                 "some_name",
                 OperationParameter::new_empty(),
             ),
+            declared_signatures: HashMap::new(),
+            is_query: false,
         }
     }
 
@@ -1181,6 +1582,9 @@ impl<'a, S: Semantics> BuilderData<'a, S> {
 
         let instruction = instruction_opt.take().unwrap();
         match instruction {
+            BI::DeclareOperation(op_id, signature) => {
+                self.declared_signatures.insert(op_id, *signature);
+            }
             BI::SelfReturnNode(output_marker, av) => {
                 self.expected_self_signature
                     .output
@@ -1193,6 +1597,10 @@ impl<'a, S: Semantics> BuilderData<'a, S> {
                     .new_edges
                     .insert((src, dst), av);
             }
+            BI::ExpectSelfQuery => {
+                self.is_query = true;
+                self.expected_self_signature.is_query = true;
+            }
             _ => {
                 // do nothing
                 let _ = instruction_opt.insert(instruction);
@@ -1257,6 +1665,9 @@ impl<'a, S: Semantics> Builder<'a, S> {
                 // TODO: do we ever enter this path even? is BuiltShapeQueryFrame ever not immediately consumed/processed?
                 BuilderShowData::ShapeQueryFrame(&frame.initial_true_branch_state)
             }
+            Some(Frame::ShapeMatch(frame)) => {
+                BuilderShowData::ShapeQueryFrame(&frame.initial_state)
+            }
             None => BuilderShowData::Other("No frame".to_string()),
         }
     }
@@ -1320,6 +1731,10 @@ impl<'a, S: Semantics> Builder<'a, S> {
                     log::trace!("Consuming for BuiltShapeQueryFrame");
                     BuiltShapeQueryFrame::consume(self, &mut instruction_opt)?;
                 }
+                Frame::ShapeMatch(..) => {
+                    log::trace!("Consuming for ShapeMatchFrame");
+                    ShapeMatchFrame::consume(self, &mut instruction_opt)?;
+                }
             }
         }
 
@@ -1353,7 +1768,39 @@ impl<'a, S: Semantics> Builder<'a, S> {
             &mut self.data.expected_self_signature,
             OperationSignature::new_noop("some name"),
         );
-        let op = self.build_unvalidated()?;
+        let declared_signature = self.data.declared_signatures.remove(&self.data.self_op_id);
+        let (op, final_state) = self.build_unvalidated_with_final_state()?;
+        if op.signature.is_query {
+            // every path must either yield a result or crash
+            if !final_state.has_yielded && !final_state.has_diverged {
+                bail!(OperationBuilderError::MissingQueryResult);
+            }
+            if op.signature.output.has_structural_changes() {
+                bail!(OperationBuilderError::QueryWithStructuralChanges);
+            }
+        }
+        // callers that were built against our declared signature must be able to rely on it
+        if let Some(declared_signature) = declared_signature {
+            if op.signature.parameter != declared_signature.parameter {
+                bail!(OperationBuilderError::DeclaredSignatureMismatch(
+                    "different parameter"
+                ));
+            }
+            if op.signature.is_query != declared_signature.is_query {
+                bail!(OperationBuilderError::DeclaredSignatureMismatch(
+                    "only one of the signatures is a query"
+                ));
+            }
+            if !op
+                .signature
+                .output
+                .is_subtype_of(&declared_signature.output)
+            {
+                bail!(OperationBuilderError::DeclaredSignatureMismatch(
+                    "output changes are not a subtype of the declared output changes"
+                ));
+            }
+        }
         // validate the operation against the expected self signature
         if op.signature.output.new_nodes != expected_signature.output.new_nodes {
             bail!(OperationBuilderError::Oneoff(
@@ -1372,7 +1819,15 @@ impl<'a, S: Semantics> Builder<'a, S> {
     }
 
     /// Builds the current operation but does not perform any final validity checks.
-    fn build_unvalidated(mut self) -> Result<UserDefinedOperation<S>, OperationBuilderError> {
+    fn build_unvalidated(self) -> Result<UserDefinedOperation<S>, OperationBuilderError> {
+        self.build_unvalidated_with_final_state()
+            .map(|(op, _final_state)| op)
+    }
+
+    /// Like [`Self::build_unvalidated`], but also returns the abstract state at the end of the operation.
+    fn build_unvalidated_with_final_state(
+        mut self,
+    ) -> Result<(UserDefinedOperation<S>, IntermediateState<S>), OperationBuilderError> {
         // this is a bit of a hack. it just works because all nested frames right now can be ended with Finalize.
         // we can 'define' the Finalize message to be just that, though.
         while self.stack.frames.len() > 1 {
@@ -1389,12 +1844,13 @@ impl<'a, S: Semantics> Builder<'a, S> {
         let output_changes = ret_frame.abstract_ud_output;
         let signature = ret_frame.signature;
 
-        Ok(UserDefinedOperation {
+        let op = UserDefinedOperation {
             // parameter: self.data.built.parameter.unwrap(),
             signature,
             instructions: instr_frame.instructions,
             output_changes,
-        })
+        };
+        Ok((op, instr_frame.current_state))
     }
 
     fn push_frame(&mut self, frame: impl Into<Frame<S>>) {
@@ -1496,6 +1952,54 @@ fn populate_signature_changes<S: Semantics>(
             .maybe_changed_edges
             .insert((*source_subst, *target_subst), edge_abstract.clone());
     }
+
+    // nodes and edges that are overwritten on every path have exactly the type they have at the end
+    for aid in &last_state.node_must_be_written_to {
+        let AbstractNodeId::ParameterMarker(subst) = aid else {
+            continue;
+        };
+        if !signature.output.maybe_changed_nodes.contains_key(subst) {
+            continue;
+        }
+        let Some(key) = last_state.node_keys_to_aid.get_right(aid) else {
+            // the node may be deleted
+            continue;
+        };
+        let node_abstract = last_state.graph.get_node_attr(*key).unwrap();
+        signature
+            .output
+            .must_changed_nodes
+            .insert(*subst, node_abstract.clone());
+    }
+    for (source_aid, target_aid) in &last_state.edge_must_be_written_to {
+        let (
+            AbstractNodeId::ParameterMarker(source_subst),
+            AbstractNodeId::ParameterMarker(target_subst),
+        ) = (source_aid, target_aid)
+        else {
+            continue;
+        };
+        let edge = (*source_subst, *target_subst);
+        if !signature.output.maybe_changed_edges.contains_key(&edge)
+            || signature.output.maybe_deleted_edges.contains(&edge)
+        {
+            continue;
+        }
+        let (Some(source_key), Some(target_key)) = (
+            last_state.node_keys_to_aid.get_right(source_aid),
+            last_state.node_keys_to_aid.get_right(target_aid),
+        ) else {
+            continue;
+        };
+        let Some(edge_abstract) = last_state.graph.get_edge_attr((*source_key, *target_key))
+        else {
+            continue;
+        };
+        signature
+            .output
+            .must_changed_edges
+            .insert(edge, edge_abstract.clone());
+    }
 }
 
 pub enum BuilderShowData<'a, S: Semantics> {
@@ -1570,6 +2074,11 @@ pub struct OperationBuilder2<'a, S: Semantics> {
     instructions: Vec<BuilderInstruction<S>>,
     active: Builder<'a, S>,
     self_op_id: OperationId,
+    /// See [`OperationBuilder::add_example`].
+    examples: Vec<DemonstrationExample<S>>,
+    /// The runs of `examples` up to the current instruction, or `None` if the current instruction cannot be traced.
+    example_runs: Option<Vec<ExampleRun<S>>>,
+    example_limits: ExecutionLimits,
 }
 
 impl<'a, S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> OperationBuilder2<'a, S> {
@@ -1587,6 +2096,11 @@ impl<'a, S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> OperationBu
             op_ctx,
             active: Builder::new(op_ctx, self_op_id),
             self_op_id,
+            examples: Vec::new(),
+            example_runs: None,
+            example_limits: ExecutionLimits::unlimited()
+                .with_max_steps(10_000)
+                .with_max_recursion_depth(100),
         }
     }
 
@@ -1680,6 +2194,23 @@ impl<'a, S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> OperationBu
         &mut self,
         query: S::BuiltinQuery,
         args: Vec<AbstractNodeId>,
+    ) -> Result<(), OperationBuilderError> {
+        self.start_query_like(BuilderQueryLike::Builtin(query), args)
+    }
+
+    /// Starts a builtin or user defined query with the given arguments.
+    ///
+    /// User defined queries are operations built with [`OperationBuilder::expect_self_query`].
+    /// Like builtin queries, they may refine node types but cannot add or remove nodes and edges.
+    ///
+    /// See [`OperationBuilder::start_query`] for the context that is entered.
+    ///
+    /// Valid in:
+    /// * statement context
+    pub fn start_query_like(
+        &mut self,
+        query: BuilderQueryLike<S>,
+        args: Vec<AbstractNodeId>,
     ) -> Result<(), OperationBuilderError> {
         self.push_instruction(BuilderInstruction::StartQuery(query, args))
     }
@@ -1730,6 +2261,44 @@ impl<'a, S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> OperationBu
         self.push_instruction(BuilderInstruction::StartShapeQuery(op_marker.into()))
     }
 
+    /// Starts a shape match, whose arms are shape queries that are tried in order.
+    ///
+    /// The newly matched nodes of the first matching arm will be bound to the map of the given marker.
+    /// Arms are added with [`OperationBuilder::start_match_arm`], the default arm with
+    /// [`OperationBuilder::enter_default_arm`], and the match is ended with [`OperationBuilder::end_query`].
+    ///
+    /// Valid in:
+    /// * statement context
+    pub fn start_shape_match(
+        &mut self,
+        op_marker: impl Into<AbstractOperationResultMarker>,
+    ) -> Result<(), OperationBuilderError> {
+        self.push_instruction(BuilderInstruction::StartShapeMatch(op_marker.into()))
+    }
+
+    /// Starts a new arm of the current shape match.
+    ///
+    /// This enters shape query parameter context for the arm's shape query. The first statement
+    /// after the shape query parameters enters the arm's statement context.
+    ///
+    /// Valid in:
+    /// * shape match context
+    /// * statement context of a previous arm, but not of the default arm
+    pub fn start_match_arm(&mut self) -> Result<(), OperationBuilderError> {
+        self.push_instruction(BuilderInstruction::StartMatchArm)
+    }
+
+    /// Enters the default arm of the current shape match, which is taken if no arm matches.
+    ///
+    /// Must come after all other arms, and can be entered at most once.
+    ///
+    /// Valid in:
+    /// * shape match context
+    /// * statement context of a previous arm
+    pub fn enter_default_arm(&mut self) -> Result<(), OperationBuilderError> {
+        self.push_instruction(BuilderInstruction::EnterDefaultArm)
+    }
+
     /// Ends the current query.
     ///
     /// Returns to the outer statement context.
@@ -1786,7 +2355,101 @@ impl<'a, S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> OperationBu
         target: AbstractNodeId,
         edge: S::EdgeAbstract,
     ) -> Result<(), OperationBuilderError> {
-        self.push_instruction(BuilderInstruction::ExpectShapeEdge(source, target, edge))
+        self.push_instruction(BuilderInstruction::ExpectShapeEdge(
+            source, target, edge, None,
+        ))
+    }
+
+    /// Like [`Self::expect_shape_edge`], but additionally requires the edge to have the given
+    /// position among the outgoing edges of `source` in the concrete graph.
+    ///
+    /// For example, `ShapeEdgeOrder::first()` only matches the first outgoing edge of `source`,
+    /// and `ShapeEdgeOrder::after(left)` only matches the edge right after the edge from `source`
+    /// to `left`. The latter edge must already be expected by the shape query.
+    ///
+    /// Valid in:
+    /// * shape query parameter context
+    pub fn expect_ordered_shape_edge(
+        &mut self,
+        source: AbstractNodeId,
+        target: AbstractNodeId,
+        edge: S::EdgeAbstract,
+        order: ShapeEdgeOrder<AbstractNodeId>,
+    ) -> Result<(), OperationBuilderError> {
+        self.push_instruction(BuilderInstruction::ExpectShapeEdge(
+            source,
+            target,
+            edge,
+            Some(order),
+        ))
+    }
+
+    /// Adds the requirement that there is no edge from `source` to `target` that matches `edge`
+    /// in order to enter the true branch.
+    ///
+    /// Valid in:
+    /// * shape query parameter context
+    pub fn expect_no_shape_edge(
+        &mut self,
+        source: AbstractNodeId,
+        target: AbstractNodeId,
+        edge: S::EdgeAbstract,
+    ) -> Result<(), OperationBuilderError> {
+        self.push_instruction(BuilderInstruction::ExpectNoShapePattern(
+            NegativeShapeCondition::edge(source, target, edge),
+        ))
+    }
+
+    /// Adds the requirement that the given pattern is not present around the nodes of the shape
+    /// query in order to enter the true branch.
+    ///
+    /// For example, `NegativeShapeCondition::out_neighbour(node, None, edge)` requires `node` to
+    /// have no outgoing edge that matches `edge`. The pattern may refer to the nodes that are
+    /// expected by the shape query so far.
+    ///
+    /// Valid in:
+    /// * shape query parameter context
+    pub fn expect_no_shape_pattern(
+        &mut self,
+        condition: NegativeShapeCondition<S, AbstractNodeId>,
+    ) -> Result<(), OperationBuilderError> {
+        self.push_instruction(BuilderInstruction::ExpectNoShapePattern(condition))
+    }
+
+    /// Adds the requirement that `target` is reachable from `source` via one or more edges that
+    /// match `edge` in order to enter the true branch.
+    ///
+    /// Both nodes must already be expected by the shape query. The nodes in between are not
+    /// bound, but see [`Self::expect_shape_path_marking`].
+    ///
+    /// Valid in:
+    /// * shape query parameter context
+    pub fn expect_shape_path(
+        &mut self,
+        source: AbstractNodeId,
+        target: AbstractNodeId,
+        edge: S::EdgeAbstract,
+    ) -> Result<(), OperationBuilderError> {
+        self.push_instruction(BuilderInstruction::ExpectShapePath(ShapePath::new(
+            source, target, edge,
+        )))
+    }
+
+    /// Like [`Self::expect_shape_path`], but additionally marks all nodes of the found path,
+    /// including `source` and `target`, with `marker` if the shape query matches.
+    ///
+    /// Valid in:
+    /// * shape query parameter context
+    pub fn expect_shape_path_marking(
+        &mut self,
+        source: AbstractNodeId,
+        target: AbstractNodeId,
+        edge: S::EdgeAbstract,
+        marker: impl Into<Marker>,
+    ) -> Result<(), OperationBuilderError> {
+        self.push_instruction(BuilderInstruction::ExpectShapePath(
+            ShapePath::new(source, target, edge).with_marker(marker),
+        ))
     }
 
     /// Adds a node marker that the currently active shape query will skip.
@@ -1944,6 +2607,51 @@ impl<'a, S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> OperationBu
         ))
     }
 
+    /// Asserts that the operation being built is a query, i.e., it can be used as the condition
+    /// of [`OperationBuilder::start_query_like`].
+    ///
+    /// Every path through a query must either end with [`OperationBuilder::yield_query_result`] or diverge,
+    /// and operations that add or remove nodes or edges are rejected.
+    /// Must be issued before any operations are added.
+    pub fn expect_self_query(&mut self) -> Result<(), OperationBuilderError> {
+        self.push_instruction(BuilderInstruction::ExpectSelfQuery)
+    }
+
+    /// Yields the result of the query being built, which decides the branch taken by the caller.
+    ///
+    /// No instructions may follow in the current branch. If one branch of a query yields,
+    /// all other non-diverging branches must yield as well.
+    ///
+    /// Valid in:
+    /// * statement context of a query, see [`OperationBuilder::expect_self_query`]
+    pub fn yield_query_result(&mut self, value: bool) -> Result<(), OperationBuilderError> {
+        self.push_instruction(BuilderInstruction::YieldQueryResult(value))
+    }
+
+    /// Declares the signature of the operation with the given ID, which is built at the same time
+    /// as this one and hence not yet part of the operation context.
+    ///
+    /// This enables groups of mutually recursive operations: declare the signatures of all
+    /// operations of the group up front in each of their builders, then build each body.
+    /// Calls via [`BuilderOpLike::FromOperationId`] to a declared operation are type-checked
+    /// against the declared signature.
+    ///
+    /// If the operation being built declares its own signature, recursive calls are type-checked
+    /// against it as well, and [`OperationBuilder::build`] fails unless the built operation has the
+    /// same parameter and its output changes are a subtype of the declared ones.
+    ///
+    /// See [`OperationBuilder::expected_signature`] for a way to obtain a signature to declare.
+    pub fn declare_operation(
+        &mut self,
+        op_id: OperationId,
+        signature: OperationSignature<S>,
+    ) -> Result<(), OperationBuilderError> {
+        self.push_instruction(BuilderInstruction::DeclareOperation(
+            op_id,
+            Box::new(signature),
+        ))
+    }
+
     /// Adds a diverge operation at the current point that crashes with the given message.
     ///
     /// This has special support for static analysis: If one of the two branches of a (shape or regular) query diverges,
@@ -1984,12 +2692,135 @@ impl<'a, S: Semantics<BuiltinQuery: Clone, BuiltinOperation: Clone>> OperationBu
         self.active.clone().build()
     }
 
+    /// Returns the signature that the operation being built is expected to have so far.
+    ///
+    /// This is the operation's parameter together with the new nodes and edges asserted via
+    /// [`OperationBuilder::expect_self_return_node`] and [`OperationBuilder::expect_self_return_edge`].
+    /// Other changes are not included, since they depend on the operation's body.
+    pub fn expected_signature(&self) -> Result<OperationSignature<S>, OperationBuilderError> {
+        let builder = self.active.clone();
+        let expected_output = builder.data.expected_self_signature.output.clone();
+        let op = builder.build_unvalidated()?;
+        Ok(OperationSignature {
+            output: expected_output,
+            ..op.signature
+        })
+    }
+
+    /// Adds a concrete example that the operation is run on after every instruction.
+    ///
+    /// `inputs` are the nodes of `graph` that are passed as the operation's explicit parameters.
+    /// The example is run with the limits set via [`OperationBuilder::set_example_limits`], so that
+    /// unfinished recursive operations terminate.
+    ///
+    /// See the [programming by demonstration](crate::operation::builder::programming_by_demonstration) module.
+    pub fn add_example(&mut self, graph: ConcreteGraph<S>, inputs: Vec<NodeKey>) {
+        self.examples.push(DemonstrationExample { graph, inputs });
+        self.rerun_examples();
+    }
+
+    /// Sets the execution limits for running the examples.
+    ///
+    /// By default, examples are limited to 10,000 steps and a recursion depth of 100.
+    pub fn set_example_limits(&mut self, limits: ExecutionLimits) {
+        self.example_limits = limits;
+        self.rerun_examples();
+    }
+
+    /// Returns how every example, in the order they were added, runs up to the current instruction.
+    ///
+    /// Returns `None` if the current instruction cannot be reached at runtime, e.g., because
+    /// the parameter is still being built or a query condition is being built.
+    pub fn example_runs(&self) -> Option<&[ExampleRun<S>]> {
+        self.example_runs.as_deref()
+    }
+
+    /// Returns true if there are examples that match the parameter, but none of them reaches the
+    /// current instruction.
+    ///
+    /// In that case, the user may want to add an example that exercises the current branch.
+    /// Examples that do not match the parameter are reported by
+    /// [`OperationBuilder::examples_not_matching_parameter`] instead.
+    pub fn no_example_reaches_current_instruction(&self) -> bool {
+        match &self.example_runs {
+            Some(runs) if runs.iter().any(|run| run.matches_parameter) => {
+                !runs.iter().any(ExampleRun::reaches_current_instruction)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the indices of the examples, in the order they were added, whose graph and inputs
+    /// do not match the operation's parameter.
+    ///
+    /// Empty if the examples cannot be run yet, see [`OperationBuilder::example_runs`].
+    pub fn examples_not_matching_parameter(&self) -> Vec<usize> {
+        self.example_runs
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter(|(_, run)| !run.matches_parameter)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    fn rerun_examples(&mut self) {
+        if self.examples.is_empty() {
+            self.example_runs = None;
+            return;
+        }
+        let probe = match self.build_example_probe() {
+            Ok(probe) => probe,
+            Err(e) => {
+                log::info!(
+                    "Current instruction cannot be traced, not running examples. error: {e:?}"
+                );
+                self.example_runs = None;
+                return;
+            }
+        };
+        let runs = programming_by_demonstration::run_examples(
+            self.op_ctx,
+            self.self_op_id,
+            probe,
+            &self.examples,
+            self.example_limits,
+        );
+        if !runs.iter().any(ExampleRun::reaches_current_instruction) {
+            log::info!("No example reaches the current instruction");
+        }
+        self.example_runs = Some(runs);
+    }
+
+    /// Builds the operation up to the current instruction, with a single trace instruction at the end.
+    fn build_example_probe(&self) -> Result<UserDefinedOperation<S>, OperationBuilderError> {
+        let mut builder = Builder::new(self.op_ctx, self.self_op_id);
+        builder.update_expected_self_output_changes(
+            self.active.data.expected_self_signature.output.clone(),
+        );
+        for instruction in &self.instructions {
+            // only the probe's own trace should be collected
+            if matches!(instruction, BuilderInstruction::Trace) {
+                continue;
+            }
+            builder.consume(instruction.clone())?;
+        }
+        builder.consume(BuilderInstruction::Trace)?;
+        builder.build_unvalidated()
+    }
+
     fn push_instruction(
         &mut self,
         instruction: BuilderInstruction<S>,
     ) -> Result<(), OperationBuilderError> {
         self.__push_instruction(instruction.clone())
-            .attach_printable_lazy(move || format!("Failed to push instruction: {instruction:?}"))
+            .attach_printable_lazy(move || {
+                format!("Failed to push instruction: {instruction:?}")
+            })?;
+        if !self.examples.is_empty() {
+            self.rerun_examples();
+        }
+        Ok(())
     }
 
     fn __push_instruction(
@@ -2158,8 +2989,9 @@ impl<
 /// - for new nodes or edges in both, they will be kept in the result with the join of the two as the expected AV result.
 /// - for nodes or edges that are changed in both, they will be kept in the result with the join of the two as the expected AV result.
 /// - for nodes or edges that are deleted in at least one, they will be kept in the result as deleted and not as changed.
+/// - for nodes or edges that are must-changed in both, they will be kept in the result as must-changed with the join of the two.
 // TODO: do the above rules make sense? should one of the two have priority? eg. should we fail if a user expects a return type of Integer but we compute Object?
-fn merge_abstract_output_changes<S: Semantics>(
+pub fn merge_abstract_output_changes<S: Semantics>(
     a: &AbstractOutputChanges<S>,
     b: &AbstractOutputChanges<S>,
 ) -> Result<AbstractOutputChanges<S>, OperationBuilderError> {
@@ -2293,5 +3125,31 @@ fn merge_abstract_output_changes<S: Semantics>(
         }
     }
 
+    // nodes and edges are only must-changed if they are must-changed in both
+    for (marker, av_a) in &a.must_changed_nodes {
+        let Some(av_b) = b.must_changed_nodes.get(marker) else {
+            continue;
+        };
+        if !result.maybe_changed_nodes.contains_key(marker) {
+            continue;
+        }
+        let joined_av = S::NodeJoin::join(av_a, av_b).ok_or(OperationBuilderError::Oneoff(
+            "Need to be able to join two different must_changed AVs",
+        ))?;
+        result.must_changed_nodes.insert(*marker, joined_av);
+    }
+    for (edge, av_a) in &a.must_changed_edges {
+        let Some(av_b) = b.must_changed_edges.get(edge) else {
+            continue;
+        };
+        if !result.maybe_changed_edges.contains_key(edge) {
+            continue;
+        }
+        let joined_av = S::EdgeJoin::join(av_a, av_b).ok_or(OperationBuilderError::Oneoff(
+            "Need to be able to join two different must_changed AVs",
+        ))?;
+        result.must_changed_edges.insert(*edge, joined_av);
+    }
+
     Ok(result)
 }
@@ -0,0 +1,1182 @@
+//! A flat, slot-indexed bytecode for user defined operations, and an interpreter for it.
+//!
+//! The default interpreter walks the nested [`Instruction`] trees of a user defined operation,
+//! resolves every [`AbstractNodeId`] through a `HashMap`, and collects the hidden nodes of every
+//! call eagerly. A [`Program`] instead lowers every user defined operation of an [`OperationContext`] once:
+//! * Every abstract node id of an operation is assigned a dense [`Slot`], so the nodes of a running
+//!   operation are stored in a `Vec` that is indexed by slot.
+//! * Query branches become jumps in a single, flat list of [`Op`]s.
+//! * Calls to user defined operations refer to the compiled callee directly, and pass their
+//!   argument nodes from the caller's slots to the callee's parameter slots.
+//! * The hidden nodes of a call are not collected when calling. They are only computed, and cached
+//!   per call, once a shape query or a trace needs them.
+//!
+//! Running an operation of a [`Program`] has the same results as [`run_from_concrete`](super::run_from_concrete),
+//! including the trace, the [`ExecutionLimits`], and errors.
+//!
+//! # Example
+//! ```rust,ignore
+//! let program = Program::compile(&op_ctx);
+//! let main = op_ctx.id_of("main").unwrap();
+//! for input in inputs {
+//!     program.run(&mut g, main, &[input])?;
+//! }
+//! ```
+
+use crate::operation::builtin::LibBuiltinOperation;
+use crate::operation::execution::{ExecutionContext, ExecutionLimits};
+use crate::operation::marker::MarkerSet;
+use crate::operation::query::{
+    GraphShapeQuery, mark_shape_paths, run_builtin_query, run_shape_query,
+};
+use crate::operation::signature::parameter::{
+    AbstractOutputNodeMarker, ConcreteOperationOutput, OperationArgument, OperationOutput,
+    ParameterSubstitution,
+};
+use crate::operation::trace::{Trace, TraceFrame};
+use crate::operation::user_defined::{
+    AbstractNodeId, AbstractOperationArgument, AbstractOperationResultMarker, Instruction,
+    InstructionWithResultMarker, OpLikeInstruction, QueryInstructions, QueryLikeInstruction,
+    UserDefinedOperation,
+};
+use crate::operation::{
+    Operation, OperationContext, OperationError, OperationId, OperationResult,
+    concrete_substitution, run_builtin_operation, run_lib_builtin_operation, run_operation,
+};
+use crate::semantics::{AbstractionCache, ConcreteGraph};
+use crate::util::bimap::BiMap;
+use crate::{NodeKey, Semantics, SubstMarker};
+use error_stack::{ResultExt, report};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+/// The index of an abstract node id of a [`CompiledOperation`].
+pub type Slot = usize;
+
+/// The slots of the nodes that are passed to a builtin operation or query.
+#[derive(Debug, Clone, Default)]
+pub struct Arg {
+    pub selected: Vec<Slot>,
+    pub subst: Vec<(SubstMarker, Slot)>,
+}
+
+/// The slots in which the output nodes of an instruction with a result marker are stored.
+#[derive(Debug, Clone)]
+pub struct Outputs {
+    pub marker: AbstractOperationResultMarker,
+    /// Output nodes without a slot are never referenced by the operation.
+    /// They are still stored, since they must be hidden from shape queries.
+    pub slots: Vec<(AbstractOutputNodeMarker, Slot)>,
+}
+
+/// An operation that is run directly, without a frame of its own.
+#[derive(derive_more::Debug)]
+pub enum BuiltinOp<'a, S: Semantics> {
+    #[debug("Builtin(???)")]
+    Builtin(&'a S::BuiltinOperation),
+    #[debug("LibBuiltin({_0:?})")]
+    LibBuiltin(&'a LibBuiltinOperation<S>),
+}
+
+/// A single bytecode instruction.
+///
+/// Execution continues with the next op, unless the op says otherwise.
+#[derive(derive_more::Debug)]
+pub enum Op<'a, S: Semantics> {
+    /// Runs a builtin operation.
+    Builtin {
+        op: BuiltinOp<'a, S>,
+        arg: Arg,
+        outputs: Option<Outputs>,
+    },
+    /// Calls the user defined operation with the given index in the [`Program`].
+    ///
+    /// `args` are pairs of a slot of the caller and a parameter slot of the callee.
+    Call {
+        callee: usize,
+        args: Vec<(Slot, Slot)>,
+        outputs: Option<Outputs>,
+        /// Set if the call replaces the calling frame, which is the case for the last call of an
+        /// operation without output nodes.
+        tail: bool,
+    },
+    /// Runs a builtin query, and continues at `not_taken` if it does not hold.
+    Query {
+        #[debug(skip)]
+        query: &'a S::BuiltinQuery,
+        arg: Arg,
+        not_taken: usize,
+    },
+    /// Calls a user defined query, and continues at `not_taken` if it yields false.
+    ///
+    /// `callee` is `None` if `op_id` is not a user defined operation.
+    CallQuery {
+        op_id: OperationId,
+        callee: Option<usize>,
+        args: Vec<(Slot, Slot)>,
+        not_taken: usize,
+        /// The op after both branches.
+        end: usize,
+    },
+    /// Runs a shape query, and continues at `not_taken` if it does not match.
+    ShapeQuery {
+        #[debug(skip)]
+        query: &'a GraphShapeQuery<S>,
+        selected: Vec<Slot>,
+        outputs: Option<Outputs>,
+        not_taken: usize,
+    },
+    /// Runs the shape queries of the arms in order, and continues at the target of the first arm
+    /// that matches. If none does, continues with the next op, which is the default branch.
+    ShapeMatch {
+        #[debug(skip)]
+        arms: Vec<(&'a GraphShapeQuery<S>, usize)>,
+        selected: Vec<Slot>,
+        outputs: Option<Outputs>,
+    },
+    /// Moves a node to another slot.
+    Move {
+        from: Slot,
+        to: Slot,
+    },
+    /// Clears a slot.
+    Forget(Slot),
+    Diverge(&'a str),
+    Trace,
+    YieldQueryResult(bool),
+    Jump(usize),
+    /// Returns from the current operation.
+    Return,
+    /// Fails with [`OperationError::InvalidOperationId`].
+    ///
+    /// Calls to operation ids that are not defined in the operation context are lowered to this.
+    Undefined(OperationId),
+}
+
+/// A user defined operation that was lowered to bytecode.
+#[derive(derive_more::Debug)]
+pub struct CompiledOperation<'a, S: Semantics> {
+    op_id: OperationId,
+    ops: Vec<Op<'a, S>>,
+    /// The abstract node id of every slot. The parameters come first.
+    aids: Vec<AbstractNodeId>,
+    parameters: usize,
+    outputs: Vec<(AbstractOutputNodeMarker, Slot)>,
+}
+
+impl<'a, S: Semantics> CompiledOperation<'a, S> {
+    pub fn op_id(&self) -> OperationId {
+        self.op_id
+    }
+
+    pub fn ops(&self) -> &[Op<'a, S>] {
+        &self.ops
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.aids.len()
+    }
+
+    /// The abstract node id that was assigned the given slot.
+    pub fn aid(&self, slot: Slot) -> AbstractNodeId {
+        self.aids[slot]
+    }
+}
+
+/// All user defined operations of an [`OperationContext`], lowered to bytecode.
+///
+/// See the [module-level documentation](self).
+pub struct Program<'a, S: Semantics> {
+    op_ctx: &'a OperationContext<S>,
+    operations: Vec<CompiledOperation<'a, S>>,
+    indices: HashMap<OperationId, usize>,
+}
+
+impl<'a, S: Semantics> Program<'a, S> {
+    pub fn compile(op_ctx: &'a OperationContext<S>) -> Self {
+        let mut ids = op_ctx.custom.keys().copied().collect::<Vec<_>>();
+        ids.sort();
+        let indices = ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| (*id, index))
+            .collect::<HashMap<_, _>>();
+        // the parameter slots of every operation must be known to lower calls to it
+        let parameters = ids
+            .iter()
+            .map(|id| parameter_markers(&op_ctx.custom[id]))
+            .collect::<Vec<_>>();
+        let operations = ids
+            .iter()
+            .zip(&parameters)
+            .map(|(id, own_parameters)| {
+                let lowering = Lowering {
+                    op_ctx,
+                    indices: &indices,
+                    parameters: &parameters,
+                    slots: HashMap::new(),
+                    aids: Vec::new(),
+                    ops: Vec::new(),
+                };
+                lowering.lower(*id, &op_ctx.custom[id], own_parameters)
+            })
+            .collect();
+        Program {
+            op_ctx,
+            operations,
+            indices,
+        }
+    }
+
+    /// Returns the bytecode of the user defined operation with the given id.
+    pub fn operation(&self, id: OperationId) -> Option<&CompiledOperation<'a, S>> {
+        self.indices.get(&id).map(|index| &self.operations[*index])
+    }
+
+    /// Like [`run_from_concrete`](super::run_from_concrete), but runs user defined operations
+    /// with the bytecode interpreter.
+    pub fn run(
+        &self,
+        g: &mut ConcreteGraph<S>,
+        op: OperationId,
+        selected_inputs: &[NodeKey],
+    ) -> OperationResult<ConcreteOperationOutput<S>> {
+        self.run_with_limits(g, op, selected_inputs, ExecutionLimits::unlimited())
+    }
+
+    /// Like [`run_from_concrete_with_limits`](super::run_from_concrete_with_limits), but runs
+    /// user defined operations with the bytecode interpreter.
+    pub fn run_with_limits(
+        &self,
+        g: &mut ConcreteGraph<S>,
+        op: OperationId,
+        selected_inputs: &[NodeKey],
+        limits: ExecutionLimits,
+    ) -> OperationResult<ConcreteOperationOutput<S>> {
+        let marker_set = RefCell::new(MarkerSet::new());
+        let trace = RefCell::new(Trace::new());
+        let abstraction = RefCell::new(AbstractionCache::new());
+        let res = (|| {
+            let subst = concrete_substitution(
+                g,
+                &mut abstraction.borrow_mut(),
+                self.op_ctx,
+                op,
+                selected_inputs,
+            )?;
+            let execution = RefCell::new(ExecutionContext::new(limits, g));
+            let arg = OperationArgument {
+                subst,
+                selected_input_nodes: selected_inputs.into(),
+                hidden_nodes: HashSet::new(),
+                marker_set: &marker_set,
+                trace: &trace,
+                execution: &execution,
+                abstraction: &abstraction,
+            };
+            self.run_operation(g, op, arg)
+        })();
+        abstraction.into_inner().clear(g);
+        Ok(ConcreteOperationOutput {
+            output: res?,
+            marker_set: marker_set.into_inner(),
+            trace: trace.into_inner(),
+        })
+    }
+
+    /// Like [`run_operation`], but runs user defined operations with the bytecode interpreter.
+    pub fn run_operation(
+        &self,
+        g: &mut ConcreteGraph<S>,
+        op: OperationId,
+        arg: OperationArgument<S>,
+    ) -> OperationResult<OperationOutput> {
+        let Some(index) = self.indices.get(&op) else {
+            return run_operation(g, self.op_ctx, op, arg);
+        };
+        let execution = arg.execution;
+        execution.borrow_mut().enter_operation(op)?;
+        let output = Interpreter::new(self, g, *index, arg).run()?;
+        execution.borrow_mut().exit_operation();
+        Ok(output)
+    }
+}
+
+/// The parameter markers of `op`, in the order of their slots.
+fn parameter_markers<S: Semantics>(op: &UserDefinedOperation<S>) -> Vec<SubstMarker> {
+    let parameter = &op.signature.parameter;
+    let mut markers = parameter.explicit_input_nodes.clone();
+    for marker in parameter.node_keys_to_subst.right_values() {
+        if !markers.contains(marker) {
+            markers.push(*marker);
+        }
+    }
+    markers
+}
+
+/// Lowers a single user defined operation.
+struct Lowering<'a, 'c, S: Semantics> {
+    op_ctx: &'a OperationContext<S>,
+    indices: &'c HashMap<OperationId, usize>,
+    /// The parameter markers of every operation of the program, by index.
+    parameters: &'c [Vec<SubstMarker>],
+    slots: HashMap<AbstractNodeId, Slot>,
+    aids: Vec<AbstractNodeId>,
+    ops: Vec<Op<'a, S>>,
+}
+
+impl<'a, S: Semantics> Lowering<'a, '_, S> {
+    fn lower(
+        mut self,
+        op_id: OperationId,
+        op: &'a UserDefinedOperation<S>,
+        parameters: &[SubstMarker],
+    ) -> CompiledOperation<'a, S> {
+        for marker in parameters {
+            self.slot(AbstractNodeId::ParameterMarker(*marker));
+        }
+        // every slot must be known before lowering, since output slots may be referenced
+        // by later instructions
+        self.assign_slots(&op.instructions);
+        let outputs = op
+            .output_changes
+            .new_nodes
+            .iter()
+            .map(|(aid, name)| (*name, self.slot(*aid)))
+            .collect::<Vec<_>>();
+
+        self.lower_block(&op.instructions);
+        self.ops.push(Op::Return);
+
+        if outputs.is_empty() {
+            let tail_calls = (0..self.ops.len())
+                .filter(|pc| matches!(self.ops[*pc], Op::Call { .. }) && self.returns_at(pc + 1))
+                .collect::<Vec<_>>();
+            for pc in tail_calls {
+                if let Op::Call { tail, .. } = &mut self.ops[pc] {
+                    *tail = true;
+                }
+            }
+        }
+
+        CompiledOperation {
+            op_id,
+            ops: self.ops,
+            aids: self.aids,
+            parameters: parameters.len(),
+            outputs,
+        }
+    }
+
+    fn slot(&mut self, aid: AbstractNodeId) -> Slot {
+        *self.slots.entry(aid).or_insert_with(|| {
+            self.aids.push(aid);
+            self.aids.len() - 1
+        })
+    }
+
+    /// Returns true if executing from `pc` on returns without executing any other op.
+    fn returns_at(&self, mut pc: usize) -> bool {
+        loop {
+            match self.ops[pc] {
+                Op::Jump(target) => pc = target,
+                Op::Return => return true,
+                _ => return false,
+            }
+        }
+    }
+
+    fn assign_slots(&mut self, instructions: &'a [InstructionWithResultMarker<S>]) {
+        for (marker, instruction) in instructions {
+            match instruction {
+                Instruction::OpLike(op_like, arg) => {
+                    self.assign_arg_slots(arg);
+                    if let (Some(marker), OpLikeInstruction::Operation(id)) = (marker, op_like)
+                        && let Some(Operation::Custom(callee)) = self.op_ctx.get(*id)
+                    {
+                        for output in callee.output_changes.new_nodes.values() {
+                            self.slot(AbstractNodeId::DynamicOutputMarker(*marker, *output));
+                        }
+                    }
+                }
+                Instruction::QueryLike(_, arg, query_instr) => {
+                    self.assign_arg_slots(arg);
+                    self.assign_slots(&query_instr.taken);
+                    self.assign_slots(&query_instr.not_taken);
+                }
+                Instruction::ShapeQuery(query, arg, query_instr) => {
+                    self.assign_arg_slots(arg);
+                    self.assign_shape_slots(*marker, query);
+                    self.assign_slots(&query_instr.taken);
+                    self.assign_slots(&query_instr.not_taken);
+                }
+                Instruction::ShapeMatch(arms, arg, default) => {
+                    self.assign_arg_slots(arg);
+                    for arm in arms {
+                        self.assign_shape_slots(*marker, &arm.query);
+                        self.assign_slots(&arm.instructions);
+                    }
+                    self.assign_slots(default);
+                }
+                Instruction::RenameNode { old, new } => {
+                    self.slot(*old);
+                    self.slot(*new);
+                }
+                Instruction::ForgetAid { aid } => {
+                    self.slot(*aid);
+                }
+                Instruction::Diverge { .. }
+                | Instruction::Trace
+                | Instruction::YieldQueryResult(_) => {}
+            }
+        }
+    }
+
+    fn assign_arg_slots(&mut self, arg: &AbstractOperationArgument) {
+        for aid in arg
+            .selected_input_nodes
+            .iter()
+            .chain(arg.subst_to_aid.values())
+        {
+            self.slot(*aid);
+        }
+    }
+
+    fn assign_shape_slots(
+        &mut self,
+        marker: Option<AbstractOperationResultMarker>,
+        query: &GraphShapeQuery<S>,
+    ) {
+        let Some(marker) = marker else {
+            return;
+        };
+        for ident in query.node_keys_to_shape_idents.right_values() {
+            let output = AbstractOutputNodeMarker((*ident).into());
+            self.slot(AbstractNodeId::DynamicOutputMarker(marker, output));
+        }
+    }
+
+    fn arg(&self, arg: &AbstractOperationArgument) -> Arg {
+        Arg {
+            selected: self.selected(arg),
+            subst: arg
+                .subst_to_aid
+                .iter()
+                .map(|(marker, aid)| (*marker, self.slots[aid]))
+                .collect(),
+        }
+    }
+
+    fn selected(&self, arg: &AbstractOperationArgument) -> Vec<Slot> {
+        arg.selected_input_nodes
+            .iter()
+            .map(|aid| self.slots[aid])
+            .collect()
+    }
+
+    /// The pairs of caller and callee slots for a call of the operation with the given index.
+    fn call_args(&self, callee: usize, arg: &AbstractOperationArgument) -> Vec<(Slot, Slot)> {
+        let parameters = &self.parameters[callee];
+        arg.subst_to_aid
+            .iter()
+            .filter_map(|(marker, aid)| {
+                let to = parameters.iter().position(|m| m == marker)?;
+                Some((self.slots[aid], to))
+            })
+            .collect()
+    }
+
+    fn outputs(&self, marker: Option<AbstractOperationResultMarker>) -> Option<Outputs> {
+        let marker = marker?;
+        let slots = self
+            .aids
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, aid)| match aid {
+                AbstractNodeId::DynamicOutputMarker(m, output) if *m == marker => {
+                    Some((*output, slot))
+                }
+                _ => None,
+            })
+            .collect();
+        Some(Outputs { marker, slots })
+    }
+
+    fn lower_block(&mut self, instructions: &'a [InstructionWithResultMarker<S>]) {
+        for (marker, instruction) in instructions {
+            self.lower_instruction(*marker, instruction);
+        }
+    }
+
+    fn lower_instruction(
+        &mut self,
+        marker: Option<AbstractOperationResultMarker>,
+        instruction: &'a Instruction<S>,
+    ) {
+        match instruction {
+            Instruction::OpLike(op_like, arg) => {
+                let outputs = self.outputs(marker);
+                let builtin = |op| Op::Builtin {
+                    op,
+                    arg: self.arg(arg),
+                    outputs: outputs.clone(),
+                };
+                let op = match op_like {
+                    OpLikeInstruction::Builtin(op) => builtin(BuiltinOp::Builtin(op)),
+                    OpLikeInstruction::LibBuiltin(op) => builtin(BuiltinOp::LibBuiltin(op)),
+                    OpLikeInstruction::Operation(id) => match self.op_ctx.get(*id) {
+                        Some(Operation::Custom(_)) => {
+                            let callee = self.indices[id];
+                            Op::Call {
+                                callee,
+                                args: self.call_args(callee, arg),
+                                outputs,
+                                tail: false,
+                            }
+                        }
+                        Some(Operation::Builtin(op)) => builtin(BuiltinOp::Builtin(op)),
+                        Some(Operation::LibBuiltin(op)) => builtin(BuiltinOp::LibBuiltin(op)),
+                        None => Op::Undefined(*id),
+                    },
+                };
+                self.ops.push(op);
+            }
+            Instruction::QueryLike(QueryLikeInstruction::Builtin(query), arg, query_instr) => {
+                let at = self.ops.len();
+                self.ops.push(Op::Query {
+                    query,
+                    arg: self.arg(arg),
+                    not_taken: 0,
+                });
+                let (not_taken, _) = self.lower_branches(query_instr);
+                if let Op::Query { not_taken: t, .. } = &mut self.ops[at] {
+                    *t = not_taken;
+                }
+            }
+            Instruction::QueryLike(QueryLikeInstruction::Operation(id), arg, query_instr) => {
+                let callee = match self.op_ctx.get(*id) {
+                    Some(Operation::Custom(_)) => Some(self.indices[id]),
+                    _ => None,
+                };
+                let at = self.ops.len();
+                self.ops.push(Op::CallQuery {
+                    op_id: *id,
+                    callee,
+                    args: callee.map_or_else(Vec::new, |callee| self.call_args(callee, arg)),
+                    not_taken: 0,
+                    end: 0,
+                });
+                let (not_taken, end) = self.lower_branches(query_instr);
+                if let Op::CallQuery {
+                    not_taken: t,
+                    end: e,
+                    ..
+                } = &mut self.ops[at]
+                {
+                    *t = not_taken;
+                    *e = end;
+                }
+            }
+            Instruction::ShapeQuery(query, arg, query_instr) => {
+                let at = self.ops.len();
+                self.ops.push(Op::ShapeQuery {
+                    query,
+                    selected: self.selected(arg),
+                    outputs: self.outputs(marker),
+                    not_taken: 0,
+                });
+                let (not_taken, _) = self.lower_branches(query_instr);
+                if let Op::ShapeQuery { not_taken: t, .. } = &mut self.ops[at] {
+                    *t = not_taken;
+                }
+            }
+            Instruction::ShapeMatch(arms, arg, default) => {
+                let at = self.ops.len();
+                self.ops.push(Op::ShapeMatch {
+                    arms: Vec::new(),
+                    selected: self.selected(arg),
+                    outputs: self.outputs(marker),
+                });
+                self.lower_block(default);
+                let mut targets = Vec::new();
+                let mut jumps = Vec::new();
+                for arm in arms {
+                    jumps.push(self.ops.len());
+                    self.ops.push(Op::Jump(0));
+                    targets.push((&arm.query, self.ops.len()));
+                    self.lower_block(&arm.instructions);
+                }
+                self.patch_jumps(&jumps);
+                if let Op::ShapeMatch { arms, .. } = &mut self.ops[at] {
+                    *arms = targets;
+                }
+            }
+            Instruction::RenameNode { old, new } => {
+                self.ops.push(Op::Move {
+                    from: self.slots[old],
+                    to: self.slots[new],
+                });
+            }
+            Instruction::ForgetAid { aid } => {
+                self.ops.push(Op::Forget(self.slots[aid]));
+            }
+            Instruction::Diverge { crash_message } => {
+                self.ops.push(Op::Diverge(crash_message));
+            }
+            Instruction::Trace => self.ops.push(Op::Trace),
+            Instruction::YieldQueryResult(value) => self.ops.push(Op::YieldQueryResult(*value)),
+        }
+    }
+
+    /// Lowers the taken branch followed by the not taken branch.
+    ///
+    /// Returns the start of the not taken branch, and the end of both branches.
+    fn lower_branches(&mut self, query_instr: &'a QueryInstructions<S>) -> (usize, usize) {
+        self.lower_block(&query_instr.taken);
+        if query_instr.not_taken.is_empty() {
+            let end = self.ops.len();
+            return (end, end);
+        }
+        let jump = self.ops.len();
+        self.ops.push(Op::Jump(0));
+        let not_taken = self.ops.len();
+        self.lower_block(&query_instr.not_taken);
+        self.patch_jumps(&[jump]);
+        (not_taken, self.ops.len())
+    }
+
+    /// Points the jumps at the given positions to the current end of the ops.
+    fn patch_jumps(&mut self, jumps: &[usize]) {
+        let end = self.ops.len();
+        for jump in jumps {
+            self.ops[*jump] = Op::Jump(end);
+        }
+    }
+}
+
+/// What the caller does once a frame returns.
+#[derive(Debug, Clone, Copy)]
+enum OnReturn {
+    Continue,
+    /// The frame is a query, and the caller branches on its result.
+    Branch {
+        not_taken: usize,
+        end: usize,
+    },
+    /// The frame replaced a query via a tail call. Like the default interpreter, the caller then
+    /// continues after the query's branches.
+    SkipTo(usize),
+}
+
+/// A single activation of a user defined operation on the [`Interpreter`]'s call stack.
+struct Frame<'p, S: Semantics> {
+    code: &'p CompiledOperation<'p, S>,
+    /// The index of the next op.
+    pc: usize,
+    slots: Vec<Option<NodeKey>>,
+    /// Output nodes of previous instructions that have no slot.
+    unbound: Vec<(
+        AbstractOperationResultMarker,
+        AbstractOutputNodeMarker,
+        NodeKey,
+    )>,
+    /// Parameter nodes that were forgotten, which are not hidden from callees anymore.
+    forgotten_params: Vec<NodeKey>,
+    /// The hidden nodes of this frame's argument, once they were needed.
+    ///
+    /// These only depend on the frames below, which do not change while this frame exists.
+    hidden: Option<HashSet<NodeKey>>,
+    /// The slots of the caller in which this frame's output nodes are stored.
+    outputs: Option<&'p Outputs>,
+    on_return: OnReturn,
+    /// Set if this frame replaced a caller via a tail call, see [`Op::Call::tail`].
+    discard_output: bool,
+    query_result: Option<bool>,
+}
+
+impl<'p, S: Semantics> Frame<'p, S> {
+    fn new(
+        code: &'p CompiledOperation<'p, S>,
+        slots: Vec<Option<NodeKey>>,
+        outputs: Option<&'p Outputs>,
+        on_return: OnReturn,
+    ) -> Self {
+        Frame {
+            code,
+            pc: 0,
+            slots,
+            unbound: Vec::new(),
+            forgotten_params: Vec::new(),
+            hidden: None,
+            outputs,
+            on_return,
+            discard_output: false,
+            query_result: None,
+        }
+    }
+
+    fn node(&self, slot: Slot) -> OperationResult<NodeKey> {
+        self.slots[slot].ok_or_else(|| report!(OperationError::UnknownAID(self.code.aids[slot])))
+    }
+
+    fn nodes(&self, slots: &[Slot]) -> OperationResult<Vec<NodeKey>> {
+        slots.iter().map(|slot| self.node(*slot)).collect()
+    }
+
+    /// All nodes that this frame has a handle to.
+    fn nodes_in_scope(&self) -> impl Iterator<Item = NodeKey> {
+        self.slots
+            .iter()
+            .flatten()
+            .copied()
+            .chain(self.unbound.iter().map(|(_, _, key)| *key))
+    }
+
+    /// Turns `hidden`, the hidden nodes of this frame's argument, into the hidden nodes of a callee.
+    fn hide_nodes_in_scope(&self, hidden: &mut HashSet<NodeKey>) {
+        hidden.extend(self.nodes_in_scope());
+        // parameters that were forgotten may be matched by callees
+        for key in &self.forgotten_params {
+            hidden.remove(key);
+        }
+    }
+
+    /// Calls `f` with the nodes that are hidden from a shape query of this frame.
+    ///
+    /// [`Frame::hidden`] must be known.
+    fn with_hidden_nodes<T>(&mut self, f: impl FnOnce(&HashSet<NodeKey>) -> T) -> T {
+        let hidden = self
+            .hidden
+            .as_mut()
+            .expect("internal error: hidden nodes must be computed first");
+        // temporarily extend the cached set instead of cloning it
+        let added = self
+            .slots
+            .iter()
+            .flatten()
+            .copied()
+            .chain(self.unbound.iter().map(|(_, _, key)| *key))
+            .filter(|key| hidden.insert(*key))
+            .collect::<Vec<_>>();
+        let removed = self
+            .forgotten_params
+            .iter()
+            .copied()
+            .filter(|key| hidden.remove(key))
+            .collect::<Vec<_>>();
+        let result = f(hidden);
+        hidden.extend(removed);
+        for key in added {
+            hidden.remove(&key);
+        }
+        result
+    }
+
+    fn concrete_arg(&self, arg: &Arg) -> OperationResult<(Vec<NodeKey>, ParameterSubstitution)> {
+        let selected = self
+            .nodes(&arg.selected)
+            .attach_printable("while converting abstract selected input nodes to concrete keys")?;
+        let subst = arg
+            .subst
+            .iter()
+            .map(|(marker, slot)| Ok((*marker, self.node(*slot)?)))
+            .collect::<OperationResult<_>>()?;
+        Ok((selected, ParameterSubstitution::new(subst)))
+    }
+
+    fn store_outputs(
+        &mut self,
+        outputs: &Outputs,
+        new_nodes: impl IntoIterator<Item = (AbstractOutputNodeMarker, NodeKey)>,
+    ) {
+        for (output, key) in new_nodes {
+            if let Some((_, slot)) = outputs.slots.iter().find(|(o, _)| *o == output) {
+                self.slots[*slot] = Some(key);
+                continue;
+            }
+            let unbound = self
+                .unbound
+                .iter_mut()
+                .find(|(marker, o, _)| *marker == outputs.marker && *o == output);
+            match unbound {
+                Some((_, _, unbound_key)) => *unbound_key = key,
+                None => self.unbound.push((outputs.marker, output, key)),
+            }
+        }
+    }
+
+    fn node_aids(&self) -> HashMap<AbstractNodeId, NodeKey> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, key)| Some((self.code.aids[slot], (*key)?)))
+            .chain(self.unbound.iter().map(|(marker, output, key)| {
+                (AbstractNodeId::DynamicOutputMarker(*marker, *output), *key)
+            }))
+            .collect()
+    }
+
+    fn output(&self) -> OperationResult<OperationOutput> {
+        let new_nodes = if self.discard_output {
+            HashMap::new()
+        } else {
+            self.code
+                .outputs
+                .iter()
+                .map(|(name, slot)| Ok((*name, self.node(*slot)?)))
+                .collect::<OperationResult<_>>()
+                .attach_printable("error while building output map")?
+        };
+        Ok(OperationOutput {
+            new_nodes,
+            removed_nodes: vec![],
+        })
+    }
+}
+
+/// Runs a compiled user defined operation, see [`Program::run_operation`].
+///
+/// Like the default interpreter, calls are executed on an explicit stack of frames,
+/// and tail calls replace the calling frame.
+struct Interpreter<'p, S: Semantics> {
+    program: &'p Program<'p, S>,
+    g: &'p mut ConcreteGraph<S>,
+    marker_set: &'p RefCell<MarkerSet>,
+    trace: &'p RefCell<Trace<S>>,
+    execution: &'p RefCell<ExecutionContext>,
+    abstraction: &'p RefCell<AbstractionCache<S>>,
+    /// The call stack, innermost last.
+    frames: Vec<Frame<'p, S>>,
+}
+
+impl<'p, S: Semantics> Interpreter<'p, S> {
+    fn new(
+        program: &'p Program<'p, S>,
+        g: &'p mut ConcreteGraph<S>,
+        index: usize,
+        arg: OperationArgument<'p, S>,
+    ) -> Self {
+        let code = &program.operations[index];
+        let slots = code.aids[..code.parameters]
+            .iter()
+            .map(|aid| match aid {
+                AbstractNodeId::ParameterMarker(marker) => arg.subst.mapping.get(marker).copied(),
+                _ => None,
+            })
+            .chain(std::iter::repeat_n(None, code.aids.len() - code.parameters))
+            .collect();
+        let mut frame = Frame::new(code, slots, None, OnReturn::Continue);
+        frame.hidden = Some(arg.hidden_nodes);
+        Interpreter {
+            program,
+            g,
+            marker_set: arg.marker_set,
+            trace: arg.trace,
+            execution: arg.execution,
+            abstraction: arg.abstraction,
+            frames: vec![frame],
+        }
+    }
+
+    fn run(&mut self) -> OperationResult<OperationOutput> {
+        loop {
+            let frame = self
+                .frames
+                .last_mut()
+                .expect("internal error: interpreter has no frames");
+            let code = frame.code;
+            let op = &code.ops[frame.pc];
+            frame.pc += 1;
+            match op {
+                Op::Jump(target) => frame.pc = *target,
+                Op::Return => {
+                    if let Some(output) = self.return_from_frame()? {
+                        return Ok(output);
+                    }
+                }
+                op => {
+                    self.execution.borrow_mut().step()?;
+                    self.execute(op)?;
+                }
+            }
+        }
+    }
+
+    fn frame(&mut self) -> &mut Frame<'p, S> {
+        self.frames
+            .last_mut()
+            .expect("internal error: interpreter has no frames")
+    }
+
+    /// Computes the hidden nodes of the frame at `index` and of all frames below it.
+    fn compute_hidden_nodes(&mut self, index: usize) {
+        let known = (0..=index)
+            .rev()
+            .find(|i| self.frames[*i].hidden.is_some())
+            .expect("internal error: the initial frame's hidden nodes are always known");
+        for i in known + 1..=index {
+            let caller = &self.frames[i - 1];
+            let mut hidden = caller.hidden.clone().unwrap();
+            caller.hide_nodes_in_scope(&mut hidden);
+            self.frames[i].hidden = Some(hidden);
+        }
+    }
+
+    fn return_from_frame(&mut self) -> OperationResult<Option<OperationOutput>> {
+        let frame = self
+            .frames
+            .pop()
+            .expect("internal error: interpreter has no frames");
+        let output = frame.output()?;
+        let Some(caller) = self.frames.last_mut() else {
+            // the initial operation returned.
+            // Note: entering and exiting the initial operation is handled by `Program::run_operation`.
+            return Ok(Some(output));
+        };
+        self.execution.borrow_mut().exit_operation();
+        self.execution.borrow().check_graph_growth(self.g)?;
+        if let Some(outputs) = frame.outputs {
+            caller.store_outputs(outputs, output.new_nodes);
+        }
+        match frame.on_return {
+            OnReturn::Continue => {}
+            OnReturn::Branch { not_taken, .. } => {
+                let taken = frame
+                    .query_result
+                    .ok_or(OperationError::MissingQueryResult(frame.code.op_id))?;
+                if !taken {
+                    caller.pc = not_taken;
+                }
+            }
+            OnReturn::SkipTo(end) => caller.pc = end,
+        }
+        Ok(None)
+    }
+
+    /// Pushes a new frame for a call to the user defined operation with the given index.
+    fn call(
+        &mut self,
+        callee: usize,
+        args: &[(Slot, Slot)],
+        outputs: Option<&'p Outputs>,
+        on_return: OnReturn,
+        tail: bool,
+    ) -> OperationResult<()> {
+        let code = &self.program.operations[callee];
+        let caller = self.frame();
+        let mut slots = vec![None; code.aids.len()];
+        for (from, to) in args {
+            slots[*to] = Some(caller.node(*from)?);
+        }
+        let mut frame = Frame::new(code, slots, outputs, on_return);
+        if tail {
+            // the caller has nothing left to do and does not return any nodes,
+            // so we can replace its frame
+            self.compute_hidden_nodes(self.frames.len() - 1);
+            let mut caller = self.frames.pop().unwrap();
+            let mut hidden = caller.hidden.take().unwrap();
+            caller.hide_nodes_in_scope(&mut hidden);
+            frame.hidden = Some(hidden);
+            frame.outputs = caller.outputs;
+            frame.on_return = match caller.on_return {
+                OnReturn::Branch { end, .. } => OnReturn::SkipTo(end),
+                on_return => on_return,
+            };
+            frame.discard_output = true;
+            self.execution.borrow_mut().exit_operation();
+        }
+        self.execution.borrow_mut().enter_operation(code.op_id)?;
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn builtin_arg(&mut self, arg: &Arg) -> OperationResult<OperationArgument<'p, S>> {
+        let (selected, subst) = self.frame().concrete_arg(arg)?;
+        Ok(OperationArgument {
+            selected_input_nodes: selected.into(),
+            subst,
+            // builtin operations and queries do not look at hidden nodes
+            hidden_nodes: HashSet::new(),
+            marker_set: self.marker_set,
+            trace: self.trace,
+            execution: self.execution,
+            abstraction: self.abstraction,
+        })
+    }
+
+    fn execute(&mut self, op: &'p Op<'p, S>) -> OperationResult<()> {
+        match op {
+            Op::Builtin { op, arg, outputs } => {
+                let concrete_arg = self.builtin_arg(arg)?;
+                let output = match op {
+                    BuiltinOp::Builtin(op) => run_builtin_operation::<S>(self.g, op, concrete_arg)?,
+                    BuiltinOp::LibBuiltin(op) => {
+                        run_lib_builtin_operation(self.g, op, concrete_arg)?
+                    }
+                };
+                self.execution.borrow().check_graph_growth(self.g)?;
+                if let Some(outputs) = outputs {
+                    self.frame().store_outputs(outputs, output.new_nodes);
+                }
+            }
+            Op::Call {
+                callee,
+                args,
+                outputs,
+                tail,
+            } => {
+                self.call(*callee, args, outputs.as_ref(), OnReturn::Continue, *tail)?;
+            }
+            Op::Query {
+                query,
+                arg,
+                not_taken,
+            } => {
+                let concrete_arg = self.builtin_arg(arg)?;
+                let result = run_builtin_query::<S>(self.g, query, concrete_arg)?;
+                if !result.taken {
+                    self.frame().pc = *not_taken;
+                }
+            }
+            Op::CallQuery {
+                op_id,
+                callee,
+                args,
+                not_taken,
+                end,
+            } => {
+                let Some(callee) = callee else {
+                    return Err(report!(OperationError::ExpectedUserDefinedOperation(
+                        *op_id
+                    )));
+                };
+                let on_return = OnReturn::Branch {
+                    not_taken: *not_taken,
+                    end: *end,
+                };
+                // the branch is entered once the query returns, see `return_from_frame`
+                self.call(*callee, args, None, on_return, false)?;
+            }
+            Op::ShapeQuery {
+                query,
+                selected,
+                outputs,
+                not_taken,
+            } => {
+                self.compute_hidden_nodes(self.frames.len() - 1);
+                let frame = self
+                    .frames
+                    .last_mut()
+                    .expect("internal error: interpreter has no frames");
+                let selected = frame.nodes(selected)?;
+                let result = frame.with_hidden_nodes(|hidden| {
+                    run_shape_query(
+                        self.g,
+                        &mut self.abstraction.borrow_mut(),
+                        query,
+                        &selected,
+                        hidden,
+                        &self.marker_set.borrow(),
+                    )
+                })?;
+                if let Some(shape_idents_to_node_keys) = result.shape_idents_to_node_keys {
+                    if let Some(outputs) = outputs {
+                        frame.store_outputs(
+                            outputs,
+                            shape_idents_to_node_keys
+                                .into_iter()
+                                .map(|(ident, key)| (AbstractOutputNodeMarker(ident.into()), key)),
+                        );
+                    }
+                    mark_shape_paths(query, &result.paths, &mut self.marker_set.borrow_mut());
+                } else {
+                    frame.pc = *not_taken;
+                }
+            }
+            Op::ShapeMatch {
+                arms,
+                selected,
+                outputs,
+            } => {
+                self.compute_hidden_nodes(self.frames.len() - 1);
+                let frame = self
+                    .frames
+                    .last_mut()
+                    .expect("internal error: interpreter has no frames");
+                let selected = frame.nodes(selected)?;
+                for (query, target) in arms {
+                    let result = frame.with_hidden_nodes(|hidden| {
+                        run_shape_query(
+                            self.g,
+                            &mut self.abstraction.borrow_mut(),
+                            query,
+                            &selected,
+                            hidden,
+                            &self.marker_set.borrow(),
+                        )
+                    })?;
+                    let Some(shape_idents_to_node_keys) = result.shape_idents_to_node_keys else {
+                        continue;
+                    };
+                    if let Some(outputs) = outputs {
+                        frame.store_outputs(
+                            outputs,
+                            shape_idents_to_node_keys
+                                .into_iter()
+                                .map(|(ident, key)| (AbstractOutputNodeMarker(ident.into()), key)),
+                        );
+                    }
+                    mark_shape_paths(query, &result.paths, &mut self.marker_set.borrow_mut());
+                    frame.pc = *target;
+                    break;
+                }
+            }
+            Op::Move { from, to } => {
+                let frame = self.frame();
+                let key = frame.slots[*from]
+                    .take()
+                    .ok_or_else(|| report!(OperationError::UnknownAID(frame.code.aids[*from])))?;
+                frame.slots[*to] = Some(key);
+            }
+            Op::Forget(slot) => {
+                let frame = self.frame();
+                let key = frame.slots[*slot]
+                    .take()
+                    .ok_or_else(|| report!(OperationError::UnknownAID(frame.code.aids[*slot])))?;
+                if *slot < frame.code.parameters {
+                    frame.forgotten_params.push(key);
+                }
+            }
+            Op::Diverge(crash_message) => {
+                return Err(report!(OperationError::UserCrash(
+                    crash_message.to_string()
+                )));
+            }
+            Op::Trace => {
+                self.compute_hidden_nodes(self.frames.len() - 1);
+                let frame = self
+                    .frames
+                    .last()
+                    .expect("internal error: interpreter has no frames");
+                let trace_frame = TraceFrame {
+                    node_aids: BiMap::from_right(frame.node_aids()),
+                    graph: self.g.clone(),
+                    hidden_nodes: frame.hidden.clone().unwrap(),
+                    marker_set: self.marker_set.borrow().clone(),
+                    op_id: frame.code.op_id,
+                };
+                self.trace.borrow_mut().push_frame(trace_frame);
+            }
+            Op::YieldQueryResult(value) => {
+                self.frame().query_result = Some(*value);
+            }
+            Op::Undefined(id) => {
+                return Err(report!(OperationError::InvalidOperationId(*id)));
+            }
+            Op::Jump(_) | Op::Return => {
+                unreachable!("internal error: control flow is handled by `Interpreter::run`")
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+//! A step-through debugger for user defined operations.
+//!
+//! In contrast to [`Trace`], which only records snapshots requested by `trace()` instructions,
+//! a [`Debugger`] pauses the concrete execution before every instruction and allows inspecting
+//! the current state of the run.
+//!
+//! # Example
+//! ```rust,ignore
+//! let ctx = DebugContext::new(ExecutionLimits::unlimited());
+//! let mut debugger = Debugger::new(&ctx, &mut g, &op_ctx, op_id, &[input])?;
+//! debugger.add_breakpoint(Breakpoint::new(op_id, InstructionPath::top_level(2)));
+//! while debugger.resume()? == DebuggerStatus::Paused {
+//!     println!("{:?}", debugger.abstract_to_concrete());
+//! }
+//! ```
+
+use crate::operation::execution::{ExecutionContext, ExecutionLimits};
+use crate::operation::marker::MarkerSet;
+use crate::operation::signature::parameter::{OperationArgument, OperationOutput};
+use crate::operation::trace::Trace;
+use crate::operation::user_defined::{AbstractNodeId, Frame, Instruction, InstructionPath, Runner};
+use crate::operation::{
+    Operation, OperationContext, OperationError, OperationId, OperationResult,
+    concrete_substitution,
+};
+use crate::semantics::{AbstractionCache, ConcreteGraph};
+use crate::{NodeKey, Semantics};
+use std::cell::{Ref, RefCell};
+use std::collections::{HashMap, HashSet};
+
+/// Owns the state that is shared by all operations of a debugged run.
+///
+/// Must outlive the [`Debugger`] that borrows it.
+pub struct DebugContext<S: Semantics> {
+    limits: ExecutionLimits,
+    marker_set: RefCell<MarkerSet>,
+    trace: RefCell<Trace<S>>,
+    execution: RefCell<ExecutionContext>,
+    abstraction: RefCell<AbstractionCache<S>>,
+}
+
+impl<S: Semantics> DebugContext<S> {
+    pub fn new(limits: ExecutionLimits) -> Self {
+        DebugContext {
+            limits,
+            marker_set: RefCell::new(MarkerSet::new()),
+            trace: RefCell::new(Trace::new()),
+            // replaced by every new debugger
+            execution: RefCell::new(ExecutionContext::new(limits, &ConcreteGraph::<S>::new())),
+            abstraction: RefCell::new(AbstractionCache::new()),
+        }
+    }
+}
+
+/// A location in a user defined operation at which the [`Debugger`] pauses.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Breakpoint {
+    pub op: OperationId,
+    pub path: InstructionPath,
+}
+
+impl Breakpoint {
+    pub fn new(op: OperationId, path: InstructionPath) -> Self {
+        Breakpoint { op, path }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebuggerStatus {
+    /// The debugger is paused before an instruction.
+    Paused,
+    /// The initial operation returned, see [`Debugger::output`].
+    Finished,
+    /// The run was aborted by an error. The debugger cannot be resumed.
+    Failed,
+}
+
+/// A resumable run of a user defined operation on a concrete graph.
+///
+/// While [`DebuggerStatus::Paused`], the debugger is always positioned before the instruction
+/// that will be executed next.
+pub struct Debugger<'a, S: Semantics> {
+    ctx: &'a DebugContext<S>,
+    runner: Runner<'a, 'a, S>,
+    breakpoints: HashSet<Breakpoint>,
+    status: DebuggerStatus,
+    output: Option<OperationOutput>,
+}
+
+impl<'a, S: Semantics> Debugger<'a, S> {
+    /// Starts debugging the user defined operation `op` on the selected inputs.
+    ///
+    /// The debugger is paused before the first instruction of `op`.
+    pub fn new(
+        ctx: &'a DebugContext<S>,
+        g: &'a mut ConcreteGraph<S>,
+        op_ctx: &'a OperationContext<S>,
+        op: OperationId,
+        selected_inputs: &[NodeKey],
+    ) -> OperationResult<Self> {
+        let Some(Operation::Custom(user_defined_op)) = op_ctx.get(op) else {
+            if op_ctx.get(op).is_none() {
+                return Err(OperationError::InvalidOperationId(op).into());
+            }
+            return Err(OperationError::ExpectedUserDefinedOperation(op).into());
+        };
+        *ctx.abstraction.borrow_mut() = AbstractionCache::new();
+        let subst = concrete_substitution(
+            g,
+            &mut ctx.abstraction.borrow_mut(),
+            op_ctx,
+            op,
+            selected_inputs,
+        )?;
+
+        *ctx.marker_set.borrow_mut() = MarkerSet::new();
+        *ctx.trace.borrow_mut() = Trace::new();
+        *ctx.execution.borrow_mut() = ExecutionContext::new(ctx.limits, g);
+        ctx.execution.borrow_mut().enter_operation(op)?;
+
+        let arg = OperationArgument {
+            subst,
+            selected_input_nodes: selected_inputs.to_vec().into(),
+            hidden_nodes: HashSet::new(),
+            marker_set: &ctx.marker_set,
+            trace: &ctx.trace,
+            execution: &ctx.execution,
+            abstraction: &ctx.abstraction,
+        };
+        let runner = Runner::new(op_ctx, g, op, user_defined_op, arg);
+        let mut debugger = Debugger {
+            ctx,
+            runner,
+            breakpoints: HashSet::new(),
+            status: DebuggerStatus::Paused,
+            output: None,
+        };
+        let settled = debugger.runner.settle();
+        debugger.handle_settled(settled)?;
+        Ok(debugger)
+    }
+
+    pub fn status(&self) -> DebuggerStatus {
+        self.status
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.insert(breakpoint);
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        self.breakpoints.remove(breakpoint)
+    }
+
+    /// Executes the next instruction.
+    ///
+    /// If the instruction calls a user defined operation, the debugger pauses before the callee's
+    /// first instruction.
+    pub fn step(&mut self) -> OperationResult<DebuggerStatus> {
+        if self.status != DebuggerStatus::Paused {
+            return Ok(self.status);
+        }
+        // the runner is always settled, so this executes exactly one instruction
+        let stepped = match self.runner.step() {
+            Ok(None) => self.runner.settle(),
+            finished_or_failed => finished_or_failed,
+        };
+        self.handle_settled(stepped)?;
+        Ok(self.status)
+    }
+
+    /// Executes the next instruction, including the entire execution of any user defined
+    /// operation it calls.
+    ///
+    /// Pauses early at breakpoints. If the current operation tail-calls another operation,
+    /// the callee replaces the current operation and the debugger pauses inside the callee.
+    pub fn step_over(&mut self) -> OperationResult<DebuggerStatus> {
+        let Some(frame) = self.runner.current_frame_id() else {
+            return Ok(self.status);
+        };
+        self.step_until(|runner| {
+            !runner.has_frame(frame) || runner.current_frame_id() == Some(frame)
+        })
+    }
+
+    /// Runs until the current operation returns, pausing early at breakpoints.
+    pub fn step_out(&mut self) -> OperationResult<DebuggerStatus> {
+        let Some(frame) = self.runner.current_frame_id() else {
+            return Ok(self.status);
+        };
+        self.step_until(|runner| !runner.has_frame(frame))
+    }
+
+    /// Runs until the next breakpoint or until the initial operation returns.
+    pub fn resume(&mut self) -> OperationResult<DebuggerStatus> {
+        self.step_until(|_| false)
+    }
+
+    /// Steps at least once, then until `stop` returns true, a breakpoint is hit, or the run ends.
+    fn step_until(
+        &mut self,
+        stop: impl Fn(&Runner<'a, 'a, S>) -> bool,
+    ) -> OperationResult<DebuggerStatus> {
+        loop {
+            if self.step()? != DebuggerStatus::Paused || stop(&self.runner) || self.at_breakpoint()
+            {
+                return Ok(self.status);
+            }
+        }
+    }
+
+    fn handle_settled(
+        &mut self,
+        settled: OperationResult<Option<OperationOutput>>,
+    ) -> OperationResult<()> {
+        if !matches!(settled, Ok(None)) {
+            // the run is over, stop tracking the changes of the graph
+            self.ctx
+                .abstraction
+                .borrow_mut()
+                .clear(self.runner.graph_mut());
+        }
+        match settled {
+            Ok(None) => {}
+            Ok(Some(output)) => {
+                self.ctx.execution.borrow_mut().exit_operation();
+                self.output = Some(output);
+                self.status = DebuggerStatus::Finished;
+            }
+            Err(err) => {
+                self.status = DebuggerStatus::Failed;
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the debugger is paused at one of its breakpoints.
+    pub fn at_breakpoint(&self) -> bool {
+        match (self.current_operation(), self.current_path()) {
+            (Some(op), Some(path)) => self.breakpoints.contains(&Breakpoint { op, path }),
+            _ => false,
+        }
+    }
+
+    /// The user defined operation the debugger is paused in.
+    pub fn current_operation(&self) -> Option<OperationId> {
+        self.current_frame().map(|frame| frame.op_id())
+    }
+
+    /// The path of the next instruction in the current operation.
+    pub fn current_path(&self) -> Option<InstructionPath> {
+        self.current_frame().and_then(|frame| frame.path())
+    }
+
+    /// The next instruction that will be executed.
+    pub fn current_instruction(&self) -> Option<&'a Instruction<S>> {
+        let frame = self.current_frame()?;
+        frame.op().instruction_at(&frame.path()?)
+    }
+
+    /// The operations on the call stack, outermost first.
+    ///
+    /// Operations that were replaced by a tail call are not included.
+    pub fn call_stack(&self) -> Vec<OperationId> {
+        self.runner
+            .frames()
+            .iter()
+            .map(|frame| frame.op_id())
+            .collect()
+    }
+
+    /// The mapping of the current operation's abstract node ids to concrete nodes.
+    pub fn abstract_to_concrete(&self) -> Option<&HashMap<AbstractNodeId, NodeKey>> {
+        self.current_frame()
+            .map(|frame| frame.abstract_to_concrete())
+    }
+
+    /// The nodes hidden from the current operation's shape queries.
+    pub fn hidden_nodes(&self) -> Option<&HashSet<NodeKey>> {
+        self.current_frame().map(|frame| frame.hidden_nodes())
+    }
+
+    pub fn marker_set(&self) -> Ref<'_, MarkerSet> {
+        self.ctx.marker_set.borrow()
+    }
+
+    /// The frames recorded by `trace()` instructions so far.
+    pub fn trace(&self) -> Ref<'_, Trace<S>> {
+        self.ctx.trace.borrow()
+    }
+
+    pub fn graph(&self) -> &ConcreteGraph<S> {
+        self.runner.graph()
+    }
+
+    /// The initial operation's output, once it has returned.
+    pub fn output(&self) -> Option<&OperationOutput> {
+        self.output.as_ref()
+    }
+
+    fn current_frame(&self) -> Option<&Frame<'a, 'a, S>> {
+        if self.status != DebuggerStatus::Paused {
+            return None;
+        }
+        self.runner.frames().last()
+    }
+}
@@ -0,0 +1,178 @@
+//! Resource limits for the concrete interpreter.
+//!
+//! User-defined operations can recurse indefinitely and grow the concrete graph without bound.
+//! An [`ExecutionContext`] is threaded through every [`OperationArgument`] of a single run and
+//! keeps track of the resources used so far, aborting the run with
+//! [`OperationError::ExecutionLimitExceeded`] as soon as one of the configured
+//! [`ExecutionLimits`] is exceeded.
+//!
+//! [`OperationArgument`]: crate::operation::signature::parameter::OperationArgument
+
+use crate::Graph;
+use crate::operation::{OperationError, OperationId, OperationResult};
+use error_stack::bail;
+use std::fmt::{Display, Formatter};
+
+/// Limits on the resources a single concrete run may use.
+///
+/// Every limit is optional, and `None` means unlimited.
+///
+/// # Example
+/// ```rust
+/// # use grabapl::operation::execution::ExecutionLimits;
+/// let limits = ExecutionLimits::unlimited()
+///     .with_max_steps(10_000)
+///     .with_max_recursion_depth(100);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutionLimits {
+    /// The maximum number of user-defined operation instructions that may be executed.
+    pub max_steps: Option<usize>,
+    /// The maximum number of nested user-defined operation calls, including the initial call.
+    pub max_recursion_depth: Option<usize>,
+    /// The maximum number of nodes the concrete graph may gain compared to the start of the run.
+    pub max_node_growth: Option<usize>,
+    /// The maximum number of edges the concrete graph may gain compared to the start of the run.
+    pub max_edge_growth: Option<usize>,
+}
+
+impl ExecutionLimits {
+    pub fn unlimited() -> Self {
+        ExecutionLimits::default()
+    }
+
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    pub fn with_max_recursion_depth(mut self, max_recursion_depth: usize) -> Self {
+        self.max_recursion_depth = Some(max_recursion_depth);
+        self
+    }
+
+    pub fn with_max_node_growth(mut self, max_node_growth: usize) -> Self {
+        self.max_node_growth = Some(max_node_growth);
+        self
+    }
+
+    pub fn with_max_edge_growth(mut self, max_edge_growth: usize) -> Self {
+        self.max_edge_growth = Some(max_edge_growth);
+        self
+    }
+}
+
+/// Identifies which of the [`ExecutionLimits`] was exceeded, and its configured value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionLimit {
+    Steps(usize),
+    RecursionDepth(usize),
+    NodeGrowth(usize),
+    EdgeGrowth(usize),
+}
+
+impl Display for ExecutionLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionLimit::Steps(max) => write!(f, "maximum of {max} steps"),
+            ExecutionLimit::RecursionDepth(max) => write!(f, "maximum recursion depth of {max}"),
+            ExecutionLimit::NodeGrowth(max) => write!(f, "maximum node growth of {max}"),
+            ExecutionLimit::EdgeGrowth(max) => write!(f, "maximum edge growth of {max}"),
+        }
+    }
+}
+
+/// Tracks the resources used by a single concrete run.
+#[derive(Debug, Clone)]
+pub struct ExecutionContext {
+    limits: ExecutionLimits,
+    steps: usize,
+    /// The user-defined operations that are currently executing, innermost last.
+    call_stack: Vec<OperationId>,
+    initial_node_count: usize,
+    initial_edge_count: usize,
+}
+
+impl ExecutionContext {
+    pub fn new<NodeAttr, EdgeAttr>(limits: ExecutionLimits, g: &Graph<NodeAttr, EdgeAttr>) -> Self {
+        ExecutionContext {
+            limits,
+            steps: 0,
+            call_stack: Vec::new(),
+            initial_node_count: g.node_count(),
+            initial_edge_count: g.edge_count(),
+        }
+    }
+
+    pub fn limits(&self) -> &ExecutionLimits {
+        &self.limits
+    }
+
+    /// The number of user-defined operation instructions executed so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// The number of currently executing, nested user-defined operations.
+    pub fn recursion_depth(&self) -> usize {
+        self.call_stack.len()
+    }
+
+    /// The innermost currently executing user-defined operation, if any.
+    pub fn current_operation(&self) -> Option<OperationId> {
+        self.call_stack.last().copied()
+    }
+
+    pub(crate) fn enter_operation(&mut self, op: OperationId) -> OperationResult<()> {
+        self.call_stack.push(op);
+        if let Some(max) = self.limits.max_recursion_depth
+            && self.call_stack.len() > max
+        {
+            bail!(OperationError::ExecutionLimitExceeded {
+                limit: ExecutionLimit::RecursionDepth(max),
+                operation: op,
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn exit_operation(&mut self) {
+        self.call_stack.pop();
+    }
+
+    /// Accounts for a single executed instruction.
+    pub(crate) fn step(&mut self) -> OperationResult<()> {
+        self.steps += 1;
+        if let Some(max) = self.limits.max_steps
+            && self.steps > max
+        {
+            self.bail_exceeded(ExecutionLimit::Steps(max))?;
+        }
+        Ok(())
+    }
+
+    /// Checks the node and edge growth of the concrete graph since the start of the run.
+    pub(crate) fn check_graph_growth<NodeAttr, EdgeAttr>(
+        &self,
+        g: &Graph<NodeAttr, EdgeAttr>,
+    ) -> OperationResult<()> {
+        if let Some(max) = self.limits.max_node_growth
+            && g.node_count().saturating_sub(self.initial_node_count) > max
+        {
+            self.bail_exceeded(ExecutionLimit::NodeGrowth(max))?;
+        }
+        if let Some(max) = self.limits.max_edge_growth
+            && g.edge_count().saturating_sub(self.initial_edge_count) > max
+        {
+            self.bail_exceeded(ExecutionLimit::EdgeGrowth(max))?;
+        }
+        Ok(())
+    }
+
+    fn bail_exceeded(&self, limit: ExecutionLimit) -> OperationResult<()> {
+        let operation = self
+            .current_operation()
+            .expect("internal error: instructions are only executed inside an operation");
+        bail!(OperationError::ExecutionLimitExceeded { limit, operation })
+    }
+}
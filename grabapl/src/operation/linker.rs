@@ -0,0 +1,198 @@
+//! Linking multiple [`OperationContext`]s into a single one.
+//!
+//! Every operation context has its own id space, and user defined operations reference other
+//! operations by id. The [`Linker`] merges contexts by giving every operation an id in the
+//! merged context and rewriting all references inside the user defined operations accordingly.
+//!
+//! Named operations keep their name, and declared operations (see [`OperationContext::declare_operation`])
+//! are resolved by name to a user defined operation that is defined in one of the linked contexts.
+//! Declarations cannot be resolved to builtin operations, since their effects cannot be checked
+//! against the declared signature.
+//!
+//! # Example
+//! ```rust,ignore
+//! let mut linker = Linker::new();
+//! linker.add(std_lib);
+//! let program = linker.add(user_program);
+//! let linked = linker.link()?;
+//! run_from_concrete(&mut g, &linked.op_ctx, linked.op_ctx.id_of("main").unwrap(), &[input])?;
+//! ```
+
+use crate::Semantics;
+use crate::operation::signature::SignatureMismatch;
+use crate::operation::{Operation, OperationContext, OperationId};
+use error_stack::{Result, bail};
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone)]
+pub enum LinkError {
+    #[error("operation `{0}` is defined in more than one context")]
+    NameCollision(String),
+    #[error("declared operation `{0}` is not defined in any context")]
+    UnresolvedDeclaration(String),
+    #[error("operation `{name}` does not match its declaration: {mismatch}")]
+    IncompatibleSignature {
+        name: String,
+        mismatch: SignatureMismatch,
+    },
+    #[error("operation {operation} of context {context} references unknown operation {id}")]
+    UnknownOperation {
+        context: usize,
+        operation: OperationId,
+        id: OperationId,
+    },
+}
+
+/// The result of [`Linker::link`].
+pub struct LinkedContext<S: Semantics> {
+    pub op_ctx: OperationContext<S>,
+    /// For every linked context, in the order they were added, maps its operation ids to the
+    /// ids in the merged context.
+    pub id_maps: Vec<HashMap<OperationId, OperationId>>,
+}
+
+/// Collects operation contexts and merges them into one, see the [module-level documentation](self).
+pub struct Linker<S: Semantics> {
+    contexts: Vec<OperationContext<S>>,
+}
+
+impl<S: Semantics> Default for Linker<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Semantics> Linker<S> {
+    pub fn new() -> Self {
+        Linker {
+            contexts: Vec::new(),
+        }
+    }
+
+    /// Adds a context to be linked, returning its index in [`LinkedContext::id_maps`].
+    pub fn add(&mut self, op_ctx: OperationContext<S>) -> usize {
+        self.contexts.push(op_ctx);
+        self.contexts.len() - 1
+    }
+}
+
+impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Linker<S> {
+    /// Merges all added contexts.
+    ///
+    /// Named operations keep their stable ids where possible, see [`OperationContext::register_name`].
+    /// Fails if two contexts define an operation with the same name, if a declaration cannot be
+    /// resolved, if the resolved operation's signature is not a subtype of the declared one
+    /// (which is never the case for builtin operations), or if an operation references an id that is neither defined nor declared in its context.
+    pub fn link(self) -> Result<LinkedContext<S>, LinkError> {
+        let mut merged = OperationContext::new();
+        let mut id_maps = vec![HashMap::new(); self.contexts.len()];
+        // the merged ids of the user defined operations, by context
+        let mut custom_ids = vec![Vec::new(); self.contexts.len()];
+
+        // named operations first, so that unnamed operations do not take away their stable ids
+        for named in [true, false] {
+            for (index, ctx) in self.contexts.iter().enumerate() {
+                // sorted, so that colliding unnamed operations are moved to the same ids on every run
+                let mut ids = ctx
+                    .builtins
+                    .keys()
+                    .chain(ctx.libbuiltins.keys())
+                    .chain(ctx.custom.keys())
+                    .copied()
+                    .collect::<Vec<_>>();
+                ids.sort_unstable();
+                for id in ids {
+                    let name = ctx.name_of(id);
+                    if name.is_some() != named {
+                        continue;
+                    }
+                    let new_id = match name {
+                        Some(name) => {
+                            if merged
+                                .id_of(name)
+                                .is_some_and(|id| merged.get(id).is_some())
+                            {
+                                bail!(LinkError::NameCollision(name.to_string()));
+                            }
+                            merged.register_name(name)
+                        }
+                        None => {
+                            let mut new_id = id;
+                            while merged.is_id_taken(new_id) {
+                                new_id = new_id.wrapping_add(1);
+                            }
+                            new_id
+                        }
+                    };
+                    id_maps[index].insert(id, new_id);
+                    match ctx.get(id).unwrap() {
+                        Operation::Builtin(op) => {
+                            merged.builtins.insert(new_id, op.clone());
+                        }
+                        Operation::LibBuiltin(op) => {
+                            merged.libbuiltins.insert(new_id, op.clone());
+                        }
+                        Operation::Custom(op) => {
+                            custom_ids[index].push((id, new_id));
+                            merged.custom.insert(new_id, op.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        for (index, ctx) in self.contexts.iter().enumerate() {
+            for (&id, declared) in &ctx.declared {
+                if ctx.get(id).is_some() {
+                    continue;
+                }
+                let name = ctx
+                    .name_of(id)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| id.to_string());
+                let Some(new_id) = merged.id_of(&name).filter(|id| merged.get(*id).is_some())
+                else {
+                    bail!(LinkError::UnresolvedDeclaration(name));
+                };
+                let op = merged.get(new_id).unwrap();
+                let res = match op.signature() {
+                    Some(signature) => signature.check_subtype_of(declared),
+                    // builtin operations have no static signature apart from their parameter,
+                    // so callers could not rely on the declared output changes
+                    None if op.parameter().is_subtype_of(&declared.parameter) => {
+                        Err(SignatureMismatch::UnknownOutput)
+                    }
+                    None => Err(SignatureMismatch::Parameter),
+                };
+                if let Err(mismatch) = res {
+                    bail!(LinkError::IncompatibleSignature { name, mismatch });
+                }
+                id_maps[index].insert(id, new_id);
+            }
+        }
+
+        for (index, ids) in custom_ids.into_iter().enumerate() {
+            for (old_id, new_id) in ids {
+                let op = merged.custom.get_mut(&new_id).unwrap();
+                if let Some(id) = op
+                    .referenced_operations()
+                    .into_iter()
+                    .find(|id| !id_maps[index].contains_key(id))
+                {
+                    bail!(LinkError::UnknownOperation {
+                        context: index,
+                        operation: old_id,
+                        id,
+                    });
+                }
+                op.remap_operation_ids(|id| id_maps[index][&id]);
+            }
+        }
+
+        Ok(LinkedContext {
+            op_ctx: merged,
+            id_maps,
+        })
+    }
+}
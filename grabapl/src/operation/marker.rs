@@ -62,14 +62,49 @@ pub enum MarkerError {
 }
 
 // TODO: this is not only a marker set, but also marked nodes themselves.
-#[derive(Debug, Clone, Default)]
+///
+/// The fields are only modified through the methods of [`MarkerSet`], such that every
+/// modification is recorded while a transaction is active.
+#[derive(Debug, Default)]
 pub struct MarkerSet {
     // which markers currently exist
-    pub markers: HashSet<Marker>,
+    markers: HashSet<Marker>,
     // which nodes are marked with a specific marker?
-    pub marker_to_marked_nodes: HashMap<Marker, HashSet<NodeKey>>,
+    marker_to_marked_nodes: HashMap<Marker, HashSet<NodeKey>>,
     // which markers does a specific node have?
-    pub marked_nodes_to_markers: HashMap<NodeKey, HashSet<Marker>>,
+    marked_nodes_to_markers: HashMap<NodeKey, HashSet<Marker>>,
+    /// Records modifications while a transaction is active.
+    undo_log: Option<Vec<MarkerChange>>,
+}
+
+impl Clone for MarkerSet {
+    /// A clone is not part of the original's transaction.
+    fn clone(&self) -> Self {
+        MarkerSet {
+            markers: self.markers.clone(),
+            marker_to_marked_nodes: self.marker_to_marked_nodes.clone(),
+            marked_nodes_to_markers: self.marked_nodes_to_markers.clone(),
+            undo_log: None,
+        }
+    }
+}
+
+impl PartialEq for MarkerSet {
+    /// Marker sets are equal if they have the same markers on the same nodes, regardless of
+    /// whether a transaction is active.
+    fn eq(&self, other: &Self) -> bool {
+        self.markers == other.markers && self.marker_to_marked_nodes == other.marker_to_marked_nodes
+    }
+}
+
+impl Eq for MarkerSet {}
+
+/// A single recorded modification of a [`MarkerSet`], holding everything that is needed to revert it.
+#[derive(Debug, Clone)]
+enum MarkerChange {
+    AddedMarker(Marker),
+    MarkedNode(Marker, NodeKey),
+    RemovedMarker(Marker, HashSet<NodeKey>),
 }
 
 impl MarkerSet {
@@ -77,6 +112,21 @@ impl MarkerSet {
         MarkerSet::default()
     }
 
+    /// Returns all markers that currently exist.
+    pub fn markers(&self) -> &HashSet<Marker> {
+        &self.markers
+    }
+
+    /// Returns the nodes marked with `marker`, or `None` if the marker does not exist.
+    pub fn marked_nodes(&self, marker: impl Into<Marker>) -> Option<&HashSet<NodeKey>> {
+        self.marker_to_marked_nodes.get(&marker.into())
+    }
+
+    /// Returns the markers of `node`, or `None` if the node has none.
+    pub fn markers_of(&self, node: NodeKey) -> Option<&HashSet<Marker>> {
+        self.marked_nodes_to_markers.get(&node)
+    }
+
     pub fn all_marked_nodes(&self) -> impl Iterator<Item = NodeKey> {
         self.marked_nodes_to_markers.keys().cloned()
     }
@@ -107,6 +157,7 @@ impl MarkerSet {
         }
         self.markers.insert(marker);
         self.marker_to_marked_nodes.insert(marker, HashSet::new());
+        self.record(MarkerChange::AddedMarker(marker));
         Ok(())
     }
 
@@ -119,10 +170,14 @@ impl MarkerSet {
         if !self.markers.contains(&marker) {
             return Err(MarkerError::MarkerDoesNotExist(marker));
         }
-        self.marker_to_marked_nodes
+        let newly_marked = self
+            .marker_to_marked_nodes
             .get_mut(&marker)
             .unwrap()
             .insert(node_key);
+        if newly_marked {
+            self.record(MarkerChange::MarkedNode(marker, node_key));
+        }
         self.marked_nodes_to_markers
             .entry(node_key)
             .or_default()
@@ -143,15 +198,68 @@ impl MarkerSet {
     pub fn remove_marker(&mut self, marker: impl Into<Marker>) {
         let marker = marker.into();
         if let Some(nodes) = self.marker_to_marked_nodes.remove(&marker) {
-            for node in nodes {
-                if let Some(markers) = self.marked_nodes_to_markers.get_mut(&node) {
-                    markers.remove(&marker);
-                    if markers.is_empty() {
-                        self.marked_nodes_to_markers.remove(&node);
+            for node in &nodes {
+                self.unmark_node_in_node_map(marker, *node);
+            }
+            self.record(MarkerChange::RemovedMarker(marker, nodes));
+        }
+        self.markers.remove(&marker);
+    }
+
+    fn unmark_node_in_node_map(&mut self, marker: Marker, node: NodeKey) {
+        if let Some(markers) = self.marked_nodes_to_markers.get_mut(&node) {
+            markers.remove(&marker);
+            if markers.is_empty() {
+                self.marked_nodes_to_markers.remove(&node);
+            }
+        }
+    }
+
+    fn record(&mut self, change: MarkerChange) {
+        if let Some(log) = &mut self.undo_log {
+            log.push(change);
+        }
+    }
+
+    /// Starts recording all modifications of this marker set, such that they can be reverted with
+    /// [`MarkerSet::rollback_transaction`].
+    pub fn begin_transaction(&mut self) {
+        self.undo_log = Some(Vec::new());
+    }
+
+    /// Keeps all modifications since [`MarkerSet::begin_transaction`] and stops recording.
+    pub fn commit_transaction(&mut self) {
+        self.undo_log = None;
+    }
+
+    /// Reverts all modifications since [`MarkerSet::begin_transaction`] and stops recording.
+    pub fn rollback_transaction(&mut self) {
+        let Some(log) = self.undo_log.take() else {
+            return;
+        };
+        for change in log.into_iter().rev() {
+            match change {
+                MarkerChange::AddedMarker(marker) => {
+                    self.markers.remove(&marker);
+                    self.marker_to_marked_nodes.remove(&marker);
+                }
+                MarkerChange::MarkedNode(marker, node) => {
+                    if let Some(nodes) = self.marker_to_marked_nodes.get_mut(&marker) {
+                        nodes.remove(&node);
+                    }
+                    self.unmark_node_in_node_map(marker, node);
+                }
+                MarkerChange::RemovedMarker(marker, nodes) => {
+                    self.markers.insert(marker);
+                    for node in &nodes {
+                        self.marked_nodes_to_markers
+                            .entry(*node)
+                            .or_default()
+                            .insert(marker);
                     }
+                    self.marker_to_marked_nodes.insert(marker, nodes);
                 }
             }
         }
-        self.markers.remove(&marker);
     }
 }
@@ -0,0 +1,327 @@
+//! Subgraph matching for parameter substitutions and shape queries.
+//!
+//! Both find a subgraph monomorphism from a pattern graph into a target graph, where some
+//! pattern nodes are anchored to given target nodes. Like with petgraph's
+//! `general_subgraph_monomorphisms_iter`, the matched subgraph need not be induced.
+//!
+//! # Match order
+//!
+//! If a pattern has several matches, the chosen one only depends on the node keys, attributes and
+//! edges of both graphs, not on the order in which nodes were added or removed:
+//!
+//! * If every pattern node is connected to an anchored node by pattern edges (in any direction),
+//!   the pattern nodes are matched one by one, starting with the anchored nodes in ascending
+//!   node key order and then expanding breadth-first along the pattern edges, visiting the
+//!   neighbors of each pattern node in ascending node key order.
+//!   Each pattern node tries its candidate target nodes in ascending node key order, and the first
+//!   complete match is taken. For example, in a tree where a parent has several matching children,
+//!   `shape [child: int, parent -> child: "next"]` binds the child with the smallest node key.
+//! * Otherwise, the whole target graph is searched with VF2, on copies of both graphs whose nodes
+//!   are ordered by ascending node key.
+//!
+//! Complete matches that are rejected by the caller's `accept` check are skipped, and the search
+//! continues with the next match in this order.
+//!
+//! [`PatternLinks`] connect pattern nodes like pattern edges do for the purpose of the match order,
+//! with the link's candidates instead of the neighbors.
+
+use crate::NodeKey;
+use crate::graph::{EdgeAttribute, Graph};
+use petgraph::Direction;
+use petgraph::algo::general_subgraph_monomorphisms_iter;
+use petgraph::graphmap::DiGraphMap;
+use petgraph::visit::NodeIndexable;
+use std::collections::{HashMap, HashSet};
+
+/// Returns the first match of `pattern` in `target` according to the
+/// [match order](crate::operation::matching), as a mapping from pattern nodes to target nodes.
+///
+/// `anchors` maps pattern nodes to the target nodes they must be matched to. `node_match` and
+/// `edge_match` are called with the pattern's node key or edge attribute first, and must enforce
+/// the anchors as well. `accept` is called with every complete mapping, for constraints that
+/// involve several matched nodes at once.
+pub(crate) fn first_match<NodeAttr, EdgeAttr>(
+    pattern: &Graph<NodeAttr, EdgeAttr>,
+    target: &Graph<NodeAttr, EdgeAttr>,
+    anchors: &HashMap<NodeKey, NodeKey>,
+    node_match: impl FnMut(&NodeKey, &NodeKey) -> bool,
+    edge_match: impl FnMut(&EdgeAttribute<EdgeAttr>, &EdgeAttribute<EdgeAttr>) -> bool,
+    accept: impl FnMut(&HashMap<NodeKey, NodeKey>) -> bool,
+) -> Option<HashMap<NodeKey, NodeKey>> {
+    first_match_with_links(
+        pattern,
+        target,
+        anchors,
+        &PatternLinks::none(),
+        node_match,
+        edge_match,
+        accept,
+    )
+}
+
+/// Connections between pattern nodes other than pattern edges, e.g., paths in the target graph.
+///
+/// The local search expands along links like along pattern edges, but does not check them.
+/// That is up to the caller's `accept` check.
+pub(crate) struct PatternLinks<'a> {
+    /// The (source, target) pattern nodes of every link.
+    pub links: &'a [(NodeKey, NodeKey)],
+    /// Returns the target nodes that one end of the link with the given index may be matched to,
+    /// given the target node matched to the other end and the direction from that end.
+    pub candidates: &'a dyn Fn(usize, NodeKey, Direction) -> Vec<NodeKey>,
+}
+
+impl PatternLinks<'_> {
+    pub fn none() -> Self {
+        PatternLinks {
+            links: &[],
+            candidates: &|_, _, _| Vec::new(),
+        }
+    }
+}
+
+/// Like [`first_match`], but the local search additionally expands along the given links.
+pub(crate) fn first_match_with_links<NodeAttr, EdgeAttr>(
+    pattern: &Graph<NodeAttr, EdgeAttr>,
+    target: &Graph<NodeAttr, EdgeAttr>,
+    anchors: &HashMap<NodeKey, NodeKey>,
+    links: &PatternLinks,
+    mut node_match: impl FnMut(&NodeKey, &NodeKey) -> bool,
+    mut edge_match: impl FnMut(&EdgeAttribute<EdgeAttr>, &EdgeAttribute<EdgeAttr>) -> bool,
+    mut accept: impl FnMut(&HashMap<NodeKey, NodeKey>) -> bool,
+) -> Option<HashMap<NodeKey, NodeKey>> {
+    match local_matching_plan(pattern, anchors, links.links) {
+        Some(plan) => {
+            // anchored patterns only need to look at the neighbourhood of the anchors
+            let mut matcher = LocalMatcher {
+                pattern,
+                target,
+                links,
+                plan: &plan,
+                node_match: &mut node_match,
+                edge_match: &mut edge_match,
+                accept: &mut accept,
+                mapping: HashMap::new(),
+                used: HashSet::new(),
+            };
+            matcher.extend(0).then_some(matcher.mapping)
+        }
+        None => {
+            let pattern_ref = &sorted_by_key(pattern);
+            let target_ref = &sorted_by_key(target);
+            let mut em = |pattern_attr: &&EdgeAttribute<EdgeAttr>,
+                          target_attr: &&EdgeAttribute<EdgeAttr>| {
+                edge_match(pattern_attr, target_attr)
+            };
+            let mut isos = general_subgraph_monomorphisms_iter(
+                &pattern_ref,
+                &target_ref,
+                &mut node_match,
+                &mut em,
+            )?;
+            isos.find_map(|iso| {
+                let mapping = iso
+                    .iter()
+                    .enumerate()
+                    .map(|(pattern_idx, &target_idx)| {
+                        (
+                            pattern_ref.from_index(pattern_idx),
+                            target_ref.from_index(target_idx),
+                        )
+                    })
+                    .collect();
+                accept(&mapping).then_some(mapping)
+            })
+        }
+    }
+}
+
+/// A copy of the graph's structure whose nodes and edges are ordered by ascending node keys.
+fn sorted_by_key<NodeAttr, EdgeAttr>(
+    g: &Graph<NodeAttr, EdgeAttr>,
+) -> DiGraphMap<NodeKey, &EdgeAttribute<EdgeAttr>> {
+    let mut nodes = g.graph.nodes().collect::<Vec<_>>();
+    nodes.sort_unstable();
+    let mut edges = g.graph.all_edges().collect::<Vec<_>>();
+    edges.sort_unstable_by_key(|(src, dst, _)| (*src, *dst));
+
+    let mut sorted = DiGraphMap::with_capacity(nodes.len(), edges.len());
+    for node in nodes {
+        sorted.add_node(node);
+    }
+    for (src, dst, attr) in edges {
+        sorted.add_edge(src, dst, attr);
+    }
+    sorted
+}
+
+/// A pattern node, in the order it is matched by the [`LocalMatcher`].
+struct PlannedNode {
+    pattern_node: NodeKey,
+    candidates: Candidates,
+}
+
+/// Where the [`LocalMatcher`] looks for the target node matching a [`PlannedNode`].
+enum Candidates {
+    /// The node is anchored to the given target node.
+    Anchor(NodeKey),
+    /// The neighbors of the target node matched to the given earlier pattern node, in the
+    /// direction of the pattern edge from that node to this one.
+    Neighbors(NodeKey, Direction),
+    /// The candidates of the link with the given index, given the target node matched to the
+    /// given earlier pattern node, in the direction of the link from that node to this one.
+    Linked(usize, NodeKey, Direction),
+}
+
+/// Orders the pattern's nodes such that every node that is not anchored is connected to an
+/// earlier node by a pattern edge or a link.
+///
+/// Returns `None` if some node is not connected to any anchored node, i.e., if a disconnected
+/// part of the pattern would need to be searched for in the entire graph.
+fn local_matching_plan<NodeAttr, EdgeAttr>(
+    pattern: &Graph<NodeAttr, EdgeAttr>,
+    anchors: &HashMap<NodeKey, NodeKey>,
+    links: &[(NodeKey, NodeKey)],
+) -> Option<Vec<PlannedNode>> {
+    let graph = &pattern.graph;
+    let mut plan: Vec<PlannedNode> = graph
+        .nodes()
+        .filter_map(|pattern_node| {
+            let anchor = anchors.get(&pattern_node)?;
+            Some(PlannedNode {
+                pattern_node,
+                candidates: Candidates::Anchor(*anchor),
+            })
+        })
+        .collect();
+    plan.sort_unstable_by_key(|node| node.pattern_node);
+    let mut planned: HashSet<NodeKey> = plan.iter().map(|node| node.pattern_node).collect();
+
+    // breadth-first along the pattern edges and links, in both directions
+    let mut next = 0;
+    while next < plan.len() {
+        let from = plan[next].pattern_node;
+        next += 1;
+        let edge_neighbors = [Direction::Outgoing, Direction::Incoming]
+            .into_iter()
+            .flat_map(|direction| {
+                graph
+                    .neighbors_directed(from, direction)
+                    .map(move |neighbor| (neighbor, Candidates::Neighbors(from, direction)))
+            });
+        let link_neighbors = links
+            .iter()
+            .enumerate()
+            .filter_map(|(index, &(source, target))| {
+                if source == from {
+                    Some((target, Candidates::Linked(index, from, Direction::Outgoing)))
+                } else if target == from {
+                    Some((source, Candidates::Linked(index, from, Direction::Incoming)))
+                } else {
+                    None
+                }
+            });
+        let mut neighbors = edge_neighbors.chain(link_neighbors).collect::<Vec<_>>();
+        // stable, so pattern edges take precedence over links to the same neighbor
+        neighbors.sort_by_key(|(neighbor, _)| *neighbor);
+        for (neighbor, candidates) in neighbors {
+            if planned.insert(neighbor) {
+                plan.push(PlannedNode {
+                    pattern_node: neighbor,
+                    candidates,
+                });
+            }
+        }
+    }
+
+    (plan.len() == graph.node_count()).then_some(plan)
+}
+
+/// Backtracking subgraph monomorphism search that only expands along the pattern edges,
+/// starting from the anchored nodes.
+struct LocalMatcher<'a, NodeAttr, EdgeAttr, NM, EM, AC> {
+    pattern: &'a Graph<NodeAttr, EdgeAttr>,
+    target: &'a Graph<NodeAttr, EdgeAttr>,
+    links: &'a PatternLinks<'a>,
+    plan: &'a [PlannedNode],
+    node_match: &'a mut NM,
+    edge_match: &'a mut EM,
+    accept: &'a mut AC,
+    /// From pattern nodes to target nodes.
+    mapping: HashMap<NodeKey, NodeKey>,
+    used: HashSet<NodeKey>,
+}
+
+impl<NodeAttr, EdgeAttr, NM, EM, AC> LocalMatcher<'_, NodeAttr, EdgeAttr, NM, EM, AC>
+where
+    NM: FnMut(&NodeKey, &NodeKey) -> bool,
+    EM: FnMut(&EdgeAttribute<EdgeAttr>, &EdgeAttribute<EdgeAttr>) -> bool,
+    AC: FnMut(&HashMap<NodeKey, NodeKey>) -> bool,
+{
+    /// Tries to match the planned nodes from index `next` on, given the current mapping.
+    fn extend(&mut self, next: usize) -> bool {
+        let Some(planned) = self.plan.get(next) else {
+            return (self.accept)(&self.mapping);
+        };
+        let target = &self.target.graph;
+        let mut candidates: Vec<NodeKey> = match planned.candidates {
+            Candidates::Anchor(anchor) => target
+                .contains_node(anchor)
+                .then_some(anchor)
+                .into_iter()
+                .collect(),
+            Candidates::Neighbors(from, direction) => target
+                .neighbors_directed(self.mapping[&from], direction)
+                .collect(),
+            Candidates::Linked(index, from, direction) => {
+                (self.links.candidates)(index, self.mapping[&from], direction)
+            }
+        };
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        for candidate in candidates {
+            if self.used.contains(&candidate) || !self.is_feasible(planned.pattern_node, candidate)
+            {
+                continue;
+            }
+            self.mapping.insert(planned.pattern_node, candidate);
+            self.used.insert(candidate);
+            if self.extend(next + 1) {
+                return true;
+            }
+            self.mapping.remove(&planned.pattern_node);
+            self.used.remove(&candidate);
+        }
+        false
+    }
+
+    /// Checks the node attribute and all pattern edges between `pattern_node` and the already
+    /// matched nodes, including a self-loop.
+    fn is_feasible(&mut self, pattern_node: NodeKey, candidate: NodeKey) -> bool {
+        if !(self.node_match)(&pattern_node, &candidate) {
+            return false;
+        }
+        let pattern = &self.pattern.graph;
+        let target = &self.target.graph;
+        let mapped = |node: NodeKey| {
+            if node == pattern_node {
+                Some(candidate)
+            } else {
+                self.mapping.get(&node).copied()
+            }
+        };
+        let outgoing = pattern
+            .edges_directed(pattern_node, Direction::Outgoing)
+            .filter_map(|(_, dst, attr)| Some((candidate, mapped(dst)?, attr)));
+        let incoming = pattern
+            .edges_directed(pattern_node, Direction::Incoming)
+            .filter_map(|(src, _, attr)| Some((mapped(src)?, candidate, attr)));
+        let required_edges = outgoing.chain(incoming).collect::<Vec<_>>();
+        required_edges.into_iter().all(|(src, dst, pattern_attr)| {
+            target
+                .edge_weight(src, dst)
+                .is_some_and(|target_attr| (self.edge_match)(pattern_attr, target_attr))
+        })
+    }
+}
@@ -1,25 +1,35 @@
 pub mod builder;
 pub mod builtin;
+pub mod bytecode;
+pub mod debugger;
+pub mod execution;
+pub mod linker;
 pub mod marker;
+pub(crate) mod matching;
+pub mod optimizer;
 pub mod query;
+pub mod session;
 pub mod signature;
 pub mod trace;
 pub mod user_defined;
 
 use crate::graph::EdgeAttribute;
 use crate::operation::builtin::LibBuiltinOperation;
+use crate::operation::execution::{ExecutionContext, ExecutionLimit, ExecutionLimits};
 use crate::operation::marker::MarkerSet;
 use crate::operation::signature::parameter::ConcreteOperationOutput;
+use crate::operation::signature::{OperationSignature, SignatureMismatch};
 use crate::operation::trace::Trace;
 use crate::operation::user_defined::{
-    AbstractNodeId, AbstractOperationResultMarker, UserDefinedOperation,
+    AbstractNodeId, AbstractOperationArgument, AbstractOperationResultMarker, UserDefinedOperation,
 };
-use crate::semantics::{AbstractGraph, AbstractMatcher, ConcreteGraph, Semantics};
+use crate::semantics::{
+    AbstractGraph, AbstractMatcher, AbstractionCache, ConcreteGraph, Semantics,
+};
+use crate::util::bimap::BiMap;
 use crate::util::log;
 use crate::{Graph, NodeKey, SubstMarker};
 use error_stack::ResultExt;
-use petgraph::algo::general_subgraph_monomorphisms_iter;
-use petgraph::visit::NodeIndexable;
 use serde::{Deserialize, Serialize};
 use signature::parameter::{
     AbstractOperationOutput, AbstractOutputNodeMarker, GraphWithSubstitution, OperationArgument,
@@ -79,6 +89,12 @@ pub struct OperationContext<S: Semantics> {
     builtins: HashMap<OperationId, S::BuiltinOperation>,
     libbuiltins: HashMap<OperationId, LibBuiltinOperation<S>>,
     custom: HashMap<OperationId, UserDefinedOperation<S>>,
+    /// Names of operations, see [`OperationContext::register_name`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    names: BiMap<String, OperationId>,
+    /// Signatures of operations that are declared, but not defined, see [`OperationContext::declare_operation`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    declared: HashMap<OperationId, OperationSignature<S>>,
 }
 
 impl<S: Semantics> Default for OperationContext<S> {
@@ -93,6 +109,8 @@ impl<S: Semantics> OperationContext<S> {
             builtins: HashMap::new(),
             libbuiltins: HashMap::new(),
             custom: HashMap::new(),
+            names: BiMap::new(),
+            declared: HashMap::new(),
         }
     }
 
@@ -101,6 +119,8 @@ impl<S: Semantics> OperationContext<S> {
             builtins,
             libbuiltins: HashMap::new(),
             custom: HashMap::new(),
+            names: BiMap::new(),
+            declared: HashMap::new(),
         }
     }
 
@@ -116,6 +136,153 @@ impl<S: Semantics> OperationContext<S> {
         self.custom.insert(id, op);
     }
 
+    /// Returns the id of the operation named `name`, registering the name if necessary.
+    ///
+    /// A new name is registered with an id that is derived from the name itself, so that the id
+    /// does not depend on the order in which operations are added, nor on which other operations exist.
+    /// Only if that id is already taken, the next free id is used instead.
+    pub fn register_name(&mut self, name: impl Into<String>) -> OperationId {
+        let name = name.into();
+        if let Some(id) = self.names.get_left(&name) {
+            return *id;
+        }
+        let mut id = stable_operation_id(&name);
+        while self.is_id_taken(id) {
+            id = id.wrapping_add(1);
+        }
+        self.names.insert(name, id);
+        id
+    }
+
+    pub fn add_named_builtin_operation(
+        &mut self,
+        name: impl Into<String>,
+        op: S::BuiltinOperation,
+    ) -> OperationId {
+        let id = self.register_name(name);
+        self.add_builtin_operation(id, op);
+        id
+    }
+
+    pub fn add_named_lib_builtin_operation(
+        &mut self,
+        name: impl Into<String>,
+        op: LibBuiltinOperation<S>,
+    ) -> OperationId {
+        let id = self.register_name(name);
+        self.add_lib_builtin_operation(id, op);
+        id
+    }
+
+    pub fn add_named_custom_operation(
+        &mut self,
+        name: impl Into<String>,
+        op: UserDefinedOperation<S>,
+    ) -> OperationId {
+        let id = self.register_name(name);
+        self.add_custom_operation(id, op);
+        id
+    }
+
+    /// Declares an operation named `name` with the given signature, without defining it.
+    ///
+    /// Operations can be built against declared operations, e.g., against the operations of a library
+    /// that is compiled separately. Declarations are resolved by name when linking, see [`linker`].
+    pub fn declare_operation(
+        &mut self,
+        name: impl Into<String>,
+        signature: OperationSignature<S>,
+    ) -> OperationId {
+        let id = self.register_name(name);
+        self.declared.insert(id, signature);
+        id
+    }
+
+    /// Returns the signature of the operation if it is declared, but not defined.
+    pub fn declared_signature(&self, id: OperationId) -> Option<&OperationSignature<S>> {
+        if self.get(id).is_some() {
+            return None;
+        }
+        self.declared.get(&id)
+    }
+
+    pub fn id_of(&self, name: &str) -> Option<OperationId> {
+        self.names.get_left(&name.to_string()).copied()
+    }
+
+    pub fn name_of(&self, id: OperationId) -> Option<&str> {
+        self.names.get_right(&id).map(String::as_str)
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<Operation<'_, S>> {
+        self.get(self.id_of(name)?)
+    }
+
+    /// Returns all named operations, sorted by name.
+    ///
+    /// Names whose operation has not been added (yet) are skipped.
+    pub fn named_operations(&self) -> Vec<(&str, OperationId, Operation<'_, S>)> {
+        let mut named = self
+            .names
+            .iter()
+            .filter_map(|(name, id)| Some((name.as_str(), *id, self.get(*id)?)))
+            .collect::<Vec<_>>();
+        named.sort_by_key(|(name, _, _)| *name);
+        named
+    }
+
+    /// Replaces the user defined operation `id` with `new`, returning the previous definition.
+    ///
+    /// Every other user defined operation that calls `id` was built against the previous signature,
+    /// so the replacement is only accepted if its signature is a subtype of the previous one
+    /// (see [`OperationSignature::check_subtype_of`]), and if every stored argument of a caller
+    /// still binds the new parameter. Otherwise, the operation context is left unchanged and the
+    /// error lists every affected caller.
+    pub fn replace_custom_operation(
+        &mut self,
+        id: OperationId,
+        new: UserDefinedOperation<S>,
+    ) -> error_stack::Result<UserDefinedOperation<S>, HotSwapError> {
+        let Some(old) = self.custom.get(&id) else {
+            error_stack::bail!(HotSwapError::NotAUserDefinedOperation(id));
+        };
+        let signature_mismatch = new.signature.check_subtype_of(&old.signature).err();
+        let mut incompatible = self
+            .custom
+            .iter()
+            .filter(|(caller, _)| **caller != id)
+            .filter_map(|(caller, op)| {
+                let args = op.call_arguments(id);
+                if args.is_empty() {
+                    return None;
+                }
+                let mismatch = args
+                    .into_iter()
+                    .find_map(|arg| binds_parameter(arg, &new.signature.parameter).err())
+                    .or(signature_mismatch)?;
+                Some(IncompatibleCaller {
+                    caller: *caller,
+                    name: self.name_of(*caller).map(str::to_string),
+                    mismatch,
+                })
+            })
+            .collect::<Vec<_>>();
+        // without callers, nothing depends on the previous signature
+        if !incompatible.is_empty() {
+            incompatible.sort_by_key(|caller| caller.caller);
+            error_stack::bail!(HotSwapError::IncompatibleCallers(incompatible));
+        }
+        Ok(self.custom.insert(id, new).unwrap())
+    }
+
+    fn is_id_taken(&self, id: OperationId) -> bool {
+        self.names.contains_right(&id)
+            || self.builtins.contains_key(&id)
+            || self.libbuiltins.contains_key(&id)
+            || self.custom.contains_key(&id)
+            || self.declared.contains_key(&id)
+    }
+
     pub fn get(&self, id: OperationId) -> Option<Operation<S>> {
         if let Some(lib_builtin) = self.libbuiltins.get(&id) {
             return Some(Operation::LibBuiltin(lib_builtin));
@@ -136,10 +303,19 @@ impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for Opera
             builtins: self.builtins.clone(),
             libbuiltins: self.libbuiltins.clone(),
             custom: self.custom.clone(),
+            names: self.names.clone(),
+            declared: self.declared.clone(),
         }
     }
 }
 
+/// The FNV-1a hash of `name`, which is stable across platforms and compiler versions.
+fn stable_operation_id(name: &str) -> OperationId {
+    name.bytes().fold(0x811c9dc5, |hash: u32, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
 pub enum Operation<'a, S: Semantics> {
     Builtin(&'a S::BuiltinOperation),
     LibBuiltin(&'a LibBuiltinOperation<S>),
@@ -167,7 +343,16 @@ impl<'a, S: Semantics> Operation<'a, S> {
         }
     }
 
+    /// Returns the operation's signature, if it is statically known.
+    ///
+    /// Only user defined operations have a statically known signature.
     // TODO: support getting the signature from also a builtin operation?
+    pub fn signature(&self) -> Option<OperationSignature<S>> {
+        match self {
+            Operation::Builtin(_) | Operation::LibBuiltin(_) => None,
+            Operation::Custom(op) => Some(op.signature.clone()),
+        }
+    }
 }
 
 pub type OperationId = u32;
@@ -222,10 +407,7 @@ args: {selected_inputs:?}"
         })
         .collect::<HashMap<_, _>>();
 
-    let arg_ref = &g.graph;
-    let param_ref = &param.parameter_graph.graph;
-
-    let mut nm = |param_node: &NodeKey, arg_node: &NodeKey| {
+    let nm = |param_node: &NodeKey, arg_node: &NodeKey| {
         if let Some(expected_arg_node) = enforced_param_to_arg_node_key_mapping.get(param_node)
             && expected_arg_node != arg_node
         {
@@ -237,31 +419,33 @@ args: {selected_inputs:?}"
         S::NodeMatcher::matches(arg_attr, param_attr)
     };
 
-    let mut em = |param_attr_wrapper: &EdgeAttribute<S::EdgeAbstract>,
-                  arg_attr_wrapper: &EdgeAttribute<S::EdgeAbstract>| {
+    let em = |param_attr_wrapper: &EdgeAttribute<S::EdgeAbstract>,
+              arg_attr_wrapper: &EdgeAttribute<S::EdgeAbstract>| {
         let param_attr = &param_attr_wrapper.edge_attr;
         let arg_attr = &arg_attr_wrapper.edge_attr;
         S::EdgeMatcher::matches(arg_attr, param_attr)
     };
 
-    let isos = general_subgraph_monomorphisms_iter(&param_ref, &arg_ref, &mut nm, &mut em)
-        .ok_or_else(return_arg_does_not_match_error_with_dbg_info)?;
-
-    isos.map(|iso| {
-        iso.iter()
-            .enumerate()
-            .map(|(param_idx, &arg_idx)| {
-                let param_node_key = param_ref.from_index(param_idx);
-                let arg_node_key = arg_ref.from_index(arg_idx);
+    matching::first_match(
+        &param.parameter_graph,
+        g,
+        &enforced_param_to_arg_node_key_mapping,
+        nm,
+        em,
+        |_| true,
+    )
+    .map(|param_to_arg| {
+        param_to_arg
+            .into_iter()
+            .map(|(param_node_key, arg_node_key)| {
                 (
-                    // unwrap is ok since it was returned by param_ref.from_index
+                    // every parameter node has a substitution marker
                     *param.node_keys_to_subst.get_left(&param_node_key).unwrap(),
                     arg_node_key,
                 )
             })
             .collect::<HashMap<_, _>>()
     })
-    .next()
     .map(ParameterSubstitution::new)
     .ok_or_else(return_arg_does_not_match_error_with_dbg_info)
 }
@@ -275,7 +459,13 @@ pub fn run_operation<S: Semantics>(
     match op_ctx.get(op).expect("Invalid operation ID") {
         Operation::LibBuiltin(lib_builtin) => run_lib_builtin_operation::<S>(g, lib_builtin, arg),
         Operation::Builtin(builtin) => run_builtin_operation::<S>(g, builtin, arg),
-        Operation::Custom(custom) => run_custom_operation::<S>(g, op_ctx, custom, arg),
+        Operation::Custom(custom) => {
+            let execution = arg.execution;
+            execution.borrow_mut().enter_operation(op)?;
+            let output = run_custom_operation::<S>(g, op_ctx, op, custom, arg)?;
+            execution.borrow_mut().exit_operation();
+            Ok(output)
+        }
     }
 }
 
@@ -312,10 +502,11 @@ fn run_builtin_or_lib_builtin_operation<S: Semantics, BO: BuiltinOperation<S = S
 fn run_custom_operation<S: Semantics>(
     g: &mut Graph<S::NodeConcrete, S::EdgeConcrete>,
     op_ctx: &OperationContext<S>,
+    op_id: OperationId,
     op: &UserDefinedOperation<S>,
     arg: OperationArgument<S>,
 ) -> OperationResult<OperationOutput> {
-    let output = op.apply(op_ctx, g, arg)?;
+    let output = op.apply(op_ctx, op_id, g, arg)?;
 
     Ok(output)
 }
@@ -326,8 +517,142 @@ pub fn run_from_concrete<S: Semantics>(
     op: OperationId,
     selected_inputs: &[NodeKey],
 ) -> OperationResult<ConcreteOperationOutput<S>> {
-    // first get substitution
-    let abstract_g = S::concrete_to_abstract(g);
+    run_from_concrete_with_limits(g, op_ctx, op, selected_inputs, ExecutionLimits::unlimited())
+}
+
+/// Like [`run_from_concrete`], but aborts the run with [`OperationError::ExecutionLimitExceeded`]
+/// as soon as it exceeds one of the given `limits`.
+///
+/// Note that an aborted run may leave `g` partially modified,
+/// see [`run_from_concrete_transactional`] to avoid that.
+pub fn run_from_concrete_with_limits<S: Semantics>(
+    g: &mut ConcreteGraph<S>,
+    op_ctx: &OperationContext<S>,
+    op: OperationId,
+    selected_inputs: &[NodeKey],
+    limits: ExecutionLimits,
+) -> OperationResult<ConcreteOperationOutput<S>> {
+    let marker_set = RefCell::new(MarkerSet::new());
+    let trace = RefCell::new(Trace::new());
+    let output = run_from_concrete_with_marker_set(
+        g,
+        op_ctx,
+        op,
+        selected_inputs,
+        limits,
+        &marker_set,
+        &trace,
+        &HashSet::new(),
+    )?;
+    Ok(ConcreteOperationOutput {
+        output,
+        marker_set: marker_set.into_inner(),
+        trace: trace.into_inner(),
+    })
+}
+
+/// Like [`run_from_concrete_with_limits`], but starts from the given `marker_set` and leaves
+/// both `g` and `marker_set` exactly as they were if the run fails.
+///
+/// On success, `marker_set` contains the markers after the run, and the returned output contains
+/// a copy of them.
+///
+/// All modifications are recorded in an undo log, see [`Graph::begin_transaction`].
+/// Must not be called while `g` or `marker_set` are already in a transaction.
+pub fn run_from_concrete_transactional<S: Semantics>(
+    g: &mut ConcreteGraph<S>,
+    marker_set: &mut MarkerSet,
+    op_ctx: &OperationContext<S>,
+    op: OperationId,
+    selected_inputs: &[NodeKey],
+    limits: ExecutionLimits,
+) -> OperationResult<ConcreteOperationOutput<S>> {
+    g.begin_transaction();
+    let markers = RefCell::new(std::mem::take(marker_set));
+    markers.borrow_mut().begin_transaction();
+
+    let trace = RefCell::new(Trace::new());
+    let res = run_from_concrete_with_marker_set(
+        g,
+        op_ctx,
+        op,
+        selected_inputs,
+        limits,
+        &markers,
+        &trace,
+        &HashSet::new(),
+    );
+    *marker_set = markers.into_inner();
+    match res {
+        Ok(output) => {
+            g.commit_transaction();
+            marker_set.commit_transaction();
+            Ok(ConcreteOperationOutput {
+                output,
+                marker_set: marker_set.clone(),
+                trace: trace.into_inner(),
+            })
+        }
+        Err(err) => {
+            g.rollback_transaction();
+            marker_set.rollback_transaction();
+            Err(err)
+        }
+    }
+}
+
+/// Runs `op` with the given `marker_set`, collecting trace frames into `trace`.
+///
+/// Shape queries of the run cannot match the nodes in `hidden_nodes`.
+/// The trace is kept even if the run fails.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn run_from_concrete_with_marker_set<S: Semantics>(
+    g: &mut ConcreteGraph<S>,
+    op_ctx: &OperationContext<S>,
+    op: OperationId,
+    selected_inputs: &[NodeKey],
+    limits: ExecutionLimits,
+    marker_set: &RefCell<MarkerSet>,
+    trace: &RefCell<Trace<S>>,
+    hidden_nodes: &HashSet<NodeKey>,
+) -> OperationResult<OperationOutput> {
+    let abstraction = RefCell::new(AbstractionCache::new());
+    let res = (|| {
+        // first get substitution
+        let subst = concrete_substitution(
+            g,
+            &mut abstraction.borrow_mut(),
+            op_ctx,
+            op,
+            selected_inputs,
+        )?;
+        // then run the operation
+        let execution = RefCell::new(ExecutionContext::new(limits, g));
+        let arg = OperationArgument {
+            subst,
+            selected_input_nodes: selected_inputs.into(),
+            hidden_nodes: hidden_nodes.clone(),
+            marker_set,
+            trace,
+            execution: &execution,
+            abstraction: &abstraction,
+        };
+
+        run_operation(g, op_ctx, op, arg)
+    })();
+    abstraction.into_inner().clear(g);
+    res
+}
+
+/// Computes the substitution of `op`'s parameter for the selected concrete input nodes.
+pub(crate) fn concrete_substitution<S: Semantics>(
+    g: &mut ConcreteGraph<S>,
+    abstraction: &mut AbstractionCache<S>,
+    op_ctx: &OperationContext<S>,
+    op: OperationId,
+    selected_inputs: &[NodeKey],
+) -> OperationResult<ParameterSubstitution> {
+    let abstract_g = abstraction.abstract_graph(g);
 
     let subst = match op_ctx
         .get(op)
@@ -335,38 +660,21 @@ pub fn run_from_concrete<S: Semantics>(
     {
         Operation::LibBuiltin(lib_builtin) => {
             let param = lib_builtin.parameter();
-            get_substitution(&abstract_g, &param, selected_inputs)
+            get_substitution(abstract_g, &param, selected_inputs)
                 .change_context(OperationError::ArgumentDoesNotMatchParameter)?
         }
         Operation::Builtin(builtin) => {
             let param = builtin.parameter();
-            get_substitution(&abstract_g, &param, selected_inputs)
+            get_substitution(abstract_g, &param, selected_inputs)
                 .change_context(OperationError::ArgumentDoesNotMatchParameter)?
         }
         Operation::Custom(custom) => {
             let param = &custom.signature.parameter;
-            get_substitution(&abstract_g, param, selected_inputs)
+            get_substitution(abstract_g, param, selected_inputs)
                 .change_context(OperationError::ArgumentDoesNotMatchParameter)?
         }
     };
-    // then run the operation
-    let marker_set = RefCell::new(MarkerSet::new());
-    let trace = RefCell::new(Trace::new());
-    let arg = OperationArgument {
-        subst,
-        selected_input_nodes: selected_inputs.into(),
-        hidden_nodes: HashSet::new(),
-        marker_set: &marker_set,
-        trace: &trace,
-    };
-
-    let op_output = run_operation(g, op_ctx, op, arg)?;
-
-    Ok(ConcreteOperationOutput {
-        output: op_output,
-        marker_set: marker_set.into_inner(),
-        trace: trace.into_inner(),
-    })
+    Ok(subst)
 }
 
 pub type OperationResult<T> = error_stack::Result<T, OperationError>;
@@ -390,6 +698,70 @@ pub enum OperationError {
     UnknownAID(AbstractNodeId),
     #[error("user crash: {0}")]
     UserCrash(String),
+    #[error("execution limit exceeded in operation {operation}: {limit}")]
+    ExecutionLimitExceeded {
+        limit: ExecutionLimit,
+        operation: OperationId,
+    },
+    #[error("operation {0} is not a user defined operation")]
+    ExpectedUserDefinedOperation(OperationId),
+    #[error("query {0} returned without yielding a result")]
+    MissingQueryResult(OperationId),
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum HotSwapError {
+    #[error("operation {0} is not a user defined operation")]
+    NotAUserDefinedOperation(OperationId),
+    #[error("replacement is incompatible with {} caller(s): {}", .0.len(), display_callers(.0))]
+    IncompatibleCallers(Vec<IncompatibleCaller>),
+}
+
+/// A caller that would break if an operation was replaced, see [`OperationContext::replace_custom_operation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncompatibleCaller {
+    pub caller: OperationId,
+    pub name: Option<String>,
+    pub mismatch: SignatureMismatch,
+}
+
+/// Checks that a stored call argument provides a node for every node of `param`, and nothing else.
+fn binds_parameter<S: Semantics>(
+    arg: &AbstractOperationArgument,
+    param: &OperationParameter<S>,
+) -> Result<(), SignatureMismatch> {
+    if arg.selected_input_nodes.len() != param.explicit_input_nodes.len() {
+        return Err(SignatureMismatch::Parameter);
+    }
+    if param
+        .explicit_input_nodes
+        .iter()
+        .any(|marker| !arg.subst_to_aid.contains_key(marker))
+    {
+        return Err(SignatureMismatch::ParameterNames);
+    }
+    let context_nodes = param.context_nodes().collect::<HashSet<_>>();
+    let bound_context_nodes = arg
+        .subst_to_aid
+        .keys()
+        .filter(|marker| !param.explicit_input_nodes.contains(marker))
+        .copied()
+        .collect::<HashSet<_>>();
+    if context_nodes != bound_context_nodes {
+        return Err(SignatureMismatch::ContextNodes);
+    }
+    Ok(())
+}
+
+fn display_callers(callers: &[IncompatibleCaller]) -> String {
+    callers
+        .iter()
+        .map(|c| match &c.name {
+            Some(name) => format!("`{name}` ({})", c.mismatch),
+            None => format!("{} ({})", c.caller, c.mismatch),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 impl From<SubstitutionError> for OperationError {
@@ -0,0 +1,593 @@
+//! Semantics-preserving optimisations of the instructions of user defined operations.
+//!
+//! [`optimize`] runs on an [`OperationContext`] after all of its operations were built, and rewrites
+//! every user defined operation in place:
+//! * Calls to small, non-recursive user defined operations are inlined, see [`OptimizerOptions::max_inline_size`].
+//!   The callee's abstract node ids are renamed so that they cannot clash with the caller's.
+//! * Instructions after a [`Instruction::Diverge`] are removed, as well as instructions after
+//!   queries whose branches all diverge.
+//! * [`Instruction::RenameNode`] and [`Instruction::ForgetAid`] instructions that have no effect
+//!   are removed, and chains of them are merged.
+//! * Optionally, [`Instruction::Trace`] instructions are stripped, see [`OptimizerOptions::release`].
+//!
+//! Running an optimised operation changes the graph in exactly the same way as running the original one.
+//! Only the number of steps and the depth of the call stack may decrease,
+//! see [`ExecutionLimits`](crate::operation::execution::ExecutionLimits).
+//!
+//! # Example
+//! ```rust,ignore
+//! let mut op_ctx = build_program();
+//! let report = optimize(&mut op_ctx, &OptimizerOptions::release());
+//! run_from_concrete(&mut g, &op_ctx, op_ctx.id_of("main").unwrap(), &[input])?;
+//! ```
+
+use crate::Semantics;
+use crate::operation::signature::parameter::{
+    AbstractOutputNodeMarker, GraphWithSubstitution, ParameterSubstitution,
+};
+use crate::operation::user_defined::{
+    AbstractNodeId, AbstractOperationArgument, AbstractOperationResultMarker, Instruction,
+    InstructionWithResultMarker, OpLikeInstruction, UserDefinedOperation,
+};
+use crate::operation::{Operation, OperationContext, OperationId};
+use std::collections::HashSet;
+
+/// Which optimisations [`optimize`] runs.
+#[derive(Debug, Clone)]
+pub struct OptimizerOptions {
+    /// Calls to user defined operations with at most this many instructions are inlined.
+    ///
+    /// Only operations that are not recursive, are not queries, and consist of a straight line of
+    /// operation calls are inlined. `0` disables inlining.
+    pub max_inline_size: usize,
+    /// Whether to remove all [`Instruction::Trace`]s, which changes the recorded trace.
+    pub strip_traces: bool,
+}
+
+impl Default for OptimizerOptions {
+    fn default() -> Self {
+        OptimizerOptions {
+            max_inline_size: 8,
+            strip_traces: false,
+        }
+    }
+}
+
+impl OptimizerOptions {
+    /// The default options, but traces are stripped as well.
+    pub fn release() -> Self {
+        OptimizerOptions {
+            strip_traces: true,
+            ..Self::default()
+        }
+    }
+}
+
+/// What [`optimize`] changed, summed over all operations.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OptimizationReport {
+    pub inlined_calls: usize,
+    pub removed_renames: usize,
+    pub pruned_instructions: usize,
+    pub stripped_traces: usize,
+}
+
+/// Optimises every user defined operation of `op_ctx`, see the [module-level documentation](self).
+pub fn optimize<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>>(
+    op_ctx: &mut OperationContext<S>,
+    options: &OptimizerOptions,
+) -> OptimizationReport {
+    let mut report = OptimizationReport::default();
+    let mut ids = op_ctx.custom.keys().copied().collect::<Vec<_>>();
+    ids.sort();
+
+    for op in op_ctx.custom.values_mut() {
+        if options.strip_traces {
+            report.stripped_traces += strip_traces(&mut op.instructions);
+        }
+        // dead instructions could otherwise prevent inlining
+        report.pruned_instructions += prune_diverged(&mut op.instructions);
+    }
+
+    if options.max_inline_size > 0 {
+        // callees are always inlined with their original instructions
+        let original = op_ctx.clone();
+        let inlinable = ids
+            .iter()
+            .copied()
+            .filter(|id| is_inlinable(&original, *id, options.max_inline_size))
+            .collect::<HashSet<_>>();
+        for id in &ids {
+            let op = op_ctx.custom.get_mut(id).unwrap();
+            if forgets_parameter(&op.instructions) {
+                // forgotten parameters are no longer hidden from the operations called afterwards,
+                // which the inlined instructions could not distinguish from their own nodes.
+                continue;
+            }
+            let mut inliner = Inliner {
+                op_ctx: &original,
+                inlinable: &inlinable,
+                next_site: next_free_inline_site(&op.instructions),
+                inlined_calls: 0,
+            };
+            let instructions = std::mem::take(&mut op.instructions);
+            op.instructions = inliner.inline_block(instructions);
+            report.inlined_calls += inliner.inlined_calls;
+        }
+    }
+
+    for op in op_ctx.custom.values_mut() {
+        // inlined operations may diverge
+        report.pruned_instructions += prune_diverged(&mut op.instructions);
+        let outputs = op.output_changes.new_nodes.keys().copied().collect();
+        report.removed_renames += simplify_renames(&mut op.instructions, &outputs, true);
+    }
+
+    report
+}
+
+/// Calls `f` on every (nested) block of instructions, innermost first.
+fn for_each_block_mut<S: Semantics>(
+    instructions: &mut Vec<InstructionWithResultMarker<S>>,
+    f: &mut impl FnMut(&mut Vec<InstructionWithResultMarker<S>>),
+) {
+    for (_, instruction) in instructions.iter_mut() {
+        for block in nested_blocks_mut(instruction) {
+            for_each_block_mut(block, f);
+        }
+    }
+    f(instructions);
+}
+
+/// Returns the blocks of the query branches or shape match arms of `instruction`.
+fn nested_blocks<S: Semantics>(
+    instruction: &Instruction<S>,
+) -> Vec<&Vec<InstructionWithResultMarker<S>>> {
+    match instruction {
+        Instruction::QueryLike(_, _, query_instr) | Instruction::ShapeQuery(_, _, query_instr) => {
+            vec![&query_instr.taken, &query_instr.not_taken]
+        }
+        Instruction::ShapeMatch(arms, _, default) => arms
+            .iter()
+            .map(|arm| &arm.instructions)
+            .chain([default])
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn nested_blocks_mut<S: Semantics>(
+    instruction: &mut Instruction<S>,
+) -> Vec<&mut Vec<InstructionWithResultMarker<S>>> {
+    match instruction {
+        Instruction::QueryLike(_, _, query_instr) | Instruction::ShapeQuery(_, _, query_instr) => {
+            vec![&mut query_instr.taken, &mut query_instr.not_taken]
+        }
+        Instruction::ShapeMatch(arms, _, default) => arms
+            .iter_mut()
+            .map(|arm| &mut arm.instructions)
+            .chain([default])
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Removes all trace instructions, returning how many were removed.
+fn strip_traces<S: Semantics>(instructions: &mut Vec<InstructionWithResultMarker<S>>) -> usize {
+    let mut removed = 0;
+    for_each_block_mut(instructions, &mut |block| {
+        let len = block.len();
+        block.retain(|(_, instruction)| !matches!(instruction, Instruction::Trace));
+        removed += len - block.len();
+    });
+    removed
+}
+
+/// Returns true if every path through `instruction` diverges.
+fn always_diverges<S: Semantics>(instruction: &Instruction<S>) -> bool {
+    match instruction {
+        Instruction::Diverge { .. } => true,
+        Instruction::QueryLike(..) | Instruction::ShapeQuery(..) | Instruction::ShapeMatch(..) => {
+            nested_blocks(instruction)
+                .into_iter()
+                .all(|block| block.iter().any(|(_, instr)| always_diverges(instr)))
+        }
+        _ => false,
+    }
+}
+
+/// Removes the instructions that follow an instruction that always diverges, returning how many
+/// instructions were removed.
+fn prune_diverged<S: Semantics>(instructions: &mut Vec<InstructionWithResultMarker<S>>) -> usize {
+    let mut removed = 0;
+    for_each_block_mut(instructions, &mut |block| {
+        if let Some(index) = block
+            .iter()
+            .position(|(_, instruction)| always_diverges(instruction))
+        {
+            removed += block.len() - index - 1;
+            block.truncate(index + 1);
+        }
+    });
+    removed
+}
+
+/// Returns true if `instruction` may read or change the mapping of `aid`.
+///
+/// Traces record the entire mapping, so they mention every AID.
+fn mentions<S: Semantics>(
+    (marker, instruction): &InstructionWithResultMarker<S>,
+    aid: AbstractNodeId,
+) -> bool {
+    if let (Some(marker), AbstractNodeId::DynamicOutputMarker(output_marker, _)) = (marker, aid)
+        && *marker == output_marker
+    {
+        return true;
+    }
+    let in_arg = |arg: &AbstractOperationArgument| {
+        arg.selected_input_nodes.contains(&aid) || arg.subst_to_aid.values().any(|a| *a == aid)
+    };
+    let in_blocks = || {
+        nested_blocks(instruction)
+            .into_iter()
+            .flatten()
+            .any(|instr| mentions(instr, aid))
+    };
+    match instruction {
+        Instruction::OpLike(_, arg) => in_arg(arg),
+        Instruction::QueryLike(_, arg, _)
+        | Instruction::ShapeQuery(_, arg, _)
+        | Instruction::ShapeMatch(_, arg, _) => in_arg(arg) || in_blocks(),
+        Instruction::RenameNode { old, new } => *old == aid || *new == aid,
+        Instruction::ForgetAid { aid: forgotten } => *forgotten == aid,
+        Instruction::Trace => true,
+        Instruction::Diverge { .. } | Instruction::YieldQueryResult(_) => false,
+    }
+}
+
+/// Removes renames and forgets that have no effect, and merges a rename with a later rename or
+/// forget of the same node. Returns how many instructions were removed.
+///
+/// `outputs` are the AIDs that the operation returns. If `tail` is set, no instructions of the
+/// operation follow this block, so trailing renames and forgets of other nodes have no effect.
+fn simplify_renames<S: Semantics>(
+    instructions: &mut Vec<InstructionWithResultMarker<S>>,
+    outputs: &HashSet<AbstractNodeId>,
+    tail: bool,
+) -> usize {
+    let mut removed = 0;
+    let last = instructions.len().saturating_sub(1);
+    for (index, (_, instruction)) in instructions.iter_mut().enumerate() {
+        for block in nested_blocks_mut(instruction) {
+            removed += simplify_renames(block, outputs, tail && index == last);
+        }
+    }
+
+    let len = instructions.len();
+    // renaming a node to itself has no effect
+    instructions.retain(
+        |(_, instruction)| !matches!(instruction, Instruction::RenameNode { old, new } if old == new),
+    );
+    while let Some((first, second, merged)) = find_rename_chain(instructions) {
+        instructions[second].1 = merged;
+        instructions.remove(first);
+    }
+    if tail {
+        // the mapping is discarded once the operation returns, except for the output nodes
+        while let Some((_, instruction)) = instructions.last() {
+            let has_effect = match instruction {
+                Instruction::RenameNode { old, new } => {
+                    outputs.contains(old) || outputs.contains(new)
+                }
+                Instruction::ForgetAid { aid } => outputs.contains(aid),
+                _ => true,
+            };
+            if has_effect {
+                break;
+            }
+            instructions.pop();
+        }
+    }
+    removed + len - instructions.len()
+}
+
+/// Finds a rename of `a` to `b` followed by a rename of `b` to `c` or a forget of `b`, such that
+/// no instruction in between mentions `a`, `b`, or `c`.
+///
+/// Returns the indices of both instructions and the instruction that replaces the second one.
+/// The node keeps its name `a` until the second instruction, so the mapped nodes stay the same.
+fn find_rename_chain<S: Semantics>(
+    instructions: &[InstructionWithResultMarker<S>],
+) -> Option<(usize, usize, Instruction<S>)> {
+    for (first, (_, instruction)) in instructions.iter().enumerate() {
+        let Instruction::RenameNode { old: a, new: b } = *instruction else {
+            continue;
+        };
+        let Some((offset, (marker, next))) = instructions[first + 1..]
+            .iter()
+            .enumerate()
+            .find(|(_, instr)| mentions(instr, a) || mentions(instr, b))
+        else {
+            continue;
+        };
+        let second = first + 1 + offset;
+        let between = &instructions[first + 1..second];
+        let merged = match *next {
+            Instruction::RenameNode { old, new: c }
+                if marker.is_none()
+                    && old == b
+                    && !between.iter().any(|instr| mentions(instr, c)) =>
+            {
+                Instruction::RenameNode { old: a, new: c }
+            }
+            // parameters are additionally un-hidden when forgotten, see `ForgetAid`'s runner
+            Instruction::ForgetAid { aid }
+                if marker.is_none()
+                    && aid == b
+                    && !matches!(a, AbstractNodeId::ParameterMarker(_)) =>
+            {
+                Instruction::ForgetAid { aid: a }
+            }
+            _ => continue,
+        };
+        return Some((first, second, merged));
+    }
+    None
+}
+
+/// Returns true if any (nested) instruction forgets a parameter node.
+fn forgets_parameter<S: Semantics>(instructions: &[InstructionWithResultMarker<S>]) -> bool {
+    instructions.iter().any(|(_, instruction)| {
+        matches!(
+            instruction,
+            Instruction::ForgetAid {
+                aid: AbstractNodeId::ParameterMarker(_)
+            }
+        ) || nested_blocks(instruction)
+            .into_iter()
+            .any(|block| forgets_parameter(block))
+    })
+}
+
+/// Returns true if calls to the user defined operation `id` can be inlined.
+fn is_inlinable<S: Semantics>(
+    op_ctx: &OperationContext<S>,
+    id: OperationId,
+    max_size: usize,
+) -> bool {
+    let Some(op) = op_ctx.custom.get(&id) else {
+        return false;
+    };
+    if op.signature.is_query || op.instructions.len() > max_size || is_recursive(op_ctx, id) {
+        return false;
+    }
+    let straight_line = op
+        .instructions
+        .iter()
+        .all(|(marker, instruction)| match instruction {
+            // the created nodes must be known to forget them after the inlined instructions
+            Instruction::OpLike(op_like, _) => {
+                marker.is_none() || output_markers(op_ctx, op_like).is_some()
+            }
+            Instruction::RenameNode { .. } | Instruction::Diverge { .. } => true,
+            Instruction::ForgetAid { aid } => !matches!(aid, AbstractNodeId::ParameterMarker(_)),
+            // traces record the callee's mapping, which inlining would change
+            _ => false,
+        });
+    let params_are_kept = op
+        .output_changes
+        .new_nodes
+        .keys()
+        .all(|aid| !matches!(aid, AbstractNodeId::ParameterMarker(_)));
+    straight_line && params_are_kept
+}
+
+/// Returns true if the user defined operation `id` may (indirectly) call itself.
+fn is_recursive<S: Semantics>(op_ctx: &OperationContext<S>, id: OperationId) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack = vec![id];
+    while let Some(current) = stack.pop() {
+        let Some(op) = op_ctx.custom.get(&current) else {
+            continue;
+        };
+        for callee in op.referenced_operations() {
+            if callee == id {
+                return true;
+            }
+            if visited.insert(callee) {
+                stack.push(callee);
+            }
+        }
+    }
+    false
+}
+
+/// Returns the markers of the nodes that the operation returns, if they are statically known.
+fn output_markers<S: Semantics>(
+    op_ctx: &OperationContext<S>,
+    op_like: &OpLikeInstruction<S>,
+) -> Option<Vec<AbstractOutputNodeMarker>> {
+    let op = match op_like {
+        OpLikeInstruction::Builtin(op) => Operation::Builtin(op),
+        OpLikeInstruction::LibBuiltin(op) => Operation::LibBuiltin(op),
+        OpLikeInstruction::Operation(id) => op_ctx.get(*id)?,
+    };
+    if let Operation::Custom(op) = op {
+        return Some(op.output_changes.new_nodes.values().copied().collect());
+    }
+    // builtins return the nodes they create when applied to their own parameter graph
+    let param = op.parameter();
+    let mut graph = param.parameter_graph.clone();
+    let subst = ParameterSubstitution::new(
+        param
+            .node_keys_to_subst
+            .iter()
+            .map(|(key, marker)| (*marker, *key))
+            .collect(),
+    );
+    let mut gws = GraphWithSubstitution::new(&mut graph, &subst);
+    let output = op.apply_abstract(op_ctx, &mut gws).ok()?;
+    Some(output.new_nodes.into_keys().collect())
+}
+
+/// Inlined nodes and markers are named `<callee's name>@inline<call site>`.
+const INLINE_SUFFIX: &str = "@inline";
+
+/// Returns a call site number that was not used by a previous optimisation of the instructions.
+fn next_free_inline_site<S: Semantics>(instructions: &[InstructionWithResultMarker<S>]) -> usize {
+    let mut next = 0;
+    for (marker, instruction) in instructions {
+        // inlined names are introduced by result markers and renames
+        let name = match (marker, instruction) {
+            (Some(AbstractOperationResultMarker::Custom(name)), _) => Some(&**name),
+            (
+                _,
+                Instruction::RenameNode {
+                    new: AbstractNodeId::Named(name),
+                    ..
+                },
+            ) => Some(&*name.0),
+            _ => None,
+        };
+        if let Some((_, site)) = name.and_then(|name| name.rsplit_once(INLINE_SUFFIX))
+            && let Ok(site) = site.parse::<usize>()
+        {
+            next = next.max(site + 1);
+        }
+        for block in nested_blocks(instruction) {
+            next = next.max(next_free_inline_site(block));
+        }
+    }
+    next
+}
+
+struct Inliner<'a, S: Semantics> {
+    op_ctx: &'a OperationContext<S>,
+    inlinable: &'a HashSet<OperationId>,
+    /// The number of the next inlined call site, used to give the inlined nodes unique names.
+    next_site: usize,
+    inlined_calls: usize,
+}
+
+impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Inliner<'_, S> {
+    fn inline_block(
+        &mut self,
+        instructions: Vec<InstructionWithResultMarker<S>>,
+    ) -> Vec<InstructionWithResultMarker<S>> {
+        let mut result = Vec::with_capacity(instructions.len());
+        for (marker, mut instruction) in instructions {
+            if let Instruction::OpLike(OpLikeInstruction::Operation(id), arg) = &instruction
+                && self.inlinable.contains(id)
+                && let Some(Operation::Custom(callee)) = self.op_ctx.get(*id)
+                && let Some(inlined) = self.inline_call(callee, arg, marker)
+            {
+                self.inlined_calls += 1;
+                // the callee may call other inlinable operations
+                result.extend(self.inline_block(inlined));
+                continue;
+            }
+            for block in nested_blocks_mut(&mut instruction) {
+                *block = self.inline_block(std::mem::take(block));
+            }
+            result.push((marker, instruction));
+        }
+        result
+    }
+
+    /// Returns the instructions of `callee`, renamed to run in the caller's mapping.
+    ///
+    /// Afterwards, the callee's output nodes are available under `result_marker` like after
+    /// the call, and all other nodes of the callee are forgotten.
+    fn inline_call(
+        &mut self,
+        callee: &UserDefinedOperation<S>,
+        arg: &AbstractOperationArgument,
+        result_marker: Option<AbstractOperationResultMarker>,
+    ) -> Option<Vec<InstructionWithResultMarker<S>>> {
+        let suffix = format!("{INLINE_SUFFIX}{}", self.next_site);
+        let rename_marker = |marker: AbstractOperationResultMarker| {
+            let name = match marker {
+                AbstractOperationResultMarker::Custom(name) => name.to_string(),
+                AbstractOperationResultMarker::Implicit(index) => index.to_string(),
+            };
+            AbstractOperationResultMarker::from(format!("{name}{suffix}"))
+        };
+        let rename = |aid: AbstractNodeId| match aid {
+            AbstractNodeId::ParameterMarker(subst) => arg.subst_to_aid.get(&subst).copied(),
+            AbstractNodeId::DynamicOutputMarker(marker, output) => Some(
+                AbstractNodeId::DynamicOutputMarker(rename_marker(marker), output),
+            ),
+            AbstractNodeId::Named(name) => {
+                Some(AbstractNodeId::named(format!("{}{suffix}", name.0)))
+            }
+        };
+        let rename_arg = |arg: &AbstractOperationArgument| {
+            Some(AbstractOperationArgument {
+                selected_input_nodes: arg
+                    .selected_input_nodes
+                    .iter()
+                    .map(|aid| rename(*aid))
+                    .collect::<Option<_>>()?,
+                subst_to_aid: arg
+                    .subst_to_aid
+                    .iter()
+                    .map(|(subst, aid)| Some((*subst, rename(*aid)?)))
+                    .collect::<Option<_>>()?,
+            })
+        };
+
+        // the callee's nodes that are mapped at the current instruction
+        let mut locals = Vec::new();
+        let mut instructions = Vec::with_capacity(callee.instructions.len());
+        for (marker, instruction) in &callee.instructions {
+            let marker = marker.map(rename_marker);
+            let instruction = match instruction {
+                Instruction::OpLike(op_like, arg) => {
+                    if let Some(marker) = marker {
+                        for output in output_markers(self.op_ctx, op_like)? {
+                            let aid = AbstractNodeId::DynamicOutputMarker(marker, output);
+                            if !locals.contains(&aid) {
+                                locals.push(aid);
+                            }
+                        }
+                    }
+                    Instruction::OpLike(op_like.clone(), rename_arg(arg)?)
+                }
+                Instruction::RenameNode { old, new } => {
+                    let (old, new) = (rename(*old)?, rename(*new)?);
+                    locals.retain(|aid| *aid != old && *aid != new);
+                    locals.push(new);
+                    Instruction::RenameNode { old, new }
+                }
+                Instruction::ForgetAid { aid } => {
+                    let aid = rename(*aid)?;
+                    locals.retain(|local| *local != aid);
+                    Instruction::ForgetAid { aid }
+                }
+                instruction => instruction.clone(),
+            };
+            instructions.push((marker, instruction));
+        }
+
+        let mut outputs = callee.output_changes.new_nodes.iter().collect::<Vec<_>>();
+        outputs.sort_by_key(|(_, output)| &*output.0);
+        for (aid, output) in outputs {
+            let aid = rename(*aid)?;
+            locals.retain(|local| *local != aid);
+            let instruction = match result_marker {
+                Some(marker) => Instruction::RenameNode {
+                    old: aid,
+                    new: AbstractNodeId::DynamicOutputMarker(marker, *output),
+                },
+                // like the runner, unnamed calls do not keep the returned nodes
+                None => Instruction::ForgetAid { aid },
+            };
+            instructions.push((None, instruction));
+        }
+        for aid in locals {
+            instructions.push((None, Instruction::ForgetAid { aid }));
+        }
+        self.next_site += 1;
+        Some(instructions)
+    }
+}
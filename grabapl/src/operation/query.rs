@@ -1,19 +1,21 @@
 use crate::graph::EdgeAttribute;
-use crate::operation::OperationResult;
-use crate::operation::marker::{MarkerSet, SkipMarkers};
+use crate::operation::marker::{Marker, MarkerSet, SkipMarkers};
+use crate::operation::matching::PatternLinks;
 use crate::operation::signature::parameter::{
     GraphWithSubstitution, OperationArgument, OperationParameter, ParameterSubstitution,
 };
-use crate::semantics::{AbstractGraph, AbstractMatcher, ConcreteGraph, Semantics};
+use crate::operation::{OperationResult, matching};
+use crate::semantics::{
+    AbstractGraph, AbstractMatcher, AbstractionCache, ConcreteGraph, Semantics,
+};
 use crate::util::bimap::BiMap;
 use crate::util::{InternString, log};
-use crate::{NodeKey, interned_string_newtype};
+use crate::{NodeKey, SubstMarker, interned_string_newtype};
 use derive_more::From;
 use derive_more::with_trait::Into;
-use petgraph::algo::general_subgraph_monomorphisms_iter;
-use petgraph::visit::NodeIndexable;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::num::NonZeroI32;
 
 pub trait BuiltinQuery {
     type S: Semantics;
@@ -26,11 +28,68 @@ pub trait BuiltinQuery {
 
     fn apply_abstract(&self, g: &mut GraphWithSubstitution<AbstractGraph<Self::S>>);
 
+    /// Returns the refined types of the parameter that hold in the branch taken if the query matches.
+    ///
+    /// This is called after [`BuiltinQuery::apply_abstract`]. Refined types must be subtypes of the
+    /// types in `g`, and only hold inside the branch. Defaults to no refinement.
+    fn apply_abstract_taken(
+        &self,
+        _g: &GraphWithSubstitution<AbstractGraph<Self::S>>,
+    ) -> QueryRefinement<Self::S> {
+        QueryRefinement::new()
+    }
+
+    /// Like [`BuiltinQuery::apply_abstract_taken`], but for the branch taken if the query does not match.
+    fn apply_abstract_not_taken(
+        &self,
+        _g: &GraphWithSubstitution<AbstractGraph<Self::S>>,
+    ) -> QueryRefinement<Self::S> {
+        QueryRefinement::new()
+    }
+
     // TODO: if we decide to actually support modification, we need to include an OperationOutput so that we can support new nodes and can keep track of
     //  changes of av's.
     fn query(&self, g: &mut GraphWithSubstitution<ConcreteGraph<Self::S>>) -> ConcreteQueryOutput;
 }
 
+/// Refined types of a builtin query's parameter nodes and edges in one of the query's branches.
+///
+/// See [`BuiltinQuery::apply_abstract_taken`].
+pub struct QueryRefinement<S: Semantics> {
+    pub nodes: HashMap<SubstMarker, S::NodeAbstract>,
+    pub edges: HashMap<(SubstMarker, SubstMarker), S::EdgeAbstract>,
+}
+
+impl<S: Semantics> QueryRefinement<S> {
+    pub fn new() -> Self {
+        QueryRefinement {
+            nodes: HashMap::new(),
+            edges: HashMap::new(),
+        }
+    }
+
+    pub fn refine_node(mut self, marker: impl Into<SubstMarker>, av: S::NodeAbstract) -> Self {
+        self.nodes.insert(marker.into(), av);
+        self
+    }
+
+    pub fn refine_edge(
+        mut self,
+        src: impl Into<SubstMarker>,
+        dst: impl Into<SubstMarker>,
+        av: S::EdgeAbstract,
+    ) -> Self {
+        self.edges.insert((src.into(), dst.into()), av);
+        self
+    }
+}
+
+impl<S: Semantics> Default for QueryRefinement<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct ConcreteQueryOutput {
     pub taken: bool,
 }
@@ -40,6 +99,297 @@ pub struct ConcreteQueryOutput {
 pub struct ShapeNodeIdentifier(pub InternString);
 interned_string_newtype!(ShapeNodeIdentifier);
 
+/// The position that a shape query edge must have among the outgoing edges of its source node.
+///
+/// Positions refer to the order of the outgoing edges in the concrete graph, and count all
+/// outgoing edges of the source node, including those that are not part of the shape query.
+///
+/// `Sibling` identifies another outgoing edge of the same source node by its target node.
+/// The builder takes an [`AbstractNodeId`](crate::operation::user_defined::AbstractNodeId),
+/// while a built [`GraphShapeQuery`] refers to the node keys of its expected graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ShapeEdgeOrder<Sibling> {
+    /// The edge is the `n`-th outgoing edge, where `FromStart(0)` is the first one.
+    FromStart(usize),
+    /// The edge is the `n`-th outgoing edge counted backwards, where `FromEnd(0)` is the last one.
+    FromEnd(usize),
+    /// The edge comes exactly `offset` positions after the edge to `sibling`, or before it if
+    /// `offset` is negative. Positions do not wrap around.
+    ///
+    /// The edge to `sibling` must be part of the shape query.
+    Relative {
+        sibling: Sibling,
+        offset: NonZeroI32,
+    },
+}
+
+impl<Sibling> ShapeEdgeOrder<Sibling> {
+    /// The first outgoing edge.
+    pub fn first() -> Self {
+        ShapeEdgeOrder::FromStart(0)
+    }
+
+    /// The last outgoing edge.
+    pub fn last() -> Self {
+        ShapeEdgeOrder::FromEnd(0)
+    }
+
+    /// The outgoing edge right after the edge to `sibling`.
+    pub fn after(sibling: Sibling) -> Self {
+        ShapeEdgeOrder::Relative {
+            sibling,
+            offset: NonZeroI32::new(1).unwrap(),
+        }
+    }
+
+    /// The outgoing edge right before the edge to `sibling`.
+    pub fn before(sibling: Sibling) -> Self {
+        ShapeEdgeOrder::Relative {
+            sibling,
+            offset: NonZeroI32::new(-1).unwrap(),
+        }
+    }
+
+    pub fn map_sibling<NewSibling, E>(
+        self,
+        f: impl FnOnce(Sibling) -> Result<NewSibling, E>,
+    ) -> Result<ShapeEdgeOrder<NewSibling>, E> {
+        Ok(match self {
+            ShapeEdgeOrder::FromStart(n) => ShapeEdgeOrder::FromStart(n),
+            ShapeEdgeOrder::FromEnd(n) => ShapeEdgeOrder::FromEnd(n),
+            ShapeEdgeOrder::Relative { sibling, offset } => ShapeEdgeOrder::Relative {
+                sibling: f(sibling)?,
+                offset,
+            },
+        })
+    }
+}
+
+/// A node of a [`NegativeShapeCondition`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NegativeShapeNode<Existing> {
+    /// A node of the shape query, i.e., an input node or a shape node.
+    Existing(Existing),
+    /// The forbidden node with the given index in [`NegativeShapeCondition::forbidden_nodes`].
+    Forbidden(usize),
+}
+
+impl<Existing> NegativeShapeNode<Existing> {
+    pub fn map_existing<NewExisting, E>(
+        self,
+        f: impl FnOnce(Existing) -> Result<NewExisting, E>,
+    ) -> Result<NegativeShapeNode<NewExisting>, E> {
+        Ok(match self {
+            NegativeShapeNode::Existing(existing) => NegativeShapeNode::Existing(f(existing)?),
+            NegativeShapeNode::Forbidden(index) => NegativeShapeNode::Forbidden(index),
+        })
+    }
+}
+
+/// A pattern that must not be present around the nodes matched by a shape query, i.e., a
+/// negative application condition.
+///
+/// The shape query does not match if the forbidden nodes can be mapped to distinct nodes of the
+/// concrete graph such that all edges of the pattern are present. Forbidden nodes may be mapped to
+/// nodes that are matched by the shape query as well. Unlike the rest of the shape query, the
+/// condition also sees hidden nodes and nodes with skipped markers, since they are still present
+/// in the concrete graph.
+///
+/// Like with [`ShapeEdgeOrder`], the builder refers to the nodes of the shape query with
+/// [`AbstractNodeId`](crate::operation::user_defined::AbstractNodeId)s, while a built
+/// [`GraphShapeQuery`] refers to the node keys of its expected graph.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound = "S: crate::serde::SemanticsSerde, Existing: Serialize + serde::de::DeserializeOwned"
+    )
+)]
+pub struct NegativeShapeCondition<S: Semantics, Existing> {
+    /// The abstract values of the forbidden nodes. `None` matches any node.
+    pub forbidden_nodes: Vec<Option<S::NodeAbstract>>,
+    pub edges: Vec<(
+        NegativeShapeNode<Existing>,
+        NegativeShapeNode<Existing>,
+        S::EdgeAbstract,
+    )>,
+}
+
+impl<S: Semantics, Existing: Clone> Clone for NegativeShapeCondition<S, Existing> {
+    fn clone(&self) -> Self {
+        NegativeShapeCondition {
+            forbidden_nodes: self.forbidden_nodes.clone(),
+            edges: self.edges.clone(),
+        }
+    }
+}
+
+impl<S: Semantics, Existing> NegativeShapeCondition<S, Existing> {
+    /// An empty pattern, which is always present.
+    pub fn new() -> Self {
+        NegativeShapeCondition {
+            forbidden_nodes: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    /// There must be no edge from `source` to `target` that matches `edge`.
+    pub fn edge(source: Existing, target: Existing, edge: S::EdgeAbstract) -> Self {
+        let mut condition = Self::new();
+        condition.add_edge(
+            NegativeShapeNode::Existing(source),
+            NegativeShapeNode::Existing(target),
+            edge,
+        );
+        condition
+    }
+
+    /// There must be no outgoing edge from `source` that matches `edge` and leads to a node that
+    /// matches `node`, or to any node if `node` is `None`.
+    pub fn out_neighbour(
+        source: Existing,
+        node: Option<S::NodeAbstract>,
+        edge: S::EdgeAbstract,
+    ) -> Self {
+        let mut condition = Self::new();
+        let neighbour = condition.add_forbidden_node(node);
+        condition.add_edge(NegativeShapeNode::Existing(source), neighbour, edge);
+        condition
+    }
+
+    /// There must be no incoming edge to `target` that matches `edge` and comes from a node that
+    /// matches `node`, or from any node if `node` is `None`.
+    pub fn in_neighbour(
+        target: Existing,
+        node: Option<S::NodeAbstract>,
+        edge: S::EdgeAbstract,
+    ) -> Self {
+        let mut condition = Self::new();
+        let neighbour = condition.add_forbidden_node(node);
+        condition.add_edge(neighbour, NegativeShapeNode::Existing(target), edge);
+        condition
+    }
+
+    /// Adds a forbidden node to the pattern and returns it.
+    pub fn add_forbidden_node(
+        &mut self,
+        node: Option<S::NodeAbstract>,
+    ) -> NegativeShapeNode<Existing> {
+        self.forbidden_nodes.push(node);
+        NegativeShapeNode::Forbidden(self.forbidden_nodes.len() - 1)
+    }
+
+    pub fn add_edge(
+        &mut self,
+        source: NegativeShapeNode<Existing>,
+        target: NegativeShapeNode<Existing>,
+        edge: S::EdgeAbstract,
+    ) {
+        self.edges.push((source, target, edge));
+    }
+
+    pub fn map_existing<NewExisting, E>(
+        self,
+        mut f: impl FnMut(Existing) -> Result<NewExisting, E>,
+    ) -> Result<NegativeShapeCondition<S, NewExisting>, E> {
+        let edges = self
+            .edges
+            .into_iter()
+            .map(|(source, target, edge)| {
+                Ok((
+                    source.map_existing(&mut f)?,
+                    target.map_existing(&mut f)?,
+                    edge,
+                ))
+            })
+            .collect::<Result<_, E>>()?;
+        Ok(NegativeShapeCondition {
+            forbidden_nodes: self.forbidden_nodes,
+            edges,
+        })
+    }
+}
+
+impl<S: Semantics, Existing> Default for NegativeShapeCondition<S, Existing> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A path of one or more edges from `source` to `target` that a shape query expects, i.e.,
+/// `target` must be reachable from `source`.
+///
+/// Every edge of the path must match `edge`. The nodes in between may be any nodes except hidden
+/// nodes and nodes with skipped markers. If there are several paths, a shortest one is taken.
+/// If `source` and `target` are the same node, the path is a cycle through that node.
+///
+/// Only `source` and `target` are bound by the shape query. The number of nodes in between is not
+/// statically known, so the path itself is not bound as a list of nodes in the abstract graph,
+/// and the query's branches cannot refer to its nodes directly. To use a node in between, split
+/// the path at that node, e.g., `a ->* n` followed by `n -> b` binds the node `n` before `b`.
+/// The matched path is only available at runtime, as an ordered list of nodes in
+/// [`ConcreteShapeQueryResult::paths`], and programs can mark its nodes with `marker`.
+///
+/// Like with [`ShapeEdgeOrder`], the builder refers to the nodes of the shape query with
+/// [`AbstractNodeId`](crate::operation::user_defined::AbstractNodeId)s, while a built
+/// [`GraphShapeQuery`] refers to the node keys of its expected graph.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound = "S: crate::serde::SemanticsSerde, Node: Serialize + serde::de::DeserializeOwned"
+    )
+)]
+pub struct ShapePath<S: Semantics, Node> {
+    pub source: Node,
+    pub target: Node,
+    pub edge: S::EdgeAbstract,
+    /// If set, all nodes of the path, including `source` and `target`, are marked with this
+    /// marker when the shape query matches.
+    pub marker: Option<Marker>,
+}
+
+impl<S: Semantics, Node: Clone> Clone for ShapePath<S, Node> {
+    fn clone(&self) -> Self {
+        ShapePath {
+            source: self.source.clone(),
+            target: self.target.clone(),
+            edge: self.edge.clone(),
+            marker: self.marker,
+        }
+    }
+}
+
+impl<S: Semantics, Node> ShapePath<S, Node> {
+    pub fn new(source: Node, target: Node, edge: S::EdgeAbstract) -> Self {
+        ShapePath {
+            source,
+            target,
+            edge,
+            marker: None,
+        }
+    }
+
+    pub fn with_marker(mut self, marker: impl Into<Marker>) -> Self {
+        self.marker = Some(marker.into());
+        self
+    }
+
+    pub fn map_nodes<NewNode, E>(
+        self,
+        mut f: impl FnMut(Node) -> Result<NewNode, E>,
+    ) -> Result<ShapePath<S, NewNode>, E> {
+        Ok(ShapePath {
+            source: f(self.source)?,
+            target: f(self.target)?,
+            edge: self.edge,
+            marker: self.marker,
+        })
+    }
+}
+
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
@@ -58,6 +408,16 @@ pub struct GraphShapeQuery<S: Semantics> {
     pub node_keys_to_shape_idents: BiMap<NodeKey, ShapeNodeIdentifier>,
     // nodes marked with which markers should be skipped in the shape query
     pub skip_markers: SkipMarkers,
+    // (source, target, order) of expected_graph edges whose position among the source's outgoing
+    // edges is constrained
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub edge_orders: Vec<(NodeKey, NodeKey, ShapeEdgeOrder<NodeKey>)>,
+    // patterns that must not be present around the matched nodes, in terms of expected_graph keys
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub negative_conditions: Vec<NegativeShapeCondition<S, NodeKey>>,
+    // paths between nodes of the expected graph, in terms of expected_graph keys
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub paths: Vec<ShapePath<S, NodeKey>>,
 }
 
 impl<S: Semantics> Clone for GraphShapeQuery<S> {
@@ -67,6 +427,9 @@ impl<S: Semantics> Clone for GraphShapeQuery<S> {
             expected_graph: self.expected_graph.clone(),
             node_keys_to_shape_idents: self.node_keys_to_shape_idents.clone(),
             skip_markers: self.skip_markers.clone(),
+            edge_orders: self.edge_orders.clone(),
+            negative_conditions: self.negative_conditions.clone(),
+            paths: self.paths.clone(),
         }
     }
 }
@@ -82,6 +445,9 @@ impl<S: Semantics> GraphShapeQuery<S> {
             expected_graph,
             node_keys_to_shape_idents,
             skip_markers: SkipMarkers::default(),
+            edge_orders: Vec::new(),
+            negative_conditions: Vec::new(),
+            paths: Vec::new(),
         }
     }
 
@@ -89,12 +455,36 @@ impl<S: Semantics> GraphShapeQuery<S> {
         self.skip_markers = skip_markers;
         self
     }
+
+    pub fn with_edge_orders(
+        mut self,
+        edge_orders: Vec<(NodeKey, NodeKey, ShapeEdgeOrder<NodeKey>)>,
+    ) -> Self {
+        self.edge_orders = edge_orders;
+        self
+    }
+
+    pub fn with_negative_conditions(
+        mut self,
+        negative_conditions: Vec<NegativeShapeCondition<S, NodeKey>>,
+    ) -> Self {
+        self.negative_conditions = negative_conditions;
+        self
+    }
+
+    pub fn with_paths(mut self, paths: Vec<ShapePath<S, NodeKey>>) -> Self {
+        self.paths = paths;
+        self
+    }
 }
 
 pub struct ConcreteShapeQueryResult {
     /// The `NodeKey`s are the concrete keys of the real graph
     /// Some(mapping) if the shape query matched, None if it did not match.
     pub shape_idents_to_node_keys: Option<HashMap<ShapeNodeIdentifier, NodeKey>>,
+    /// For every [`ShapePath`] of the query, in order, the concrete nodes of the matched path
+    /// from its source to its target. Empty if the shape query did not match.
+    pub paths: Vec<Vec<NodeKey>>,
 }
 
 pub(crate) fn run_builtin_query<S: Semantics>(
@@ -107,36 +497,37 @@ pub(crate) fn run_builtin_query<S: Semantics>(
     Ok(output)
 }
 
-// TODO: We could make the graph shape query have match arms in the form of a list of (match_arm_name, expected_graph) list that get checked in sequence
-//  and the QueryInstructions would contain a hashmap from match_arm_name to the list of instructions to take assuming that match arm is taken.
-
 /// Runs a shape query on the given concrete graph.
 ///
 /// It works by finding an isomorphism between the expected abstract graph (of the shape query) and
 /// most precise abstraction of the concrete graph. The selected inputs anchor the shape query to a
 /// specific region of the concrete graph.
 ///
+/// If every expected node is connected to an anchored node, the search only expands along the
+/// expected edges from the anchors, so its cost does not depend on the size of the graph.
+/// Otherwise, the entire graph is searched. If there are several matches, the first one in the
+/// [match order](crate::operation::matching) is taken.
+///
 /// This is for concrete graphs. Abstract graphs handle shape queries explicitly in the [`OperationBuilder`].
 ///
 /// [`OperationBuilder`]: crate::operation::builder::OperationBuilder
 pub(crate) fn run_shape_query<S: Semantics>(
     g: &mut ConcreteGraph<S>,
+    abstraction: &mut AbstractionCache<S>,
     query: &GraphShapeQuery<S>,
     selected_inputs: &[NodeKey],
     hidden_nodes: &HashSet<NodeKey>,
     marker_set: &MarkerSet,
 ) -> OperationResult<ConcreteShapeQueryResult> {
-    let abstract_graph = S::concrete_to_abstract(g);
+    let abstract_graph = abstraction.abstract_graph(g);
     let subst = ParameterSubstitution::infer_explicit_for_param(selected_inputs, &query.parameter)?;
 
-    // TODO: implement edge order?
-
     let mut hidden_nodes_incl_marker_hidden = hidden_nodes.clone();
     hidden_nodes_incl_marker_hidden.extend(marker_set.skipped_nodes(&query.skip_markers));
 
     get_shape_query_substitution(
         query,
-        &abstract_graph,
+        abstract_graph,
         &subst,
         &hidden_nodes_incl_marker_hidden,
     )
@@ -144,6 +535,21 @@ pub(crate) fn run_shape_query<S: Semantics>(
     // TODO: after calling this, the abstract graph needs to somehow know that it can be changed for changed values!
 }
 
+/// Marks the nodes of the matched paths of a shape query with their [`ShapePath::marker`]s.
+pub(crate) fn mark_shape_paths<S: Semantics>(
+    query: &GraphShapeQuery<S>,
+    matched_paths: &[Vec<NodeKey>],
+    marker_set: &mut MarkerSet,
+) {
+    for (path, nodes) in query.paths.iter().zip(matched_paths) {
+        if let Some(marker) = path.marker {
+            for node in nodes {
+                marker_set.create_marker_and_mark_node(marker, *node);
+            }
+        }
+    }
+}
+
 fn get_shape_query_substitution<S: Semantics>(
     query: &GraphShapeQuery<S>,
     dynamic_graph: &AbstractGraph<S>,
@@ -157,9 +563,6 @@ fn get_shape_query_substitution<S: Semantics>(
     );
     let desired_shape = &query.expected_graph;
 
-    let desired_shape_ref = &desired_shape.graph;
-    let dynamic_graph_ref = &dynamic_graph.graph;
-
     // derive an enforced mapping from the existing parameter subst
     let mut enforced_desired_to_dynamic: HashMap<NodeKey, NodeKey> = HashMap::new();
     for (subst_marker, dynamic_node_key) in &subst.mapping {
@@ -172,7 +575,7 @@ fn get_shape_query_substitution<S: Semantics>(
         enforced_desired_to_dynamic.insert(*desired_node_key, *dynamic_node_key);
     }
 
-    let mut nm = |desired_shape_node_key: &NodeKey, dynamic_graph_node_key: &NodeKey| {
+    let nm = |desired_shape_node_key: &NodeKey, dynamic_graph_node_key: &NodeKey| {
         if let Some(expected_dynamic_node_key) =
             enforced_desired_to_dynamic.get(desired_shape_node_key)
         {
@@ -202,44 +605,274 @@ fn get_shape_query_substitution<S: Semantics>(
         S::NodeMatcher::matches(dynamic_graph_attr, desired_shape_attr)
     };
 
-    let mut em =
-        |desired_shape_edge_attr_wrapper: &EdgeAttribute<S::EdgeAbstract>,
-         dynamic_graph_edge_attr_wrapper: &EdgeAttribute<S::EdgeAbstract>| {
-            let desired_shape_edge_attr = &desired_shape_edge_attr_wrapper.edge_attr;
-            let dynamic_graph_edge_attr = &dynamic_graph_edge_attr_wrapper.edge_attr;
-            S::EdgeMatcher::matches(dynamic_graph_edge_attr, desired_shape_edge_attr)
-        };
+    let em = |desired_shape_edge_attr_wrapper: &EdgeAttribute<S::EdgeAbstract>,
+              dynamic_graph_edge_attr_wrapper: &EdgeAttribute<S::EdgeAbstract>| {
+        let desired_shape_edge_attr = &desired_shape_edge_attr_wrapper.edge_attr;
+        let dynamic_graph_edge_attr = &dynamic_graph_edge_attr_wrapper.edge_attr;
+        S::EdgeMatcher::matches(dynamic_graph_edge_attr, desired_shape_edge_attr)
+    };
 
-    let Some(isos) = general_subgraph_monomorphisms_iter(
-        &desired_shape_ref,
-        &dynamic_graph_ref,
-        &mut nm,
-        &mut em,
-    ) else {
-        return Ok(ConcreteShapeQueryResult {
-            shape_idents_to_node_keys: None,
-        });
+    let links = query
+        .paths
+        .iter()
+        .map(|path| (path.source, path.target))
+        .collect::<Vec<_>>();
+    let link_candidates = |index: usize, from: NodeKey, direction: petgraph::Direction| {
+        let path = &query.paths[index];
+        reachable_via::<S>(dynamic_graph, from, &path.edge, direction, hidden_nodes)
+            .into_keys()
+            .collect()
     };
+    let path_links = PatternLinks {
+        links: &links,
+        candidates: &link_candidates,
+    };
+
+    let desired_to_dynamic = matching::first_match_with_links(
+        desired_shape,
+        dynamic_graph,
+        &enforced_desired_to_dynamic,
+        &path_links,
+        nm,
+        em,
+        |desired_to_dynamic| {
+            edge_orders_hold(query, dynamic_graph, desired_to_dynamic)
+                && query.paths.iter().all(|path| {
+                    find_path(dynamic_graph, path, desired_to_dynamic, hidden_nodes).is_some()
+                })
+                && query.negative_conditions.iter().all(|condition| {
+                    !negative_condition_present(condition, dynamic_graph, desired_to_dynamic)
+                })
+        },
+    );
 
-    let opt_mapping = isos
-        .map(|iso| {
-            iso.iter()
-                .enumerate()
-                .filter_map(|(desired_shape_idx, &dynamic_graph_idx)| {
-                    let desired_shape_node_key = desired_shape_ref.from_index(desired_shape_idx);
-                    let dynamic_graph_node_key = dynamic_graph_ref.from_index(dynamic_graph_idx);
-                    Some((
-                        *query
-                            .node_keys_to_shape_idents
-                            .get_left(&desired_shape_node_key)?,
-                        dynamic_graph_node_key,
-                    ))
+    let paths = desired_to_dynamic
+        .as_ref()
+        .map(|desired_to_dynamic| {
+            query
+                .paths
+                .iter()
+                .map(|path| {
+                    find_path(dynamic_graph, path, desired_to_dynamic, hidden_nodes)
+                        .expect("internal error: accepted match must contain all paths")
                 })
-                .collect::<HashMap<_, _>>()
+                .collect()
         })
-        .next();
+        .unwrap_or_default();
+
+    let opt_mapping = desired_to_dynamic.map(|desired_to_dynamic| {
+        desired_to_dynamic
+            .into_iter()
+            .filter_map(|(desired_shape_node_key, dynamic_graph_node_key)| {
+                Some((
+                    *query
+                        .node_keys_to_shape_idents
+                        .get_left(&desired_shape_node_key)?,
+                    dynamic_graph_node_key,
+                ))
+            })
+            .collect::<HashMap<_, _>>()
+    });
 
     Ok(ConcreteShapeQueryResult {
         shape_idents_to_node_keys: opt_mapping,
+        paths,
+    })
+}
+
+/// Returns all nodes reachable from `start` via one or more edges matching `edge` in the given
+/// direction, each mapped to its predecessor on a shortest such path.
+///
+/// `start` itself is only contained if it lies on a cycle. Hidden nodes can be reached, but the
+/// search does not continue through them.
+fn reachable_via<S: Semantics>(
+    dynamic_graph: &AbstractGraph<S>,
+    start: NodeKey,
+    edge: &S::EdgeAbstract,
+    direction: petgraph::Direction,
+    hidden_nodes: &HashSet<NodeKey>,
+) -> HashMap<NodeKey, NodeKey> {
+    let inner = dynamic_graph.inner_graph();
+    let mut predecessors = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(current) = queue.pop_front() {
+        if current != start && hidden_nodes.contains(&current) {
+            continue;
+        }
+        let mut next_nodes = inner
+            .neighbors_directed(current, direction)
+            .filter(|next| !predecessors.contains_key(next))
+            .filter(|next| {
+                let key = match direction {
+                    petgraph::Direction::Outgoing => (current, *next),
+                    petgraph::Direction::Incoming => (*next, current),
+                };
+                dynamic_graph
+                    .get_edge_attr(key)
+                    .is_some_and(|attr| S::EdgeMatcher::matches(attr, edge))
+            })
+            .collect::<Vec<_>>();
+        // visit in ascending key order, so the chosen shortest path is deterministic
+        next_nodes.sort_unstable();
+        next_nodes.dedup();
+        for next in next_nodes {
+            predecessors.insert(next, current);
+            // reaching `start` again closes a cycle, which does not lead to new nodes
+            if next != start {
+                queue.push_back(next);
+            }
+        }
+    }
+    predecessors
+}
+
+/// Finds a shortest path for `path` between the nodes its endpoints are matched to.
+///
+/// Returns the nodes of the path, from source to target. The path has at least one edge, so if
+/// both endpoints are the same node, it is a cycle and the node is both first and last.
+fn find_path<S: Semantics>(
+    dynamic_graph: &AbstractGraph<S>,
+    path: &ShapePath<S, NodeKey>,
+    desired_to_dynamic: &HashMap<NodeKey, NodeKey>,
+    hidden_nodes: &HashSet<NodeKey>,
+) -> Option<Vec<NodeKey>> {
+    let source = desired_to_dynamic[&path.source];
+    let target = desired_to_dynamic[&path.target];
+    let predecessors = reachable_via::<S>(
+        dynamic_graph,
+        source,
+        &path.edge,
+        petgraph::Direction::Outgoing,
+        hidden_nodes,
+    );
+    let mut current = *predecessors.get(&target)?;
+    let mut nodes = vec![target, current];
+    while current != source {
+        current = *predecessors.get(&current)?;
+        nodes.push(current);
+    }
+    nodes.reverse();
+    Some(nodes)
+}
+
+/// Checks the query's edge order constraints against the order of the matched edges.
+fn edge_orders_hold<S: Semantics>(
+    query: &GraphShapeQuery<S>,
+    dynamic_graph: &AbstractGraph<S>,
+    desired_to_dynamic: &HashMap<NodeKey, NodeKey>,
+) -> bool {
+    let position = |src: &NodeKey, dst: &NodeKey| {
+        dynamic_graph.out_edge_position(desired_to_dynamic[src], desired_to_dynamic[dst])
+    };
+    query.edge_orders.iter().all(|(src, dst, order)| {
+        let Some((index, count)) = position(src, dst) else {
+            return false;
+        };
+        match *order {
+            ShapeEdgeOrder::FromStart(n) => index == n,
+            ShapeEdgeOrder::FromEnd(n) => count - 1 - index == n,
+            ShapeEdgeOrder::Relative { sibling, offset } => {
+                position(src, &sibling).is_some_and(|(sibling_index, _)| {
+                    index as i64 - sibling_index as i64 == offset.get() as i64
+                })
+            }
+        }
     })
 }
+
+/// Checks if the pattern of a negative condition is present around the matched nodes, by trying
+/// to map its forbidden nodes one after the other.
+fn negative_condition_present<S: Semantics>(
+    condition: &NegativeShapeCondition<S, NodeKey>,
+    dynamic_graph: &AbstractGraph<S>,
+    desired_to_dynamic: &HashMap<NodeKey, NodeKey>,
+) -> bool {
+    fn extend<S: Semantics>(
+        condition: &NegativeShapeCondition<S, NodeKey>,
+        dynamic_graph: &AbstractGraph<S>,
+        desired_to_dynamic: &HashMap<NodeKey, NodeKey>,
+        forbidden_to_dynamic: &mut Vec<NodeKey>,
+    ) -> bool {
+        let resolve =
+            |node: &NegativeShapeNode<NodeKey>, forbidden_to_dynamic: &[NodeKey]| match node {
+                NegativeShapeNode::Existing(key) => desired_to_dynamic.get(key).copied(),
+                NegativeShapeNode::Forbidden(index) => forbidden_to_dynamic.get(*index).copied(),
+            };
+        // every edge whose endpoints are both mapped must be present
+        let edges_present = condition.edges.iter().all(|(src, dst, edge)| {
+            match (
+                resolve(src, forbidden_to_dynamic),
+                resolve(dst, forbidden_to_dynamic),
+            ) {
+                (Some(src), Some(dst)) => dynamic_graph
+                    .get_edge_attr((src, dst))
+                    .is_some_and(|attr| S::EdgeMatcher::matches(attr, edge)),
+                _ => true,
+            }
+        });
+        if !edges_present {
+            return false;
+        }
+        let next = forbidden_to_dynamic.len();
+        if next == condition.forbidden_nodes.len() {
+            return true;
+        }
+
+        // candidates are the neighbours along an edge to a mapped node if there is one,
+        // and all nodes otherwise
+        let inner = dynamic_graph.inner_graph();
+        let neighbour_candidates = condition.edges.iter().find_map(|(src, dst, _)| {
+            if *src == NegativeShapeNode::Forbidden(next) {
+                let dst = resolve(dst, forbidden_to_dynamic)?;
+                Some(
+                    inner
+                        .neighbors_directed(dst, petgraph::Direction::Incoming)
+                        .collect::<Vec<_>>(),
+                )
+            } else if *dst == NegativeShapeNode::Forbidden(next) {
+                let src = resolve(src, forbidden_to_dynamic)?;
+                Some(
+                    inner
+                        .neighbors_directed(src, petgraph::Direction::Outgoing)
+                        .collect::<Vec<_>>(),
+                )
+            } else {
+                None
+            }
+        });
+        let mut candidates =
+            neighbour_candidates.unwrap_or_else(|| inner.nodes().collect::<Vec<_>>());
+        candidates.sort();
+
+        // hidden nodes are candidates as well, since they are still present in the concrete graph
+        for candidate in candidates {
+            if forbidden_to_dynamic.contains(&candidate) {
+                continue;
+            }
+            if let Some(av) = &condition.forbidden_nodes[next] {
+                let attr = dynamic_graph.get_node_attr(candidate).unwrap();
+                if !S::NodeMatcher::matches(attr, av) {
+                    continue;
+                }
+            }
+            forbidden_to_dynamic.push(candidate);
+            if extend(
+                condition,
+                dynamic_graph,
+                desired_to_dynamic,
+                forbidden_to_dynamic,
+            ) {
+                return true;
+            }
+            forbidden_to_dynamic.pop();
+        }
+        false
+    }
+
+    extend(
+        condition,
+        dynamic_graph,
+        desired_to_dynamic,
+        &mut Vec::new(),
+    )
+}
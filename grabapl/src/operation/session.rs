@@ -0,0 +1,200 @@
+//! Long-lived runs of multiple operations on the same concrete graph.
+//!
+//! [`run_from_concrete`](super::run_from_concrete) starts every run from scratch: markers and
+//! trace frames of previous runs are not visible to the next run.
+//! A [`Session`] instead owns the concrete graph, the operation context and all state that should
+//! persist between runs, which is useful for REPL-style workflows.
+//!
+//! # Example
+//! ```rust,ignore
+//! let mut session = Session::new(op_ctx);
+//! let a = session.graph_mut().add_node(value);
+//! session.run(fn_names["mark"], &[a])?;
+//! // the markers of the first run are still set
+//! session.run(fn_names["visit_marked"], &[a])?;
+//! println!("{}", session.trace().chained_dot());
+//! ```
+
+use crate::operation::execution::ExecutionLimits;
+use crate::operation::marker::MarkerSet;
+use crate::operation::signature::parameter::{AbstractOutputNodeMarker, OperationOutput};
+use crate::operation::trace::{Trace, TraceFrame};
+use crate::operation::{
+    OperationContext, OperationId, OperationResult, run_from_concrete_with_marker_set,
+};
+use crate::semantics::ConcreteGraph;
+use crate::{NodeKey, Semantics};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// A successful run of an operation in a [`Session`].
+pub struct SessionRun {
+    pub op: OperationId,
+    pub inputs: Vec<NodeKey>,
+    pub output: OperationOutput,
+    /// The indices of the frames this run added to the session's [`Trace`].
+    pub trace_frames: Range<usize>,
+}
+
+impl SessionRun {
+    pub fn key_of_output_marker(
+        &self,
+        marker: impl Into<AbstractOutputNodeMarker>,
+    ) -> Option<NodeKey> {
+        self.output.new_nodes.get(&marker.into()).copied()
+    }
+}
+
+/// Owns a concrete graph together with the state that persists across multiple operation runs.
+///
+/// The markers and hidden nodes of a session are carried over from one run to the next,
+/// and all runs append to the same trace.
+pub struct Session<S: Semantics> {
+    graph: ConcreteGraph<S>,
+    op_ctx: OperationContext<S>,
+    marker_set: MarkerSet,
+    /// Nodes that may not be matched by shape queries of any run.
+    hidden_nodes: HashSet<NodeKey>,
+    trace: Trace<S>,
+    limits: ExecutionLimits,
+    history: Vec<SessionRun>,
+}
+
+impl<S: Semantics> Session<S> {
+    /// Creates a session with an empty graph.
+    pub fn new(op_ctx: OperationContext<S>) -> Self {
+        Self::with_graph(op_ctx, ConcreteGraph::<S>::new())
+    }
+
+    pub fn with_graph(op_ctx: OperationContext<S>, graph: ConcreteGraph<S>) -> Self {
+        Session {
+            graph,
+            op_ctx,
+            marker_set: MarkerSet::new(),
+            hidden_nodes: HashSet::new(),
+            trace: Trace::new(),
+            limits: ExecutionLimits::unlimited(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Sets the limits that every subsequent run is checked against individually.
+    pub fn set_limits(&mut self, limits: ExecutionLimits) {
+        self.limits = limits;
+    }
+
+    /// Runs `op` on the session's graph with the given explicit inputs.
+    ///
+    /// The run starts with the markers and hidden nodes left behind by the previous runs.
+    /// Trace frames are appended to the session's trace, even if the run fails.
+    ///
+    /// Note that a failed run may leave the graph and the markers partially modified.
+    /// It is not added to [`Session::history`].
+    pub fn run(&mut self, op: OperationId, inputs: &[NodeKey]) -> OperationResult<&SessionRun> {
+        let trace_start = self.trace.frames.len();
+        let marker_set = RefCell::new(std::mem::take(&mut self.marker_set));
+        let trace = RefCell::new(std::mem::take(&mut self.trace));
+        let res = run_from_concrete_with_marker_set(
+            &mut self.graph,
+            &self.op_ctx,
+            op,
+            inputs,
+            self.limits,
+            &marker_set,
+            &trace,
+            &self.hidden_nodes,
+        );
+        self.marker_set = marker_set.into_inner();
+        self.trace = trace.into_inner();
+
+        let output = res?;
+        for removed in &output.removed_nodes {
+            self.hidden_nodes.remove(removed);
+        }
+        self.history.push(SessionRun {
+            op,
+            inputs: inputs.to_vec(),
+            output,
+            trace_frames: trace_start..self.trace.frames.len(),
+        });
+        Ok(self.history.last().unwrap())
+    }
+
+    pub fn graph(&self) -> &ConcreteGraph<S> {
+        &self.graph
+    }
+
+    /// Gives direct access to the graph, e.g., to add input nodes between runs.
+    pub fn graph_mut(&mut self) -> &mut ConcreteGraph<S> {
+        &mut self.graph
+    }
+
+    pub fn op_ctx(&self) -> &OperationContext<S> {
+        &self.op_ctx
+    }
+
+    /// Gives access to the operation context, e.g., to add operations between runs.
+    pub fn op_ctx_mut(&mut self) -> &mut OperationContext<S> {
+        &mut self.op_ctx
+    }
+
+    pub fn marker_set(&self) -> &MarkerSet {
+        &self.marker_set
+    }
+
+    pub fn marker_set_mut(&mut self) -> &mut MarkerSet {
+        &mut self.marker_set
+    }
+
+    pub fn hidden_nodes(&self) -> &HashSet<NodeKey> {
+        &self.hidden_nodes
+    }
+
+    /// Hides the node from the shape queries of all subsequent runs.
+    ///
+    /// The node can still be passed as an explicit input.
+    pub fn hide_node(&mut self, node: NodeKey) {
+        self.hidden_nodes.insert(node);
+    }
+
+    /// Returns true if the node was hidden.
+    pub fn unhide_node(&mut self, node: NodeKey) -> bool {
+        self.hidden_nodes.remove(&node)
+    }
+
+    /// The trace accumulated by all runs of this session.
+    pub fn trace(&self) -> &Trace<S> {
+        &self.trace
+    }
+
+    /// The trace frames added by the given run.
+    pub fn trace_of(&self, run: &SessionRun) -> &[TraceFrame<S>] {
+        &self.trace.frames[run.trace_frames.clone()]
+    }
+
+    /// Returns the accumulated trace and starts a new, empty one.
+    ///
+    /// The frames of previous runs are then only available in the returned trace.
+    pub fn take_trace(&mut self) -> Trace<S> {
+        let trace = std::mem::take(&mut self.trace);
+        for run in &mut self.history {
+            run.trace_frames = 0..0;
+        }
+        trace
+    }
+
+    /// All successful runs so far, in execution order.
+    pub fn history(&self) -> &[SessionRun] {
+        &self.history
+    }
+
+    pub fn last_run(&self) -> Option<&SessionRun> {
+        self.history.last()
+    }
+
+    /// Ends the session, returning its graph.
+    pub fn into_graph(self) -> ConcreteGraph<S> {
+        self.graph
+    }
+}
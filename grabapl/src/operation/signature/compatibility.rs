@@ -0,0 +1,465 @@
+//! Reports whether a new version of an operation can replace an old version without breaking
+//! its callers.
+//!
+//! The verdict is always that of [`OperationSignature::check_subtype_of`]. On top of that, the
+//! report explains *what* changed, e.g., which parameters were renamed, which nodes may now be
+//! deleted or which output nodes changed their type.
+
+use crate::SubstMarker;
+use crate::operation::signature::parameter::{AbstractOutputNodeMarker, OperationParameter};
+use crate::operation::signature::{
+    AbstractOutputChanges, AbstractSignatureEdgeId, AbstractSignatureNodeId, OperationSignature,
+    ParameterEdgeId, SignatureMismatch,
+};
+use crate::operation::{Operation, OperationContext};
+use crate::semantics::{AbstractMatcher, Semantics};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// How the parameter of an operation changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterChange {
+    Unchanged,
+    /// The new parameter accepts every argument of the old one, and more.
+    Widened,
+    /// The new parameter rejects some arguments of the old one.
+    Narrowed,
+}
+
+/// A change to how callers pass their arguments to an operation, which breaks every caller of
+/// the old version, see [`OperationParameter::check_same_bindings`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindingChange {
+    /// The explicit parameter at the given position was renamed from the first to the second marker.
+    RenamedParameter(usize, SubstMarker, SubstMarker),
+    AddedContextNode(SubstMarker),
+    RemovedContextNode(SubstMarker),
+    AddedContextEdge(ParameterEdgeId),
+    RemovedContextEdge(ParameterEdgeId),
+}
+
+/// A change to the output of an operation that breaks callers of the old version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputChange {
+    /// A node that was guaranteed to be created is no longer created.
+    MissingNewNode(AbstractOutputNodeMarker),
+    /// A created node is no longer a subtype of its old type.
+    NewNodeType(AbstractOutputNodeMarker),
+    MissingNewEdge(AbstractSignatureEdgeId),
+    NewEdgeType(AbstractSignatureEdgeId),
+    /// A parameter node may be changed to a type that the old version did not change it to.
+    ChangedNodeType(SubstMarker),
+    ChangedEdgeType(ParameterEdgeId),
+    /// A parameter node that was guaranteed to be overwritten may now keep its value.
+    NoLongerMustChangedNode(SubstMarker),
+    NoLongerMustChangedEdge(ParameterEdgeId),
+    /// A parameter node may be deleted, while the old version kept it.
+    MaybeDeletedNode(SubstMarker),
+    MaybeDeletedEdge(ParameterEdgeId),
+}
+
+/// The comparison of an old and a new signature of the same operation, see [`compare_signatures`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureComparison {
+    /// Why the new signature cannot replace the old one, if it cannot.
+    pub mismatch: Option<SignatureMismatch>,
+    pub parameter: ParameterChange,
+    /// Every change to how callers pass their arguments, sorted.
+    pub bindings: Vec<BindingChange>,
+    /// Every change to the output that breaks callers of the old version, sorted.
+    ///
+    /// Empty if the output of either version is not statically known.
+    pub output: Vec<OutputChange>,
+}
+
+impl SignatureComparison {
+    pub fn is_backwards_compatible(&self) -> bool {
+        self.mismatch.is_none()
+    }
+}
+
+/// Compares the old and new signature of an operation.
+///
+/// The new signature is backwards compatible if it is a subtype of the old one, i.e., if the new
+/// version can be called wherever the old version was called.
+pub fn compare_signatures<S: Semantics>(
+    old: &OperationSignature<S>,
+    new: &OperationSignature<S>,
+) -> SignatureComparison {
+    SignatureComparison {
+        mismatch: new.check_subtype_of(old).err(),
+        parameter: compare_parameters(&old.parameter, &new.parameter),
+        bindings: binding_changes(&old.parameter, &new.parameter),
+        output: breaking_output_changes(&old.output, &new.output),
+    }
+}
+
+fn compare_parameters<S: Semantics>(
+    old: &OperationParameter<S>,
+    new: &OperationParameter<S>,
+) -> ParameterChange {
+    if !new.is_subtype_of(old) {
+        ParameterChange::Narrowed
+    } else if old.is_subtype_of(new) {
+        ParameterChange::Unchanged
+    } else {
+        ParameterChange::Widened
+    }
+}
+
+/// Lists the differences that [`OperationParameter::check_same_bindings`] rejects.
+fn binding_changes<S: Semantics>(
+    old: &OperationParameter<S>,
+    new: &OperationParameter<S>,
+) -> Vec<BindingChange> {
+    let mut changes = Vec::new();
+    // a different number of explicit parameters is a narrowed parameter
+    for (index, (old_marker, new_marker)) in old
+        .explicit_input_nodes
+        .iter()
+        .zip(&new.explicit_input_nodes)
+        .enumerate()
+    {
+        if old_marker != new_marker {
+            changes.push(BindingChange::RenamedParameter(
+                index,
+                *old_marker,
+                *new_marker,
+            ));
+        }
+    }
+
+    let old_nodes = old.context_nodes().collect::<HashSet<_>>();
+    let new_nodes = new.context_nodes().collect::<HashSet<_>>();
+    changes.extend(
+        new_nodes
+            .difference(&old_nodes)
+            .map(|marker| BindingChange::AddedContextNode(*marker)),
+    );
+    changes.extend(
+        old_nodes
+            .difference(&new_nodes)
+            .map(|marker| BindingChange::RemovedContextNode(*marker)),
+    );
+    let old_edges = old.edge_types().into_keys().collect::<HashSet<_>>();
+    let new_edges = new.edge_types().into_keys().collect::<HashSet<_>>();
+    changes.extend(
+        new_edges
+            .difference(&old_edges)
+            .map(|edge| BindingChange::AddedContextEdge(*edge)),
+    );
+    changes.extend(
+        old_edges
+            .difference(&new_edges)
+            .map(|edge| BindingChange::RemovedContextEdge(*edge)),
+    );
+
+    changes.sort_by_cached_key(BindingChange::to_string);
+    changes
+}
+
+/// Lists the reasons why `new` is not a subtype of `old`, following
+/// [`AbstractOutputChanges::is_subtype_of`].
+fn breaking_output_changes<S: Semantics>(
+    old: &AbstractOutputChanges<S>,
+    new: &AbstractOutputChanges<S>,
+) -> Vec<OutputChange> {
+    let mut changes = Vec::new();
+    for (marker, old_type) in &old.new_nodes {
+        match new.new_nodes.get(marker) {
+            None => changes.push(OutputChange::MissingNewNode(*marker)),
+            Some(new_type) if !S::NodeMatcher::matches(new_type, old_type) => {
+                changes.push(OutputChange::NewNodeType(*marker))
+            }
+            Some(_) => {}
+        }
+    }
+    for (edge, old_type) in &old.new_edges {
+        match new.new_edges.get(edge) {
+            None => changes.push(OutputChange::MissingNewEdge(*edge)),
+            Some(new_type) if !S::EdgeMatcher::matches(new_type, old_type) => {
+                changes.push(OutputChange::NewEdgeType(*edge))
+            }
+            Some(_) => {}
+        }
+    }
+
+    // must-changed nodes of `new` that are only maybe-changed in `old` are checked against the
+    // maybe-changed type, which is what callers of `old` join with.
+    let changed_node_types = new.maybe_changed_nodes.iter().chain(
+        new.must_changed_nodes
+            .iter()
+            .filter(|(marker, _)| !old.must_changed_nodes.contains_key(marker)),
+    );
+    for (marker, new_type) in changed_node_types {
+        if !old
+            .maybe_changed_nodes
+            .get(marker)
+            .is_some_and(|old_type| S::NodeMatcher::matches(new_type, old_type))
+        {
+            changes.push(OutputChange::ChangedNodeType(*marker));
+        }
+    }
+    let changed_edge_types = new.maybe_changed_edges.iter().chain(
+        new.must_changed_edges
+            .iter()
+            .filter(|(edge, _)| !old.must_changed_edges.contains_key(edge)),
+    );
+    for (edge, new_type) in changed_edge_types {
+        if !old
+            .maybe_changed_edges
+            .get(edge)
+            .is_some_and(|old_type| S::EdgeMatcher::matches(new_type, old_type))
+        {
+            changes.push(OutputChange::ChangedEdgeType(*edge));
+        }
+    }
+
+    for (marker, old_type) in &old.must_changed_nodes {
+        match new.must_changed_nodes.get(marker) {
+            None => changes.push(OutputChange::NoLongerMustChangedNode(*marker)),
+            Some(new_type) if !S::NodeMatcher::matches(new_type, old_type) => {
+                changes.push(OutputChange::ChangedNodeType(*marker))
+            }
+            Some(_) => {}
+        }
+    }
+    for (edge, old_type) in &old.must_changed_edges {
+        match new.must_changed_edges.get(edge) {
+            None => changes.push(OutputChange::NoLongerMustChangedEdge(*edge)),
+            Some(new_type) if !S::EdgeMatcher::matches(new_type, old_type) => {
+                changes.push(OutputChange::ChangedEdgeType(*edge))
+            }
+            Some(_) => {}
+        }
+    }
+
+    for marker in new.maybe_deleted_nodes.difference(&old.maybe_deleted_nodes) {
+        changes.push(OutputChange::MaybeDeletedNode(*marker));
+    }
+    for edge in new.maybe_deleted_edges.difference(&old.maybe_deleted_edges) {
+        changes.push(OutputChange::MaybeDeletedEdge(*edge));
+    }
+
+    changes.sort_by_cached_key(OutputChange::to_string);
+    // a node may be reported more than once, e.g., if it is both maybe- and must-changed
+    changes.dedup();
+    changes
+}
+
+/// How a named operation changed between two operation contexts, see [`compare_contexts`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperationChange {
+    /// Only the new context defines the operation.
+    Added,
+    /// Only the old context defines the operation. This breaks all of its callers.
+    Removed,
+    Changed(SignatureComparison),
+}
+
+impl OperationChange {
+    pub fn is_backwards_compatible(&self) -> bool {
+        match self {
+            OperationChange::Added => true,
+            OperationChange::Removed => false,
+            OperationChange::Changed(comparison) => comparison.is_backwards_compatible(),
+        }
+    }
+}
+
+/// The result of [`compare_contexts`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatibilityReport {
+    /// Every named operation of either context, sorted by name.
+    pub operations: Vec<(String, OperationChange)>,
+}
+
+impl CompatibilityReport {
+    pub fn is_backwards_compatible(&self) -> bool {
+        self.operations
+            .iter()
+            .all(|(_, change)| change.is_backwards_compatible())
+    }
+
+    /// Returns the names of all operations that break their callers.
+    pub fn breaking_operations(&self) -> impl Iterator<Item = &str> {
+        self.operations
+            .iter()
+            .filter(|(_, change)| !change.is_backwards_compatible())
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&OperationChange> {
+        self.operations
+            .iter()
+            .find(|(op_name, _)| op_name == name)
+            .map(|(_, change)| change)
+    }
+}
+
+/// Compares every named operation of two versions of a program, see
+/// [`OperationContext::register_name`].
+///
+/// Builtin operations have no statically known output, so only their parameters are compared.
+pub fn compare_contexts<S: Semantics>(
+    old: &OperationContext<S>,
+    new: &OperationContext<S>,
+) -> CompatibilityReport {
+    let old_ops = old.named_operations();
+    let new_ops = new.named_operations();
+    let names = old_ops
+        .iter()
+        .chain(&new_ops)
+        .map(|(name, _, _)| *name)
+        .collect::<BTreeSet<_>>();
+
+    let find = |ops: &[(&str, _, Operation<'_, S>)], name: &str| {
+        ops.iter()
+            .find(|(op_name, _, _)| *op_name == name)
+            .map(|(_, _, op)| (op.parameter(), op.signature()))
+    };
+    let operations = names
+        .into_iter()
+        .map(|name| {
+            let change = match (find(&old_ops, name), find(&new_ops, name)) {
+                (None, _) => OperationChange::Added,
+                (_, None) => OperationChange::Removed,
+                (Some((_, Some(old_sig))), Some((_, Some(new_sig)))) => {
+                    OperationChange::Changed(compare_signatures(&old_sig, &new_sig))
+                }
+                (Some((old_param, _)), Some((new_param, _))) => {
+                    let parameter = compare_parameters(&old_param, &new_param);
+                    let mismatch = if parameter == ParameterChange::Narrowed {
+                        Some(SignatureMismatch::Parameter)
+                    } else {
+                        new_param.check_same_bindings(&old_param).err()
+                    };
+                    OperationChange::Changed(SignatureComparison {
+                        mismatch,
+                        parameter,
+                        bindings: binding_changes(&old_param, &new_param),
+                        output: Vec::new(),
+                    })
+                }
+            };
+            (name.to_string(), change)
+        })
+        .collect();
+    CompatibilityReport { operations }
+}
+
+fn fmt_node(node: &AbstractSignatureNodeId) -> String {
+    match node {
+        AbstractSignatureNodeId::ExistingNode(marker) => marker.0.to_string(),
+        AbstractSignatureNodeId::NewNode(marker) => marker.0.to_string(),
+    }
+}
+
+impl Display for BindingChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindingChange::RenamedParameter(index, old, new) => write!(
+                f,
+                "renamed parameter {index} from `{}` to `{}`",
+                old.0, new.0
+            ),
+            BindingChange::AddedContextNode(marker) => {
+                write!(f, "added context node `{}`", marker.0)
+            }
+            BindingChange::RemovedContextNode(marker) => {
+                write!(f, "removed context node `{}`", marker.0)
+            }
+            BindingChange::AddedContextEdge((src, dst)) => {
+                write!(f, "added context edge `{}->{}`", src.0, dst.0)
+            }
+            BindingChange::RemovedContextEdge((src, dst)) => {
+                write!(f, "removed context edge `{}->{}`", src.0, dst.0)
+            }
+        }
+    }
+}
+
+impl Display for OutputChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputChange::MissingNewNode(marker) => {
+                write!(f, "no longer creates node `{}`", marker.0)
+            }
+            OutputChange::NewNodeType(marker) => {
+                write!(f, "changed the type of new node `{}`", marker.0)
+            }
+            OutputChange::MissingNewEdge((src, dst)) => {
+                write!(
+                    f,
+                    "no longer creates edge `{}->{}`",
+                    fmt_node(src),
+                    fmt_node(dst)
+                )
+            }
+            OutputChange::NewEdgeType((src, dst)) => write!(
+                f,
+                "changed the type of new edge `{}->{}`",
+                fmt_node(src),
+                fmt_node(dst)
+            ),
+            OutputChange::ChangedNodeType(marker) => {
+                write!(f, "may change node `{}` to a new type", marker.0)
+            }
+            OutputChange::ChangedEdgeType((src, dst)) => {
+                write!(f, "may change edge `{}->{}` to a new type", src.0, dst.0)
+            }
+            OutputChange::NoLongerMustChangedNode(marker) => {
+                write!(f, "no longer always overwrites node `{}`", marker.0)
+            }
+            OutputChange::NoLongerMustChangedEdge((src, dst)) => {
+                write!(f, "no longer always overwrites edge `{}->{}`", src.0, dst.0)
+            }
+            OutputChange::MaybeDeletedNode(marker) => {
+                write!(f, "may delete node `{}`", marker.0)
+            }
+            OutputChange::MaybeDeletedEdge((src, dst)) => {
+                write!(f, "may delete edge `{}->{}`", src.0, dst.0)
+            }
+        }
+    }
+}
+
+impl Display for CompatibilityReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (name, change) in &self.operations {
+            let comparison = match change {
+                OperationChange::Added => {
+                    writeln!(f, "{name}: added")?;
+                    continue;
+                }
+                OperationChange::Removed => {
+                    writeln!(f, "{name}: BREAKING: removed")?;
+                    continue;
+                }
+                OperationChange::Changed(comparison) => comparison,
+            };
+            let mut details = Vec::new();
+            match comparison.parameter {
+                ParameterChange::Unchanged => {}
+                ParameterChange::Widened => details.push("parameter widened".to_string()),
+                ParameterChange::Narrowed => details.push("parameter narrowed".to_string()),
+            }
+            if comparison.mismatch == Some(SignatureMismatch::Query) {
+                details.push(SignatureMismatch::Query.to_string());
+            }
+            details.extend(comparison.bindings.iter().map(BindingChange::to_string));
+            details.extend(comparison.output.iter().map(OutputChange::to_string));
+            let verdict = if comparison.is_backwards_compatible() {
+                "compatible"
+            } else {
+                "BREAKING"
+            };
+            if details.is_empty() {
+                writeln!(f, "{name}: {verdict}")?;
+            } else {
+                writeln!(f, "{name}: {verdict}: {}", details.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
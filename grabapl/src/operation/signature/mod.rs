@@ -1,4 +1,5 @@
 use crate::SubstMarker;
+use crate::graph::GraphTrait;
 use crate::operation::signature::parameter::{
     AbstractOperationOutput, AbstractOutputNodeMarker, GraphWithSubstitution, NodeMarker,
     OperationParameter,
@@ -8,8 +9,10 @@ use crate::util::bimap::BiMap;
 use derive_more::From;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use thiserror::Error;
 use crate::util::log;
 
+pub mod compatibility;
 pub mod parameter;
 pub mod parameterbuilder;
 
@@ -52,12 +55,20 @@ pub struct OperationSignature<S: Semantics> {
     /// The operation's output, i.e., the deleted nodes and edges, potential new nodes and edges,
     /// and changes to existing nodes and edges.
     pub output: AbstractOutputChanges<S>,
+    /// Whether the operation is a query, i.e., yields `true` or `false` and can be used as the
+    /// condition of a query instead of a builtin query.
+    ///
+    /// Queries never have structural changes, i.e., their output has no new or deleted nodes and edges.
+    #[serde(default)]
+    pub is_query: bool,
 }
 
 impl<S: Semantics> PartialEq for OperationSignature<S> {
     fn eq(&self, other: &Self) -> bool {
         // (self.name == other.name || true) && // todo: define if equality is a requirement for names
-        self.parameter == other.parameter && self.output.is_subtype_of(&other.output)
+        self.parameter == other.parameter
+            && self.output.is_subtype_of(&other.output)
+            && self.is_query == other.is_query
     }
 }
 
@@ -67,6 +78,7 @@ impl<S: Semantics> Clone for OperationSignature<S> {
             name: self.name.clone(),
             parameter: self.parameter.clone(),
             output: self.output.clone(),
+            is_query: self.is_query,
         }
     }
 }
@@ -79,6 +91,7 @@ impl<S: Semantics> OperationSignature<S> {
             name: name.into(),
             parameter,
             output: AbstractOutputChanges::new(),
+            is_query: false,
         }
     }
 
@@ -87,8 +100,44 @@ impl<S: Semantics> OperationSignature<S> {
             name: name.into(),
             parameter: OperationParameter::new_empty(),
             output: AbstractOutputChanges::new(),
+            is_query: false,
         }
     }
+
+    /// Checks that an operation with signature `self` can be used wherever an operation with
+    /// signature `other` is expected, i.e., `self <: other`.
+    pub fn check_subtype_of(&self, other: &Self) -> Result<(), SignatureMismatch> {
+        if self.is_query != other.is_query {
+            return Err(SignatureMismatch::Query);
+        }
+        if !self.parameter.is_subtype_of(&other.parameter) {
+            return Err(SignatureMismatch::Parameter);
+        }
+        self.parameter.check_same_bindings(&other.parameter)?;
+        if !self.output.is_subtype_of(&other.output) {
+            return Err(SignatureMismatch::Output);
+        }
+        Ok(())
+    }
+}
+
+/// The reason why a signature is not a subtype of another, see [`OperationSignature::check_subtype_of`].
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureMismatch {
+    #[error("only one of the signatures is a query")]
+    Query,
+    #[error("the parameter does not accept every argument of the expected parameter")]
+    Parameter,
+    #[error("the explicit parameters are named differently")]
+    ParameterNames,
+    #[error("the parameters have different context nodes")]
+    ContextNodes,
+    #[error("the parameters have different context edges")]
+    ContextEdges,
+    #[error("the output changes are not a subtype of the expected output changes")]
+    Output,
+    #[error("the output changes of a builtin operation are not statically known")]
+    UnknownOutput,
 }
 
 /// The changes to the graph that an operation will cause.
@@ -109,17 +158,19 @@ pub struct AbstractOutputChanges<S: Semantics> {
     /// Pre-existing edges that may have been modified to be of the given type.
     #[serde(with = "serde_json_any_key::any_key_map")]
     pub maybe_changed_edges: HashMap<ParameterEdgeId, S::EdgeAbstract>,
-    // TODO: think about also having "must_changed_nodes" and "must_changed_edges" here,
-    //  which would be useful for:
-    //  1. More precise states, since the builder would not have to join the must-written value with the old value - it knows that the old value is not needed!
-    //  2. More coherence, since builtin operation currently act this way when executed directly inside an operation.
-    //     i.e., they do not join the old value with the new value, but rather overwrite it, since they know for a fact what happens.
-    //  And they might be easy to compute:
-    //  1. At the end of an operation, if we see that an AID x for which we have maybe written an AV t is statically
-    //  known to be of AV t, then we can pretend that it was must-changed.
-    //  This is useful in eg such a case:
-    //  foo(x: Object) must_change x: Integer { if ... { x = 2 } else { x = 3 } // x: Integer now! }
-    //  x: Object. Call foo(x). Now we know x: Integer. Without must_changed, x: Object still.
+    /// Pre-existing nodes that are guaranteed to be overwritten, and are of the given type afterwards.
+    ///
+    /// Every must-changed node is also maybe-changed. Callers do not need to join the given type
+    /// with the node's previous type, since the previous value is gone.
+    /// For example, after calling `foo(x: Object)`, which writes an Integer to `x` on every path,
+    /// the caller knows that `x` is an Integer.
+    #[serde(default)]
+    pub must_changed_nodes: HashMap<SubstMarker, S::NodeAbstract>,
+    /// Pre-existing edges that are guaranteed to be overwritten, and are of the given type afterwards.
+    ///
+    /// Every must-changed edge is also maybe-changed.
+    #[serde(default, with = "serde_json_any_key::any_key_map")]
+    pub must_changed_edges: HashMap<ParameterEdgeId, S::EdgeAbstract>,
     /// Pre-existing nodes that may have been deleted by the operation.
     pub maybe_deleted_nodes: HashSet<SubstMarker>,
     /// Pre-existing edges that may have been deleted by the operation.
@@ -133,6 +184,8 @@ impl<S: Semantics> Clone for AbstractOutputChanges<S> {
             new_edges: self.new_edges.clone(),
             maybe_changed_nodes: self.maybe_changed_nodes.clone(),
             maybe_changed_edges: self.maybe_changed_edges.clone(),
+            must_changed_nodes: self.must_changed_nodes.clone(),
+            must_changed_edges: self.must_changed_edges.clone(),
             maybe_deleted_nodes: self.maybe_deleted_nodes.clone(),
             maybe_deleted_edges: self.maybe_deleted_edges.clone(),
         }
@@ -152,11 +205,22 @@ impl<S: Semantics> AbstractOutputChanges<S> {
             new_edges: HashMap::new(),
             maybe_changed_nodes: HashMap::new(),
             maybe_changed_edges: HashMap::new(),
+            must_changed_nodes: HashMap::new(),
+            must_changed_edges: HashMap::new(),
             maybe_deleted_nodes: HashSet::new(),
             maybe_deleted_edges: HashSet::new(),
         }
     }
 
+    /// Returns `true` if nodes or edges may be created or deleted, i.e., if the changes are not
+    /// limited to the values of pre-existing nodes and edges.
+    pub fn has_structural_changes(&self) -> bool {
+        !self.new_nodes.is_empty()
+            || !self.new_edges.is_empty()
+            || !self.maybe_deleted_nodes.is_empty()
+            || !self.maybe_deleted_edges.is_empty()
+    }
+
     pub fn debug_string(&self) -> String {
         #[derive(Debug)]
         #[allow(dead_code)]
@@ -165,6 +229,8 @@ impl<S: Semantics> AbstractOutputChanges<S> {
             new_edges: HashMap<AbstractSignatureEdgeId, String>,
             maybe_changed_nodes: HashMap<SubstMarker, String>,
             maybe_changed_edges: HashMap<ParameterEdgeId, String>,
+            must_changed_nodes: HashMap<SubstMarker, String>,
+            must_changed_edges: HashMap<ParameterEdgeId, String>,
             maybe_deleted_nodes: HashSet<SubstMarker>,
             maybe_deleted_edges: HashSet<ParameterEdgeId>,
         }
@@ -176,6 +242,8 @@ impl<S: Semantics> AbstractOutputChanges<S> {
                 new_edges: self.new_edges.iter().map(|(k, v)| (*k, S::EdgeMatcher::debug_hack(v))).collect(),
                 maybe_changed_nodes: self.maybe_changed_nodes.iter().map(|(k, v)| (*k, S::NodeMatcher::debug_hack(v))).collect(),
                 maybe_changed_edges: self.maybe_changed_edges.iter().map(|(k, v)| (*k, S::EdgeMatcher::debug_hack(v))).collect(),
+                must_changed_nodes: self.must_changed_nodes.iter().map(|(k, v)| (*k, S::NodeMatcher::debug_hack(v))).collect(),
+                must_changed_edges: self.must_changed_edges.iter().map(|(k, v)| (*k, S::EdgeMatcher::debug_hack(v))).collect(),
                 maybe_deleted_nodes: self.maybe_deleted_nodes.clone(),
                 maybe_deleted_edges: self.maybe_deleted_edges.clone(),
             }
@@ -272,6 +340,62 @@ impl<S: Semantics> AbstractOutputChanges<S> {
             }
         }
 
+        // All must-changed nodes and edges from `other` must be must-changed in `self`, with a subtype
+        // of their counterpart in `other`.
+        for (marker, other_type) in &other.must_changed_nodes {
+            if let Some(self_type) = self.must_changed_nodes.get(marker) {
+                if !S::NodeMatcher::matches(self_type, other_type) {
+                    // Any caller working with the assumption of `other` would assume an incorrect type.
+                    log::info!("Must-changed node type mismatch for {marker:?}");
+                    return false;
+                }
+            } else {
+                // `self` may keep the old value, which callers working with the assumption of `other` discard.
+                log::info!("Missing must-changed node for {marker:?}");
+                return false;
+            }
+        }
+        for (edge_id, other_type) in &other.must_changed_edges {
+            if let Some(self_type) = self.must_changed_edges.get(edge_id) {
+                if !S::EdgeMatcher::matches(self_type, other_type) {
+                    log::info!("Must-changed edge type mismatch for {edge_id:?}");
+                    return false;
+                }
+            } else {
+                log::info!("Missing must-changed edge for {edge_id:?}");
+                return false;
+            }
+        }
+        // Conversely, must-changed nodes and edges of `self` that are only maybe-changed in `other`
+        // are joined with the old value by callers, so their new type must be a subtype of the
+        // maybe-changed type in `other`.
+        for (marker, self_type) in &self.must_changed_nodes {
+            if other.must_changed_nodes.contains_key(marker) {
+                continue;
+            }
+            let Some(other_type) = other.maybe_changed_nodes.get(marker) else {
+                log::info!("Missing changed node for must-changed {marker:?}");
+                return false;
+            };
+            if !S::NodeMatcher::matches(self_type, other_type) {
+                log::info!("Must-changed node type mismatch for {marker:?}");
+                return false;
+            }
+        }
+        for (edge_id, self_type) in &self.must_changed_edges {
+            if other.must_changed_edges.contains_key(edge_id) {
+                continue;
+            }
+            let Some(other_type) = other.maybe_changed_edges.get(edge_id) else {
+                log::info!("Missing changed edge for must-changed {edge_id:?}");
+                return false;
+            };
+            if !S::EdgeMatcher::matches(self_type, other_type) {
+                log::info!("Must-changed edge type mismatch for {edge_id:?}");
+                return false;
+            }
+        }
+
         // All deleted nodes and edges from `self` must be present in `other`.
         for marker in &self.maybe_deleted_nodes {
             if !other.maybe_deleted_nodes.contains(marker) {
@@ -333,6 +457,11 @@ impl<S: Semantics> AbstractOutputChanges<S> {
         // handle changed nodes
         for (subst, av) in &self.maybe_changed_nodes {
             let node_marker = NodeMarker::Subst(*subst);
+            if let Some(must_av) = self.must_changed_nodes.get(subst) {
+                // the old value is overwritten, so there is nothing to join with
+                g.set_node_value(node_marker, must_av.clone());
+                continue;
+            }
             // Note: It could be that the node received an unjoinable write with the current value.
             // In this case, just like when merging IntermediateStates, we hide the node.
             // See tests/signature.rs/writing_unjoinable_av_to_param
@@ -359,6 +488,11 @@ impl<S: Semantics> AbstractOutputChanges<S> {
                 g.indicate_maybe_edge_change(src_marker, dst_marker, av.clone());
                 continue;
             };
+            if let Some(must_av) = self.must_changed_edges.get(&(*src, *dst)) {
+                // the old value is overwritten, so there is nothing to join with
+                g.set_edge_value(src_marker, dst_marker, must_av.clone());
+                continue;
+            }
             if S::EdgeJoin::join(old_av, av).is_none() {
                 g.delete_edge(src_marker, dst_marker);
                 continue;
@@ -435,8 +569,77 @@ impl<S: Semantics> OperationParameter<S> {
         // with the same connections/shape.
         // TODO: implement above? (it's kind of implemented - UDOps store the context graph mapping by context node ID.)
 
-        // TODO: implement subgraph checks for context graph.
+        // Context nodes and edges that are shared by both parameters must also be supertypes.
+        // Whether the parameters share all of them is checked by `check_same_bindings`.
+        let self_nodes = self.node_types();
+        for (marker, other_type) in other.node_types() {
+            if let Some(self_type) = self_nodes.get(&marker)
+                && !S::NodeMatcher::matches(other_type, self_type)
+            {
+                return false;
+            }
+        }
+        let self_edges = self.edge_types();
+        for (edge, other_type) in other.edge_types() {
+            if let Some(self_type) = self_edges.get(&edge)
+                && !S::EdgeMatcher::matches(other_type, self_type)
+            {
+                return false;
+            }
+        }
 
         true
     }
+
+    /// Checks that callers of `other` pass their arguments to `self` in the same way.
+    ///
+    /// Callers store the argument of a call by the callee's substitution markers (see
+    /// [`AbstractOperationArgument::subst_to_aid`](crate::operation::user_defined::AbstractOperationArgument::subst_to_aid)),
+    /// and they only provide the context edges that `other` asked for. Hence both parameters must
+    /// name their explicit nodes the same, and must have the same context nodes and edges.
+    pub fn check_same_bindings(
+        &self,
+        other: &OperationParameter<S>,
+    ) -> Result<(), SignatureMismatch> {
+        if self.explicit_input_nodes != other.explicit_input_nodes {
+            return Err(SignatureMismatch::ParameterNames);
+        }
+        if self.context_nodes().collect::<HashSet<_>>()
+            != other.context_nodes().collect::<HashSet<_>>()
+        {
+            return Err(SignatureMismatch::ContextNodes);
+        }
+        if self.edge_types().keys().collect::<HashSet<_>>()
+            != other.edge_types().keys().collect::<HashSet<_>>()
+        {
+            return Err(SignatureMismatch::ContextEdges);
+        }
+        Ok(())
+    }
+
+    /// Returns the implicitly matched nodes, i.e., all nodes that are not explicitly selected.
+    pub fn context_nodes(&self) -> impl Iterator<Item = SubstMarker> + '_ {
+        self.node_keys_to_subst
+            .right_values()
+            .copied()
+            .filter(|marker| !self.explicit_input_nodes.contains(marker))
+    }
+
+    fn node_types(&self) -> HashMap<SubstMarker, &S::NodeAbstract> {
+        self.node_keys_to_subst
+            .iter()
+            .filter_map(|(key, marker)| Some((*marker, self.parameter_graph.get_node_attr(*key)?)))
+            .collect()
+    }
+
+    fn edge_types(&self) -> HashMap<ParameterEdgeId, &S::EdgeAbstract> {
+        self.parameter_graph
+            .edges()
+            .filter_map(|(src, dst, attr)| {
+                let src = self.node_keys_to_subst.get_left(&src)?;
+                let dst = self.node_keys_to_subst.get_left(&dst)?;
+                Some(((*src, *dst), attr))
+            })
+            .collect()
+    }
 }
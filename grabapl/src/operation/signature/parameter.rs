@@ -1,8 +1,9 @@
 use crate::graph::GraphTrait;
+use crate::operation::execution::ExecutionContext;
 use crate::operation::marker::MarkerSet;
 use crate::operation::trace::Trace;
 use crate::operation::{OperationError, OperationResult};
-use crate::semantics::AbstractGraph;
+use crate::semantics::{AbstractGraph, AbstractionCache};
 use crate::util::bimap::BiMap;
 use crate::util::{InternString, log};
 use crate::{NodeKey, Semantics, SubstMarker, interned_string_newtype};
@@ -168,6 +169,9 @@ pub struct GraphWithSubstitution<'a, G: GraphTrait> {
     removed_edges: Vec<(NodeKey, NodeKey)>,
     changed_node_av: HashMap<NodeKey, G::NodeAttr>,
     changed_edge_av: HashMap<(NodeKey, NodeKey), G::EdgeAttr>,
+    /// Nodes and edges whose value was overwritten unconditionally, see `set_node_value`.
+    must_changed_nodes: HashSet<NodeKey>,
+    must_changed_edges: HashSet<(NodeKey, NodeKey)>,
 }
 
 // TODO: in this entire trait, the Option<> return types are kind of confusing.
@@ -186,6 +190,8 @@ impl<'a, G: GraphTrait<NodeAttr: Clone, EdgeAttr: Clone>> GraphWithSubstitution<
             removed_edges: Vec::new(),
             changed_node_av: HashMap::new(),
             changed_edge_av: HashMap::new(),
+            must_changed_nodes: HashSet::new(),
+            must_changed_edges: HashSet::new(),
         }
     }
 
@@ -306,6 +312,8 @@ impl<'a, G: GraphTrait<NodeAttr: Clone, EdgeAttr: Clone>> GraphWithSubstitution<
         if old_value.is_some() {
             // we only changed it if it exists, by semantics of set_node_attr
             self.changed_node_av.insert(node_key, value);
+            // the old value is gone, no matter what it was
+            self.must_changed_nodes.insert(node_key);
         }
         old_value
     }
@@ -373,6 +381,7 @@ impl<'a, G: GraphTrait<NodeAttr: Clone, EdgeAttr: Clone>> GraphWithSubstitution<
         if old_value.is_some() {
             // we only changed it if it exists, by semantics of set_edge_attr
             self.changed_edge_av.insert((src_key, dst_key), value);
+            self.must_changed_edges.insert((src_key, dst_key));
         } else {
             log::warn!(
                 "Attempted to set edge value for non-existing edge from {:?} to {:?}.",
@@ -487,6 +496,18 @@ impl<'a, G: GraphTrait<NodeAttr: Clone, EdgeAttr: Clone>> GraphWithSubstitution<
                 changed_abstract_edges.insert((src, dst), edge_av.clone());
             // }
         }
+        let must_changed_nodes = self
+            .must_changed_nodes
+            .iter()
+            .filter(|node_key| changed_abstract_values_nodes.contains_key(node_key))
+            .copied()
+            .collect();
+        let must_changed_edges = self
+            .must_changed_edges
+            .iter()
+            .filter(|edge| changed_abstract_edges.contains_key(edge))
+            .copied()
+            .collect();
 
         AbstractOperationOutput {
             new_nodes,
@@ -498,6 +519,8 @@ impl<'a, G: GraphTrait<NodeAttr: Clone, EdgeAttr: Clone>> GraphWithSubstitution<
             removed_nodes: self.removed_nodes.clone(),
             changed_abstract_values_nodes,
             changed_abstract_values_edges: changed_abstract_edges,
+            must_changed_nodes,
+            must_changed_edges,
         }
     }
 
@@ -529,6 +552,12 @@ pub struct OperationArgument<'a, S: Semantics> {
     pub marker_set: &'a RefCell<MarkerSet>,
     #[debug(skip)]
     pub trace: &'a RefCell<Trace<S>>,
+    /// The resources used so far by the run this argument is part of.
+    #[debug(skip)]
+    pub execution: &'a RefCell<ExecutionContext>,
+    /// The abstraction of the concrete graph, shared by all operations of the run.
+    #[debug(skip)]
+    pub abstraction: &'a RefCell<AbstractionCache<S>>,
 }
 
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, From)]
@@ -587,6 +616,10 @@ pub struct AbstractOperationOutput<S: Semantics> {
     /// These maps contain any abstract values that are set (not necessarily changed) during the operation execution.
     pub changed_abstract_values_nodes: HashMap<NodeKey, S::NodeAbstract>,
     pub changed_abstract_values_edges: HashMap<(NodeKey, NodeKey), S::EdgeAbstract>,
+    /// The subset of the changed nodes whose previous value was overwritten unconditionally.
+    pub must_changed_nodes: HashSet<NodeKey>,
+    /// The subset of the changed edges whose previous value was overwritten unconditionally.
+    pub must_changed_edges: HashSet<(NodeKey, NodeKey)>,
 }
 // TODO(severe): since this is basically an AID output, we must make sure that during *concrete* execution,
 //  we don't accidentally overwrite the mapping from AID to NodeKey from some existing operation.
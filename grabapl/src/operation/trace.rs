@@ -2,6 +2,7 @@
 
 use crate::graph::EdgeAttribute;
 use crate::graph::dot::DotCollector;
+use crate::operation::OperationId;
 use crate::operation::marker::MarkerSet;
 use crate::prelude::{AbstractNodeId, ConcreteGraph};
 use crate::util::bimap::BiMap;
@@ -15,6 +16,8 @@ pub struct TraceFrame<S: Semantics> {
     pub hidden_nodes: HashSet<NodeKey>,
     pub marker_set: MarkerSet,
     pub node_aids: BiMap<NodeKey, AbstractNodeId>,
+    /// The operation whose trace instruction recorded this frame.
+    pub op_id: OperationId,
 }
 
 impl<S: Semantics<NodeConcrete: Debug, EdgeConcrete: Debug>> TraceFrame<S> {
@@ -33,7 +36,7 @@ impl<S: Semantics<NodeConcrete: Debug, EdgeConcrete: Debug>> TraceFrame<S> {
         let node_get = |_g, (key, _)| {
             let value_debug = format!("{:?}", self.graph.get_node_attr(key).unwrap());
             let mut value_escaped = value_debug.escape_debug().to_string();
-            if let Some(markers) = self.marker_set.marked_nodes_to_markers.get(&key) {
+            if let Some(markers) = self.marker_set.markers_of(key) {
                 // if the node has markers, append them to the value
                 let markers_inner = markers
                     .iter()
@@ -1,10 +1,13 @@
 use crate::operation::builtin::LibBuiltinOperation;
-use crate::operation::query::{GraphShapeQuery, run_builtin_query, run_shape_query};
+use crate::operation::query::{
+    GraphShapeQuery, mark_shape_paths, run_builtin_query, run_shape_query,
+};
 use crate::operation::signature::OperationSignature;
 use crate::operation::signature::parameter::{
     AbstractOperationOutput, AbstractOutputNodeMarker, GraphWithSubstitution, OperationArgument,
     OperationOutput, OperationParameter, ParameterSubstitution,
 };
+use crate::operation::execution::ExecutionContext;
 use crate::operation::trace::TraceFrame;
 use crate::operation::{
     OperationError, OperationResult, run_builtin_operation, run_lib_builtin_operation,
@@ -18,6 +21,7 @@ use crate::{NodeKey, Semantics, SubstMarker, interned_string_newtype};
 use derive_more::with_trait::From;
 use error_stack::{ResultExt, bail, report};
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 
@@ -211,6 +215,30 @@ impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for OpLik
     }
 }
 
+/// The condition of an [`Instruction::QueryLike`].
+#[derive(derive_more::Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(bound = "S: crate::serde::SemanticsSerde")
+)]
+pub enum QueryLikeInstruction<S: Semantics> {
+    #[debug("Builtin(???)")]
+    Builtin(S::BuiltinQuery),
+    /// A user defined operation that is a query, see [`OperationSignature::is_query`].
+    #[debug("Operation({_0:#?})")]
+    Operation(OperationId),
+}
+
+impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for QueryLikeInstruction<S> {
+    fn clone(&self) -> Self {
+        match self {
+            QueryLikeInstruction::Builtin(query) => QueryLikeInstruction::Builtin(query.clone()),
+            QueryLikeInstruction::Operation(id) => QueryLikeInstruction::Operation(*id),
+        }
+    }
+}
+
 #[derive(derive_more::Debug)]
 #[cfg_attr(
     feature = "serde",
@@ -220,10 +248,9 @@ impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for OpLik
 pub enum Instruction<S: Semantics> {
     #[debug("OpLike({_0:#?}, {_1:#?})")]
     OpLike(OpLikeInstruction<S>, AbstractOperationArgument),
-    // TODO: Split into Instruction::QueryLike (which includes BuiltinQuery and potential future custom queries).
-    #[debug("BuiltinQuery(???, {_1:#?}, {_2:#?})")]
-    BuiltinQuery(
-        S::BuiltinQuery,
+    #[debug("QueryLike({_0:#?}, {_1:#?}, {_2:#?})")]
+    QueryLike(
+        QueryLikeInstruction<S>,
         AbstractOperationArgument,
         QueryInstructions<S>,
     ),
@@ -234,6 +261,17 @@ pub enum Instruction<S: Semantics> {
         AbstractOperationArgument,
         QueryInstructions<S>,
     ),
+    /// Runs the shape queries of the arms in order, and executes the instructions of the first
+    /// arm that matches, or the default instructions if none does.
+    ///
+    /// The nodes bound by the matching arm are available under this instruction's result marker.
+    #[debug("ShapeMatch({_0:#?}, {_1:#?}, [{}])", _2.iter().map(|(opt, inst)| format!("({opt:#?}, {inst:#?})")).collect::<Vec<_>>().join(", "))]
+    ShapeMatch(
+        Vec<ShapeMatchArm<S>>,
+        // Note: the arms' shape queries all share this argument.
+        AbstractOperationArgument,
+        Vec<InstructionWithResultMarker<S>>,
+    ),
     #[debug("RenameNode({old:#?} ==> {new:#?})")]
     RenameNode {
         old: AbstractNodeId,
@@ -249,18 +287,25 @@ pub enum Instruction<S: Semantics> {
     },
     /// Pushes a trace frame to the operation trace.
     Trace,
+    /// Sets the result of the query that is being executed.
+    ///
+    /// This is the last instruction on its path, apart from [`Instruction::ForgetAid`]s.
+    YieldQueryResult(bool),
 }
 
 impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for Instruction<S> {
     fn clone(&self) -> Self {
         match self {
             Instruction::OpLike(oplike, arg) => Instruction::OpLike(oplike.clone(), arg.clone()),
-            Instruction::BuiltinQuery(query, arg, query_instr) => {
-                Instruction::BuiltinQuery(query.clone(), arg.clone(), query_instr.clone())
+            Instruction::QueryLike(query, arg, query_instr) => {
+                Instruction::QueryLike(query.clone(), arg.clone(), query_instr.clone())
             }
             Instruction::ShapeQuery(query, arg, query_instr) => {
                 Instruction::ShapeQuery(query.clone(), arg.clone(), query_instr.clone())
             }
+            Instruction::ShapeMatch(arms, arg, default) => {
+                Instruction::ShapeMatch(arms.clone(), arg.clone(), default.clone())
+            }
             Instruction::RenameNode { old, new } => Instruction::RenameNode {
                 old: *old,
                 new: *new,
@@ -270,6 +315,7 @@ impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for Instr
                 crash_message: crash_message.clone(),
             },
             Instruction::Trace => Instruction::Trace,
+            Instruction::YieldQueryResult(value) => Instruction::YieldQueryResult(*value),
         }
     }
 }
@@ -297,6 +343,29 @@ impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for Query
     }
 }
 
+/// One arm of an [`Instruction::ShapeMatch`].
+#[derive(derive_more::Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(bound = "S: crate::serde::SemanticsSerde")
+)]
+pub struct ShapeMatchArm<S: Semantics> {
+    #[debug(skip)]
+    pub query: GraphShapeQuery<S>,
+    #[debug("[{}]", instructions.iter().map(|(opt, inst)| format!("({opt:#?}, {inst:#?})")).collect::<Vec<_>>().join(", "))]
+    pub instructions: Vec<InstructionWithResultMarker<S>>,
+}
+
+impl<S: Semantics<BuiltinOperation: Clone, BuiltinQuery: Clone>> Clone for ShapeMatchArm<S> {
+    fn clone(&self) -> Self {
+        ShapeMatchArm {
+            query: self.query.clone(),
+            instructions: self.instructions.clone(),
+        }
+    }
+}
+
 pub type InstructionWithResultMarker<S> = (Option<AbstractOperationResultMarker>, Instruction<S>);
 
 #[derive(Clone)]
@@ -381,44 +450,206 @@ impl<S: Semantics> UserDefinedOperation<S> {
         Ok(self.signature.output.apply_abstract(g))
     }
 
-    pub(crate) fn apply(
-        &self,
-        op_ctx: &OperationContext<S>,
-        g: &mut ConcreteGraph<S>,
-        arg: OperationArgument<S>,
+    pub(crate) fn apply<'a, 'arg>(
+        &'a self,
+        op_ctx: &'a OperationContext<S>,
+        self_op_id: OperationId,
+        g: &'a mut ConcreteGraph<S>,
+        arg: OperationArgument<'arg, S>,
     ) -> OperationResult<OperationOutput> {
-        let mut runner = Runner::new(op_ctx, g, &arg);
-        runner.run(&self.instructions)?;
-
-        let our_output_map = self
-            .output_changes
-            .new_nodes
-            .iter()
-            .map(|(aid, name)| Ok((*name, runner.aid_to_node_key(*aid)?)))
-            .collect::<OperationResult<_>>()
-            .attach_printable_lazy(|| "error while building output map")?;
+        let mut runner = Runner::new(op_ctx, g, self_op_id, self, arg);
+        runner.run()
+    }
 
-        // TODO: How to define a good output here?
-        //  probably should be part of the UserDefinedOperation struct. AbstractNodeId should be used, and then we get the actual node key based on what's happening.
-        Ok(OperationOutput {
-            new_nodes: our_output_map,
-            // TODO: populate this
-            removed_nodes: vec![],
-        })
+    /// Returns the instruction at the given path, if it exists.
+    pub fn instruction_at(&self, path: &InstructionPath) -> Option<&Instruction<S>> {
+        let mut block = &self.instructions[..];
+        for (query_index, branch) in &path.queries {
+            block = match (&block.get(*query_index)?.1, branch) {
+                (
+                    Instruction::QueryLike(_, _, query_instr)
+                    | Instruction::ShapeQuery(_, _, query_instr),
+                    QueryBranch::Taken,
+                ) => &query_instr.taken,
+                (
+                    Instruction::QueryLike(_, _, query_instr)
+                    | Instruction::ShapeQuery(_, _, query_instr),
+                    QueryBranch::NotTaken,
+                ) => &query_instr.not_taken,
+                (Instruction::ShapeMatch(arms, _, _), QueryBranch::Arm(arm)) => {
+                    &arms.get(*arm)?.instructions
+                }
+                (Instruction::ShapeMatch(_, _, default), QueryBranch::NotTaken) => default,
+                _ => return None,
+            };
+        }
+        block.get(path.index).map(|(_, instruction)| instruction)
     }
 
     pub fn signature(&self) -> OperationSignature<S> {
         // TODO: borrow
         self.signature.clone()
     }
+
+    /// Returns the ids of all operations that are called or queried by this operation,
+    /// including recursive calls.
+    pub fn referenced_operations(&self) -> HashSet<OperationId> {
+        let mut ids = HashSet::new();
+        for_each_operation_id(&self.instructions, &mut |id, _| {
+            ids.insert(id);
+        });
+        ids
+    }
+
+    /// Returns the argument of every call or query of the operation `id`.
+    pub fn call_arguments(&self, id: OperationId) -> Vec<&AbstractOperationArgument> {
+        let mut args = Vec::new();
+        for_each_operation_id(&self.instructions, &mut |callee, arg| {
+            if callee == id {
+                args.push(arg);
+            }
+        });
+        args
+    }
+
+    /// Replaces every operation id that is called or queried by this operation with `f(id)`.
+    pub fn remap_operation_ids(&mut self, mut f: impl FnMut(OperationId) -> OperationId) {
+        for_each_operation_id_mut(&mut self.instructions, &mut |id| *id = f(*id));
+    }
 }
 
-/// Runs a user defined operation.
-struct Runner<'a, 'arg, S: Semantics> {
-    op_ctx: &'a OperationContext<S>,
-    g: &'a mut ConcreteGraph<S>,
-    /// The argument with which our operation was called.
-    arg: &'a OperationArgument<'arg, S>,
+fn for_each_operation_id<'a, S: Semantics>(
+    instructions: &'a [InstructionWithResultMarker<S>],
+    f: &mut impl FnMut(OperationId, &'a AbstractOperationArgument),
+) {
+    for (_, instruction) in instructions {
+        match instruction {
+            Instruction::OpLike(OpLikeInstruction::Operation(id), arg) => f(*id, arg),
+            Instruction::QueryLike(query, arg, query_instr) => {
+                if let QueryLikeInstruction::Operation(id) = query {
+                    f(*id, arg);
+                }
+                for_each_operation_id(&query_instr.taken, f);
+                for_each_operation_id(&query_instr.not_taken, f);
+            }
+            Instruction::ShapeQuery(_, _, query_instr) => {
+                for_each_operation_id(&query_instr.taken, f);
+                for_each_operation_id(&query_instr.not_taken, f);
+            }
+            Instruction::ShapeMatch(arms, _, default) => {
+                for arm in arms {
+                    for_each_operation_id(&arm.instructions, f);
+                }
+                for_each_operation_id(default, f);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn for_each_operation_id_mut<S: Semantics>(
+    instructions: &mut [InstructionWithResultMarker<S>],
+    f: &mut impl FnMut(&mut OperationId),
+) {
+    for (_, instruction) in instructions {
+        match instruction {
+            Instruction::OpLike(OpLikeInstruction::Operation(id), _) => f(id),
+            Instruction::QueryLike(query, _, query_instr) => {
+                if let QueryLikeInstruction::Operation(id) = query {
+                    f(id);
+                }
+                for_each_operation_id_mut(&mut query_instr.taken, f);
+                for_each_operation_id_mut(&mut query_instr.not_taken, f);
+            }
+            Instruction::ShapeQuery(_, _, query_instr) => {
+                for_each_operation_id_mut(&mut query_instr.taken, f);
+                for_each_operation_id_mut(&mut query_instr.not_taken, f);
+            }
+            Instruction::ShapeMatch(arms, _, default) => {
+                for arm in arms {
+                    for_each_operation_id_mut(&mut arm.instructions, f);
+                }
+                for_each_operation_id_mut(default, f);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// One of the branches of a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryBranch {
+    Taken,
+    /// Also the default arm of a shape match.
+    NotTaken,
+    /// The arm of a shape match with the given index.
+    Arm(usize),
+}
+
+/// The location of an instruction inside a user defined operation's (nested) instructions.
+///
+/// # Example
+/// In the following operation, `increment(x)` is at `InstructionPath::new(vec![(1, QueryBranch::NotTaken)], 0)`:
+/// ```rust,ignore
+/// fn foo(x: int) {
+///     trace();
+///     if is_zero(x) {
+///     } else {
+///         increment(x);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct InstructionPath {
+    /// The queries enclosing the instruction, outermost first.
+    /// Each query is given by its index in its block, and the branch that contains the instruction.
+    pub queries: Vec<(usize, QueryBranch)>,
+    /// The index of the instruction in its block.
+    pub index: usize,
+}
+
+impl InstructionPath {
+    pub fn new(queries: Vec<(usize, QueryBranch)>, index: usize) -> Self {
+        InstructionPath { queries, index }
+    }
+
+    /// The path of an instruction at the top level of an operation, i.e., not inside any query.
+    pub fn top_level(index: usize) -> Self {
+        InstructionPath::new(Vec::new(), index)
+    }
+}
+
+/// A block of instructions that is currently being executed.
+struct Block<'a, S: Semantics> {
+    instructions: &'a [InstructionWithResultMarker<S>],
+    /// The index of the next instruction to execute.
+    next: usize,
+    /// The branch of the enclosing query this block belongs to, if any.
+    branch: Option<QueryBranch>,
+}
+
+impl<'a, S: Semantics> Block<'a, S> {
+    fn new(instructions: &'a [InstructionWithResultMarker<S>], branch: Option<QueryBranch>) -> Self {
+        Block {
+            instructions,
+            next: 0,
+            branch,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.next >= self.instructions.len()
+    }
+}
+
+/// A single activation of a user defined operation on the [`Runner`]'s call stack.
+pub(crate) struct Frame<'a, 'arg, S: Semantics> {
+    /// Unique per run, used to identify frames across calls.
+    id: usize,
+    op_id: OperationId,
+    op: &'a UserDefinedOperation<S>,
+    /// The argument with which this operation was called.
+    arg: OperationArgument<'arg, S>,
     // Note: should not store AID::Parameter nodes, those are in `arg` already.
     // TODO: ^ double check this. I'm currently violating it for ForgetAid.
     abstract_to_concrete: HashMap<AbstractNodeId, NodeKey>,
@@ -428,135 +659,132 @@ struct Runner<'a, 'arg, S: Semantics> {
     /// However, since we maybe_delete the node, the call-site will not have that node anymore.
     /// Hence we should not have it in our hidden_nodes when we call other operation.
     forgotten_params: HashSet<NodeKey>,
+    /// The stack of (nested) query branches that are being executed, innermost last.
+    blocks: Vec<Block<'a, S>>,
+    /// The marker under which the caller stores this operation's output nodes.
+    result_marker: Option<AbstractOperationResultMarker>,
+    /// Set if this frame replaced a caller without output nodes via a tail call.
+    /// The output of this frame must then be discarded, since it is the caller's output that is returned.
+    discard_output: bool,
+    /// The result yielded by this operation, if it is a query.
+    query_result: Option<bool>,
+    /// Set if this operation was called as the condition of a query.
+    /// Once it returns, the caller continues with the branch selected by the yielded result.
+    query_branches: Option<&'a QueryInstructions<S>>,
 }
 
-impl<'a, 'arg, S: Semantics> Runner<'a, 'arg, S> {
-    pub fn new(
-        op_ctx: &'a OperationContext<S>,
-        g: &'a mut ConcreteGraph<S>,
-        arg: &'a OperationArgument<'arg, S>,
+impl<'a, 'arg, S: Semantics> Frame<'a, 'arg, S> {
+    fn new(
+        id: usize,
+        op_id: OperationId,
+        op: &'a UserDefinedOperation<S>,
+        arg: OperationArgument<'arg, S>,
+        result_marker: Option<AbstractOperationResultMarker>,
     ) -> Self {
-        Runner {
-            op_ctx,
-            g,
-            arg,
+        Frame {
+            id,
+            op_id,
+            op,
             abstract_to_concrete: arg
                 .subst
                 .mapping
                 .iter()
                 .map(|(s, n)| (AbstractNodeId::ParameterMarker(*s), *n))
                 .collect(),
+            arg,
             forgotten_params: HashSet::new(),
+            blocks: vec![Block::new(&op.instructions, None)],
+            result_marker,
+            discard_output: false,
+            query_result: None,
+            query_branches: None,
         }
     }
 
-    fn run(&mut self, instructions: &[InstructionWithResultMarker<S>]) -> OperationResult<()> {
-        for (abstract_output_id, instruction) in instructions {
-            match instruction {
-                Instruction::OpLike(oplike, arg) => {
-                    let concrete_arg = self.abstract_to_concrete_arg(arg)?;
-                    log::trace!("Resulting concrete arg: {concrete_arg:#?}");
-                    // TODO: How do we support *mutually* recursive user defined operations?
-                    //  - I think just specifying the ID directly? this will mainly be a problem for the OperationBuilder
-                    // TODO: we need some ExecutionContext that potentially stores information like fuel (to avoid infinite loops and timing out)
-                    let output = match oplike {
-                        OpLikeInstruction::Operation(op_id) => {
-                            run_operation::<S>(self.g, self.op_ctx, *op_id, concrete_arg)?
-                        }
-                        OpLikeInstruction::Builtin(op) => {
-                            run_builtin_operation::<S>(self.g, op, concrete_arg)?
-                        }
-                        OpLikeInstruction::LibBuiltin(op) => {
-                            run_lib_builtin_operation(self.g, op, concrete_arg)?
-                        }
-                    };
-                    if let Some(abstract_output_id) = abstract_output_id {
-                        self.extend_abstract_mapping(*abstract_output_id, output.new_nodes);
-                        // TODO: also handle output.removed_nodes.
-                    }
-                }
-                Instruction::BuiltinQuery(query, arg, query_instr) => {
-                    let concrete_arg = self.abstract_to_concrete_arg(arg)?;
-                    let result = run_builtin_query::<S>(self.g, query, concrete_arg)?;
-                    let next_instr = if result.taken {
-                        &query_instr.taken
-                    } else {
-                        &query_instr.not_taken
-                    };
-                    // TODO: don't use function stack (ie, dont recurse), instead use explicit stack
-                    self.run(next_instr)?
-                }
-                Instruction::ShapeQuery(query, arg, query_instr) => {
-                    let concrete_arg = self.abstract_to_concrete_arg(arg)?;
-                    let result = run_shape_query(
-                        self.g,
-                        query,
-                        &concrete_arg.selected_input_nodes,
-                        &concrete_arg.hidden_nodes,
-                        &concrete_arg.marker_set.borrow(),
-                    )?;
-                    let next_instr =
-                        if let Some(shape_idents_to_node_keys) = result.shape_idents_to_node_keys {
-                            // apply the shape idents to node keys mapping
-
-                            let mut query_result_map = HashMap::new();
-                            for (ident, node_key) in shape_idents_to_node_keys {
-                                // TODO: add helper function, or add new variant to AbstractOutputNodeMarker, or just use that one for the shape query mapping and get rid of ShapeNodeIdentifier.
-                                let output_marker = AbstractOutputNodeMarker(ident.into());
-                                query_result_map.insert(output_marker, node_key);
-                            }
-                            if let Some(abstract_output_id) = abstract_output_id {
-                                self.extend_abstract_mapping(*abstract_output_id, query_result_map);
-                            }
-
-                            &query_instr.taken
-                        } else {
-                            &query_instr.not_taken
-                        };
-                    self.run(next_instr)?;
-                }
-                Instruction::RenameNode { old, new } => {
-                    let Some(key) = self.abstract_to_concrete.remove(old) else {
-                        return Err(report!(OperationError::UnknownAID(*old)))
-                            .attach_printable_lazy(|| {
-                                format!("Cannot rename node {old:#?} to {new:#?}, since it is not in the mapping: {:#?}", self.abstract_to_concrete)
-                            });
-                    };
-                    self.abstract_to_concrete.insert(*new, key);
-                }
-                Instruction::ForgetAid { aid } => {
-                    // Remove the aid from the mapping, so it is not used anymore.
-                    let Some(removed_key) = self.abstract_to_concrete.remove(aid) else {
-                        return Err(report!(OperationError::UnknownAID(*aid)))
-                            .attach_printable_lazy(|| {
-                                format!("Cannot forget aid {aid:?}, since it is not in the mapping: {:#?}", self.abstract_to_concrete)
-                            });
-                    };
-                    if let AbstractNodeId::ParameterMarker(_) = aid {
-                        // hack
-                        self.forgotten_params.insert(removed_key);
-                    }
-                    log::trace!(
-                        "Forgot aid {aid:?} from mapping: {:#?}",
-                        self.abstract_to_concrete
-                    );
-                }
-                Instruction::Diverge { crash_message } => {
-                    return Err(report!(OperationError::UserCrash(crash_message.clone())));
-                }
-                Instruction::Trace => {
-                    let node_aids = self.abstract_to_concrete.clone();
-                    let frame = TraceFrame {
-                        node_aids: BiMap::from_right(node_aids),
-                        graph: self.g.clone(),
-                        hidden_nodes: self.arg.hidden_nodes.clone(),
-                        marker_set: self.arg.marker_set.borrow().clone(),
-                    };
-                    self.arg.trace.borrow_mut().push_frame(frame);
-                }
+    /// Returns the next instruction of this frame without advancing, leaving finished query branches.
+    fn peek_instruction(&mut self) -> Option<&'a InstructionWithResultMarker<S>> {
+        while let Some(block) = self.blocks.last() {
+            if let Some(instruction) = block.instructions.get(block.next) {
+                return Some(instruction);
             }
+            self.blocks.pop();
         }
-        Ok(())
+        None
+    }
+
+    /// Advances to the next instruction of this frame, leaving finished query branches.
+    fn next_instruction(&mut self) -> Option<&'a InstructionWithResultMarker<S>> {
+        let instruction = self.peek_instruction()?;
+        self.blocks.last_mut().unwrap().next += 1;
+        Some(instruction)
+    }
+
+    /// Returns true if this frame has no instructions left to execute.
+    fn is_finished(&self) -> bool {
+        self.blocks.iter().all(Block::is_finished)
+    }
+
+    /// The operation this frame is executing.
+    pub(crate) fn op_id(&self) -> OperationId {
+        self.op_id
+    }
+
+    pub(crate) fn op(&self) -> &'a UserDefinedOperation<S> {
+        self.op
+    }
+
+    pub(crate) fn abstract_to_concrete(&self) -> &HashMap<AbstractNodeId, NodeKey> {
+        &self.abstract_to_concrete
+    }
+
+    pub(crate) fn hidden_nodes(&self) -> &HashSet<NodeKey> {
+        &self.arg.hidden_nodes
+    }
+
+    /// The path of the next instruction, if this frame has any instructions left.
+    ///
+    /// Assumes finished blocks were already left, see [`Frame::peek_instruction`].
+    pub(crate) fn path(&self) -> Option<InstructionPath> {
+        let innermost = self.blocks.last()?;
+        if innermost.is_finished() {
+            return None;
+        }
+        let queries = self
+            .blocks
+            .windows(2)
+            .map(|outer_inner| {
+                // the enclosing query was the last executed instruction of the outer block
+                let (outer, inner) = (&outer_inner[0], &outer_inner[1]);
+                let branch = inner
+                    .branch
+                    .expect("internal error: nested blocks must belong to a query branch");
+                (outer.next - 1, branch)
+            })
+            .collect();
+        Some(InstructionPath::new(queries, innermost.next))
+    }
+
+    fn output(&self) -> OperationResult<OperationOutput> {
+        let our_output_map = self
+            .op
+            .output_changes
+            .new_nodes
+            .iter()
+            .map(|(aid, name)| Ok((*name, self.aid_to_node_key(*aid)?)))
+            .collect::<OperationResult<_>>()
+            .attach_printable_lazy(|| "error while building output map")?;
+
+        // TODO: How to define a good output here?
+        //  probably should be part of the UserDefinedOperation struct. AbstractNodeId should be used, and then we get the actual node key based on what's happening.
+        Ok(OperationOutput {
+            new_nodes: if self.discard_output {
+                HashMap::new()
+            } else {
+                our_output_map
+            },
+            // TODO: populate this
+            removed_nodes: vec![],
+        })
     }
 
     fn extend_abstract_mapping(
@@ -585,28 +813,6 @@ impl<'a, 'arg, S: Semantics> Runner<'a, 'arg, S> {
                     self.abstract_to_concrete
                 )
             })
-
-        /*
-        // Get a param aid from our argument's substitution, and the rest from the map.
-        match aid {
-            AbstractNodeId::ParameterMarker(subst_marker) => self
-                .arg
-                .subst
-                .mapping
-                .get(&subst_marker)
-                .copied()
-                .ok_or(report!(OperationError::UnknownParameterMarker(
-                    subst_marker
-                ))),
-            AbstractNodeId::DynamicOutputMarker(..) | AbstractNodeId::Named(..) => {
-                let key = self
-                    .abstract_to_concrete
-                    .get(&aid)
-                    .copied()
-                    .ok_or(OperationError::UnknownAID(aid))?;
-                Ok(key)
-            }
-        }*/
     }
 
     // TODO: decide if we really want to have this be fallible, since we may want to instead have some
@@ -663,10 +869,351 @@ impl<'a, 'arg, S: Semantics> Runner<'a, 'arg, S> {
             hidden_nodes,
             marker_set: self.arg.marker_set,
             trace: self.arg.trace,
+            execution: self.arg.execution,
+            abstraction: self.arg.abstraction,
         })
     }
 }
 
+/// Runs a user defined operation.
+///
+/// The runner is resumable: [`Runner::step`] executes a single instruction, which is used by the
+/// [`Debugger`](crate::operation::debugger::Debugger).
+///
+/// Calls to other user defined operations as well as query branches are executed on an explicit,
+/// heap-allocated stack instead of the native call stack, so the depth of a recursion is only limited
+/// by the available memory (and the [`ExecutionLimits`](crate::operation::execution::ExecutionLimits)).
+///
+/// A call that is the last instruction of an operation without output nodes is a tail call,
+/// and replaces the calling frame instead of growing the stack.
+pub(crate) struct Runner<'a, 'arg, S: Semantics> {
+    op_ctx: &'a OperationContext<S>,
+    g: &'a mut ConcreteGraph<S>,
+    execution: &'arg RefCell<ExecutionContext>,
+    /// The call stack of user defined operations, innermost last.
+    frames: Vec<Frame<'a, 'arg, S>>,
+    next_frame_id: usize,
+}
+
+impl<'a, 'arg, S: Semantics> Runner<'a, 'arg, S> {
+    pub fn new(
+        op_ctx: &'a OperationContext<S>,
+        g: &'a mut ConcreteGraph<S>,
+        op_id: OperationId,
+        op: &'a UserDefinedOperation<S>,
+        arg: OperationArgument<'arg, S>,
+    ) -> Self {
+        Runner {
+            op_ctx,
+            g,
+            execution: arg.execution,
+            frames: vec![Frame::new(0, op_id, op, arg, None)],
+            next_frame_id: 1,
+        }
+    }
+
+    pub(crate) fn graph(&self) -> &ConcreteGraph<S> {
+        self.g
+    }
+
+    pub(crate) fn graph_mut(&mut self) -> &mut ConcreteGraph<S> {
+        self.g
+    }
+
+    /// The call stack of user defined operations, innermost last.
+    pub(crate) fn frames(&self) -> &[Frame<'a, 'arg, S>] {
+        &self.frames
+    }
+
+    /// The id of the innermost frame.
+    pub(crate) fn current_frame_id(&self) -> Option<usize> {
+        self.frames.last().map(|frame| frame.id)
+    }
+
+    pub(crate) fn has_frame(&self, id: usize) -> bool {
+        self.frames.iter().any(|frame| frame.id == id)
+    }
+
+    /// Returns from all finished frames, until the innermost frame has an instruction left.
+    ///
+    /// Returns the initial operation's output if it returned.
+    pub(crate) fn settle(&mut self) -> OperationResult<Option<OperationOutput>> {
+        loop {
+            let frame = self
+                .frames
+                .last_mut()
+                .expect("internal error: runner has no frames");
+            if frame.peek_instruction().is_some() {
+                return Ok(None);
+            }
+            if let Some(output) = self.return_from_frame()? {
+                return Ok(Some(output));
+            }
+        }
+    }
+
+    /// Runs until the initial operation returns.
+    fn run(&mut self) -> OperationResult<OperationOutput> {
+        loop {
+            if let Some(output) = self.step()? {
+                return Ok(output);
+            }
+        }
+    }
+
+    /// Executes the next instruction of the innermost frame, or returns from that frame if it is finished.
+    ///
+    /// Returns the initial operation's output once it has returned.
+    pub(crate) fn step(&mut self) -> OperationResult<Option<OperationOutput>> {
+        let frame = self
+            .frames
+            .last_mut()
+            .expect("internal error: runner has no frames");
+        let Some((abstract_output_id, instruction)) = frame.next_instruction() else {
+            return self.return_from_frame();
+        };
+        self.execution.borrow_mut().step()?;
+        self.execute(*abstract_output_id, instruction)?;
+        Ok(None)
+    }
+
+    fn return_from_frame(&mut self) -> OperationResult<Option<OperationOutput>> {
+        let frame = self
+            .frames
+            .pop()
+            .expect("internal error: runner has no frames");
+        let output = frame.output()?;
+        let Some(caller) = self.frames.last_mut() else {
+            // the initial operation returned.
+            // Note: entering and exiting the initial operation is handled by `run_operation`.
+            return Ok(Some(output));
+        };
+        self.execution.borrow_mut().exit_operation();
+        self.execution.borrow().check_graph_growth(self.g)?;
+        if let Some(abstract_output_id) = frame.result_marker {
+            caller.extend_abstract_mapping(abstract_output_id, output.new_nodes);
+            // TODO: also handle output.removed_nodes.
+        }
+        if let Some(query_instr) = frame.query_branches {
+            let taken = frame
+                .query_result
+                .ok_or(OperationError::MissingQueryResult(frame.op_id))?;
+            caller.blocks.push(if taken {
+                Block::new(&query_instr.taken, Some(QueryBranch::Taken))
+            } else {
+                Block::new(&query_instr.not_taken, Some(QueryBranch::NotTaken))
+            });
+        }
+        Ok(None)
+    }
+
+    /// Pushes a new frame for a call to a user defined operation.
+    ///
+    /// If the operation is called as the condition of a query, `query_branches` are the branches
+    /// of that query.
+    fn call(
+        &mut self,
+        op_id: OperationId,
+        op: &'a UserDefinedOperation<S>,
+        arg: OperationArgument<'arg, S>,
+        result_marker: Option<AbstractOperationResultMarker>,
+        query_branches: Option<&'a QueryInstructions<S>>,
+    ) -> OperationResult<()> {
+        let mut frame = Frame::new(self.next_frame_id, op_id, op, arg, result_marker);
+        frame.query_branches = query_branches;
+        self.next_frame_id += 1;
+        let caller = self
+            .frames
+            .last()
+            .expect("internal error: runner has no frames");
+        if caller.is_finished()
+            && caller.op.output_changes.new_nodes.is_empty()
+            && query_branches.is_none()
+        {
+            // Tail call: the caller has nothing left to do and does not return any nodes,
+            // so we can replace its frame.
+            let caller = self.frames.pop().unwrap();
+            frame.result_marker = caller.result_marker;
+            frame.discard_output = true;
+            self.execution.borrow_mut().exit_operation();
+        }
+        self.execution.borrow_mut().enter_operation(op_id)?;
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn execute(
+        &mut self,
+        abstract_output_id: Option<AbstractOperationResultMarker>,
+        instruction: &'a Instruction<S>,
+    ) -> OperationResult<()> {
+        let frame = self
+            .frames
+            .last_mut()
+            .expect("internal error: runner has no frames");
+        match instruction {
+            Instruction::OpLike(oplike, arg) => {
+                let concrete_arg = frame.abstract_to_concrete_arg(arg)?;
+                log::trace!("Resulting concrete arg: {concrete_arg:#?}");
+                // TODO: How do we support *mutually* recursive user defined operations?
+                //  - I think just specifying the ID directly? this will mainly be a problem for the OperationBuilder
+                let output = match oplike {
+                    OpLikeInstruction::Operation(op_id) => {
+                        if let Some(Operation::Custom(op)) = self.op_ctx.get(*op_id) {
+                            return self.call(*op_id, op, concrete_arg, abstract_output_id, None);
+                        }
+                        run_operation::<S>(self.g, self.op_ctx, *op_id, concrete_arg)?
+                    }
+                    OpLikeInstruction::Builtin(op) => {
+                        run_builtin_operation::<S>(self.g, op, concrete_arg)?
+                    }
+                    OpLikeInstruction::LibBuiltin(op) => {
+                        run_lib_builtin_operation(self.g, op, concrete_arg)?
+                    }
+                };
+                self.execution.borrow().check_graph_growth(self.g)?;
+                if let Some(abstract_output_id) = abstract_output_id {
+                    frame.extend_abstract_mapping(abstract_output_id, output.new_nodes);
+                    // TODO: also handle output.removed_nodes.
+                }
+            }
+            Instruction::QueryLike(QueryLikeInstruction::Operation(op_id), arg, query_instr) => {
+                let concrete_arg = frame.abstract_to_concrete_arg(arg)?;
+                let Some(Operation::Custom(op)) = self.op_ctx.get(*op_id) else {
+                    return Err(report!(OperationError::ExpectedUserDefinedOperation(
+                        *op_id
+                    )));
+                };
+                // the branch is entered once the query returns, see `return_from_frame`
+                return self.call(*op_id, op, concrete_arg, None, Some(query_instr));
+            }
+            Instruction::QueryLike(QueryLikeInstruction::Builtin(query), arg, query_instr) => {
+                let concrete_arg = frame.abstract_to_concrete_arg(arg)?;
+                let result = run_builtin_query::<S>(self.g, query, concrete_arg)?;
+                let next_instr = if result.taken {
+                    Block::new(&query_instr.taken, Some(QueryBranch::Taken))
+                } else {
+                    Block::new(&query_instr.not_taken, Some(QueryBranch::NotTaken))
+                };
+                frame.blocks.push(next_instr);
+            }
+            Instruction::ShapeQuery(query, arg, query_instr) => {
+                let concrete_arg = frame.abstract_to_concrete_arg(arg)?;
+                let result = run_shape_query(
+                    self.g,
+                    &mut concrete_arg.abstraction.borrow_mut(),
+                    query,
+                    &concrete_arg.selected_input_nodes,
+                    &concrete_arg.hidden_nodes,
+                    &concrete_arg.marker_set.borrow(),
+                )?;
+                let next_instr =
+                    if let Some(shape_idents_to_node_keys) = result.shape_idents_to_node_keys {
+                        // apply the shape idents to node keys mapping
+
+                        let mut query_result_map = HashMap::new();
+                        for (ident, node_key) in shape_idents_to_node_keys {
+                            // TODO: add helper function, or add new variant to AbstractOutputNodeMarker, or just use that one for the shape query mapping and get rid of ShapeNodeIdentifier.
+                            let output_marker = AbstractOutputNodeMarker(ident.into());
+                            query_result_map.insert(output_marker, node_key);
+                        }
+                        if let Some(abstract_output_id) = abstract_output_id {
+                            frame.extend_abstract_mapping(abstract_output_id, query_result_map);
+                        }
+                        mark_shape_paths(
+                            query,
+                            &result.paths,
+                            &mut concrete_arg.marker_set.borrow_mut(),
+                        );
+
+                        Block::new(&query_instr.taken, Some(QueryBranch::Taken))
+                    } else {
+                        Block::new(&query_instr.not_taken, Some(QueryBranch::NotTaken))
+                    };
+                frame.blocks.push(next_instr);
+            }
+            Instruction::ShapeMatch(arms, arg, default) => {
+                let concrete_arg = frame.abstract_to_concrete_arg(arg)?;
+                let mut next_instr = Block::new(default, Some(QueryBranch::NotTaken));
+                for (arm_index, arm) in arms.iter().enumerate() {
+                    let result = run_shape_query(
+                        self.g,
+                        &mut concrete_arg.abstraction.borrow_mut(),
+                        &arm.query,
+                        &concrete_arg.selected_input_nodes,
+                        &concrete_arg.hidden_nodes,
+                        &concrete_arg.marker_set.borrow(),
+                    )?;
+                    let Some(shape_idents_to_node_keys) = result.shape_idents_to_node_keys else {
+                        continue;
+                    };
+                    if let Some(abstract_output_id) = abstract_output_id {
+                        let query_result_map = shape_idents_to_node_keys
+                            .into_iter()
+                            .map(|(ident, node_key)| {
+                                (AbstractOutputNodeMarker(ident.into()), node_key)
+                            })
+                            .collect();
+                        frame.extend_abstract_mapping(abstract_output_id, query_result_map);
+                    }
+                    mark_shape_paths(
+                        &arm.query,
+                        &result.paths,
+                        &mut concrete_arg.marker_set.borrow_mut(),
+                    );
+                    next_instr = Block::new(&arm.instructions, Some(QueryBranch::Arm(arm_index)));
+                    break;
+                }
+                frame.blocks.push(next_instr);
+            }
+            Instruction::RenameNode { old, new } => {
+                let Some(key) = frame.abstract_to_concrete.remove(old) else {
+                    return Err(report!(OperationError::UnknownAID(*old)))
+                        .attach_printable_lazy(|| {
+                            format!("Cannot rename node {old:#?} to {new:#?}, since it is not in the mapping: {:#?}", frame.abstract_to_concrete)
+                        });
+                };
+                frame.abstract_to_concrete.insert(*new, key);
+            }
+            Instruction::ForgetAid { aid } => {
+                // Remove the aid from the mapping, so it is not used anymore.
+                let Some(removed_key) = frame.abstract_to_concrete.remove(aid) else {
+                    return Err(report!(OperationError::UnknownAID(*aid)))
+                        .attach_printable_lazy(|| {
+                            format!("Cannot forget aid {aid:?}, since it is not in the mapping: {:#?}", frame.abstract_to_concrete)
+                        });
+                };
+                if let AbstractNodeId::ParameterMarker(_) = aid {
+                    // hack
+                    frame.forgotten_params.insert(removed_key);
+                }
+                log::trace!(
+                    "Forgot aid {aid:?} from mapping: {:#?}",
+                    frame.abstract_to_concrete
+                );
+            }
+            Instruction::Diverge { crash_message } => {
+                return Err(report!(OperationError::UserCrash(crash_message.clone())));
+            }
+            Instruction::Trace => {
+                let node_aids = frame.abstract_to_concrete.clone();
+                let trace_frame = TraceFrame {
+                    node_aids: BiMap::from_right(node_aids),
+                    graph: self.g.clone(),
+                    hidden_nodes: frame.arg.hidden_nodes.clone(),
+                    marker_set: frame.arg.marker_set.borrow().clone(),
+                    op_id: frame.op_id,
+                };
+                frame.arg.trace.borrow_mut().push_frame(trace_frame);
+            }
+            Instruction::YieldQueryResult(value) => {
+                frame.query_result = Some(*value);
+            }
+        }
+        Ok(())
+    }
+}
+
 // What happens when the query results in true.
 //
 // Analogy in Rust:
@@ -3,9 +3,12 @@ use crate::graph::NodeAttribute;
 use crate::operation::BuiltinOperation;
 use crate::operation::query::BuiltinQuery;
 
+mod abstraction_cache;
 pub mod example;
 pub mod example_with_ref;
 
+pub use abstraction_cache::AbstractionCache;
+
 /// This matcher always returns true.
 #[derive(Default)]
 pub struct AnyMatcher<A> {
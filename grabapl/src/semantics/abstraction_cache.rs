@@ -0,0 +1,110 @@
+use crate::graph::{GraphChange, NodeAttribute};
+use crate::semantics::{AbstractGraph, ConcreteGraph, ConcreteToAbstract, Semantics};
+
+/// The most precise abstraction of a concrete graph, kept up to date incrementally.
+///
+/// The first call to [`AbstractionCache::abstract_graph`] abstracts the entire concrete graph
+/// with [`Semantics::concrete_to_abstract`] and starts tracking the concrete graph's changes.
+/// Later calls only re-abstract the nodes and edges that changed in the meantime.
+///
+/// The result is always identical to [`Semantics::concrete_to_abstract`], including the order
+/// of the nodes.
+pub struct AbstractionCache<S: Semantics> {
+    /// The abstraction and the id of the concrete graph's tracking session it belongs to.
+    cached: Option<(AbstractGraph<S>, u64)>,
+}
+
+impl<S: Semantics> Default for AbstractionCache<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Semantics> AbstractionCache<S> {
+    pub fn new() -> Self {
+        AbstractionCache { cached: None }
+    }
+
+    /// Returns the abstraction of `g`.
+    ///
+    /// A cache must only be used with a single concrete graph at a time.
+    pub fn abstract_graph(&mut self, g: &mut ConcreteGraph<S>) -> &AbstractGraph<S> {
+        let changes = self
+            .cached
+            .as_ref()
+            .and_then(|(_, session)| g.take_changes(*session));
+        match (&mut self.cached, changes) {
+            (Some((abstract_g, _)), Some(changes)) => {
+                Self::apply_changes(abstract_g, g, changes);
+            }
+            _ => {
+                // start tracking before abstracting, so no change is missed
+                let session = g.track_changes();
+                self.cached = Some((S::concrete_to_abstract(g), session));
+            }
+        }
+        &self.cached.as_ref().unwrap().0
+    }
+
+    /// Forgets the cached abstraction and stops tracking changes of `g`.
+    pub fn clear(&mut self, g: &mut ConcreteGraph<S>) {
+        if self.cached.take().is_some() {
+            g.stop_tracking_changes();
+        }
+    }
+
+    fn apply_changes(
+        abstract_g: &mut AbstractGraph<S>,
+        g: &ConcreteGraph<S>,
+        changes: Vec<GraphChange>,
+    ) {
+        // Nodes are added and removed in the same order as in the concrete graph, so that the
+        // node order matches. Values are always taken from the current concrete graph.
+        for change in changes {
+            match change {
+                GraphChange::AddedNode(node_key) => {
+                    abstract_g.graph.add_node(node_key);
+                    // if the node is gone by now, a later change removes it again
+                    if let Some(value) = g.get_node_attr(node_key) {
+                        let value = S::NodeConcreteToAbstract::concrete_to_abstract(value);
+                        abstract_g
+                            .node_attr_map
+                            .insert(node_key, NodeAttribute::new(value));
+                    }
+                }
+                GraphChange::RemovedNode(node_key) => {
+                    abstract_g.graph.remove_node(node_key);
+                    abstract_g.node_attr_map.remove(&node_key);
+                }
+                GraphChange::ChangedNode(node_key) => {
+                    if let (Some(value), Some(attr)) = (
+                        g.get_node_attr(node_key),
+                        abstract_g.node_attr_map.get_mut(&node_key),
+                    ) {
+                        attr.node_attr = S::NodeConcreteToAbstract::concrete_to_abstract(value);
+                    }
+                }
+                GraphChange::ChangedEdge(src, dst) => {
+                    if !abstract_g.graph.contains_node(src) || !abstract_g.graph.contains_node(dst)
+                    {
+                        // one of the nodes was removed in the meantime
+                        continue;
+                    }
+                    match g.graph.edge_weight(src, dst) {
+                        Some(weight) => {
+                            let edge_abstract =
+                                S::EdgeConcreteToAbstract::concrete_to_abstract(weight.attr());
+                            abstract_g
+                                .graph
+                                .add_edge(src, dst, weight.with(edge_abstract));
+                        }
+                        None => {
+                            abstract_g.graph.remove_edge(src, dst);
+                        }
+                    }
+                }
+            }
+        }
+        abstract_g.max_node_key = g.max_node_key;
+    }
+}
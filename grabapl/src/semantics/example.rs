@@ -3,7 +3,7 @@
 //! Defined here for easy reusability elsewhere without running into cyclic crate dependency issues.
 
 use crate::operation::ConcreteData;
-use crate::operation::query::ConcreteQueryOutput;
+use crate::operation::query::{ConcreteQueryOutput, QueryRefinement};
 use crate::operation::signature::parameter::{AbstractOperationOutput, OperationOutput};
 use crate::prelude::*;
 use crate::semantics::*;
@@ -487,6 +487,8 @@ pub enum ExampleQuery {
     ValuesEqual,
     ValueEqualTo(NodeValue),
     CmpFstSnd(MyOrdering),
+    /// Checks if the node's value has the given type. Refines the node's type in the taken branch.
+    HasType(NodeType),
 }
 
 impl BuiltinQuery for ExampleQuery {
@@ -503,7 +505,7 @@ impl BuiltinQuery for ExampleQuery {
                     .expect_explicit_input_node("b", NodeType::Object)
                     .unwrap();
             }
-            ExampleQuery::ValueEqualTo(_) => {
+            ExampleQuery::ValueEqualTo(_) | ExampleQuery::HasType(_) => {
                 param_builder
                     .expect_explicit_input_node("a", NodeType::Object)
                     .unwrap();
@@ -524,6 +526,25 @@ impl BuiltinQuery for ExampleQuery {
         // does nothing, not testing side-effect-ful queries here
     }
 
+    fn apply_abstract_taken(
+        &self,
+        g: &GraphWithSubstitution<AbstractGraph<Self::S>>,
+    ) -> QueryRefinement<Self::S> {
+        let refined = match self {
+            ExampleQuery::ValueEqualTo(value) => {
+                NodeConcreteToAbstract::concrete_to_abstract(value)
+            }
+            ExampleQuery::HasType(node_type) => *node_type,
+            _ => return QueryRefinement::new(),
+        };
+        let current = g.get_node_value(SubstMarker::from("a")).unwrap();
+        // we can only narrow the current type
+        if !NodeMatcher::matches(&refined, current) {
+            return QueryRefinement::new();
+        }
+        QueryRefinement::new().refine_node("a", refined)
+    }
+
     fn query(&self, g: &mut GraphWithSubstitution<ConcreteGraph<Self::S>>) -> ConcreteQueryOutput {
         match self {
             ExampleQuery::ValuesEqual => {
@@ -539,6 +560,13 @@ impl BuiltinQuery for ExampleQuery {
                     taken: node_value == value,
                 }
             }
+            ExampleQuery::HasType(node_type) => {
+                let node_value = g.get_node_value(SubstMarker::from("a")).unwrap();
+                let value_type = NodeConcreteToAbstract::concrete_to_abstract(node_value);
+                ConcreteQueryOutput {
+                    taken: NodeMatcher::matches(&value_type, node_type),
+                }
+            }
             ExampleQuery::CmpFstSnd(ordering) => {
                 let value1 = g.get_node_value(SubstMarker::from("a")).unwrap();
                 let value2 = g.get_node_value(SubstMarker::from("b")).unwrap();
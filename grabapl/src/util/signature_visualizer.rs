@@ -12,6 +12,7 @@ enum Output<A> {
     Unchanged,
     MaybeDeleted,
     MaybeWritten(A),
+    MustWritten(A),
     New(A),
 }
 
@@ -29,6 +30,9 @@ pub fn visualize_signature<S: Semantics<NodeAbstract: Debug, EdgeAbstract: Debug
     for (subst, written_value) in sig.output.maybe_changed_nodes.iter() {
         node_outputs.insert(subst.0, Output::MaybeWritten(written_value.clone()));
     }
+    for (subst, written_value) in sig.output.must_changed_nodes.iter() {
+        node_outputs.insert(subst.0, Output::MustWritten(written_value.clone()));
+    }
     for (marker, new_value) in sig.output.new_nodes.iter() {
         node_outputs.insert(marker.0, Output::New(new_value.clone()));
         let key = output_graph.add_node(new_value.clone());
@@ -48,6 +52,11 @@ pub fn visualize_signature<S: Semantics<NodeAbstract: Debug, EdgeAbstract: Debug
         let dst = sig.parameter.node_keys_to_subst.get_right(dst).unwrap();
         edge_outputs.insert((*src, *dst), Output::MaybeWritten(edge_av.clone()));
     }
+    for ((src, dst), edge_av) in sig.output.must_changed_edges.iter() {
+        let src = sig.parameter.node_keys_to_subst.get_right(src).unwrap();
+        let dst = sig.parameter.node_keys_to_subst.get_right(dst).unwrap();
+        edge_outputs.insert((*src, *dst), Output::MustWritten(edge_av.clone()));
+    }
     let sig_to_node_key = |sig_id: &AbstractSignatureNodeId| {
         match sig_id {
             AbstractSignatureNodeId::ExistingNode(subst) => {
@@ -112,6 +121,11 @@ pub fn visualize_signature<S: Semantics<NodeAbstract: Debug, EdgeAbstract: Debug
                 let av = format!("{}", av.escape_debug());
                 format!("label = \"{} 🡐 {}\", color=\"blue\", fontcolor=\"blue\"", marker.0, av)
             }
+            Output::MustWritten(av) => {
+                let av = format!("{av:?}");
+                let av = format!("{}", av.escape_debug());
+                format!("style=\"bold\", label = \"{} 🡐 {}\", color=\"blue\", fontcolor=\"blue\"", marker.0, av)
+            }
             Output::New(av) => {
                 let av = format!("{av:?}");
                 let av = format!("{}", av.escape_debug());
@@ -134,6 +148,11 @@ pub fn visualize_signature<S: Semantics<NodeAbstract: Debug, EdgeAbstract: Debug
                 let av = format!("{}", av.escape_debug());
                 format!("label = \"🡐 {}\", color=\"blue\", fontcolor=\"blue\"", av)
             }
+            Output::MustWritten(av) => {
+                let av = format!("{av:?}");
+                let av = format!("{}", av.escape_debug());
+                format!("style=\"bold\", label = \"🡐 {}\", color=\"blue\", fontcolor=\"blue\"", av)
+            }
             Output::New(av) => {
                 let av = format!("{av:?}");
                 let av = format!("{}", av.escape_debug());
@@ -0,0 +1,144 @@
+mod util;
+
+use grabapl::graph::EdgeAttribute;
+use grabapl::prelude::*;
+use grabapl::semantics::{AbstractGraph, AbstractionCache};
+use proptest::proptest;
+use proptest::test_runner::Config;
+use syntax::grabapl_defs;
+use util::semantics::*;
+
+/// Compares everything a shape query can observe, including the order of the nodes.
+fn assert_same_abstraction(
+    actual: &AbstractGraph<TestSemantics>,
+    expected: &AbstractGraph<TestSemantics>,
+) {
+    let nodes =
+        |g: &AbstractGraph<TestSemantics>| g.nodes().map(|(k, v)| (k, *v)).collect::<Vec<_>>();
+    assert_eq!(nodes(actual), nodes(expected));
+    let edges = |g: &AbstractGraph<TestSemantics>| {
+        let mut edges = g
+            .inner_graph()
+            .all_edges()
+            .map(|(src, dst, attr)| (src, dst, attr.clone()))
+            .collect::<Vec<(_, _, EdgeAttribute<EdgeType>)>>();
+        edges.sort_by_key(|(src, dst, _)| (*src, *dst));
+        edges
+    };
+    assert_eq!(edges(actual), edges(expected));
+}
+
+/// Applies a modification chosen by `kind` to `g`, picking nodes by index.
+fn modify(g: &mut ConcreteGraph<TestSemantics>, kind: u8, a: usize, b: usize, value: i32) {
+    let nodes = g.nodes().map(|(k, _)| k).collect::<Vec<_>>();
+    let pick = |i: usize| nodes[i % nodes.len()];
+    let kind = kind % 8;
+    if nodes.is_empty() || kind == 0 {
+        g.add_node(NodeValue::Integer(value));
+        return;
+    }
+    let (a, b) = (pick(a), pick(b));
+    match kind {
+        1 => {
+            g.remove_node(a);
+        }
+        2 | 3 => {
+            g.add_edge(a, b, format!("e{}", value % 3));
+        }
+        4 => {
+            g.remove_edge_between(a, b);
+        }
+        5 => {
+            g.set_node_attr(a, NodeValue::String(value.to_string()));
+        }
+        6 => {
+            if let Some(attr) = g.get_mut_edge_attr((a, b)) {
+                *attr = format!("e{}", value % 3);
+            }
+        }
+        _ => {
+            g.add_node(NodeValue::Integer(value));
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(Config::with_cases(200))]
+    #[test]
+    fn incremental_abstraction_matches_full_abstraction(
+        steps in proptest::collection::vec(
+            (proptest::num::u8::ANY, proptest::num::usize::ANY, proptest::num::usize::ANY, -5i32..5, proptest::bool::ANY),
+            1..60,
+        ),
+        rollback_at in proptest::option::of(0usize..60),
+    ) {
+        let mut g = ConcreteGraph::<TestSemantics>::new();
+        let mut cache = AbstractionCache::<TestSemantics>::new();
+        for (i, (kind, a, b, value, sync)) in steps.into_iter().enumerate() {
+            if rollback_at == Some(i) {
+                g.begin_transaction();
+            }
+            modify(&mut g, kind, a, b, value);
+            if sync {
+                let expected = TestSemantics::concrete_to_abstract(&g);
+                assert_same_abstraction(cache.abstract_graph(&mut g), &expected);
+            }
+        }
+        g.rollback_transaction();
+        let expected = TestSemantics::concrete_to_abstract(&g);
+        assert_same_abstraction(cache.abstract_graph(&mut g), &expected);
+    }
+}
+
+#[test_log::test]
+fn cache_is_not_shared_with_clones() {
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let a = g.add_node(NodeValue::Integer(0));
+    let mut cache = AbstractionCache::<TestSemantics>::new();
+    cache.abstract_graph(&mut g);
+
+    let mut clone = g.clone();
+    clone.add_node(NodeValue::String("x".to_string()));
+    clone.add_edge(a, a, "self".to_string());
+    let expected = TestSemantics::concrete_to_abstract(&clone);
+    assert_same_abstraction(cache.abstract_graph(&mut clone), &expected);
+}
+
+grabapl_defs!(get_ops, TestSemantics,
+fn count_down(x: int) {
+    if is_zero(x) {
+    } else {
+        decrement(x);
+        let! n = add_node<int,0>();
+        add_edge<"next">(x, n);
+        if shape [c: int, x -> c: "next"] {
+            remove_edge(x, c);
+            remove_node(c);
+        }
+        count_down(x);
+    }
+}
+);
+
+#[test_log::test]
+fn shape_queries_see_changes_of_the_run() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = g.add_node(NodeValue::Integer(3));
+    let others = (0..3)
+        .map(|_| {
+            let c = g.add_node(NodeValue::Integer(7));
+            g.add_edge(x, c, "next".to_string());
+            c
+        })
+        .collect::<Vec<_>>();
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["count_down"], &[x]).unwrap();
+
+    assert_eq!(g.get_node_attr(x), Some(&NodeValue::Integer(0)));
+    // every iteration removed one of the initial children, but not the ones added by the run
+    for c in others {
+        assert_eq!(g.get_node_attr(c), None);
+    }
+    assert_eq!(g.out_edges(x).count(), 3);
+}
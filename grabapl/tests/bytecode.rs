@@ -0,0 +1,390 @@
+mod util;
+
+use grabapl::operation::bytecode::{Op, Program};
+use grabapl::operation::execution::ExecutionLimits;
+use grabapl::operation::marker::MarkerSet;
+use grabapl::operation::run_from_concrete_with_limits;
+use grabapl::prelude::*;
+use proptest::proptest;
+use proptest::test_runner::Config;
+use std::collections::HashSet;
+use syntax::grabapl_defs;
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+// pulls the max element down the list, then the min element up, until every element is fixed
+fn bubble_sort(head: int) {
+    let! direction = add_node<int,0>();
+    bubble_sort_helper(head, direction);
+    remove_node(direction);
+}
+
+fn bubble_sort_helper(curr: int, direction: int) {
+    if is_eq<0>(direction) {
+        if shape [next: int, curr -> next: *] skipping ["fixed"] {
+            if cmp_fst_snd%>%(curr, next) {
+                swap_values(curr, next);
+            }
+            hide_node(curr);
+            bubble_sort_helper(next, direction);
+        } else {
+            mark_node<"fixed">(curr);
+            increment(direction);
+            if shape [prev: int, prev -> curr: *] skipping ["fixed"] {
+                bubble_sort_helper(prev, direction);
+            }
+        }
+    } else {
+        if shape [prev: int, prev -> curr: *] skipping ["fixed"] {
+            if cmp_fst_snd%>%(prev, curr) {
+                swap_values(prev, curr);
+            }
+            hide_node(curr);
+            bubble_sort_helper(prev, direction);
+        } else {
+            mark_node<"fixed">(curr);
+            decrement(direction);
+            if shape [next: int, curr -> next: *] skipping ["fixed"] {
+                bubble_sort_helper(next, direction);
+            }
+        }
+    }
+}
+
+fn swap_values(a: int, b: int) {
+    let! temp = add_node<int,0>();
+    copy_value_from_to(a, temp);
+    copy_value_from_to(b, a);
+    copy_value_from_to(temp, b);
+    remove_node(temp);
+}
+
+// statically 'maybe' deletes the node, so it can be matched by shape queries of callees again
+fn hide_node(node: object) {
+    let! one = add_node<int,1>();
+    if is_eq<0>(one) {
+        remove_node(node);
+    }
+    remove_node(one);
+}
+
+// inserts the nodes reachable from `start` into a new list, layer by layer
+fn bfs(start: int) -> (head: int) {
+    let! head = add_node<int,0>();
+    copy_value_from_to(start, head);
+    let! max_height = max_height(start);
+    if shape [initial_child: int, start -> initial_child: *] {
+        let! curr_dist = add_node<int,0>();
+        decrement(max_height);
+        decrement(max_height);
+        bfs_iter(start, head, curr_dist, max_height);
+        remove_node(curr_dist);
+    }
+    remove_node(max_height);
+    return (head: head);
+}
+
+fn bfs_iter(start: int, head: int, curr_dist: int, max_dist: int)
+    [initial_child: int, start -> initial_child: *] {
+    if cmp_fst_snd%>%(curr_dist, max_dist) {
+    } else {
+        let! layer = add_node<int,0>();
+        copy_value_from_to(curr_dist, layer);
+        bfs_insert_layer(initial_child, head, layer);
+        remove_node(layer);
+        increment(curr_dist);
+        bfs_iter(start, head, curr_dist, max_dist);
+    }
+}
+
+fn bfs_insert_layer(child: int, head: int, layer: int) [parent: int, parent -> child: *] {
+    mark_node<"visited", object>(child);
+    if is_eq<0>(layer) {
+        bfs_insert_siblings(child, head);
+    } else {
+        if shape [sibling: int, parent -> sibling: *] {
+            bfs_insert_layer(sibling, head, layer);
+        }
+        if is_eq<1>(layer) {
+            if shape [grandchild: int, child -> grandchild: *] skipping ["visited"] {
+                bfs_insert_siblings(grandchild, head);
+            }
+        } else if shape [grandchild: int, child -> grandchild: *] {
+            let! layer_copy = add_node<int,0>();
+            copy_value_from_to(layer, layer_copy);
+            decrement(layer_copy);
+            bfs_insert_layer(grandchild, head, layer_copy);
+            remove_node(layer_copy);
+        }
+    }
+}
+
+fn bfs_insert_siblings(child: int, head: int) [parent: int, parent -> child: *] {
+    mark_node<"visited", object>(child);
+    list_insert_by_copy(head, child);
+    if shape [sibling: int, parent -> sibling: *] skipping ["visited"] {
+        bfs_insert_siblings(sibling, head);
+    }
+}
+
+fn max_height(start: object) -> (max_height: int) {
+    let! res = add_node<int,1>();
+    if shape [child: object, start -> child: *] {
+        let! child_max = max_height_helper(child);
+        increment(child_max);
+        copy_value_from_to(child_max, res);
+        remove_node(child_max);
+    }
+    return (max_height: res);
+}
+
+fn max_height_helper(child: object) [parent: object, parent -> child: *] -> (max_height: int) {
+    let! our_height = add_node<int,1>();
+    if shape [sibling: object, parent -> sibling: *] {
+        let! sibling_max = max_height_helper(sibling);
+        set_fst_to_max(our_height, sibling_max);
+        remove_node(sibling_max);
+    }
+    if shape [grandchild: object, child -> grandchild: *] {
+        let! child_max = max_height_helper(grandchild);
+        increment(child_max);
+        set_fst_to_max(our_height, child_max);
+        remove_node(child_max);
+    }
+    return (max_height: our_height);
+}
+
+fn set_fst_to_max(a: int, b: int) {
+    if cmp_fst_snd%<%(a, b) {
+        copy_value_from_to(b, a);
+    }
+}
+
+fn list_insert_by_copy(head: int, value: int) {
+    if shape [child: int, head -> child: "next"] {
+        list_insert_by_copy(child, value);
+    } else {
+        let! new_node = add_node<int,0>();
+        copy_value_from_to(value, new_node);
+        add_edge<"next">(head, new_node);
+    }
+}
+
+fn is_sorted(x: int) -> bool {
+    if shape [n: int, x -> n: *] {
+        if cmp_fst_snd%>%(x, n) {
+            return false;
+        } else {
+            if is_sorted(n) {
+                return true;
+            } else {
+                return false;
+            }
+        }
+    } else {
+        return true;
+    }
+}
+
+// exercises user defined queries, shape matches, traces, and diverging
+fn classify(x: int) {
+    trace();
+    if is_sorted(x) {
+        increment(x);
+    }
+    match shape {
+        [c: int, x -> c: *] => {
+            if is_eq<0>(c) {
+                diverge<"zero child">();
+            }
+            trace();
+            decrement(c);
+        },
+        _ => {
+            // the new node is not bound, but it is still hidden from the shape query
+            add_node<int,7>();
+            if shape [n: int] {
+                increment(n);
+            }
+            trace();
+        }
+    }
+}
+);
+
+/// A list of the given values, connected by edges from each node to the next.
+fn list_graph(values: &[i32]) -> (ConcreteGraph<TestSemantics>, Vec<NodeKey>) {
+    let mut g = TestSemantics::new_concrete_graph();
+    let keys = values
+        .iter()
+        .map(|value| g.add_node(NodeValue::Integer(*value)))
+        .collect::<Vec<_>>();
+    for pair in keys.windows(2) {
+        g.add_edge(pair[0], pair[1], "".to_string());
+    }
+    (g, keys)
+}
+
+/// Runs `op` with both the default interpreter and the bytecode interpreter on a copy of `g`,
+/// and checks that the results are the same.
+fn assert_same_results(
+    op_ctx: &OperationContext<TestSemantics>,
+    program: &Program<TestSemantics>,
+    op: OperationId,
+    g: &ConcreteGraph<TestSemantics>,
+    inputs: &[NodeKey],
+    limits: ExecutionLimits,
+) {
+    let mut expected_g = g.clone();
+    let mut actual_g = g.clone();
+    let expected = run_from_concrete_with_limits(&mut expected_g, op_ctx, op, inputs, limits);
+    let actual = program.run_with_limits(&mut actual_g, op, inputs, limits);
+    match (expected, actual) {
+        (Ok(expected), Ok(actual)) => {
+            assert_eq!(expected.new_nodes(), actual.new_nodes());
+            assert_eq!(marked(&expected.marker_set), marked(&actual.marker_set));
+            assert_eq!(expected.trace.frames.len(), actual.trace.frames.len());
+            for (expected, actual) in expected.trace.frames.iter().zip(&actual.trace.frames) {
+                assert_eq!(expected.node_aids, actual.node_aids);
+                assert_eq!(expected.hidden_nodes, actual.hidden_nodes);
+                assert_eq!(marked(&expected.marker_set), marked(&actual.marker_set));
+            }
+        }
+        (Err(expected), Err(actual)) => {
+            assert_eq!(
+                expected.current_context().to_string(),
+                actual.current_context().to_string()
+            );
+        }
+        (expected, actual) => panic!(
+            "interpreters disagree: expected {:?}, got {:?}",
+            expected.map(|_| ()),
+            actual.map(|_| ())
+        ),
+    }
+    assert!(
+        expected_g.semantically_matches_with_same_keys(&actual_g),
+        "interpreters produced different graphs"
+    );
+}
+
+fn marked(marker_set: &MarkerSet) -> HashSet<NodeKey> {
+    marker_set.all_marked_nodes().collect()
+}
+
+fn values(g: &ConcreteGraph<TestSemantics>, keys: &[NodeKey]) -> Vec<i32> {
+    keys.iter()
+        .map(|key| match g.get_node_attr(*key) {
+            Some(NodeValue::Integer(value)) => *value,
+            other => panic!("expected an integer node, found {other:?}"),
+        })
+        .collect()
+}
+
+#[test_log::test]
+fn bubble_sort_sorts() {
+    let (op_ctx, fn_map) = get_ops();
+    let program = Program::compile(&op_ctx);
+    let (mut g, keys) = list_graph(&[5, -2, 9, 0, 3]);
+    program
+        .run(&mut g, fn_map["bubble_sort"], &[keys[0]])
+        .unwrap();
+    assert_eq!(values(&g, &keys), [-2, 0, 3, 5, 9]);
+}
+
+#[test_log::test]
+fn operations_are_flat() {
+    let (op_ctx, fn_map) = get_ops();
+    let program = Program::compile(&op_ctx);
+    let helper = program.operation(fn_map["bubble_sort_helper"]).unwrap();
+    assert_eq!(helper.op_id(), fn_map["bubble_sort_helper"]);
+    assert!(matches!(helper.ops().last(), Some(Op::Return)));
+    assert!(helper.ops().iter().any(|op| matches!(op, Op::Jump(_))));
+
+    // the recursive call is the last instruction of `bfs_iter`, which returns no nodes
+    let tail_calls = |name: &str| {
+        program
+            .operation(fn_map[name])
+            .unwrap()
+            .ops()
+            .iter()
+            .filter_map(|op| match op {
+                Op::Call { tail, .. } => Some(*tail),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(tail_calls("bfs_iter"), [false, true]);
+    // `bfs` returns a node, so its calls are never tail calls
+    assert!(!tail_calls("bfs").contains(&true));
+    assert!(program.operation(OperationId::MAX).is_none());
+}
+
+#[test_log::test]
+fn bfs_and_queries_behave_like_the_default_interpreter() {
+    let (op_ctx, fn_map) = get_ops();
+    let program = Program::compile(&op_ctx);
+
+    // a diamond with a back edge
+    let mut g = TestSemantics::new_concrete_graph();
+    let nodes = (0..5)
+        .map(|value| g.add_node(NodeValue::Integer(value)))
+        .collect::<Vec<_>>();
+    for (src, dst) in [(0, 1), (0, 2), (1, 3), (2, 3), (3, 4), (4, 0)] {
+        g.add_edge(nodes[src], nodes[dst], "".to_string());
+    }
+    for start in &nodes {
+        for name in ["bfs", "classify"] {
+            assert_same_results(
+                &op_ctx,
+                &program,
+                fn_map[name],
+                &g,
+                &[*start],
+                ExecutionLimits::unlimited(),
+            );
+        }
+    }
+}
+
+#[test_log::test]
+fn execution_limits_are_the_same() {
+    let (op_ctx, fn_map) = get_ops();
+    let program = Program::compile(&op_ctx);
+    let (g, keys) = list_graph(&[4, 3, 2, 1]);
+    for limits in [
+        ExecutionLimits::unlimited().with_max_steps(40),
+        ExecutionLimits::unlimited().with_max_recursion_depth(3),
+        ExecutionLimits::unlimited().with_max_node_growth(1),
+    ] {
+        assert_same_results(
+            &op_ctx,
+            &program,
+            fn_map["bubble_sort"],
+            &g,
+            &[keys[0]],
+            limits,
+        );
+    }
+}
+
+proptest! {
+    #![proptest_config(Config::with_cases(20))]
+    #[test]
+    fn bytecode_behaves_like_the_default_interpreter(
+        values in proptest::collection::vec(-5..5, 1..6),
+        max_steps in 1usize..300,
+    ) {
+        let (op_ctx, fn_map) = get_ops();
+        let program = Program::compile(&op_ctx);
+        let (g, keys) = list_graph(&values);
+        for name in ["bubble_sort", "bfs", "classify"] {
+            for limits in [
+                ExecutionLimits::unlimited(),
+                ExecutionLimits::unlimited().with_max_steps(max_steps),
+            ] {
+                assert_same_results(&op_ctx, &program, fn_map[name], &g, &[keys[0]], limits);
+            }
+        }
+    }
+}
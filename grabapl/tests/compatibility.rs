@@ -0,0 +1,205 @@
+mod util;
+
+use grabapl::operation::signature::SignatureMismatch;
+use grabapl::operation::signature::compatibility::{
+    BindingChange, CompatibilityReport, OperationChange, OutputChange, ParameterChange,
+    SignatureComparison, compare_contexts,
+};
+use grabapl::prelude::*;
+use util::semantics::*;
+
+fn compile(src: &str) -> OperationContext<TestSemantics> {
+    syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false)
+        .op_ctx_and_map
+        .unwrap()
+        .0
+}
+
+fn compare(old: &str, new: &str) -> CompatibilityReport {
+    compare_contexts(&compile(old), &compile(new))
+}
+
+fn comparison<'a>(report: &'a CompatibilityReport, name: &str) -> &'a SignatureComparison {
+    match report.get(name) {
+        Some(OperationChange::Changed(comparison)) => comparison,
+        other => panic!("expected `{name}` to be in both versions, found {other:?}"),
+    }
+}
+
+#[test_log::test]
+fn parameter_changes() {
+    let report = compare(
+        stringify!(
+            fn widened(x: int) {}
+            fn narrowed(x: object) {}
+            fn unchanged(x: int) {}
+        ),
+        stringify!(
+            fn widened(x: object) {}
+            fn narrowed(x: int) {}
+            fn unchanged(x: int) {}
+        ),
+    );
+
+    let widened = comparison(&report, "widened");
+    assert_eq!(widened.parameter, ParameterChange::Widened);
+    assert!(widened.is_backwards_compatible());
+
+    let narrowed = comparison(&report, "narrowed");
+    assert_eq!(narrowed.parameter, ParameterChange::Narrowed);
+    assert_eq!(narrowed.mismatch, Some(SignatureMismatch::Parameter));
+
+    let unchanged = comparison(&report, "unchanged");
+    assert_eq!(unchanged.parameter, ParameterChange::Unchanged);
+    assert!(unchanged.is_backwards_compatible());
+
+    assert_eq!(
+        report.breaking_operations().collect::<Vec<_>>(),
+        ["narrowed"]
+    );
+}
+
+#[test_log::test]
+fn renamed_parameters_break_callers() {
+    let report = compare(
+        stringify!(
+            fn bump(x: int) {}
+        ),
+        stringify!(
+            fn bump(y: int) {}
+        ),
+    );
+    let bump = comparison(&report, "bump");
+    // the types did not change, but callers pass their argument as `x`
+    assert_eq!(bump.parameter, ParameterChange::Unchanged);
+    assert_eq!(bump.mismatch, Some(SignatureMismatch::ParameterNames));
+    assert_eq!(
+        bump.bindings,
+        [BindingChange::RenamedParameter(0, "x".into(), "y".into())]
+    );
+    assert_eq!(
+        report.to_string(),
+        "bump: BREAKING: renamed parameter 0 from `x` to `y`\n"
+    );
+}
+
+#[test_log::test]
+fn context_changes_break_callers() {
+    let report = compare(
+        stringify!(
+            fn added(x: int) {}
+            fn moved(x: int) [c: int, x -> c: *] {}
+        ),
+        stringify!(
+            fn added(x: int) [c: int, x -> c: *] {}
+            fn moved(x: int) [c: int, c -> x: *] {}
+        ),
+    );
+    let added = comparison(&report, "added");
+    assert_eq!(added.mismatch, Some(SignatureMismatch::ContextNodes));
+    assert_eq!(
+        added.bindings,
+        [
+            BindingChange::AddedContextEdge(("x".into(), "c".into())),
+            BindingChange::AddedContextNode("c".into()),
+        ]
+    );
+
+    let moved = comparison(&report, "moved");
+    assert_eq!(moved.mismatch, Some(SignatureMismatch::ContextEdges));
+    assert_eq!(
+        moved.bindings,
+        [
+            BindingChange::AddedContextEdge(("c".into(), "x".into())),
+            BindingChange::RemovedContextEdge(("x".into(), "c".into())),
+        ]
+    );
+    assert_eq!(
+        report.breaking_operations().collect::<Vec<_>>(),
+        ["added", "moved"]
+    );
+}
+
+#[test_log::test]
+fn output_changes() {
+    let report = compare(
+        stringify!(
+            fn new_child(x: int) -> (child: int) {
+                let! child = add_node<int,0>();
+                add_edge<"child">(x, child);
+                return (child: child);
+            }
+
+            fn maybe_delete(x: int, y: int) {}
+        ),
+        stringify!(
+            fn new_child(x: int) -> (child: object) {
+                let! child = add_node<int,0>();
+                add_edge<"child">(x, child);
+                return (child: child);
+            }
+
+            fn maybe_delete(x: int, y: int) {
+                if is_eq<0>(x) {
+                    remove_node(y);
+                }
+            }
+        ),
+    );
+
+    let new_child = comparison(&report, "new_child");
+    assert_eq!(new_child.mismatch, Some(SignatureMismatch::Output));
+    assert_eq!(
+        new_child.output,
+        [OutputChange::NewNodeType("child".into())]
+    );
+
+    let maybe_delete = comparison(&report, "maybe_delete");
+    assert_eq!(maybe_delete.parameter, ParameterChange::Unchanged);
+    assert_eq!(maybe_delete.mismatch, Some(SignatureMismatch::Output));
+    assert!(
+        maybe_delete
+            .output
+            .contains(&OutputChange::MaybeDeletedNode("y".into()))
+    );
+
+    // the reverse direction does not break any callers
+    let report = compare(
+        stringify!(
+            fn maybe_delete(x: int, y: int) {
+                remove_node(y);
+            }
+        ),
+        stringify!(
+            fn maybe_delete(x: int, y: int) {}
+        ),
+    );
+    assert!(report.is_backwards_compatible());
+}
+
+#[test_log::test]
+fn added_and_removed_operations() {
+    let report = compare(
+        stringify!(
+            fn removed(x: int) {}
+            fn kept(x: int, y: string) {}
+        ),
+        stringify!(
+            fn added(x: int) {}
+            fn kept(x: int, y: string) {
+                copy_value_from_to(y, x);
+            }
+        ),
+    );
+    assert_eq!(report.get("added"), Some(&OperationChange::Added));
+    assert_eq!(report.get("removed"), Some(&OperationChange::Removed));
+    assert!(!report.is_backwards_compatible());
+
+    // `kept` now writes a string to `x`, which its callers did not expect
+    assert_eq!(
+        report.to_string(),
+        "added: added\n\
+         kept: BREAKING: may change node `x` to a new type\n\
+         removed: BREAKING: removed\n"
+    );
+}
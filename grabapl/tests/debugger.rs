@@ -0,0 +1,185 @@
+mod util;
+
+use grabapl::operation::OperationError;
+use grabapl::operation::debugger::{Breakpoint, DebugContext, Debugger, DebuggerStatus};
+use grabapl::operation::user_defined::{Instruction, InstructionPath, QueryBranch};
+use grabapl::prelude::*;
+use syntax::grabapl_defs;
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+fn main(x: int) {
+    inc_twice(x);
+    if is_zero(x) {
+        decrement(x);
+    } else {
+        let! y = add_node<int,0>();
+        copy_value_from_to(x, y);
+    }
+    mark_node<"m">(x);
+}
+
+fn inc_twice(x: int) {
+    increment(x);
+    increment(x);
+}
+);
+
+fn setup() -> (
+    OperationContext<TestSemantics>,
+    std::collections::HashMap<&'static str, OperationId>,
+    ConcreteGraph<TestSemantics>,
+    NodeKey,
+) {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = g.add_node(NodeValue::Integer(0));
+    (op_ctx, fn_names, g, x)
+}
+
+#[test_log::test]
+fn step_into_and_out_of_calls() {
+    let (op_ctx, fn_names, mut g, x) = setup();
+    let ctx = DebugContext::new(ExecutionLimits::unlimited());
+    let mut dbg = Debugger::new(&ctx, &mut g, &op_ctx, fn_names["main"], &[x]).unwrap();
+
+    assert_eq!(dbg.status(), DebuggerStatus::Paused);
+    assert_eq!(dbg.current_operation(), Some(fn_names["main"]));
+    assert_eq!(dbg.current_path(), Some(InstructionPath::top_level(0)));
+    assert_eq!(
+        dbg.abstract_to_concrete().unwrap()[&AbstractNodeId::param("x")],
+        x
+    );
+
+    // step into inc_twice
+    dbg.step().unwrap();
+    assert_eq!(dbg.current_operation(), Some(fn_names["inc_twice"]));
+    assert_eq!(
+        dbg.call_stack(),
+        vec![fn_names["main"], fn_names["inc_twice"]]
+    );
+    assert_eq!(dbg.current_path(), Some(InstructionPath::top_level(0)));
+    // nodes the caller has a handle to are hidden from the callee's shape queries
+    assert!(dbg.hidden_nodes().unwrap().contains(&x));
+
+    dbg.step().unwrap();
+    assert_eq!(dbg.graph().get_node_attr(x), Some(&NodeValue::Integer(1)));
+    assert_eq!(dbg.current_path(), Some(InstructionPath::top_level(1)));
+
+    // step out back into main, right before the query
+    dbg.step_out().unwrap();
+    assert_eq!(dbg.current_operation(), Some(fn_names["main"]));
+    assert_eq!(dbg.current_path(), Some(InstructionPath::top_level(1)));
+    assert_eq!(dbg.graph().get_node_attr(x), Some(&NodeValue::Integer(2)));
+
+    // the query takes the else branch
+    dbg.step().unwrap();
+    assert_eq!(
+        dbg.current_path(),
+        Some(InstructionPath::new(vec![(1, QueryBranch::NotTaken)], 0))
+    );
+    // `let!` bindings are followed by a rename instruction
+    dbg.step().unwrap();
+    dbg.step().unwrap();
+    let y = *dbg
+        .abstract_to_concrete()
+        .unwrap()
+        .values()
+        .find(|&&n| n != x)
+        .unwrap();
+    dbg.step().unwrap();
+    assert_eq!(dbg.graph().get_node_attr(y), Some(&NodeValue::Integer(2)));
+    // leave the branch, which may end in bookkeeping instructions
+    while !dbg.current_path().unwrap().queries.is_empty() {
+        dbg.step().unwrap();
+    }
+    assert_eq!(dbg.current_path(), Some(InstructionPath::top_level(2)));
+
+    assert!(!dbg.marker_set().all_marked_nodes().any(|n| n == x));
+    assert_eq!(dbg.step().unwrap(), DebuggerStatus::Finished);
+    assert!(dbg.marker_set().all_marked_nodes().any(|n| n == x));
+    assert!(dbg.output().is_some());
+    assert_eq!(dbg.current_operation(), None);
+}
+
+#[test_log::test]
+fn step_over_calls() {
+    let (op_ctx, fn_names, mut g, x) = setup();
+    let ctx = DebugContext::new(ExecutionLimits::unlimited());
+    let mut dbg = Debugger::new(&ctx, &mut g, &op_ctx, fn_names["main"], &[x]).unwrap();
+
+    dbg.step_over().unwrap();
+    assert_eq!(dbg.current_operation(), Some(fn_names["main"]));
+    assert_eq!(dbg.current_path(), Some(InstructionPath::top_level(1)));
+    assert_eq!(dbg.graph().get_node_attr(x), Some(&NodeValue::Integer(2)));
+}
+
+#[test_log::test]
+fn breakpoints() {
+    let (op_ctx, fn_names, mut g, x) = setup();
+    let ctx = DebugContext::new(ExecutionLimits::unlimited());
+    let mut dbg = Debugger::new(&ctx, &mut g, &op_ctx, fn_names["main"], &[x]).unwrap();
+
+    let in_callee = Breakpoint::new(fn_names["inc_twice"], InstructionPath::top_level(1));
+    let in_branch = Breakpoint::new(
+        fn_names["main"],
+        // `let!` bindings are followed by a rename instruction
+        InstructionPath::new(vec![(1, QueryBranch::NotTaken)], 2),
+    );
+    dbg.add_breakpoint(in_callee.clone());
+    dbg.add_breakpoint(in_branch);
+
+    // step over still stops at breakpoints inside the callee
+    assert_eq!(dbg.step_over().unwrap(), DebuggerStatus::Paused);
+    assert!(dbg.at_breakpoint());
+    assert_eq!(dbg.current_operation(), Some(fn_names["inc_twice"]));
+    assert_eq!(dbg.graph().get_node_attr(x), Some(&NodeValue::Integer(1)));
+
+    assert!(dbg.remove_breakpoint(&in_callee));
+    assert_eq!(dbg.resume().unwrap(), DebuggerStatus::Paused);
+    assert_eq!(dbg.current_operation(), Some(fn_names["main"]));
+    assert!(matches!(
+        dbg.current_instruction(),
+        Some(Instruction::OpLike(..))
+    ));
+    assert_eq!(dbg.abstract_to_concrete().unwrap().len(), 2);
+
+    assert_eq!(dbg.resume().unwrap(), DebuggerStatus::Finished);
+    // finished debuggers do not step
+    assert_eq!(dbg.step().unwrap(), DebuggerStatus::Finished);
+}
+
+#[test_log::test]
+fn errors_stop_the_debugger() {
+    let (op_ctx, fn_names, mut g, x) = setup();
+    let ctx = DebugContext::new(ExecutionLimits::unlimited().with_max_steps(3));
+    let mut dbg = Debugger::new(&ctx, &mut g, &op_ctx, fn_names["main"], &[x]).unwrap();
+
+    let err = dbg.resume().unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        OperationError::ExecutionLimitExceeded { .. }
+    ));
+    assert_eq!(dbg.status(), DebuggerStatus::Failed);
+    assert_eq!(dbg.step().unwrap(), DebuggerStatus::Failed);
+}
+
+#[test_log::test]
+fn only_user_defined_operations_can_be_debugged() {
+    let (mut op_ctx, _, mut g, _) = setup();
+    let ctx = DebugContext::new(ExecutionLimits::unlimited());
+    let add_node = 1000;
+    op_ctx.add_lib_builtin_operation(
+        add_node,
+        LibBuiltinOperation::AddNode {
+            value: NodeValue::Integer(0),
+        },
+    );
+    let Err(err) = Debugger::new(&ctx, &mut g, &op_ctx, add_node, &[]) else {
+        panic!("expected an error");
+    };
+    assert!(matches!(
+        err.current_context(),
+        OperationError::ExpectedUserDefinedOperation(op) if *op == add_node
+    ));
+}
@@ -0,0 +1,80 @@
+mod util;
+
+use grabapl::prelude::*;
+use syntax::grabapl_defs;
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+// tail recursive
+fn count_down(x: int) {
+    if is_zero(x) {
+    } else {
+        decrement(x);
+        count_down(x);
+    }
+}
+
+// not tail recursive, every level keeps its frame alive
+fn transfer(x: int, acc: int) {
+    if is_zero(x) {
+    } else {
+        decrement(x);
+        transfer(x, acc);
+        increment(acc);
+    }
+}
+
+fn list_length(head: int, acc: int) {
+    increment(acc);
+    if shape [
+        next: int,
+        head -> next: "next",
+    ] {
+        list_length(next, acc);
+    }
+}
+);
+
+const DEPTH: i32 = 100_000;
+
+#[test_log::test]
+fn deep_tail_recursion_does_not_grow_the_stack() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = g.add_node(NodeValue::Integer(DEPTH));
+
+    // tail calls replace the calling frame
+    let limits = ExecutionLimits::unlimited().with_max_recursion_depth(1);
+    run_from_concrete_with_limits(&mut g, &op_ctx, fn_names["count_down"], &[x], limits).unwrap();
+    assert_eq!(g.get_node_attr(x), Some(&NodeValue::Integer(0)));
+}
+
+#[test_log::test]
+fn deep_non_tail_recursion_does_not_overflow() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = g.add_node(NodeValue::Integer(DEPTH));
+    let acc = g.add_node(NodeValue::Integer(0));
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["transfer"], &[x, acc]).unwrap();
+    assert_eq!(g.get_node_attr(x), Some(&NodeValue::Integer(0)));
+    assert_eq!(g.get_node_attr(acc), Some(&NodeValue::Integer(DEPTH)));
+}
+
+#[test_log::test]
+fn list_walk() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let length = 300;
+    let head = g.add_node(NodeValue::Integer(0));
+    let mut prev = head;
+    for i in 1..length {
+        let next = g.add_node(NodeValue::Integer(i));
+        g.add_edge(prev, next, "next".to_string());
+        prev = next;
+    }
+    let acc = g.add_node(NodeValue::Integer(0));
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["list_length"], &[head, acc]).unwrap();
+    assert_eq!(g.get_node_attr(acc), Some(&NodeValue::Integer(length)));
+}
@@ -0,0 +1,114 @@
+mod util;
+
+use grabapl::operation::execution::ExecutionLimit;
+use grabapl::operation::signature::parameter::ConcreteOperationOutput;
+use grabapl::operation::{OperationError, OperationResult};
+use grabapl::prelude::*;
+use syntax::grabapl_defs;
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+fn spin(x: int) {
+    spin(x);
+    // not a tail call, so every call grows the stack
+    increment(x);
+}
+
+fn grow(x: int) {
+    let! n = add_node<int,0>();
+    add_edge<"next">(x, n);
+    grow(n);
+}
+
+fn count_down(x: int) {
+    if is_zero(x) {
+    } else {
+        decrement(x);
+        count_down(x);
+        increment(x);
+    }
+}
+);
+
+fn expect_limit(
+    res: OperationResult<ConcreteOperationOutput<TestSemantics>>,
+) -> (ExecutionLimit, OperationId) {
+    let Err(err) = res else {
+        panic!("expected the run to exceed its limits");
+    };
+    match err.current_context() {
+        OperationError::ExecutionLimitExceeded { limit, operation } => (*limit, *operation),
+        other => panic!("unexpected error: {other:?}"),
+    }
+}
+
+#[test_log::test]
+fn recursion_depth_limit() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = g.add_node(NodeValue::Integer(0));
+
+    let limits = ExecutionLimits::unlimited().with_max_recursion_depth(50);
+    let res = run_from_concrete_with_limits(&mut g, &op_ctx, fn_names["spin"], &[x], limits);
+    let (limit, operation) = expect_limit(res);
+    assert_eq!(limit, ExecutionLimit::RecursionDepth(50));
+    assert_eq!(operation, fn_names["spin"]);
+}
+
+#[test_log::test]
+fn step_limit() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = g.add_node(NodeValue::Integer(0));
+
+    let limits = ExecutionLimits::unlimited().with_max_steps(20);
+    let res = run_from_concrete_with_limits(&mut g, &op_ctx, fn_names["spin"], &[x], limits);
+    let (limit, operation) = expect_limit(res);
+    assert_eq!(limit, ExecutionLimit::Steps(20));
+    assert_eq!(operation, fn_names["spin"]);
+}
+
+#[test_log::test]
+fn node_and_edge_growth_limits() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = g.add_node(NodeValue::Integer(0));
+
+    let limits = ExecutionLimits::unlimited().with_max_node_growth(10);
+    let res = run_from_concrete_with_limits(&mut g, &op_ctx, fn_names["grow"], &[x], limits);
+    let (limit, _) = expect_limit(res);
+    assert_eq!(limit, ExecutionLimit::NodeGrowth(10));
+    // the limit is checked immediately after the offending instruction
+    assert_eq!(g.node_count(), 1 + 11);
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = g.add_node(NodeValue::Integer(0));
+    let limits = ExecutionLimits::unlimited().with_max_edge_growth(5);
+    let res = run_from_concrete_with_limits(&mut g, &op_ctx, fn_names["grow"], &[x], limits);
+    let (limit, _) = expect_limit(res);
+    assert_eq!(limit, ExecutionLimit::EdgeGrowth(5));
+    assert_eq!(g.edge_count(), 6);
+}
+
+#[test_log::test]
+fn terminating_run_within_limits_succeeds() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = g.add_node(NodeValue::Integer(10));
+
+    let limits = ExecutionLimits::unlimited()
+        .with_max_steps(1000)
+        .with_max_recursion_depth(20)
+        .with_max_node_growth(0)
+        .with_max_edge_growth(0);
+    run_from_concrete_with_limits(&mut g, &op_ctx, fn_names["count_down"], &[x], limits).unwrap();
+    assert_eq!(g.get_node_attr(x), Some(&NodeValue::Integer(10)));
+
+    // the same run exceeds a tighter recursion limit
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = g.add_node(NodeValue::Integer(10));
+    let limits = ExecutionLimits::unlimited().with_max_recursion_depth(5);
+    let res = run_from_concrete_with_limits(&mut g, &op_ctx, fn_names["count_down"], &[x], limits);
+    let (limit, _) = expect_limit(res);
+    assert_eq!(limit, ExecutionLimit::RecursionDepth(5));
+}
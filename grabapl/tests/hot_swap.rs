@@ -0,0 +1,265 @@
+mod util;
+
+use grabapl::operation::signature::SignatureMismatch;
+use grabapl::operation::{HotSwapError, IncompatibleCaller};
+use grabapl::prelude::*;
+use util::semantics::*;
+
+const PROGRAM: &str = stringify!(
+    fn bump(x: object) {}
+
+    fn bump_twice(x: int) {
+        bump(x);
+        bump(x);
+    }
+
+    fn bump_thrice(x: int) {
+        bump(x);
+        bump(x);
+        bump(x);
+    }
+);
+
+fn compile(src: &str) -> OperationContext<TestSemantics> {
+    syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false)
+        .op_ctx_and_map
+        .unwrap()
+        .0
+}
+
+/// Compiles `src` on its own and returns its definition of `name`.
+fn recompile(src: &str, name: &str) -> UserDefinedOperation<TestSemantics> {
+    match compile(src).get_by_name(name) {
+        Some(Operation::Custom(op)) => op.clone(),
+        _ => panic!("expected `{name}` to be a user defined operation"),
+    }
+}
+
+/// Replaces `id` with `new`, expecting the replacement to be rejected.
+fn swap_error(
+    op_ctx: &mut OperationContext<TestSemantics>,
+    id: OperationId,
+    new: UserDefinedOperation<TestSemantics>,
+) -> HotSwapError {
+    let Err(err) = op_ctx.replace_custom_operation(id, new) else {
+        panic!("expected the replacement to be rejected");
+    };
+    err.current_context().clone()
+}
+
+#[test_log::test]
+fn compatible_replacement_is_used_by_callers() {
+    let mut op_ctx = compile(&PROGRAM.replace("bump(x: object)", "bump(x: int)"));
+    let bump = op_ctx.id_of("bump").unwrap();
+    let new = recompile(
+        stringify!(
+            fn bump(x: int) {
+                increment(x);
+            }
+        ),
+        "bump",
+    );
+    op_ctx.replace_custom_operation(bump, new).unwrap();
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let node = g.add_node(NodeValue::Integer(0));
+    run_from_concrete(
+        &mut g,
+        &op_ctx,
+        op_ctx.id_of("bump_twice").unwrap(),
+        &[node],
+    )
+    .unwrap();
+    assert_eq!(g.get_node_attr(node), Some(&NodeValue::Integer(2)));
+
+    // accepting more arguments is fine as well
+    let new = recompile(
+        stringify!(
+            fn bump(x: object) {}
+        ),
+        "bump",
+    );
+    op_ctx.replace_custom_operation(bump, new).unwrap();
+}
+
+#[test_log::test]
+fn incompatible_replacement_reports_every_caller() {
+    let mut op_ctx = compile(PROGRAM);
+    let bump = op_ctx.id_of("bump").unwrap();
+    let new = recompile(
+        stringify!(
+            fn bump(x: int) {}
+        ),
+        "bump",
+    );
+    let HotSwapError::IncompatibleCallers(callers) = swap_error(&mut op_ctx, bump, new) else {
+        panic!("expected incompatible callers");
+    };
+    let mut names = callers
+        .iter()
+        .map(|caller| caller.name.as_deref().unwrap())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, ["bump_thrice", "bump_twice"]);
+    assert!(callers.iter().all(|caller| matches!(
+        caller,
+        IncompatibleCaller {
+            mismatch: SignatureMismatch::Parameter,
+            ..
+        }
+    )));
+
+    // the previous definition is kept
+    let int_parameter = recompile("fn bump(x: int) {}", "bump").signature.parameter;
+    assert!(
+        op_ctx
+            .get(bump)
+            .unwrap()
+            .parameter()
+            .is_subtype_of(&int_parameter)
+    );
+}
+
+#[test_log::test]
+fn output_changes_must_be_compatible() {
+    let src = stringify!(
+        fn new_child(x: int) -> (child: int) {
+            let! child = add_node<int,0>();
+            add_edge<"child">(x, child);
+            return (child: child);
+        }
+
+        fn uses_child(x: int) {
+            let! child = new_child(x);
+            increment(child);
+        }
+    );
+    let mut op_ctx = compile(src);
+    let new_child = op_ctx.id_of("new_child").unwrap();
+    let new = recompile(
+        stringify!(
+            fn new_child(x: int) {}
+        ),
+        "new_child",
+    );
+    let HotSwapError::IncompatibleCallers(callers) = swap_error(&mut op_ctx, new_child, new) else {
+        panic!("expected incompatible callers");
+    };
+    assert_eq!(
+        callers,
+        [IncompatibleCaller {
+            caller: op_ctx.id_of("uses_child").unwrap(),
+            name: Some("uses_child".to_string()),
+            mismatch: SignatureMismatch::Output,
+        }]
+    );
+}
+
+#[test_log::test]
+fn operations_without_callers_can_be_replaced_freely() {
+    let mut op_ctx = compile(PROGRAM);
+    let bump_twice = op_ctx.id_of("bump_twice").unwrap();
+    let new = recompile(
+        stringify!(
+            fn bump_twice(x: string) {}
+        ),
+        "bump_twice",
+    );
+    let old = op_ctx.replace_custom_operation(bump_twice, new).unwrap();
+    assert_eq!(old.signature.parameter.explicit_input_nodes.len(), 1);
+
+    let builtin = op_ctx.add_named_builtin_operation("add_one", TestOperation::AddInteger(1));
+    assert!(matches!(
+        swap_error(&mut op_ctx, builtin, recompile(PROGRAM, "bump")),
+        HotSwapError::NotAUserDefinedOperation(id) if id == builtin
+    ));
+}
+
+#[test_log::test]
+fn renamed_parameters_are_rejected() {
+    let mut op_ctx = compile(&PROGRAM.replace("bump(x: object)", "bump(x: int)"));
+    let bump = op_ctx.id_of("bump").unwrap();
+    // callers pass their arguments by the callee's parameter names
+    let new = recompile(
+        stringify!(
+            fn bump(y: int) {
+                increment(y);
+            }
+        ),
+        "bump",
+    );
+    let HotSwapError::IncompatibleCallers(callers) = swap_error(&mut op_ctx, bump, new) else {
+        panic!("expected incompatible callers");
+    };
+    assert_eq!(callers.len(), 2);
+    assert!(
+        callers
+            .iter()
+            .all(|caller| caller.mismatch == SignatureMismatch::ParameterNames)
+    );
+
+    // the previous definition still works
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let node = g.add_node(NodeValue::Integer(0));
+    run_from_concrete(
+        &mut g,
+        &op_ctx,
+        op_ctx.id_of("bump_twice").unwrap(),
+        &[node],
+    )
+    .unwrap();
+    assert_eq!(g.get_node_attr(node), Some(&NodeValue::Integer(0)));
+}
+
+#[test_log::test]
+fn context_changes_are_rejected() {
+    let src = stringify!(
+        fn bump_child(x: int) [c: int, x -> c: *] {
+            increment(c);
+        }
+
+        fn caller(x: int) {
+            if shape [c: int, x -> c: *] {
+                bump_child(x);
+            }
+        }
+    );
+    let mut op_ctx = compile(src);
+    let bump_child = op_ctx.id_of("bump_child").unwrap();
+    let caller = op_ctx.id_of("caller").unwrap();
+
+    let mismatch = |op_ctx: &mut OperationContext<TestSemantics>, new_src: &str| {
+        let new = recompile(new_src, "bump_child");
+        let HotSwapError::IncompatibleCallers(callers) = swap_error(op_ctx, bump_child, new) else {
+            panic!("expected incompatible callers");
+        };
+        assert_eq!(callers.len(), 1);
+        assert_eq!(callers[0].caller, caller);
+        callers[0].mismatch
+    };
+    assert_eq!(
+        mismatch(
+            &mut op_ctx,
+            "fn bump_child(x: int) [d: int, x -> d: *] { increment(d); }"
+        ),
+        SignatureMismatch::ContextNodes
+    );
+    assert_eq!(
+        mismatch(
+            &mut op_ctx,
+            "fn bump_child(x: int) [c: int, x -> c: *, c -> x: *] { increment(c); }"
+        ),
+        SignatureMismatch::ContextEdges
+    );
+    assert_eq!(
+        mismatch(&mut op_ctx, "fn bump_child(x: int) { increment(x); }"),
+        SignatureMismatch::ContextNodes
+    );
+
+    // the same context with a different body is fine
+    let new = recompile(
+        "fn bump_child(x: int) [c: int, x -> c: *] { decrement(c); }",
+        "bump_child",
+    );
+    op_ctx.replace_custom_operation(bump_child, new).unwrap();
+}
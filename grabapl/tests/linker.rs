@@ -0,0 +1,222 @@
+mod util;
+
+use grabapl::operation::linker::{LinkError, Linker};
+use grabapl::operation::signature::SignatureMismatch;
+use grabapl::prelude::*;
+use util::semantics::*;
+
+const LIBRARY: &str = stringify!(
+    fn bump(x: int) {
+        increment(x);
+    }
+);
+
+fn compile(src: &str) -> OperationContext<TestSemantics> {
+    syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false)
+        .op_ctx_and_map
+        .unwrap()
+        .0
+}
+
+fn compile_with_library(
+    src: &str,
+    library: &OperationContext<TestSemantics>,
+) -> OperationContext<TestSemantics> {
+    syntax::try_parse_to_op_ctx_and_map_with_library::<TestSemantics>(src, false, library)
+        .op_ctx_and_map
+        .unwrap()
+        .0
+}
+
+fn link_error(contexts: Vec<OperationContext<TestSemantics>>) -> LinkError {
+    let mut linker = Linker::new();
+    for op_ctx in contexts {
+        linker.add(op_ctx);
+    }
+    let Err(err) = linker.link() else {
+        panic!("expected linking to fail");
+    };
+    err.current_context().clone()
+}
+
+#[test_log::test]
+fn library_is_linked_into_programs() {
+    let library = compile(LIBRARY);
+    let bump_twice = compile_with_library(
+        stringify!(
+            fn bump_twice(x: int) {
+                bump(x);
+                bump(x);
+            }
+        ),
+        &library,
+    );
+    let bump_thrice = compile_with_library(
+        stringify!(
+            fn bump_thrice(x: int) {
+                bump(x);
+                bump(x);
+                bump(x);
+            }
+        ),
+        &library,
+    );
+
+    let mut linker = Linker::new();
+    linker.add(library);
+    linker.add(bump_twice);
+    linker.add(bump_thrice);
+    let op_ctx = linker.link().unwrap().op_ctx;
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let node = g.add_node(NodeValue::Integer(0));
+    for name in ["bump", "bump_twice", "bump_thrice"] {
+        run_from_concrete(&mut g, &op_ctx, op_ctx.id_of(name).unwrap(), &[node]).unwrap();
+    }
+    assert_eq!(g.get_node_attr(node), Some(&NodeValue::Integer(6)));
+}
+
+#[test_log::test]
+fn declarations_must_be_resolved() {
+    let library = compile(LIBRARY);
+    let program = compile_with_library(
+        stringify!(
+            fn bump_twice(x: int) {
+                bump(x);
+                bump(x);
+            }
+        ),
+        &library,
+    );
+    assert!(matches!(
+        link_error(vec![program]),
+        LinkError::UnresolvedDeclaration(name) if name == "bump"
+    ));
+}
+
+#[test_log::test]
+fn names_must_be_unique() {
+    assert!(matches!(
+        link_error(vec![compile(LIBRARY), compile(LIBRARY)]),
+        LinkError::NameCollision(name) if name == "bump"
+    ));
+}
+
+#[test_log::test]
+fn declared_signature_must_be_satisfied() {
+    let old_library = compile(stringify!(
+        fn bump(x: object) {}
+    ));
+    let program = compile_with_library(
+        stringify!(
+            fn bump_any(x: object) {
+                bump(x);
+            }
+        ),
+        &old_library,
+    );
+    // the new version only accepts integers
+    assert!(matches!(
+        link_error(vec![compile(LIBRARY), program]),
+        LinkError::IncompatibleSignature {
+            mismatch: SignatureMismatch::Parameter,
+            ..
+        }
+    ));
+}
+
+/// Builds an operation that calls `callee` on its integer parameter.
+fn caller_of(
+    op_ctx: &OperationContext<TestSemantics>,
+    id: OperationId,
+    callee: BuilderOpLike<TestSemantics>,
+) -> UserDefinedOperation<TestSemantics> {
+    let mut builder = OperationBuilder::<TestSemantics>::new(op_ctx, id);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    builder
+        .add_operation(callee, vec![AbstractNodeId::param("p0")])
+        .unwrap();
+    builder.build().unwrap()
+}
+
+#[test_log::test]
+fn unnamed_operations_are_remapped() {
+    // both contexts use ids 0 and 1, where 1 calls 0
+    let contexts = [1, 10].map(|amount| {
+        let mut op_ctx = OperationContext::<TestSemantics>::new();
+        let add = caller_of(
+            &op_ctx,
+            0,
+            BuilderOpLike::Builtin(TestOperation::AddInteger(amount)),
+        );
+        op_ctx.add_custom_operation(0, add);
+        let call = caller_of(&op_ctx, 1, BuilderOpLike::FromOperationId(0));
+        op_ctx.add_custom_operation(1, call);
+        op_ctx
+    });
+
+    let mut linker = Linker::new();
+    for op_ctx in contexts {
+        linker.add(op_ctx);
+    }
+    let linked = linker.link().unwrap();
+    assert_ne!(linked.id_maps[0][&1], linked.id_maps[1][&1]);
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let node = g.add_node(NodeValue::Integer(0));
+    for id_map in &linked.id_maps {
+        run_from_concrete(&mut g, &linked.op_ctx, id_map[&1], &[node]).unwrap();
+    }
+    assert_eq!(g.get_node_attr(node), Some(&NodeValue::Integer(11)));
+}
+
+#[test_log::test]
+fn unnamed_operations_are_remapped_deterministically() {
+    // both contexts use ids 0 to 7, so every operation of the second context collides
+    let link = || {
+        let mut linker = Linker::new();
+        for _ in 0..2 {
+            let mut op_ctx = OperationContext::<TestSemantics>::new();
+            for id in 0..8 {
+                let add = caller_of(
+                    &op_ctx,
+                    id,
+                    BuilderOpLike::Builtin(TestOperation::AddInteger(1)),
+                );
+                op_ctx.add_custom_operation(id, add);
+            }
+            linker.add(op_ctx);
+        }
+        linker.link().unwrap().id_maps
+    };
+    let first = link();
+    for _ in 0..10 {
+        assert_eq!(link(), first);
+    }
+}
+
+#[test_log::test]
+fn declarations_cannot_resolve_to_builtins() {
+    let library = compile(LIBRARY);
+    let program = compile_with_library(
+        stringify!(
+            fn bump_twice(x: int) {
+                bump(x);
+                bump(x);
+            }
+        ),
+        &library,
+    );
+    // the builtin accepts the declared parameter, but its effects cannot be checked
+    let mut builtins = OperationContext::<TestSemantics>::new();
+    builtins.add_named_builtin_operation("bump", TestOperation::AddInteger(1));
+    assert!(matches!(
+        link_error(vec![builtins, program]),
+        LinkError::IncompatibleSignature {
+            mismatch: SignatureMismatch::UnknownOutput,
+            ..
+        }
+    ));
+}
@@ -0,0 +1,130 @@
+mod util;
+
+use grabapl::operation::signature::AbstractOutputChanges;
+use grabapl::prelude::*;
+use util::semantics::*;
+
+const SET_TO_INT: &str = stringify!(
+    fn main(x: object) {
+        set_to_int(x);
+        needs_int(x);
+        show_state();
+    }
+
+    // writes an integer to `x` on every path
+    fn set_to_int(x: object) {
+        let! i = add_node<int,1>();
+        if shape [c: int, x -> c: *] {
+            copy_value_from_to(c, x);
+        } else {
+            copy_value_from_to(i, x);
+        }
+    }
+
+    fn needs_int(n: int) {}
+);
+
+#[test_log::test]
+fn must_written_node_has_precise_type_at_call_site() {
+    let res = syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(SET_TO_INT, false);
+    let (op_ctx, fn_names) = res.op_ctx_and_map.unwrap();
+    let state = res.state_map.values().next().unwrap();
+    assert_eq!(
+        state.node_av_of_aid(&AbstractNodeId::param("x")),
+        Some(&NodeType::Integer)
+    );
+
+    let Some(Operation::Custom(set_to_int)) = op_ctx.get(fn_names["set_to_int"]) else {
+        panic!("expected a user defined operation");
+    };
+    assert_eq!(
+        set_to_int.signature.output.must_changed_nodes,
+        [("x".into(), NodeType::Integer)].into()
+    );
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = g.add_node(NodeValue::String("hello".to_string()));
+    run_from_concrete(&mut g, &op_ctx, fn_names["main"], &[x]).unwrap();
+    assert_eq!(g.get_node_attr(x), Some(&NodeValue::Integer(1)));
+}
+
+#[test_log::test]
+fn maybe_written_node_is_joined_with_old_type() {
+    let src = SET_TO_INT.replace("copy_value_from_to(i, x);", "");
+    let res = syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(&src, false);
+    assert!(res.op_ctx_and_map.is_err());
+
+    let src = src.replace("needs_int(x);", "");
+    let res = syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(&src, false);
+    let (op_ctx, fn_names) = res.op_ctx_and_map.unwrap();
+    let Some(Operation::Custom(set_to_int)) = op_ctx.get(fn_names["set_to_int"]) else {
+        panic!("expected a user defined operation");
+    };
+    let output = &set_to_int.signature.output;
+    assert!(output.maybe_changed_nodes.contains_key(&"x".into()));
+    assert!(output.must_changed_nodes.is_empty());
+}
+
+#[test_log::test]
+fn builder_infers_must_changed_edges() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Object)
+        .unwrap();
+    builder
+        .expect_parameter_node("p1", NodeType::Object)
+        .unwrap();
+    let p0 = AbstractNodeId::param("p0");
+    let p1 = AbstractNodeId::param("p1");
+    builder
+        .expect_parameter_edge("p0", "p1", EdgeType::Wildcard)
+        .unwrap();
+    builder
+        .add_operation(
+            BuilderOpLike::Builtin(TestOperation::SetEdgeTo {
+                node_typ: NodeType::Object,
+                param_typ: EdgeType::Wildcard,
+                target_typ: EdgeType::Exact("set".to_string()),
+                value: "set".to_string(),
+            }),
+            vec![p0, p1],
+        )
+        .unwrap();
+    let op = builder.build().unwrap();
+
+    let output = &op.signature.output;
+    assert_eq!(
+        output.must_changed_edges,
+        [(
+            ("p0".into(), "p1".into()),
+            EdgeType::Exact("set".to_string())
+        )]
+        .into()
+    );
+    assert!(output.must_changed_nodes.is_empty());
+}
+
+#[test_log::test]
+fn subtyping_respects_must_changes() {
+    let x = SubstMarker::from("x");
+    let mut must_int = AbstractOutputChanges::<TestSemantics>::new();
+    must_int.maybe_changed_nodes.insert(x, NodeType::Integer);
+    must_int.must_changed_nodes.insert(x, NodeType::Integer);
+    let mut maybe_int = AbstractOutputChanges::<TestSemantics>::new();
+    maybe_int.maybe_changed_nodes.insert(x, NodeType::Integer);
+    let mut maybe_object = AbstractOutputChanges::<TestSemantics>::new();
+    maybe_object.maybe_changed_nodes.insert(x, NodeType::Object);
+    let mut must_object = maybe_object.clone();
+    must_object.must_changed_nodes.insert(x, NodeType::Object);
+
+    // callers expecting a maybe-change are fine with a must-change
+    assert!(must_int.is_subtype_of(&maybe_int));
+    assert!(must_int.is_subtype_of(&maybe_object));
+    // callers expecting a must-change rely on the old value being gone
+    assert!(!maybe_int.is_subtype_of(&must_int));
+    assert!(!maybe_object.is_subtype_of(&must_object));
+    // the must-changed type is covariant
+    assert!(must_int.is_subtype_of(&must_object));
+    assert!(!must_object.is_subtype_of(&must_int));
+}
@@ -0,0 +1,221 @@
+mod util;
+
+use grabapl::operation::builder::OperationBuilderError;
+use grabapl::operation::signature::OperationSignature;
+use grabapl::prelude::*;
+use syntax::grabapl_defs;
+use util::semantics::helpers::{int, int_value};
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+fn bump_every_other(head: int) {
+    bump_even(head);
+}
+
+// bumps `n` and every other node after it
+fn bump_even(n: int) {
+    increment(n);
+    if shape [next: int, n -> next: "next"] {
+        skip_odd(next);
+    }
+}
+
+fn skip_odd(n: int) {
+    if shape [next: int, n -> next: "next"] {
+        bump_even(next);
+    }
+}
+
+// appends a child to the deepest node of a chain, alternating between two labels
+fn append_child(p: int) -> (leaf: int) {
+    if shape [c: int, p -> c: "a"] {
+        let! leaf = append_other_child(c);
+    } else {
+        let! leaf = add_node<int,0>();
+        add_edge<"a">(p, leaf);
+    }
+    return (leaf: leaf);
+}
+
+fn append_other_child(p: int) -> (leaf: int) {
+    if shape [c: int, p -> c: "b"] {
+        let! leaf = append_child(c);
+    } else {
+        let! leaf = add_node<int,0>();
+        add_edge<"b">(p, leaf);
+    }
+    return (leaf: leaf);
+}
+);
+
+#[test_log::test]
+fn even_odd_list_walk() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let list = (0..5).map(|_| int(&mut g, 0)).collect::<Vec<_>>();
+    for pair in list.windows(2) {
+        g.add_edge(pair[0], pair[1], "next".to_string());
+    }
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_every_other"], &[list[0]]).unwrap();
+    let values = list.iter().map(|&n| int_value(&g, n)).collect::<Vec<_>>();
+    assert_eq!(values, [1, 0, 1, 0, 1]);
+}
+
+#[test_log::test]
+fn mutually_recursive_functions_return_nodes() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let root = int(&mut g, 0);
+
+    for _ in 0..3 {
+        run_from_concrete(&mut g, &op_ctx, fn_names["append_child"], &[root]).unwrap();
+    }
+    let a = g.out_edges(root).next().unwrap();
+    assert_eq!(a.1, "a");
+    let b = g.out_edges(a.0).next().unwrap();
+    assert_eq!(b.1, "b");
+    let leaf = g.out_edges(b.0).next().unwrap();
+    assert_eq!(leaf.1, "a");
+    assert_eq!(g.out_edges(leaf.0).count(), 0);
+}
+
+#[test_log::test]
+fn declared_signatures_are_widened_by_inferred_changes() {
+    // `copy_into` only changes `dst` via `copy`, which is part of the same group.
+    // Callers must not assume that `dst` keeps its type.
+    let src = stringify!(
+        fn main(x: int, s: string) {
+            copy_into(x, s);
+            needs_string(s);
+        }
+
+        fn copy_into(src: object, dst: object) {
+            if shape [c: object, src -> c: *] {
+                copy(c, dst);
+            }
+        }
+
+        fn copy(src: object, dst: object) {
+            copy_value_from_to(src, dst);
+            copy_into(src, dst);
+        }
+
+        fn needs_string(s: string) {}
+    );
+    let res = syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false);
+    assert!(res.op_ctx_and_map.is_err());
+
+    let valid_src = src.replace("needs_string(s);", "");
+    let res = syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(&valid_src, false);
+    assert!(res.op_ctx_and_map.is_ok());
+}
+
+#[test_log::test]
+fn calls_are_checked_against_declared_signatures() {
+    let src = stringify!(
+        fn bump_strings(n: int) {
+            if shape [s: string, n -> s: *] {
+                bump_ints(s);
+            }
+        }
+
+        fn bump_ints(n: int) {
+            increment(n);
+            bump_strings(n);
+        }
+    );
+    let res = syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false);
+    assert!(res.op_ctx_and_map.is_err());
+}
+
+/// The signature of an operation with a single integer parameter and no changes.
+fn int_param_signature(
+    op_ctx: &OperationContext<TestSemantics>,
+) -> OperationSignature<TestSemantics> {
+    let mut builder = OperationBuilder::<TestSemantics>::new(op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    builder.expected_signature().unwrap()
+}
+
+#[test_log::test]
+fn builder_builds_mutually_recursive_operations() {
+    let mut op_ctx = OperationContext::<TestSemantics>::new();
+    let mut signature = int_param_signature(&op_ctx);
+    signature
+        .output
+        .maybe_changed_nodes
+        .insert("p0".into(), NodeType::Integer);
+
+    // operation `op_id` bumps its parameter by `amount` and calls `other` on a "next" child
+    let build = |op_ctx: &OperationContext<TestSemantics>, op_id, other, amount| {
+        let mut builder = OperationBuilder::<TestSemantics>::new(op_ctx, op_id);
+        builder.declare_operation(0, signature.clone()).unwrap();
+        builder.declare_operation(1, signature.clone()).unwrap();
+        builder
+            .expect_parameter_node("p0", NodeType::Integer)
+            .unwrap();
+        let p0 = AbstractNodeId::param("p0");
+        builder
+            .add_operation(
+                BuilderOpLike::Builtin(TestOperation::AddInteger(amount)),
+                vec![p0],
+            )
+            .unwrap();
+        builder.start_shape_query("q").unwrap();
+        builder
+            .expect_shape_node("next".into(), NodeType::Integer)
+            .unwrap();
+        let next = AbstractNodeId::dynamic_output("q", "next");
+        builder
+            .expect_shape_edge(p0, next, EdgeType::Exact("next".to_string()))
+            .unwrap();
+        builder.enter_true_branch().unwrap();
+        builder
+            .add_operation(BuilderOpLike::FromOperationId(other), vec![next])
+            .unwrap();
+        builder.end_query().unwrap();
+        builder.build().unwrap()
+    };
+    let even = build(&op_ctx, 0, 1, 1);
+    let odd = build(&op_ctx, 1, 0, 10);
+    op_ctx.add_custom_operation(0, even);
+    op_ctx.add_custom_operation(1, odd);
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let list = (0..4).map(|_| int(&mut g, 0)).collect::<Vec<_>>();
+    for pair in list.windows(2) {
+        g.add_edge(pair[0], pair[1], "next".to_string());
+    }
+    run_from_concrete(&mut g, &op_ctx, 0, &[list[0]]).unwrap();
+    let values = list.iter().map(|&n| int_value(&g, n)).collect::<Vec<_>>();
+    assert_eq!(values, [1, 10, 1, 10]);
+}
+
+#[test_log::test]
+fn builder_rejects_operations_that_exceed_their_declared_signature() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    // declares no changes at all
+    let signature = int_param_signature(&op_ctx);
+
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder.declare_operation(0, signature).unwrap();
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    builder
+        .add_operation(
+            BuilderOpLike::Builtin(TestOperation::DeleteNode),
+            vec![AbstractNodeId::param("p0")],
+        )
+        .unwrap();
+    let Err(err) = builder.build() else {
+        panic!("expected building to fail");
+    };
+    assert!(matches!(
+        err.current_context(),
+        OperationBuilderError::DeclaredSignatureMismatch(_)
+    ));
+}
@@ -0,0 +1,245 @@
+mod util;
+
+use grabapl::operation::builder::OperationBuilderError;
+use grabapl::prelude::*;
+use syntax::grabapl_defs;
+use util::semantics::helpers::{int, int_value};
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+// bumps the last node of a linked list
+fn bump_last(n: int) {
+    if shape [!(n -> _: "next")] {
+        increment(n);
+    } else {
+        if shape [next: int, n -> next: "next"] {
+            bump_last(next);
+        }
+    }
+}
+
+// calls `bump_last` on `a`, whose `next` node `b` is hidden from `bump_last`
+fn bump_last_from_pair(a: int, b: int) [a -> b: "next"] {
+    bump_last(a);
+}
+
+// bumps a child that does not have children of its own
+fn bump_leaf_child(p: int) {
+    if shape [
+        c: int,
+        p -> c: *,
+        !(c -> _: *),
+    ] {
+        increment(c);
+    }
+}
+
+// bumps `b` if there is no edge from `a` to `b`
+fn bump_if_unconnected(a: int, b: int) {
+    if shape [!(a -> b: *)] {
+        increment(b);
+    }
+}
+
+// bumps `n` if it has no string child that has an edge back to `n`
+fn bump_without_string_cycle(n: int) {
+    if shape [!(s: string, n -> s: *, s -> n: *)] {
+        increment(n);
+    }
+}
+
+// bumps `n` if it has no parent
+fn bump_root(n: int) {
+    if shape [!(_ -> n: *)] {
+        increment(n);
+    }
+}
+);
+
+#[test_log::test]
+fn forbidden_out_edge_finds_the_end_of_a_list() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let list = (0..4).map(|_| int(&mut g, 0)).collect::<Vec<_>>();
+    for pair in list.windows(2) {
+        g.add_edge(pair[0], pair[1], "next".to_string());
+    }
+    // edges with other labels do not count
+    let other = int(&mut g, 0);
+    g.add_edge(list[3], other, "other".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_last"], &[list[0]]).unwrap();
+    let values = list.iter().map(|&n| int_value(&g, n)).collect::<Vec<_>>();
+    assert_eq!(values, [0, 0, 0, 1]);
+    assert_eq!(int_value(&g, other), 0);
+}
+
+#[test_log::test]
+fn hidden_nodes_are_forbidden_neighbours() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let a = int(&mut g, 0);
+    let b = int(&mut g, 0);
+    g.add_edge(a, b, "next".to_string());
+
+    // `b` is not part of `bump_last`'s parameter, but `a` still has a `next` edge
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_last_from_pair"], &[a, b]).unwrap();
+    assert_eq!(int_value(&g, a), 0);
+    assert_eq!(int_value(&g, b), 0);
+}
+
+#[test_log::test]
+fn forbidden_pattern_around_a_shape_node() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let p = int(&mut g, 0);
+    let inner = int(&mut g, 0);
+    let leaf = int(&mut g, 0);
+    let grandchild = int(&mut g, 0);
+    g.add_edge(p, inner, "child".to_string());
+    g.add_edge(p, leaf, "child".to_string());
+    g.add_edge(inner, grandchild, "child".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_leaf_child"], &[p]).unwrap();
+    assert_eq!(
+        [inner, leaf, grandchild].map(|n| int_value(&g, n)),
+        [0, 1, 0]
+    );
+}
+
+#[test_log::test]
+fn forbidden_edge_between_inputs() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let a = int(&mut g, 0);
+    let b = int(&mut g, 0);
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_if_unconnected"], &[a, b]).unwrap();
+    assert_eq!(int_value(&g, b), 1);
+
+    // edges in the other direction do not count
+    g.add_edge(b, a, "edge".to_string());
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_if_unconnected"], &[a, b]).unwrap();
+    assert_eq!(int_value(&g, b), 2);
+
+    g.add_edge(a, b, "edge".to_string());
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_if_unconnected"], &[a, b]).unwrap();
+    assert_eq!(int_value(&g, b), 2);
+}
+
+#[test_log::test]
+fn forbidden_nodes_must_match_their_type_and_all_edges() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let n = int(&mut g, 0);
+    // an integer node with a cycle, and a string node without one
+    let i = int(&mut g, 0);
+    g.add_edge(n, i, "child".to_string());
+    g.add_edge(i, n, "parent".to_string());
+    let s = g.add_node(NodeValue::String("child".to_string()));
+    g.add_edge(n, s, "child".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_without_string_cycle"], &[n]).unwrap();
+    assert_eq!(int_value(&g, n), 1);
+
+    g.add_edge(s, n, "parent".to_string());
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_without_string_cycle"], &[n]).unwrap();
+    assert_eq!(int_value(&g, n), 1);
+}
+
+#[test_log::test]
+fn forbidden_in_edge() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let root = int(&mut g, 0);
+    let child = int(&mut g, 0);
+    g.add_edge(root, child, "child".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_root"], &[root]).unwrap();
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_root"], &[child]).unwrap();
+    assert_eq!([root, child].map(|n| int_value(&g, n)), [1, 0]);
+}
+
+#[test_log::test]
+fn builder_accepts_negative_conditions() {
+    let mut op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    let p0 = AbstractNodeId::param("p0");
+    builder.start_shape_query("q").unwrap();
+    builder
+        .expect_shape_node("c".into(), NodeType::Integer)
+        .unwrap();
+    let c = AbstractNodeId::dynamic_output("q", "c");
+    builder
+        .expect_shape_edge(p0, c, EdgeType::Wildcard)
+        .unwrap();
+    builder
+        .expect_no_shape_edge(c, p0, EdgeType::Exact("back".to_string()))
+        .unwrap();
+    builder
+        .expect_no_shape_pattern(NegativeShapeCondition::out_neighbour(
+            c,
+            Some(NodeType::String),
+            EdgeType::Wildcard,
+        ))
+        .unwrap();
+    builder.enter_true_branch().unwrap();
+    builder
+        .add_operation(
+            BuilderOpLike::Builtin(TestOperation::AddInteger(1)),
+            vec![c],
+        )
+        .unwrap();
+    builder.end_query().unwrap();
+    op_ctx.add_custom_operation(0, builder.build().unwrap());
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let p = int(&mut g, 0);
+    let c = int(&mut g, 0);
+    g.add_edge(p, c, "child".to_string());
+    g.add_edge(c, p, "other".to_string());
+    let i = int(&mut g, 0);
+    g.add_edge(c, i, "child".to_string());
+    run_from_concrete(&mut g, &op_ctx, 0, &[p]).unwrap();
+    assert_eq!(int_value(&g, c), 1);
+
+    g.set_edge_attr((c, p), "back".to_string());
+    run_from_concrete(&mut g, &op_ctx, 0, &[p]).unwrap();
+    assert_eq!(int_value(&g, c), 1);
+
+    g.remove_edge_between(c, p);
+    let s = g.add_node(NodeValue::String("child".to_string()));
+    g.add_edge(c, s, "child".to_string());
+    run_from_concrete(&mut g, &op_ctx, 0, &[p]).unwrap();
+    assert_eq!(int_value(&g, c), 1);
+}
+
+#[test_log::test]
+fn builder_rejects_unknown_forbidden_nodes() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    let p0 = AbstractNodeId::param("p0");
+    builder.start_shape_query("q").unwrap();
+
+    let mut condition = NegativeShapeCondition::new();
+    condition.add_edge(
+        NegativeShapeNode::Existing(p0),
+        NegativeShapeNode::Forbidden(0),
+        EdgeType::Wildcard,
+    );
+    let res = builder.expect_no_shape_pattern(condition);
+    assert!(matches!(
+        res.unwrap_err().current_context(),
+        OperationBuilderError::NegativeShapeConditionNodeNotFound(0)
+    ));
+
+    let unknown = AbstractNodeId::named("unknown");
+    let res = builder.expect_no_shape_edge(p0, unknown, EdgeType::Wildcard);
+    assert!(res.is_err());
+}
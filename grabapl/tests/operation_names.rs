@@ -0,0 +1,120 @@
+mod util;
+
+use grabapl::prelude::*;
+use util::semantics::*;
+
+fn compile(src: &str) -> OperationContext<TestSemantics> {
+    let (op_ctx, fn_names) = syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false)
+        .op_ctx_and_map
+        .unwrap();
+    for (name, id) in fn_names {
+        assert_eq!(op_ctx.id_of(name), Some(id));
+    }
+    op_ctx
+}
+
+#[test_log::test]
+fn ids_are_stable_across_recompiles() {
+    let before = compile(stringify!(
+        fn bump(x: int) {
+            increment(x);
+        }
+
+        fn bump_twice(x: int) {
+            bump(x);
+            bump(x);
+        }
+    ));
+    let after = compile(stringify!(
+        fn added(x: int) {
+            bump_twice(x);
+        }
+
+        fn bump_twice(x: int) {
+            bump(x);
+            bump(x);
+        }
+
+        fn bump(x: int) {
+            increment(x);
+        }
+    ));
+    let without_bump_twice = compile(stringify!(
+        fn bump(x: int) {
+            increment(x);
+        }
+    ));
+
+    for name in ["bump", "bump_twice"] {
+        assert_eq!(before.id_of(name), after.id_of(name));
+    }
+    assert_eq!(before.id_of("bump"), without_bump_twice.id_of("bump"));
+    assert_eq!(without_bump_twice.id_of("bump_twice"), None);
+}
+
+#[test_log::test]
+fn registration_and_lookup_by_name() {
+    let mut op_ctx = OperationContext::<TestSemantics>::new();
+    let add_one = op_ctx.add_named_builtin_operation("add_one", TestOperation::AddInteger(1));
+    assert_eq!(op_ctx.register_name("add_one"), add_one);
+    assert_eq!(op_ctx.name_of(add_one), Some("add_one"));
+
+    let id = op_ctx.register_name("add_two");
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, id);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    let p0 = AbstractNodeId::param("p0");
+    for _ in 0..2 {
+        builder
+            .add_operation(BuilderOpLike::FromOperationId(add_one), vec![p0])
+            .unwrap();
+    }
+    let add_two = builder.build().unwrap();
+    assert_eq!(op_ctx.add_named_custom_operation("add_two", add_two), id);
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let node = g.add_node(NodeValue::Integer(0));
+    run_from_concrete(&mut g, &op_ctx, op_ctx.id_of("add_two").unwrap(), &[node]).unwrap();
+    assert_eq!(g.get_node_attr(node), Some(&NodeValue::Integer(2)));
+    assert!(matches!(
+        op_ctx.get_by_name("add_one"),
+        Some(Operation::Builtin(_))
+    ));
+    assert!(op_ctx.get_by_name("add_three").is_none());
+
+    let listed = op_ctx.named_operations();
+    let names = listed.iter().map(|(name, _, _)| *name).collect::<Vec<_>>();
+    assert_eq!(names, ["add_one", "add_two"]);
+    assert!(listed[0].2.signature().is_none());
+    let signature = listed[1].2.signature().unwrap();
+    assert_eq!(signature.parameter.explicit_input_nodes.len(), 1);
+}
+
+#[test_log::test]
+fn colliding_ids_are_not_reused() {
+    let mut op_ctx = OperationContext::<TestSemantics>::new();
+    let id = op_ctx.register_name("foo");
+    // an unnamed operation already uses the id of `bar`
+    let mut other = OperationContext::<TestSemantics>::new();
+    let bar = other.register_name("bar");
+    op_ctx.add_builtin_operation(bar, TestOperation::AddInteger(1));
+
+    let new_bar = op_ctx.register_name("bar");
+    assert_ne!(new_bar, bar);
+    assert_ne!(new_bar, id);
+}
+
+#[cfg(feature = "serde")]
+#[test_log::test]
+fn names_survive_serialization() {
+    let op_ctx = compile(stringify!(
+        fn bump(x: int) {
+            increment(x);
+        }
+    ));
+    let serialized = serde_json::to_string(&op_ctx).unwrap();
+    let deserialized: OperationContext<TestSemantics> = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(deserialized.id_of("bump"), op_ctx.id_of("bump"));
+    assert!(deserialized.get_by_name("bump").is_some());
+}
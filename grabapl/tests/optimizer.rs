@@ -0,0 +1,263 @@
+mod util;
+
+use grabapl::operation::optimizer::{OptimizerOptions, optimize};
+use grabapl::operation::user_defined::{
+    Instruction, InstructionWithResultMarker, OpLikeInstruction,
+};
+use grabapl::prelude::*;
+use proptest::proptest;
+use proptest::test_runner::Config;
+use util::semantics::*;
+
+const PROGRAM: &str = stringify!(
+    fn add_child(x: int) -> (child: int) {
+        let! child = add_node<int,0>();
+        add_edge<"child">(x, child);
+        copy_value_from_to(x, child);
+        return (child: child);
+    }
+
+    fn bump(x: int) {
+        increment(x);
+    }
+
+    fn bump_twice(x: int) {
+        bump(x);
+        bump(x);
+    }
+
+    fn grow(x: int) {
+        let! c = add_child(x);
+        bump_twice(c);
+        // the returned child is not bound, so the shape query below may match it
+        add_child(x);
+        if shape [y: int, x -> y: *] {
+            bump(y);
+        }
+    }
+
+    fn countdown(x: int) {
+        if is_zero(x) {
+        } else {
+            trace();
+            decrement(x);
+            countdown(x);
+        }
+    }
+
+    fn guarded(x: int) {
+        if is_eq<0>(x) {
+            diverge<"zero">();
+            increment(x);
+        }
+        trace();
+        bump(x);
+    }
+);
+
+fn compile(src: &str) -> OperationContext<TestSemantics> {
+    syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false)
+        .op_ctx_and_map
+        .unwrap()
+        .0
+}
+
+fn custom<'a>(
+    op_ctx: &'a OperationContext<TestSemantics>,
+    name: &str,
+) -> &'a UserDefinedOperation<TestSemantics> {
+    match op_ctx.get_by_name(name) {
+        Some(Operation::Custom(op)) => op,
+        _ => panic!("expected `{name}` to be a user defined operation"),
+    }
+}
+
+/// Returns the number of (nested) instructions that satisfy `f`.
+fn count(
+    instructions: &[InstructionWithResultMarker<TestSemantics>],
+    f: &impl Fn(&Instruction<TestSemantics>) -> bool,
+) -> usize {
+    instructions
+        .iter()
+        .map(|(_, instruction)| {
+            let nested = match instruction {
+                Instruction::QueryLike(_, _, query_instr)
+                | Instruction::ShapeQuery(_, _, query_instr) => {
+                    count(&query_instr.taken, f) + count(&query_instr.not_taken, f)
+                }
+                _ => 0,
+            };
+            usize::from(f(instruction)) + nested
+        })
+        .sum()
+}
+
+/// A node with the given value and a child for each of the given values.
+fn input_graph(value: i32, children: &[i32]) -> (ConcreteGraph<TestSemantics>, NodeKey) {
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let root = g.add_node(NodeValue::Integer(value));
+    for child in children {
+        let child = g.add_node(NodeValue::Integer(*child));
+        g.add_edge(root, child, "child".to_string());
+    }
+    (g, root)
+}
+
+/// Runs `name` with both contexts on the same input and checks that the results are the same.
+fn assert_same_behavior(
+    original: &OperationContext<TestSemantics>,
+    optimized: &OperationContext<TestSemantics>,
+    name: &str,
+    value: i32,
+    children: &[i32],
+) {
+    let (mut expected, root) = input_graph(value, children);
+    let (mut actual, _) = input_graph(value, children);
+    let id = original.id_of(name).unwrap();
+    let expected_res = run_from_concrete(&mut expected, original, id, &[root]);
+    let actual_res = run_from_concrete(&mut actual, optimized, id, &[root]);
+    match (expected_res, actual_res) {
+        (Ok(expected_output), Ok(actual_output)) => {
+            assert_eq!(expected_output.new_nodes(), actual_output.new_nodes());
+        }
+        (Err(expected_err), Err(actual_err)) => {
+            assert_eq!(
+                expected_err.current_context().to_string(),
+                actual_err.current_context().to_string()
+            );
+        }
+        (expected_res, actual_res) => panic!(
+            "`{name}` behaves differently: expected {:?}, got {:?}",
+            expected_res.map(|_| ()),
+            actual_res.map(|_| ())
+        ),
+    }
+    assert!(
+        expected.semantically_matches_with_same_keys(&actual),
+        "`{name}` produced different graphs"
+    );
+}
+
+#[test_log::test]
+fn small_callees_are_inlined() {
+    let mut op_ctx = compile(PROGRAM);
+    let report = optimize(&mut op_ctx, &OptimizerOptions::default());
+    assert!(report.inlined_calls > 0);
+
+    let is_call = |instruction: &Instruction<TestSemantics>| {
+        matches!(
+            instruction,
+            Instruction::OpLike(OpLikeInstruction::Operation(_), _)
+        )
+    };
+    // all calls of `grow` are inlined, transitively
+    assert_eq!(count(&custom(&op_ctx, "grow").instructions, &is_call), 0);
+    // recursive operations are not inlined
+    assert_eq!(
+        count(&custom(&op_ctx, "countdown").instructions, &is_call),
+        1
+    );
+
+    // the callee's nodes are forgotten after the inlined instructions, so `y` may match the unbound child
+    let (mut g, root) = input_graph(5, &[]);
+    run_from_concrete(&mut g, &op_ctx, op_ctx.id_of("grow").unwrap(), &[root]).unwrap();
+    let mut values = g
+        .nodes()
+        .map(|(_, value)| value.clone())
+        .collect::<Vec<_>>();
+    values.sort_by_key(|value| format!("{value:?}"));
+    assert_eq!(
+        values,
+        [
+            NodeValue::Integer(5),
+            NodeValue::Integer(6),
+            NodeValue::Integer(7)
+        ]
+    );
+}
+
+#[test_log::test]
+fn dead_instructions_and_traces_are_removed() {
+    let mut op_ctx = compile(PROGRAM);
+    let original = custom(&op_ctx, "guarded").instructions.len();
+    let report = optimize(&mut op_ctx, &OptimizerOptions::default());
+    assert!(report.pruned_instructions > 0);
+    assert_eq!(report.stripped_traces, 0);
+    let is_increment = |instruction: &Instruction<TestSemantics>| {
+        matches!(
+            instruction,
+            Instruction::OpLike(OpLikeInstruction::Builtin(_), _)
+        )
+    };
+    // only the inlined `bump` is left
+    assert_eq!(
+        count(&custom(&op_ctx, "guarded").instructions, &is_increment),
+        1
+    );
+    assert!(custom(&op_ctx, "guarded").instructions.len() <= original);
+
+    let report = optimize(&mut op_ctx, &OptimizerOptions::release());
+    assert_eq!(report.stripped_traces, 2);
+    for name in ["guarded", "countdown"] {
+        let traces = count(&custom(&op_ctx, name).instructions, &|instruction| {
+            matches!(instruction, Instruction::Trace)
+        });
+        assert_eq!(traces, 0);
+    }
+}
+
+#[test_log::test]
+fn renames_without_effect_are_removed() {
+    let mut op_ctx = compile(PROGRAM);
+    let is_rename_or_forget = |instruction: &Instruction<TestSemantics>| {
+        matches!(
+            instruction,
+            Instruction::RenameNode { .. } | Instruction::ForgetAid { .. }
+        )
+    };
+    let before = count(&custom(&op_ctx, "grow").instructions, &is_rename_or_forget);
+    let report = optimize(
+        &mut op_ctx,
+        &OptimizerOptions {
+            max_inline_size: 0,
+            ..OptimizerOptions::default()
+        },
+    );
+    assert_eq!(report.inlined_calls, 0);
+    assert!(report.removed_renames > 0);
+    let after = count(&custom(&op_ctx, "grow").instructions, &is_rename_or_forget);
+    assert!(after < before);
+}
+
+#[test_log::test]
+fn optimizing_twice_is_fine() {
+    let original = compile(PROGRAM);
+    let mut optimized = original.clone();
+    optimize(&mut optimized, &OptimizerOptions::release());
+    optimize(&mut optimized, &OptimizerOptions::release());
+    for name in ["grow", "countdown", "guarded"] {
+        assert_same_behavior(&original, &optimized, name, 3, &[1, 2]);
+    }
+}
+
+proptest! {
+    #![proptest_config(Config::with_cases(30))]
+    #[test]
+    fn optimized_operations_behave_like_the_original(
+        value in 0..10,
+        children in proptest::collection::vec(0..10, 0..3),
+        release: bool,
+    ) {
+        let original = compile(PROGRAM);
+        let mut optimized = original.clone();
+        let options = if release {
+            OptimizerOptions::release()
+        } else {
+            OptimizerOptions::default()
+        };
+        optimize(&mut optimized, &options);
+        for name in ["add_child", "bump_twice", "grow", "countdown", "guarded"] {
+            assert_same_behavior(&original, &optimized, name, value, &children);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+mod util;
+
+use grabapl::EdgeInsertionOrder;
+use grabapl::operation::builder::OperationBuilderError;
+use grabapl::prelude::*;
+use syntax::grabapl_defs;
+use util::semantics::helpers::{int, int_value};
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+fn bump_first(p: int) {
+    if shape [
+        c: int,
+        p -> c: * @ first,
+    ] {
+        increment(c);
+    }
+}
+
+fn bump_last(p: int) {
+    if shape [
+        c: int,
+        p -> c: * @ last,
+    ] {
+        increment(c);
+    }
+}
+
+fn bump_second(p: int) {
+    if shape [
+        c: int,
+        p -> c: * @ 1,
+    ] {
+        increment(c);
+    }
+}
+
+fn bump_second_to_last(p: int) {
+    if shape [
+        c: int,
+        p -> c: * @ -2,
+    ] {
+        increment(c);
+    }
+}
+
+// the children right after and before the child with the label "mid"
+fn bump_neighbors_of_mid(p: int) {
+    if shape [
+        mid: int,
+        l: int,
+        r: int,
+        p -> mid: "mid",
+        p -> r: * @ after mid,
+        p -> l: * @ before mid,
+    ] {
+        increment(l);
+        increment(r);
+    }
+}
+
+// follows the first outgoing edge down to the leftmost leaf of a tree
+fn mark_leftmost(n: int) {
+    if shape [
+        left: int,
+        n -> left: * @ first,
+    ] {
+        mark_leftmost(left);
+    } else {
+        increment(n);
+    }
+}
+);
+
+/// A parent whose children are ordered by their edges in the reverse order of their node keys,
+/// such that the match order alone would pick the last child.
+fn parent_with_children(
+    g: &mut ConcreteGraph<TestSemantics>,
+    count: usize,
+) -> (NodeKey, Vec<NodeKey>) {
+    let parent = int(g, 0);
+    let mut children = (0..count).map(|_| int(g, 0)).collect::<Vec<_>>();
+    children.reverse();
+    for &child in &children {
+        g.add_edge(parent, child, "child".to_string());
+    }
+    (parent, children)
+}
+
+#[test_log::test]
+fn absolute_positions_follow_the_edge_order() {
+    let (op_ctx, fn_names) = get_ops();
+    for (name, expected) in [
+        ("bump_first", [1, 0, 0, 0]),
+        ("bump_second", [0, 1, 0, 0]),
+        ("bump_second_to_last", [0, 0, 1, 0]),
+        ("bump_last", [0, 0, 0, 1]),
+    ] {
+        let mut g = ConcreteGraph::<TestSemantics>::new();
+        let (parent, children) = parent_with_children(&mut g, 4);
+
+        run_from_concrete(&mut g, &op_ctx, fn_names[name], &[parent]).unwrap();
+        let values = children
+            .iter()
+            .map(|&c| int_value(&g, c))
+            .collect::<Vec<_>>();
+        assert_eq!(values, expected, "{name}");
+    }
+}
+
+#[test_log::test]
+fn positions_count_edges_outside_the_shape() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let (parent, children) = parent_with_children(&mut g, 2);
+    // the first and last edges now lead to a string node, which the shape node cannot match
+    let first = g.add_node(NodeValue::String("first".to_string()));
+    g.add_edge_ordered(
+        parent,
+        first,
+        "child".to_string(),
+        EdgeInsertionOrder::Prepend,
+        EdgeInsertionOrder::Append,
+    );
+    let last = g.add_node(NodeValue::String("last".to_string()));
+    g.add_edge(parent, last, "child".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_first"], &[parent]).unwrap();
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_last"], &[parent]).unwrap();
+    assert_eq!(int_value(&g, children[0]), 0);
+    assert_eq!(int_value(&g, children[1]), 0);
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_second"], &[parent]).unwrap();
+    assert_eq!(int_value(&g, children[0]), 1);
+}
+
+#[test_log::test]
+fn relative_positions_follow_the_edge_order() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let (parent, children) = parent_with_children(&mut g, 5);
+    g.set_edge_attr((parent, children[2]), "mid".to_string());
+
+    run_from_concrete(
+        &mut g,
+        &op_ctx,
+        fn_names["bump_neighbors_of_mid"],
+        &[parent],
+    )
+    .unwrap();
+    let values = children
+        .iter()
+        .map(|&c| int_value(&g, c))
+        .collect::<Vec<_>>();
+    assert_eq!(values, [0, 1, 0, 1, 0]);
+}
+
+#[test_log::test]
+fn relative_positions_do_not_wrap_around() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let (parent, children) = parent_with_children(&mut g, 3);
+    // "mid" is the last edge, so there is no edge after it
+    g.remove_edge_between(parent, children[2]);
+    g.add_edge(parent, children[2], "mid".to_string());
+
+    run_from_concrete(
+        &mut g,
+        &op_ctx,
+        fn_names["bump_neighbors_of_mid"],
+        &[parent],
+    )
+    .unwrap();
+    let values = children
+        .iter()
+        .map(|&c| int_value(&g, c))
+        .collect::<Vec<_>>();
+    assert_eq!(values, [0, 0, 0]);
+}
+
+#[test_log::test]
+fn ordered_children_of_a_tree() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    // root -> [a -> [c, d], b]
+    let root = int(&mut g, 0);
+    let b = int(&mut g, 0);
+    let a = int(&mut g, 0);
+    let d = int(&mut g, 0);
+    let c = int(&mut g, 0);
+    g.add_edge(root, a, "child".to_string());
+    g.add_edge(root, b, "child".to_string());
+    g.add_edge(a, c, "child".to_string());
+    g.add_edge(a, d, "child".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["mark_leftmost"], &[root]).unwrap();
+    let values = [root, a, b, c, d].map(|n| int_value(&g, n));
+    assert_eq!(values, [0, 0, 0, 1, 0]);
+}
+
+#[test_log::test]
+fn builder_accepts_ordered_shape_edges() {
+    let mut op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    let p0 = AbstractNodeId::param("p0");
+    builder.start_shape_query("q").unwrap();
+    builder
+        .expect_shape_node("second".into(), NodeType::Integer)
+        .unwrap();
+    let second = AbstractNodeId::dynamic_output("q", "second");
+    builder
+        .expect_ordered_shape_edge(p0, second, EdgeType::Wildcard, ShapeEdgeOrder::FromStart(1))
+        .unwrap();
+    builder.enter_true_branch().unwrap();
+    builder
+        .add_operation(
+            BuilderOpLike::Builtin(TestOperation::AddInteger(5)),
+            vec![second],
+        )
+        .unwrap();
+    builder.end_query().unwrap();
+    op_ctx.add_custom_operation(0, builder.build().unwrap());
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let (parent, children) = parent_with_children(&mut g, 3);
+    run_from_concrete(&mut g, &op_ctx, 0, &[parent]).unwrap();
+    let values = children
+        .iter()
+        .map(|&c| int_value(&g, c))
+        .collect::<Vec<_>>();
+    assert_eq!(values, [0, 5, 0]);
+}
+
+#[test_log::test]
+fn relative_order_requires_the_sibling_edge() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    let p0 = AbstractNodeId::param("p0");
+    builder.start_shape_query("q").unwrap();
+    builder
+        .expect_shape_node("l".into(), NodeType::Integer)
+        .unwrap();
+    builder
+        .expect_shape_node("r".into(), NodeType::Integer)
+        .unwrap();
+    let l = AbstractNodeId::dynamic_output("q", "l");
+    let r = AbstractNodeId::dynamic_output("q", "r");
+
+    // there is no expected edge from p0 to l
+    let res =
+        builder.expect_ordered_shape_edge(p0, r, EdgeType::Wildcard, ShapeEdgeOrder::after(l));
+    assert!(matches!(
+        res.unwrap_err().current_context(),
+        OperationBuilderError::ShapeEdgeOrderSiblingNotFound(src, sibling)
+            if *src == p0 && *sibling == l
+    ));
+}
@@ -0,0 +1,207 @@
+mod util;
+
+use grabapl::operation::OperationError;
+use grabapl::operation::execution::ExecutionLimit;
+use grabapl::prelude::*;
+use util::semantics::helpers::int_value;
+use util::semantics::*;
+
+/// Returns a graph with a single integer node, and optionally a child of that node.
+fn example(with_child: bool) -> (ConcreteGraph<TestSemantics>, NodeKey, Option<NodeKey>) {
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let parent = g.add_node(NodeValue::Integer(0));
+    let child = with_child.then(|| {
+        let child = g.add_node(NodeValue::Integer(10));
+        g.add_edge(parent, child, "child".to_string());
+        child
+    });
+    (g, parent, child)
+}
+
+/// Starts a shape query for a child of `p0` and enters its true branch.
+fn start_child_query(builder: &mut OperationBuilder<TestSemantics>) -> AbstractNodeId {
+    let p0 = AbstractNodeId::param("p0");
+    builder.start_shape_query("q").unwrap();
+    builder
+        .expect_shape_node("child".into(), NodeType::Integer)
+        .unwrap();
+    let child = AbstractNodeId::dynamic_output("q", "child");
+    builder
+        .expect_shape_edge(p0, child, EdgeType::Exact("child".to_string()))
+        .unwrap();
+    builder.enter_true_branch().unwrap();
+    child
+}
+
+#[test_log::test]
+fn examples_follow_the_current_branch() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    let (with_child, with_child_parent, child) = example(true);
+    let (without_child, without_child_parent, _) = example(false);
+    builder.add_example(with_child, vec![with_child_parent]);
+    builder.add_example(without_child, vec![without_child_parent]);
+
+    let child_aid = start_child_query(&mut builder);
+    let runs = builder.example_runs().unwrap();
+    assert!(runs[0].reaches_current_instruction());
+    assert!(!runs[1].reaches_current_instruction());
+    assert!(!builder.no_example_reaches_current_instruction());
+
+    builder
+        .add_operation(
+            BuilderOpLike::Builtin(TestOperation::AddInteger(5)),
+            vec![child_aid],
+        )
+        .unwrap();
+    let runs = builder.example_runs().unwrap();
+    let frame = &runs[0].reached[0];
+    assert_eq!(int_value(&frame.graph, child.unwrap()), 15);
+    assert_eq!(frame.node_aids.get_right(&child_aid), Some(&child.unwrap()));
+
+    builder.enter_false_branch().unwrap();
+    let runs = builder.example_runs().unwrap();
+    assert!(!runs[0].reaches_current_instruction());
+    assert!(runs[1].reaches_current_instruction());
+    assert!(runs.iter().all(|run| run.error.is_none()));
+
+    // the examples do not change what is built
+    builder.end_query().unwrap();
+    builder.build().unwrap();
+}
+
+#[test_log::test]
+fn unreached_instruction_is_reported() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    // without examples, nothing is reported
+    start_child_query(&mut builder);
+    assert!(builder.example_runs().is_none());
+    assert!(!builder.no_example_reaches_current_instruction());
+
+    let (without_child, parent, _) = example(false);
+    builder.add_example(without_child, vec![parent]);
+    assert!(builder.no_example_reaches_current_instruction());
+
+    // adding an example that exercises the branch resolves it
+    let (with_child, parent, _) = example(true);
+    builder.add_example(with_child, vec![parent]);
+    assert!(!builder.no_example_reaches_current_instruction());
+}
+
+#[test_log::test]
+fn traces_of_callees_do_not_count_as_reaching_the_instruction() {
+    let mut op_ctx = OperationContext::<TestSemantics>::new();
+    let mut callee = OperationBuilder::<TestSemantics>::new(&op_ctx, 1);
+    callee
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    callee.trace().unwrap();
+    op_ctx.add_custom_operation(1, callee.build().unwrap());
+
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    builder
+        .add_operation(
+            BuilderOpLike::FromOperationId(1),
+            vec![AbstractNodeId::param("p0")],
+        )
+        .unwrap();
+    let (without_child, parent, _) = example(false);
+    builder.add_example(without_child, vec![parent]);
+    assert!(builder.example_runs().unwrap()[0].reaches_current_instruction());
+
+    // the callee traces before the query, but its trace is not the current instruction
+    start_child_query(&mut builder);
+    assert!(!builder.example_runs().unwrap()[0].reaches_current_instruction());
+    assert!(builder.no_example_reaches_current_instruction());
+}
+
+#[test_log::test]
+fn examples_not_matching_the_parameter_are_reported_separately() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    start_child_query(&mut builder);
+
+    // a string node does not match the integer parameter
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let string = g.add_node(NodeValue::String("hello".to_string()));
+    builder.add_example(g, vec![string]);
+    let runs = builder.example_runs().unwrap();
+    assert!(!runs[0].matches_parameter);
+    assert!(runs[0].reached.is_empty());
+    assert!(runs[0].error.is_some());
+    assert_eq!(builder.examples_not_matching_parameter(), vec![0]);
+    // a non-matching example does not count towards the reachability check
+    assert!(!builder.no_example_reaches_current_instruction());
+
+    let (without_child, parent, _) = example(false);
+    builder.add_example(without_child, vec![parent]);
+    assert!(builder.example_runs().unwrap()[1].matches_parameter);
+    assert_eq!(builder.examples_not_matching_parameter(), vec![0]);
+    assert!(builder.no_example_reaches_current_instruction());
+}
+
+#[test_log::test]
+fn recursive_examples_keep_their_trace_on_failure() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    let p0 = AbstractNodeId::param("p0");
+
+    // a chain of `child` edges, where the last node has a `right` child
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let root = g.add_node(NodeValue::Integer(0));
+    let right = g.add_node(NodeValue::Integer(0));
+    g.add_edge(root, right, "right".to_string());
+    let mut last = root;
+    for _ in 0..10 {
+        let next = g.add_node(NodeValue::Integer(0));
+        g.add_edge(last, next, "child".to_string());
+        last = next;
+    }
+    builder.add_example(g, vec![root]);
+    builder.set_example_limits(ExecutionLimits::unlimited().with_max_recursion_depth(5));
+
+    // recurse into the `right` child first, then into the chain
+    for label in ["right", "child"] {
+        builder.start_shape_query(label).unwrap();
+        builder
+            .expect_shape_node("next".into(), NodeType::Integer)
+            .unwrap();
+        let next = AbstractNodeId::dynamic_output(label, "next");
+        builder
+            .expect_shape_edge(p0, next, EdgeType::Exact(label.to_string()))
+            .unwrap();
+        builder.enter_true_branch().unwrap();
+        builder
+            .add_operation(BuilderOpLike::Recurse, vec![next])
+            .unwrap();
+        builder.end_query().unwrap();
+    }
+
+    let runs = builder.example_runs().unwrap();
+    // the call on `right` reached the end before the chain exceeded the recursion depth
+    assert_eq!(runs[0].reached.len(), 1);
+    let err = runs[0].error.as_ref().unwrap();
+    assert!(matches!(
+        err.current_context(),
+        OperationError::ExecutionLimitExceeded {
+            limit: ExecutionLimit::RecursionDepth(5),
+            ..
+        }
+    ));
+}
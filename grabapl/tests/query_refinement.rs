@@ -0,0 +1,102 @@
+mod util;
+
+use grabapl::prelude::*;
+use util::semantics::helpers::parses;
+use util::semantics::*;
+
+#[test_log::test]
+fn taken_branch_is_refined() {
+    let src = stringify!(
+        fn bump_if_int(x: object) {
+            if has_type<int>(x) {
+                increment(x);
+            }
+        }
+    );
+    let (op_ctx, fn_names) = syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false)
+        .op_ctx_and_map
+        .unwrap();
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let int = g.add_node(NodeValue::Integer(1));
+    let string = g.add_node(NodeValue::String("one".to_string()));
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_if_int"], &[int]).unwrap();
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_if_int"], &[string]).unwrap();
+    assert_eq!(g.get_node_attr(int), Some(&NodeValue::Integer(2)));
+    assert_eq!(
+        g.get_node_attr(string),
+        Some(&NodeValue::String("one".to_string()))
+    );
+}
+
+#[test_log::test]
+fn refinement_does_not_leak_out_of_the_branch() {
+    let src = stringify!(
+        fn bump(x: object) {
+            if has_type<int>(x) {
+            } else {
+                increment(x);
+            }
+        }
+    );
+    assert!(!parses(src));
+
+    let src = stringify!(
+        fn bump(x: object) {
+            if has_type<int>(x) {}
+            increment(x);
+        }
+    );
+    assert!(!parses(src));
+
+    let src = stringify!(
+        fn bump(x: object) {
+            if is_eq<5>(x) {
+                increment(x);
+            }
+        }
+    );
+    assert!(parses(src));
+}
+
+#[test_log::test]
+fn refinement_is_not_a_change() {
+    let src = stringify!(
+        fn check(x: object) {
+            if has_type<int>(x) {
+                needs_int(x);
+            }
+        }
+
+        fn needs_int(x: int) {}
+    );
+    let (op_ctx, fn_names) = syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false)
+        .op_ctx_and_map
+        .unwrap();
+    let Some(Operation::Custom(check)) = op_ctx.get(fn_names["check"]) else {
+        panic!("expected a user defined operation");
+    };
+    assert!(check.signature.output.maybe_changed_nodes.is_empty());
+}
+
+#[test_log::test]
+fn builder_refines_each_branch_separately() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Object)
+        .unwrap();
+    let p0 = AbstractNodeId::param("p0");
+    builder
+        .start_query(TestQuery::HasType(NodeType::String), vec![p0])
+        .unwrap();
+    builder.enter_true_branch().unwrap();
+    let state = builder.show_state().unwrap();
+    assert_eq!(state.node_av_of_aid(&p0), Some(&NodeType::String));
+    builder.enter_false_branch().unwrap();
+    let state = builder.show_state().unwrap();
+    assert_eq!(state.node_av_of_aid(&p0), Some(&NodeType::Object));
+    builder.end_query().unwrap();
+    let state = builder.show_state().unwrap();
+    assert_eq!(state.node_av_of_aid(&p0), Some(&NodeType::Object));
+}
@@ -0,0 +1,117 @@
+mod util;
+
+use grabapl::operation::session::Session;
+use syntax::grabapl_defs;
+use util::semantics::helpers::int_value;
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+fn mark(x: int) {
+    mark_node<"visited", int>(x);
+}
+
+// increments the first unvisited child of `x`
+fn bump_unvisited_child(x: int) {
+    if shape [c: int, x -> c: *] skipping ["visited"] {
+        trace();
+        increment(c);
+    }
+}
+
+fn bump_any_child(x: int) {
+    if shape [c: int, x -> c: *] {
+        increment(c);
+    }
+}
+
+fn new_child(x: int) -> (child: int) {
+    let! child = add_node<int,0>();
+    add_edge<"child">(x, child);
+    return (child: child);
+}
+);
+
+#[test_log::test]
+fn markers_persist_across_runs() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut session = Session::new(op_ctx);
+    let parent = session.graph_mut().add_node(NodeValue::Integer(0));
+    let child = session.graph_mut().add_node(NodeValue::Integer(0));
+    session
+        .graph_mut()
+        .add_edge(parent, child, "child".to_string());
+
+    session.run(fn_names["mark"], &[child]).unwrap();
+    // the child was marked by the previous run, so it is skipped
+    session
+        .run(fn_names["bump_unvisited_child"], &[parent])
+        .unwrap();
+    assert_eq!(int_value(session.graph(), child), 0);
+    assert!(session.trace().frames.is_empty());
+
+    session.marker_set_mut().remove_marker("visited");
+    session
+        .run(fn_names["bump_unvisited_child"], &[parent])
+        .unwrap();
+    assert_eq!(int_value(session.graph(), child), 1);
+    assert_eq!(session.trace().frames.len(), 1);
+}
+
+#[test_log::test]
+fn hidden_nodes_are_not_matched() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut session = Session::new(op_ctx);
+    let parent = session.graph_mut().add_node(NodeValue::Integer(0));
+    let child = session.graph_mut().add_node(NodeValue::Integer(0));
+    session
+        .graph_mut()
+        .add_edge(parent, child, "child".to_string());
+
+    session.hide_node(child);
+    session.run(fn_names["bump_any_child"], &[parent]).unwrap();
+    assert_eq!(int_value(session.graph(), child), 0);
+    // hidden nodes can still be passed explicitly
+    session.run(fn_names["mark"], &[child]).unwrap();
+
+    assert!(session.unhide_node(child));
+    session.run(fn_names["bump_any_child"], &[parent]).unwrap();
+    assert_eq!(int_value(session.graph(), child), 1);
+}
+
+#[test_log::test]
+fn history_keeps_intermediate_results() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut session = Session::new(op_ctx);
+    let parent = session.graph_mut().add_node(NodeValue::Integer(0));
+
+    let first = session
+        .run(fn_names["new_child"], &[parent])
+        .unwrap()
+        .key_of_output_marker("child")
+        .unwrap();
+    session.run(fn_names["mark"], &[first]).unwrap();
+    session
+        .run(fn_names["bump_unvisited_child"], &[parent])
+        .unwrap();
+    let second = session
+        .run(fn_names["new_child"], &[parent])
+        .unwrap()
+        .key_of_output_marker("child")
+        .unwrap();
+    session
+        .run(fn_names["bump_unvisited_child"], &[parent])
+        .unwrap();
+    assert_eq!(int_value(session.graph(), first), 0);
+    assert_eq!(int_value(session.graph(), second), 1);
+
+    let history = session.history();
+    assert_eq!(history.len(), 5);
+    assert_eq!(history[0].key_of_output_marker("child"), Some(first));
+    assert_eq!(history[3].key_of_output_marker("child"), Some(second));
+    assert!(session.trace_of(&history[2]).is_empty());
+    assert_eq!(session.trace_of(&history[4]).len(), 1);
+
+    // failed runs are not recorded
+    assert!(session.run(fn_names["mark"], &[]).is_err());
+    assert_eq!(session.history().len(), 5);
+}
@@ -0,0 +1,247 @@
+mod util;
+
+use grabapl::operation::builder::OperationBuilderError;
+use grabapl::prelude::*;
+use syntax::grabapl_defs;
+use util::semantics::helpers::{int, int_value};
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+// bumps the child behind the first matching edge label, or the parent if there is none
+fn bump_by_label(p: int) {
+    match shape {
+        [c: int, p -> c: "first"] => {
+            increment(c);
+        },
+        [c: int, p -> c: "second"] => {
+            increment(c);
+            increment(c);
+        },
+        [s: string, p -> s: *] => {
+            decrement(p);
+        }
+        _ => {
+            increment(p);
+            increment(p);
+            increment(p);
+        }
+    }
+}
+
+// without a default arm, nothing happens if no arm matches
+fn bump_only_first(p: int) {
+    match shape {
+        [c: int, p -> c: "first"] => {
+            increment(c);
+        }
+    }
+}
+
+// every arm creates `result`, so it is still available after the match
+fn count_children(p: int) {
+    match shape {
+        [a: int, b: int, p -> a: *, p -> b: *] => {
+            let! result = add_node<int,2>();
+        },
+        [a: int, p -> a: *] => {
+            let! result = add_node<int,1>();
+        },
+        _ => {
+            let! result = add_node<int,0>();
+        },
+    }
+    add_edge<"count">(p, result);
+}
+
+fn mark_then_bump_unmarked_child(p: int) {
+    if shape [c: int, p -> c: *] {
+        mark_node<"visited", int>(c);
+    }
+    bump_unmarked_child(p);
+}
+
+// the arm skips children that are marked as visited
+fn bump_unmarked_child(p: int) {
+    match shape {
+        [c: int, p -> c: *] skipping ["visited"] => {
+            increment(c);
+        }
+        _ => {}
+    }
+}
+);
+
+#[test_log::test]
+fn the_first_matching_arm_is_taken() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let p = int(&mut g, 0);
+    let second = int(&mut g, 0);
+    let first = int(&mut g, 0);
+    g.add_edge(p, second, "second".to_string());
+    g.add_edge(p, first, "first".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_by_label"], &[p]).unwrap();
+    assert_eq!([p, first, second].map(|n| int_value(&g, n)), [0, 1, 0]);
+
+    // without the first edge, the second arm matches
+    g.remove_edge_between(p, first);
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_by_label"], &[p]).unwrap();
+    assert_eq!([p, first, second].map(|n| int_value(&g, n)), [0, 1, 2]);
+}
+
+#[test_log::test]
+fn arms_bind_their_own_shape_nodes() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let p = int(&mut g, 0);
+    let s = g.add_node(NodeValue::String("child".to_string()));
+    g.add_edge(p, s, "first".to_string());
+
+    // the string child cannot be matched by the integer shape nodes of the first two arms
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_by_label"], &[p]).unwrap();
+    assert_eq!(int_value(&g, p), -1);
+}
+
+#[test_log::test]
+fn the_default_arm_is_taken_if_no_arm_matches() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let p = int(&mut g, 0);
+    let other = int(&mut g, 0);
+    g.add_edge(p, other, "other".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_by_label"], &[p]).unwrap();
+    assert_eq!([p, other].map(|n| int_value(&g, n)), [3, 0]);
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_only_first"], &[p]).unwrap();
+    assert_eq!([p, other].map(|n| int_value(&g, n)), [3, 0]);
+}
+
+#[test_log::test]
+fn nodes_created_in_every_arm_are_merged() {
+    let (op_ctx, fn_names) = get_ops();
+    for (children, expected) in [(0, 0), (1, 1), (2, 2), (3, 2)] {
+        let mut g = ConcreteGraph::<TestSemantics>::new();
+        let p = int(&mut g, 0);
+        for _ in 0..children {
+            let child = int(&mut g, 0);
+            g.add_edge(p, child, "child".to_string());
+        }
+
+        run_from_concrete(&mut g, &op_ctx, fn_names["count_children"], &[p]).unwrap();
+        let count = g
+            .out_edges(p)
+            .find(|(_, attr)| *attr == "count")
+            .map(|(target, _)| target)
+            .expect("count edge should exist");
+        assert_eq!(int_value(&g, count), expected, "{children} children");
+    }
+}
+
+#[test_log::test]
+fn arms_respect_skipped_markers() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let p = int(&mut g, 0);
+    let c = int(&mut g, 0);
+    g.add_edge(p, c, "child".to_string());
+
+    run_from_concrete(
+        &mut g,
+        &op_ctx,
+        fn_names["mark_then_bump_unmarked_child"],
+        &[p],
+    )
+    .unwrap();
+    assert_eq!(int_value(&g, c), 0);
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_unmarked_child"], &[p]).unwrap();
+    assert_eq!(int_value(&g, c), 1);
+}
+
+#[test_log::test]
+fn builder_builds_shape_matches() {
+    let mut op_ctx = OperationContext::<TestSemantics>::new();
+    let p0 = AbstractNodeId::param("p0");
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    builder.start_shape_match("m").unwrap();
+    let c = AbstractNodeId::dynamic_output("m", "c");
+    for (label, amount) in [("a", 1), ("b", 10)] {
+        builder.start_match_arm().unwrap();
+        builder
+            .expect_shape_node("c".into(), NodeType::Integer)
+            .unwrap();
+        builder
+            .expect_shape_edge(p0, c, EdgeType::Exact(label.to_string()))
+            .unwrap();
+        builder
+            .add_operation(
+                BuilderOpLike::Builtin(TestOperation::AddInteger(amount)),
+                vec![c],
+            )
+            .unwrap();
+    }
+    builder.enter_default_arm().unwrap();
+    builder
+        .add_operation(
+            BuilderOpLike::Builtin(TestOperation::AddInteger(100)),
+            vec![p0],
+        )
+        .unwrap();
+    builder.end_query().unwrap();
+    op_ctx.add_custom_operation(0, builder.build().unwrap());
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let p = int(&mut g, 0);
+    let b = int(&mut g, 0);
+    g.add_edge(p, b, "b".to_string());
+    run_from_concrete(&mut g, &op_ctx, 0, &[p]).unwrap();
+    run_from_concrete(&mut g, &op_ctx, 0, &[b]).unwrap();
+    assert_eq!([p, b].map(|n| int_value(&g, n)), [0, 110]);
+}
+
+#[test_log::test]
+fn builder_rejects_arms_after_the_default_arm() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    builder.start_shape_match("m").unwrap();
+    builder.enter_default_arm().unwrap();
+    assert!(matches!(
+        builder.enter_default_arm().unwrap_err().current_context(),
+        OperationBuilderError::AlreadyVisitedDefaultArm
+    ));
+    assert!(builder.start_match_arm().is_err());
+}
+
+#[test_log::test]
+fn shape_match_nodes_are_not_visible_after_the_match() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let p0 = AbstractNodeId::param("p0");
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    builder.start_shape_match("m").unwrap();
+    builder.start_match_arm().unwrap();
+    builder
+        .expect_shape_node("c".into(), NodeType::Integer)
+        .unwrap();
+    let c = AbstractNodeId::dynamic_output("m", "c");
+    builder
+        .expect_shape_edge(p0, c, EdgeType::Wildcard)
+        .unwrap();
+    builder.end_query().unwrap();
+
+    let res = builder.add_operation(
+        BuilderOpLike::Builtin(TestOperation::AddInteger(1)),
+        vec![c],
+    );
+    assert!(res.is_err());
+}
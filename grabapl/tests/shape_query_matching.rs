@@ -0,0 +1,237 @@
+mod util;
+
+use grabapl::prelude::*;
+use syntax::grabapl_defs;
+use util::semantics::helpers::int;
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+// a cycle through x that is two hops away from x
+fn mark_cycle(x: int) {
+    if shape [
+        a: int,
+        b: int,
+        x -> a: "next",
+        a -> b: "next",
+        b -> x: "back",
+    ] {
+        increment(a);
+        increment(b);
+    }
+}
+
+// expands along an incoming edge
+fn bump_parent(x: int) {
+    if shape [
+        p: int,
+        p -> x: "next",
+    ] {
+        increment(p);
+    }
+}
+
+// distinct shape nodes must match distinct nodes
+fn two_children(x: int) {
+    if shape [
+        a: int,
+        b: int,
+        x -> a: "next",
+        x -> b: "next",
+    ] {
+        increment(x);
+    }
+}
+
+// not connected to x, so this needs to search the entire graph
+fn any_string(x: int) {
+    if shape [
+        s: string,
+    ] {
+        increment(x);
+    }
+}
+
+fn bump_child(parent: int) {
+    if shape [
+        child: int,
+        parent -> child: "next",
+    ] {
+        increment(child);
+    }
+}
+
+fn bump_any(x: int) {
+    if shape [
+        other: int,
+    ] {
+        increment(other);
+    }
+}
+
+fn bump_context(x: int) [p: int, p -> x: "next"] {
+    increment(p);
+}
+
+fn list_length(head: int, acc: int) {
+    increment(acc);
+    if shape [
+        next: int,
+        head -> next: "next",
+    ] {
+        list_length(next, acc);
+    }
+}
+);
+
+#[test_log::test]
+fn matches_multi_hop_patterns_around_the_anchor() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = int(&mut g, 0);
+    // a dead end that looks like the start of the cycle
+    let decoy = int(&mut g, 0);
+    let decoy_next = int(&mut g, 0);
+    g.add_edge(x, decoy, "next".to_string());
+    g.add_edge(decoy, decoy_next, "next".to_string());
+    // the actual cycle
+    let a = int(&mut g, 0);
+    let b = int(&mut g, 0);
+    g.add_edge(x, a, "next".to_string());
+    g.add_edge(a, b, "next".to_string());
+    g.add_edge(b, x, "back".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["mark_cycle"], &[x]).unwrap();
+
+    assert_eq!(g.get_node_attr(a), Some(&NodeValue::Integer(1)));
+    assert_eq!(g.get_node_attr(b), Some(&NodeValue::Integer(1)));
+    assert_eq!(g.get_node_attr(decoy), Some(&NodeValue::Integer(0)));
+    assert_eq!(g.get_node_attr(decoy_next), Some(&NodeValue::Integer(0)));
+}
+
+#[test_log::test]
+fn matches_along_incoming_edges() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = int(&mut g, 0);
+    let child = int(&mut g, 0);
+    let parent = int(&mut g, 0);
+    g.add_edge(x, child, "next".to_string());
+    g.add_edge(parent, x, "next".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_parent"], &[x]).unwrap();
+
+    assert_eq!(g.get_node_attr(parent), Some(&NodeValue::Integer(1)));
+    assert_eq!(g.get_node_attr(child), Some(&NodeValue::Integer(0)));
+}
+
+#[test_log::test]
+fn shape_nodes_match_distinct_nodes() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = int(&mut g, 0);
+    let a = int(&mut g, 0);
+    g.add_edge(x, a, "next".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["two_children"], &[x]).unwrap();
+    assert_eq!(g.get_node_attr(x), Some(&NodeValue::Integer(0)));
+
+    let b = int(&mut g, 0);
+    g.add_edge(x, b, "next".to_string());
+    run_from_concrete(&mut g, &op_ctx, fn_names["two_children"], &[x]).unwrap();
+    assert_eq!(g.get_node_attr(x), Some(&NodeValue::Integer(1)));
+}
+
+#[test_log::test]
+fn disconnected_patterns_search_the_entire_graph() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = int(&mut g, 0);
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["any_string"], &[x]).unwrap();
+    assert_eq!(g.get_node_attr(x), Some(&NodeValue::Integer(0)));
+
+    g.add_node(NodeValue::String("far away".to_string()));
+    run_from_concrete(&mut g, &op_ctx, fn_names["any_string"], &[x]).unwrap();
+    assert_eq!(g.get_node_attr(x), Some(&NodeValue::Integer(1)));
+}
+
+#[test_log::test]
+fn traversal_in_a_large_graph() {
+    const LENGTH: i32 = 200;
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    // many unrelated nodes, which anchored queries never need to look at
+    for i in 0..20_000 {
+        let n = int(&mut g, i);
+        let m = int(&mut g, i);
+        g.add_edge(n, m, "next".to_string());
+    }
+    let head = int(&mut g, 0);
+    let mut last = head;
+    for i in 1..LENGTH {
+        let next = int(&mut g, i);
+        g.add_edge(last, next, "next".to_string());
+        last = next;
+    }
+    let acc = int(&mut g, 0);
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["list_length"], &[head, acc]).unwrap();
+    assert_eq!(g.get_node_attr(acc), Some(&NodeValue::Integer(LENGTH)));
+}
+
+/// Adds `count` nodes and removes the first one, such that the remaining nodes are not stored in
+/// the order of their keys anymore.
+fn shuffled_ints(g: &mut ConcreteGraph<TestSemantics>, count: usize) -> Vec<NodeKey> {
+    let mut nodes = (0..count).map(|_| int(g, 0)).collect::<Vec<_>>();
+    g.remove_node(nodes.remove(0));
+    nodes
+}
+
+#[test_log::test]
+fn shape_queries_bind_the_smallest_node_key() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let parent = int(&mut g, 0);
+    let children = shuffled_ints(&mut g, 4);
+    // edges are not added in key order either
+    for &child in children.iter().rev() {
+        g.add_edge(parent, child, "next".to_string());
+    }
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_child"], &[parent]).unwrap();
+    let values = children
+        .iter()
+        .map(|&child| g.get_node_attr(child).cloned())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        values,
+        [1, 0, 0].map(|value| Some(NodeValue::Integer(value)))
+    );
+}
+
+#[test_log::test]
+fn disconnected_patterns_bind_the_smallest_node_key() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let x = int(&mut g, 0);
+    let others = shuffled_ints(&mut g, 4);
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_any"], &[x]).unwrap();
+    assert_eq!(g.get_node_attr(x), Some(&NodeValue::Integer(0)));
+    assert_eq!(g.get_node_attr(others[0]), Some(&NodeValue::Integer(1)));
+}
+
+#[test_log::test]
+fn substitutions_bind_the_smallest_node_key() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let parents = shuffled_ints(&mut g, 4);
+    let x = int(&mut g, 0);
+    for &parent in parents.iter().rev() {
+        g.add_edge(parent, x, "next".to_string());
+    }
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_context"], &[x]).unwrap();
+    assert_eq!(g.get_node_attr(parents[0]), Some(&NodeValue::Integer(1)));
+    assert_eq!(g.get_node_attr(parents[1]), Some(&NodeValue::Integer(0)));
+}
@@ -0,0 +1,240 @@
+mod util;
+
+use grabapl::prelude::*;
+use syntax::grabapl_defs;
+use util::semantics::helpers::{int, int_value};
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+// bumps the last node of a linked list
+fn bump_last(head: int) {
+    if shape [
+        last: int,
+        head ->* last: "next",
+        !(last -> _: "next"),
+    ] {
+        increment(last);
+    }
+}
+
+// bumps the node before the last node of a linked list
+fn bump_second_to_last(head: int) {
+    if shape [
+        prev: int,
+        last: int,
+        head ->* prev: "next",
+        prev -> last: "next",
+        !(last -> _: "next"),
+    ] {
+        increment(prev);
+    }
+}
+
+// bumps `b` if it can be reached from `a`
+fn bump_if_reachable(a: int, b: int) {
+    if shape [a ->* b: "next"] {
+        increment(b);
+    }
+}
+
+// bumps the children of `r` that are not on a path from `a` to `b`
+fn bump_off_path(a: int, b: int, r: int) {
+    if shape [a ->* b: "next" marking "path"] {}
+    bump_unmarked_children(r);
+}
+
+// bumps `a` if it is on a cycle, and the children of `r` that are not on that cycle
+fn bump_on_cycle(a: int, r: int) {
+    if shape [a ->* a: "next" marking "path"] {
+        increment(a);
+    }
+    bump_unmarked_children(r);
+}
+
+fn bump_unmarked_children(r: int) {
+    if shape [c: int, r -> c: "child"] skipping ["path", "done"] {
+        increment(c);
+        mark_node<"done", int>(c);
+        bump_unmarked_children(r);
+    }
+}
+);
+
+fn chain(g: &mut ConcreteGraph<TestSemantics>, nodes: &[NodeKey]) {
+    for pair in nodes.windows(2) {
+        g.add_edge(pair[0], pair[1], "next".to_string());
+    }
+}
+
+#[test_log::test]
+fn path_binds_the_end_of_a_list() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let list = (0..5).map(|_| int(&mut g, 0)).collect::<Vec<_>>();
+    chain(&mut g, &list);
+    // edges with other labels are not part of the list
+    let other = int(&mut g, 0);
+    g.add_edge(list[4], other, "other".to_string());
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_last"], &[list[0]]).unwrap();
+    let values = list.iter().map(|&n| int_value(&g, n)).collect::<Vec<_>>();
+    assert_eq!(values, [0, 0, 0, 0, 1]);
+    assert_eq!(int_value(&g, other), 0);
+}
+
+#[test_log::test]
+fn split_path_binds_a_node_in_between() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let list = (0..5).map(|_| int(&mut g, 0)).collect::<Vec<_>>();
+    chain(&mut g, &list);
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_second_to_last"], &[list[0]]).unwrap();
+    let values = list.iter().map(|&n| int_value(&g, n)).collect::<Vec<_>>();
+    assert_eq!(values, [0, 0, 0, 1, 0]);
+}
+
+#[test_log::test]
+fn path_needs_at_least_one_edge() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let head = int(&mut g, 0);
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_last"], &[head]).unwrap();
+    assert_eq!(int_value(&g, head), 0);
+}
+
+#[test_log::test]
+fn path_from_a_node_to_itself_is_a_cycle() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let a = int(&mut g, 0);
+    let r = int(&mut g, 0);
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_on_cycle"], &[a, r]).unwrap();
+    assert_eq!(int_value(&g, a), 0);
+
+    let cycle = [(); 2].map(|_| int(&mut g, 0));
+    let off_cycle = int(&mut g, 0);
+    chain(&mut g, &[a, cycle[0], cycle[1], a]);
+    g.add_edge(cycle[1], off_cycle, "next".to_string());
+    for child in [cycle[0], cycle[1], off_cycle] {
+        g.add_edge(r, child, "child".to_string());
+    }
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_on_cycle"], &[a, r]).unwrap();
+    assert_eq!(int_value(&g, a), 1);
+    assert_eq!(cycle.map(|n| int_value(&g, n)), [0, 0]);
+    assert_eq!(int_value(&g, off_cycle), 1);
+}
+
+#[test_log::test]
+fn path_follows_edge_type_and_direction() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let list = (0..3).map(|_| int(&mut g, 0)).collect::<Vec<_>>();
+    chain(&mut g, &list);
+    let behind_other = int(&mut g, 0);
+    g.add_edge(list[2], behind_other, "other".to_string());
+
+    run_from_concrete(
+        &mut g,
+        &op_ctx,
+        fn_names["bump_if_reachable"],
+        &[list[0], list[2]],
+    )
+    .unwrap();
+    assert_eq!(int_value(&g, list[2]), 1);
+
+    run_from_concrete(
+        &mut g,
+        &op_ctx,
+        fn_names["bump_if_reachable"],
+        &[list[2], list[0]],
+    )
+    .unwrap();
+    assert_eq!(int_value(&g, list[0]), 0);
+
+    run_from_concrete(
+        &mut g,
+        &op_ctx,
+        fn_names["bump_if_reachable"],
+        &[list[0], behind_other],
+    )
+    .unwrap();
+    assert_eq!(int_value(&g, behind_other), 0);
+}
+
+#[test_log::test]
+fn path_marking_marks_a_shortest_path() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    // a long and a short route from `a` to `b`
+    let a = int(&mut g, 0);
+    let long = [(); 3].map(|_| int(&mut g, 0));
+    let short = int(&mut g, 0);
+    let b = int(&mut g, 0);
+    chain(&mut g, &[a, long[0], long[1], long[2], b]);
+    chain(&mut g, &[a, short, b]);
+
+    let r = int(&mut g, 0);
+    for child in [a, long[0], long[1], long[2], short, b] {
+        g.add_edge(r, child, "child".to_string());
+    }
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["bump_off_path"], &[a, b, r]).unwrap();
+    assert_eq!([a, short, b].map(|n| int_value(&g, n)), [0, 0, 0]);
+    assert_eq!(long.map(|n| int_value(&g, n)), [1, 1, 1]);
+}
+
+#[test_log::test]
+fn builder_builds_shape_paths() {
+    let mut op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    let p0 = AbstractNodeId::param("p0");
+    builder.start_shape_query("q").unwrap();
+    builder
+        .expect_shape_node("s".into(), NodeType::String)
+        .unwrap();
+    let s = AbstractNodeId::dynamic_output("q", "s");
+    builder
+        .expect_shape_path(p0, s, EdgeType::Wildcard)
+        .unwrap();
+    builder.enter_true_branch().unwrap();
+    builder
+        .add_operation(
+            BuilderOpLike::Builtin(TestOperation::AddInteger(1)),
+            vec![p0],
+        )
+        .unwrap();
+    builder.end_query().unwrap();
+    op_ctx.add_custom_operation(0, builder.build().unwrap());
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let p = int(&mut g, 0);
+    let i = int(&mut g, 0);
+    g.add_edge(p, i, "a".to_string());
+    run_from_concrete(&mut g, &op_ctx, 0, &[p]).unwrap();
+    assert_eq!(int_value(&g, p), 0);
+
+    let s = g.add_node(NodeValue::String("end".to_string()));
+    g.add_edge(i, s, "b".to_string());
+    run_from_concrete(&mut g, &op_ctx, 0, &[p]).unwrap();
+    assert_eq!(int_value(&g, p), 1);
+}
+
+#[test_log::test]
+fn builder_rejects_paths_to_unknown_nodes() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    let p0 = AbstractNodeId::param("p0");
+    builder.start_shape_query("q").unwrap();
+
+    let unknown = AbstractNodeId::dynamic_output("q", "unknown");
+    let res = builder.expect_shape_path(p0, unknown, EdgeType::Wildcard);
+    assert!(res.is_err());
+}
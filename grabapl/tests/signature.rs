@@ -197,7 +197,11 @@ fn writing_unjoinable_av_to_param() {
         }),
         vec![a0, a1],
     ).unwrap();
-    let op = builder.build().unwrap();
+    let mut op = builder.build().unwrap();
+    // the writes above are unconditional, so callers would simply see the new types.
+    // pretend they are only maybe-writes, which callers must join with the old types.
+    op.signature.output.must_changed_nodes.clear();
+    op.signature.output.must_changed_edges.clear();
     op_ctx.add_custom_operation(0, op);
 
     let mut builder = OperationBuilder::new(&op_ctx, 0);
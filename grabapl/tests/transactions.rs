@@ -0,0 +1,174 @@
+mod util;
+
+use grabapl::operation::OperationError;
+use grabapl::operation::marker::MarkerSet;
+use grabapl::prelude::*;
+use syntax::grabapl_defs;
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+fn wreck(x: int) {
+    increment(x);
+    let! n = add_node<int,5>();
+    add_edge<"next">(x, n);
+    mark_node<"new">(n);
+    if shape [c: int, x -> c: "next"] {
+        remove_edge(x, c);
+        remove_node(c);
+    }
+    if is_zero(x) {
+    } else {
+        diverge<"boom">();
+    }
+}
+
+fn spin(x: int) {
+    let! n = add_node<int,0>();
+    add_edge<"next">(x, n);
+    spin(x);
+    increment(x);
+}
+);
+
+/// A list `head -> a -> b` where `head` is also pointed to by `b`, with some markers.
+fn setup() -> (ConcreteGraph<TestSemantics>, MarkerSet, NodeKey) {
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let head = g.add_node(NodeValue::Integer(1));
+    let a = g.add_node(NodeValue::Integer(2));
+    let b = g.add_node(NodeValue::Integer(3));
+    g.add_edge(head, a, "next".to_string());
+    g.add_edge(a, b, "next".to_string());
+    g.add_edge(b, head, "back".to_string());
+    g.add_edge(a, a, "self".to_string());
+
+    let mut marker_set = MarkerSet::new();
+    marker_set.create_marker_and_mark_node("old", b);
+    (g, marker_set, head)
+}
+
+fn assert_same_graph(g: &ConcreteGraph<TestSemantics>, expected: &ConcreteGraph<TestSemantics>) {
+    assert!(g.semantically_matches_with_same_keys(expected));
+    let edges = |g: &ConcreteGraph<TestSemantics>| {
+        let mut edges = g
+            .inner_graph()
+            .all_edges()
+            .map(|(src, dst, attr)| (src, dst, attr.clone()))
+            .collect::<Vec<_>>();
+        edges.sort_by_key(|(src, dst, _)| (*src, *dst));
+        edges
+    };
+    // includes the edge orders
+    assert_eq!(edges(g), edges(expected));
+}
+
+#[test_log::test]
+fn failed_run_is_rolled_back() {
+    let (op_ctx, fn_names) = get_ops();
+    let (mut g, mut marker_set, head) = setup();
+    let g_before = g.clone();
+    let markers_before = marker_set.clone();
+
+    let res = run_from_concrete_transactional(
+        &mut g,
+        &mut marker_set,
+        &op_ctx,
+        fn_names["wreck"],
+        &[head],
+        ExecutionLimits::unlimited(),
+    );
+    let Err(err) = res else {
+        panic!("expected the run to fail");
+    };
+    assert!(matches!(
+        err.current_context(),
+        OperationError::UserCrash(_)
+    ));
+
+    assert_same_graph(&g, &g_before);
+    assert!(!g.in_transaction());
+    assert_eq!(marker_set, markers_before);
+
+    // node key allocation is restored as well
+    let mut g_before = g_before;
+    assert_eq!(
+        g.add_node(NodeValue::Integer(0)),
+        g_before.add_node(NodeValue::Integer(0))
+    );
+}
+
+#[test_log::test]
+fn runs_aborted_by_limits_are_rolled_back() {
+    let (op_ctx, fn_names) = get_ops();
+    let (mut g, mut marker_set, head) = setup();
+    let g_before = g.clone();
+
+    let limits = ExecutionLimits::unlimited().with_max_recursion_depth(20);
+    let res = run_from_concrete_transactional(
+        &mut g,
+        &mut marker_set,
+        &op_ctx,
+        fn_names["spin"],
+        &[head],
+        limits,
+    );
+    assert!(res.is_err());
+    assert_same_graph(&g, &g_before);
+}
+
+#[test_log::test]
+fn successful_run_is_kept() {
+    let (op_ctx, fn_names) = get_ops();
+    let (mut g, mut marker_set, _) = setup();
+    let x = g.add_node(NodeValue::Integer(-1));
+
+    let output = run_from_concrete_transactional(
+        &mut g,
+        &mut marker_set,
+        &op_ctx,
+        fn_names["wreck"],
+        &[x],
+        ExecutionLimits::unlimited(),
+    )
+    .unwrap();
+
+    assert!(!g.in_transaction());
+    assert_eq!(g.get_node_attr(x), Some(&NodeValue::Integer(0)));
+    // the shape query cannot match the new node, since the operation has a handle to it
+    let new_nodes = g.out_edges(x).map(|(n, _)| n).collect::<Vec<_>>();
+    assert_eq!(new_nodes.len(), 1);
+    assert_eq!(g.get_node_attr(new_nodes[0]), Some(&NodeValue::Integer(5)));
+    // previous and new markers are kept
+    assert!(marker_set.markers().contains(&"old".into()));
+    assert!(marker_set.markers().contains(&"new".into()));
+    assert_eq!(output.marker_set.markers(), marker_set.markers());
+
+    // the graph can still be rolled back manually, but there is nothing to roll back
+    let g_after = g.clone();
+    g.rollback_transaction();
+    assert_same_graph(&g, &g_after);
+}
+
+#[test_log::test]
+fn clones_are_not_part_of_the_transaction() {
+    let (mut g, mut marker_set, head) = setup();
+    g.begin_transaction();
+    marker_set.begin_transaction();
+    g.add_node(NodeValue::Integer(7));
+    marker_set.create_marker_and_mark_node("new", head);
+
+    let mut g_clone = g.clone();
+    let mut markers_clone = marker_set.clone();
+    assert!(!g_clone.in_transaction());
+    // rolling back a clone does nothing
+    g_clone.rollback_transaction();
+    markers_clone.rollback_transaction();
+    assert_same_graph(&g_clone, &g);
+    assert_eq!(markers_clone, marker_set);
+
+    g.rollback_transaction();
+    marker_set.rollback_transaction();
+    assert_eq!(g.nodes().count(), 3);
+    assert_eq!(marker_set.marked_nodes("new"), None);
+    assert_eq!(g_clone.nodes().count(), 4);
+    assert_eq!(markers_clone.markers_of(head).unwrap().len(), 1);
+}
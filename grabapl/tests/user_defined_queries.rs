@@ -0,0 +1,271 @@
+mod util;
+
+use grabapl::operation::builder::{BuilderQueryLike, OperationBuilderError};
+use grabapl::prelude::*;
+use syntax::grabapl_defs;
+use util::semantics::helpers::{int, int_value, parses};
+use util::semantics::*;
+
+grabapl_defs!(get_ops, TestSemantics,
+fn is_leaf(x: int) -> bool {
+    if shape [c: int, x -> c: *] {
+        return false;
+    } else {
+        return true;
+    }
+}
+
+// whether the values of the "next" list starting at `x` are ascending
+fn is_sorted(x: int) -> bool {
+    if shape [n: int, x -> n: "next"] {
+        if cmp_fst_snd%>%(x, n) {
+            return false;
+        } else {
+            if is_sorted(n) {
+                return true;
+            } else {
+                return false;
+            }
+        }
+    } else {
+        return true;
+    }
+}
+
+fn mark_leaf(x: int) {
+    if is_leaf(x) {
+        increment(x);
+    }
+}
+
+fn mark_sorted(x: int) {
+    if is_sorted(x) {
+        increment(x);
+    } else {
+        decrement(x);
+    }
+}
+);
+
+fn list(g: &mut ConcreteGraph<TestSemantics>, values: &[i32]) -> Vec<NodeKey> {
+    let nodes = values.iter().map(|&v| int(g, v)).collect::<Vec<_>>();
+    for pair in nodes.windows(2) {
+        g.add_edge(pair[0], pair[1], "next".to_string());
+    }
+    nodes
+}
+
+#[test_log::test]
+fn user_defined_query_decides_branch() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let nodes = list(&mut g, &[0, 0]);
+
+    for &node in &nodes {
+        run_from_concrete(&mut g, &op_ctx, fn_names["mark_leaf"], &[node]).unwrap();
+    }
+    assert_eq!(int_value(&g, nodes[0]), 0);
+    assert_eq!(int_value(&g, nodes[1]), 1);
+}
+
+#[test_log::test]
+fn recursive_query() {
+    let (op_ctx, fn_names) = get_ops();
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let sorted = list(&mut g, &[1, 2, 2, 5]);
+    let unsorted = list(&mut g, &[1, 3, 2, 5]);
+
+    run_from_concrete(&mut g, &op_ctx, fn_names["mark_sorted"], &[sorted[0]]).unwrap();
+    run_from_concrete(&mut g, &op_ctx, fn_names["mark_sorted"], &[unsorted[0]]).unwrap();
+    assert_eq!(int_value(&g, sorted[0]), 2);
+    assert_eq!(int_value(&g, unsorted[0]), 0);
+    // the query itself does not change the graph
+    assert_eq!(int_value(&g, unsorted[2]), 2);
+}
+
+#[test_log::test]
+fn query_signature_is_marked() {
+    let (op_ctx, fn_names) = get_ops();
+    let Some(Operation::Custom(is_leaf)) = op_ctx.get(fn_names["is_leaf"]) else {
+        panic!("expected a user defined operation");
+    };
+    assert!(is_leaf.signature.is_query);
+    let Some(Operation::Custom(mark_leaf)) = op_ctx.get(fn_names["mark_leaf"]) else {
+        panic!("expected a user defined operation");
+    };
+    assert!(!mark_leaf.signature.is_query);
+}
+
+#[test_log::test]
+fn queries_cannot_change_structure() {
+    let src = stringify!(
+        fn has_child(x: int) -> bool {
+            let! c = add_node<int,0>();
+            return true;
+        }
+    );
+    assert!(!parses(src));
+
+    let src = stringify!(
+        fn has_child(x: int) -> bool {
+            if shape [c: int, x -> c: *] {
+                remove_node(c);
+                return true;
+            } else {
+                return false;
+            }
+        }
+    );
+    assert!(!parses(src));
+
+    // changing values is allowed
+    let src = stringify!(
+        fn has_child(x: int) -> bool {
+            increment(x);
+            return true;
+        }
+    );
+    assert!(parses(src));
+}
+
+#[test_log::test]
+fn every_path_must_yield() {
+    let src = stringify!(
+        fn is_leaf(x: int) -> bool {
+            if shape [c: int, x -> c: *] {
+                return false;
+            }
+        }
+    );
+    assert!(!parses(src));
+
+    // a diverging branch does not need to yield
+    let src = stringify!(
+        fn is_leaf(x: int) -> bool {
+            if shape [c: int, x -> c: *] {
+                return false;
+            } else {
+                diverge<"no child">();
+            }
+        }
+    );
+    assert!(parses(src));
+
+    let src = stringify!(
+        fn is_leaf(x: int) -> bool {
+            return true;
+            increment(x);
+        }
+    );
+    assert!(!parses(src));
+}
+
+#[test_log::test]
+fn only_queries_can_be_conditions_and_yield() {
+    let src = stringify!(
+        fn not_a_query(x: int) {}
+
+        fn main(x: int) {
+            if not_a_query(x) {
+                increment(x);
+            }
+        }
+    );
+    assert!(!parses(src));
+
+    let src = stringify!(
+        fn not_a_query(x: int) {
+            return true;
+        }
+    );
+    assert!(!parses(src));
+}
+
+#[test_log::test]
+fn builder_builds_user_defined_queries() {
+    let mut op_ctx = OperationContext::<TestSemantics>::new();
+
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    builder.expect_self_query().unwrap();
+    builder
+        .start_query(
+            TestQuery::ValueEqualTo(NodeValue::Integer(0)),
+            vec![AbstractNodeId::param("p0")],
+        )
+        .unwrap();
+    builder.enter_true_branch().unwrap();
+    builder.yield_query_result(false).unwrap();
+    // nothing may follow a yield
+    let Err(err) = builder.add_operation(
+        BuilderOpLike::Builtin(TestOperation::AddInteger(1)),
+        vec![AbstractNodeId::param("p0")],
+    ) else {
+        panic!("expected the instruction to be rejected");
+    };
+    assert!(matches!(
+        err.current_context(),
+        OperationBuilderError::InstructionAfterYield
+    ));
+    builder.enter_false_branch().unwrap();
+    builder.yield_query_result(true).unwrap();
+    builder.end_query().unwrap();
+    let is_nonzero = builder.build().unwrap();
+    op_ctx.add_custom_operation(0, is_nonzero);
+
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 1);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    builder
+        .start_query_like(
+            BuilderQueryLike::FromOperationId(0),
+            vec![AbstractNodeId::param("p0")],
+        )
+        .unwrap();
+    builder.enter_true_branch().unwrap();
+    builder
+        .add_operation(
+            BuilderOpLike::Builtin(TestOperation::AddInteger(1)),
+            vec![AbstractNodeId::param("p0")],
+        )
+        .unwrap();
+    builder.end_query().unwrap();
+    let bump_nonzero = builder.build().unwrap();
+    op_ctx.add_custom_operation(1, bump_nonzero);
+
+    let mut g = ConcreteGraph::<TestSemantics>::new();
+    let zero = int(&mut g, 0);
+    let two = int(&mut g, 2);
+    run_from_concrete(&mut g, &op_ctx, 1, &[zero]).unwrap();
+    run_from_concrete(&mut g, &op_ctx, 1, &[two]).unwrap();
+    assert_eq!(int_value(&g, zero), 0);
+    assert_eq!(int_value(&g, two), 3);
+}
+
+#[test_log::test]
+fn builder_rejects_yield_in_some_branches() {
+    let op_ctx = OperationContext::<TestSemantics>::new();
+    let mut builder = OperationBuilder::<TestSemantics>::new(&op_ctx, 0);
+    builder
+        .expect_parameter_node("p0", NodeType::Integer)
+        .unwrap();
+    builder.expect_self_query().unwrap();
+    builder
+        .start_query(
+            TestQuery::ValueEqualTo(NodeValue::Integer(0)),
+            vec![AbstractNodeId::param("p0")],
+        )
+        .unwrap();
+    builder.enter_true_branch().unwrap();
+    builder.yield_query_result(true).unwrap();
+    let Err(err) = builder.end_query() else {
+        panic!("expected ending the query to fail");
+    };
+    assert!(matches!(
+        err.current_context(),
+        OperationBuilderError::YieldInSomeBranches
+    ));
+}
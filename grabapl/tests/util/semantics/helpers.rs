@@ -26,3 +26,23 @@ pub fn list_to_value_vec_generic<S: Semantics>(
 pub fn list_to_value_vec(graph: &ConcreteGraph<TestSemantics>, head: NodeKey) -> Vec<NodeValue> {
     list_to_value_vec_generic::<TestSemantics>(graph, head)
 }
+
+#[allow(dead_code)]
+pub fn int(g: &mut ConcreteGraph<TestSemantics>, value: i32) -> NodeKey {
+    g.add_node(NodeValue::Integer(value))
+}
+
+#[allow(dead_code)]
+pub fn int_value(g: &ConcreteGraph<TestSemantics>, node: NodeKey) -> i32 {
+    match g.get_node_attr(node) {
+        Some(NodeValue::Integer(value)) => *value,
+        other => panic!("expected an integer node, found {other:?}"),
+    }
+}
+
+#[allow(dead_code)]
+pub fn parses(src: &str) -> bool {
+    syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false)
+        .op_ctx_and_map
+        .is_ok()
+}
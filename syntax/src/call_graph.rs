@@ -0,0 +1,189 @@
+use crate::custom_syntax::CustomSyntax;
+use crate::{Block, IfCond, Program, Span, Spanned, Statement};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// The graph of calls between the functions of a [`Program`].
+///
+/// Calls to operations that are not defined in the program (e.g., builtin operations) are not
+/// part of the graph.
+#[derive(Debug, Clone)]
+pub struct CallGraph<'src> {
+    /// The functions of the program, in source order.
+    functions: Vec<&'src str>,
+    /// The call sites of every function, in source order.
+    calls: HashMap<&'src str, Vec<Spanned<&'src str>>>,
+}
+
+impl<'src> CallGraph<'src> {
+    pub fn new<CS: CustomSyntax>(program: &Program<'src, CS>) -> Self {
+        let functions: Vec<&str> = program.functions.iter().map(|(name, _)| *name).collect();
+        let defined: HashSet<&str> = functions.iter().copied().collect();
+        let calls = program
+            .functions
+            .iter()
+            .map(|(name, fn_def)| {
+                let mut call_sites = Vec::new();
+                collect_call_sites(&fn_def.0.body.0, &mut call_sites);
+                call_sites.retain(|(callee, _)| defined.contains(callee));
+                (*name, call_sites)
+            })
+            .collect();
+        Self { functions, calls }
+    }
+
+    /// Returns the call sites in `caller` that call another function of the program.
+    pub fn call_sites(&self, caller: &str) -> &[Spanned<&'src str>] {
+        self.calls.get(caller).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the distinct functions called by `caller`, in order of their first call.
+    pub fn callees(&self, caller: &str) -> Vec<&'src str> {
+        let mut seen = HashSet::new();
+        self.call_sites(caller)
+            .iter()
+            .map(|(callee, _)| *callee)
+            .filter(|callee| seen.insert(*callee))
+            .collect()
+    }
+
+    /// Returns the strongly connected components of the graph in a valid processing order,
+    /// i.e., every function comes after all functions it calls, unless they are in the same
+    /// component.
+    ///
+    /// Functions of a component are ordered as in the source. The order is deterministic:
+    /// independent components are ordered by their first function in the source.
+    pub fn processing_order(&self) -> Vec<Vec<&'src str>> {
+        // Tarjan's algorithm emits components in reverse topological order, i.e., callees first.
+        let mut tarjan = Tarjan {
+            graph: self,
+            next_index: 0,
+            index: HashMap::new(),
+            low_link: HashMap::new(),
+            stack: Vec::new(),
+            on_stack: HashSet::new(),
+            components: Vec::new(),
+        };
+        for function in &self.functions {
+            if !tarjan.index.contains_key(function) {
+                tarjan.visit(function);
+            }
+        }
+        let position: HashMap<&str, usize> = self
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (*name, i))
+            .collect();
+        let mut components = tarjan.components;
+        for component in &mut components {
+            component.sort_by_key(|name| position[name]);
+        }
+        components
+    }
+
+    /// Returns the call sites along a shortest call cycle that starts and ends at `function`.
+    ///
+    /// The last call site is the one that closes the cycle.
+    pub fn find_cycle(&self, function: &str) -> Option<Vec<Spanned<&'src str>>> {
+        let (start, _) = self.calls.get_key_value(function)?;
+        // for every visited function, the call site through which it was first reached
+        let mut reached_via: HashMap<&str, (&str, Span)> = HashMap::new();
+        let mut queue = VecDeque::from([*start]);
+        while let Some(caller) = queue.pop_front() {
+            for (callee, span) in self.call_sites(caller) {
+                if *callee == *start {
+                    let mut cycle = vec![(*callee, *span)];
+                    let mut current = caller;
+                    while current != *start {
+                        let (prev, span) = reached_via[current];
+                        cycle.push((current, span));
+                        current = prev;
+                    }
+                    cycle.reverse();
+                    return Some(cycle);
+                }
+                if !reached_via.contains_key(callee) {
+                    reached_via.insert(callee, (caller, *span));
+                    queue.push_back(callee);
+                }
+            }
+        }
+        None
+    }
+}
+
+struct Tarjan<'a, 'src> {
+    graph: &'a CallGraph<'src>,
+    next_index: usize,
+    index: HashMap<&'src str, usize>,
+    low_link: HashMap<&'src str, usize>,
+    stack: Vec<&'src str>,
+    on_stack: HashSet<&'src str>,
+    components: Vec<Vec<&'src str>>,
+}
+
+impl<'src> Tarjan<'_, 'src> {
+    fn visit(&mut self, function: &'src str) {
+        self.index.insert(function, self.next_index);
+        self.low_link.insert(function, self.next_index);
+        self.next_index += 1;
+        self.stack.push(function);
+        self.on_stack.insert(function);
+
+        for callee in self.graph.callees(function) {
+            if !self.index.contains_key(callee) {
+                self.visit(callee);
+                let low = self.low_link[function].min(self.low_link[callee]);
+                self.low_link.insert(function, low);
+            } else if self.on_stack.contains(callee) {
+                let low = self.low_link[function].min(self.index[callee]);
+                self.low_link.insert(function, low);
+            }
+        }
+
+        if self.low_link[function] == self.index[function] {
+            let mut component = Vec::new();
+            loop {
+                let member = self.stack.pop().unwrap();
+                self.on_stack.remove(member);
+                component.push(member);
+                if member == function {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+/// Collects all calls in the block, including calls to builtin operations.
+fn collect_call_sites<'src, CS: CustomSyntax>(
+    block: &Block<'src, CS>,
+    call_sites: &mut Vec<Spanned<&'src str>>,
+) {
+    for (statement, _) in &block.statements {
+        match statement {
+            Statement::Let((let_stmt, _)) => {
+                call_sites.push(let_stmt.call.0.name);
+            }
+            Statement::FnCall((call, _)) => {
+                call_sites.push(call.name);
+            }
+            Statement::If((if_stmt, _)) => {
+                // user defined queries are called in the condition
+                if let IfCond::Query((query, _)) = &if_stmt.cond.0 {
+                    call_sites.push(query.name);
+                }
+                collect_call_sites(&if_stmt.then_block.0, call_sites);
+                collect_call_sites(&if_stmt.else_block.0, call_sites);
+            }
+            Statement::Match((match_stmt, _)) => {
+                for (arm, _) in &match_stmt.arms {
+                    collect_call_sites(&arm.block.0, call_sites);
+                }
+                collect_call_sites(&match_stmt.default_block.0, call_sites);
+            }
+            Statement::Return(..) | Statement::Rename(..) | Statement::Yield(..) => {}
+        }
+    }
+}
@@ -330,6 +330,12 @@ impl SemanticsWithCustomSyntax for ExampleSemantics {
                 let x = i32::from_str(args_src).ok()?;
                 Some(ExampleQuery::ValueEqualTo(NodeValue::Integer(x)))
             }
+            "has_type" => {
+                let args_src = args?.0;
+                let node_type =
+                    Self::convert_node_type(MyCustomType::Primitive(args_src.to_string()))?;
+                Some(ExampleQuery::HasType(node_type))
+            }
             _ => None,
         }
     }
@@ -1,18 +1,23 @@
+use crate::call_graph::CallGraph;
 use crate::custom_syntax::{CustomSyntax, SemanticsWithCustomSyntax};
+use crate::lints::{Lint, LintConfig, LintLevel, LintWarning, SpanlessEq};
 use crate::{
     Block, FnCallExpr, FnDef, FnImplicitParam, FnNodeParam, IfCond, IfStmt, LetStmt, MacroArgs,
-    NodeId, Program, RenameStmt, ReturnStmt, ReturnStmtMapping, ShapeQueryParam, ShapeQueryParams,
-    Span, Spanned, Statement, Token, lexer,
+    MatchStmt, NodeId, Program, RenameStmt, ReturnStmt, ReturnStmtMapping, ShapeEdgeOrderParam,
+    ShapeQueryParam, ShapeQueryParams, Span, Spanned, Statement, Token, lexer,
 };
 use chumsky::input::Stream;
 use chumsky::prelude::*;
 use error_stack::{Report, Result, ResultExt, report};
 use grabapl::operation::builder::IntermediateState;
+use grabapl::operation::builder::stack_based_builder::merge_abstract_output_changes;
 use grabapl::operation::marker::SkipMarkers;
-use grabapl::operation::signature::AbstractSignatureNodeId;
 use grabapl::operation::signature::parameter::AbstractOutputNodeMarker;
+use grabapl::operation::signature::{AbstractSignatureNodeId, OperationSignature};
 use grabapl::prelude::*;
-use std::collections::HashMap;
+use grabapl::semantics::AbstractMatcher;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
 pub fn parse_abstract_node_type<S: SemanticsWithCustomSyntax>(
@@ -141,10 +146,14 @@ pub enum InterpreterError {
     NotFoundReturnMarker(String),
     #[error("Failed to parse type: {0}")]
     InvalidType(String),
+    #[error("Call cycle cannot be handled: {0}")]
+    UnresolvableCallCycle(String),
     #[error("Error: {0}")]
     Custom(&'static str),
     #[error("Error: {0}")]
     CustomOwned(String),
+    #[error("Denied lint: {0}")]
+    DeniedLint(String),
 }
 
 impl InterpreterError {
@@ -165,23 +174,54 @@ pub struct InterpreterResult<'src, S: SemanticsWithCustomSyntax, E> {
         std::result::Result<(OperationContext<S>, HashMap<&'src str, OperationId>), E>,
     // This is *outside* the result, since we might have a state_map even if the operation context fails to build!
     pub state_map: HashMap<String, IntermediateState<S>>,
+    /// The reported lints, sorted by their span. Like the state map, these are available even if
+    /// the operation context fails to build.
+    pub warnings: Vec<LintWarning>,
 }
 
 pub fn interpret<S: SemanticsWithCustomSyntax>(
     prog: Spanned<Program<S::CS>>,
 ) -> InterpreterResult<S, Report<SpannedInterpreterError>> {
+    Interpreter::<S>::new().finish(prog)
+}
+
+/// Like [`interpret`], but with the given lint levels instead of the default ones.
+///
+/// Compilation fails if a lint with level [`LintLevel::Deny`] is reported.
+pub fn interpret_with_lints<'src, S: SemanticsWithCustomSyntax>(
+    prog: Spanned<Program<'src, S::CS>>,
+    lints: &LintConfig,
+) -> InterpreterResult<'src, S, Report<SpannedInterpreterError>> {
+    let mut interpreter = Interpreter::<S>::new();
+    interpreter.lint_config = lints.clone();
+    interpreter.finish(prog)
+}
+
+/// Like [`interpret`], but the program may additionally call the named user defined operations of `library`.
+///
+/// The library's operations are only declared in the resulting operation context and are not part
+/// of the returned name map. The context must be linked with the library before running it,
+/// see [`grabapl::operation::linker`].
+pub fn interpret_with_library<'src, S: SemanticsWithCustomSyntax>(
+    prog: Spanned<Program<'src, S::CS>>,
+    library: &'src OperationContext<S>,
+) -> InterpreterResult<'src, S, Report<SpannedInterpreterError>> {
     let mut interpreter = Interpreter::<S>::new();
-    let res = interpreter.interpret_program(prog);
-    InterpreterResult {
-        op_ctx_and_map: res.map(|_| (interpreter.built_op_ctx, interpreter.fns_to_op_ids)),
-        state_map: interpreter.state_map,
+    for (name, _, op) in library.named_operations() {
+        if let Some(signature) = op.signature() {
+            let op_id = interpreter.built_op_ctx.declare_operation(name, signature);
+            interpreter.fns_to_op_ids.insert(name, op_id);
+        }
     }
+    interpreter.finish(prog)
 }
 
 struct Interpreter<'src, S: SemanticsWithCustomSyntax> {
     fns_to_op_ids: HashMap<&'src str, u32>,
     built_op_ctx: OperationContext<S>,
     state_map: HashMap<String, IntermediateState<S>>,
+    lint_config: LintConfig,
+    lints: Vec<(Lint, Span, String)>,
 }
 
 impl<'src, S: SemanticsWithCustomSyntax> Interpreter<'src, S> {
@@ -190,20 +230,83 @@ impl<'src, S: SemanticsWithCustomSyntax> Interpreter<'src, S> {
             fns_to_op_ids: HashMap::new(),
             built_op_ctx: OperationContext::new(),
             state_map: HashMap::new(),
+            lint_config: LintConfig::new(),
+            lints: Vec::new(),
         }
     }
 
+    fn finish(
+        mut self,
+        prog: Spanned<Program<'src, S::CS>>,
+    ) -> InterpreterResult<'src, S, Report<SpannedInterpreterError>> {
+        let res = self.interpret_program(prog);
+        let warnings = self.warnings();
+        let res = res.and_then(
+            |()| match warnings.iter().find(|w| w.level == LintLevel::Deny) {
+                Some(denied) => Err(report!(
+                    InterpreterError::DeniedLint(denied.to_string()).with_span(denied.span)
+                )),
+                None => Ok(()),
+            },
+        );
+        let op_ctx = self.built_op_ctx;
+        let mut fns_to_op_ids = self.fns_to_op_ids;
+        fns_to_op_ids.retain(|_, op_id| op_ctx.declared_signature(*op_id).is_none());
+        InterpreterResult {
+            op_ctx_and_map: res.map(|_| (op_ctx, fns_to_op_ids)),
+            state_map: self.state_map,
+            warnings,
+        }
+    }
+
+    /// Returns the reported lints that are not allowed by the lint configuration.
+    fn warnings(&self) -> Vec<LintWarning> {
+        let mut warnings = self
+            .lints
+            .iter()
+            .filter_map(|(lint, span, message)| {
+                let level = self.lint_config.level(*lint);
+                (level != LintLevel::Allow).then(|| LintWarning {
+                    lint: *lint,
+                    level,
+                    span: *span,
+                    message: message.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        warnings.sort_by_key(|w| (w.span.start, w.span.end));
+        warnings.dedup();
+        warnings
+    }
+
     fn interpret_program(
         &mut self,
         prog: Spanned<Program<'src, S::CS>>,
     ) -> Result<(), SpannedInterpreterError> {
-        // we iterate in reverse order such that all functions have their dependencies already parsed
+        let call_graph = CallGraph::new(&prog.0);
+        let mut fn_defs: HashMap<&'src str, Spanned<FnDef<'src, S::CS>>> =
+            prog.0.functions.into_iter().collect();
+
+        // every function is built after the functions it calls, except for call cycles, whose
+        // functions are built together
         let mut err = None;
-        for (name, fn_def) in prog.0.functions.into_iter().rev() {
-            let op_id = self.fns_to_op_ids.len() as u32;
+        for component in call_graph.processing_order() {
+            let mut members = component
+                .into_iter()
+                .map(|name| (name, fn_defs.remove(name).unwrap()))
+                .collect::<Vec<_>>();
+            if members.len() > 1 {
+                if let Err(e) = self.interpret_recursive_group(members, &call_graph) {
+                    err.get_or_insert(e);
+                }
+                continue;
+            }
+            let (name, fn_def) = members.pop().unwrap();
+
+            let op_id = self.built_op_ctx.register_name(name);
             self.fns_to_op_ids.insert(name, op_id);
 
-            let res_user_op = self.interpret_fn_def(op_id, fn_def);
+            let res_user_op = self.interpret_fn_def(op_id, fn_def, &HashMap::new());
             match res_user_op {
                 Ok(user_op) => {
                     self.built_op_ctx.add_custom_operation(op_id, user_op);
@@ -220,21 +323,112 @@ impl<'src, S: SemanticsWithCustomSyntax> Interpreter<'src, S> {
         Ok(())
     }
 
+    /// Builds a group of mutually recursive functions.
+    ///
+    /// Every function is type-checked against the declared signatures of the others. The declared
+    /// signatures start out as the functions' parameters and return signatures, and are widened
+    /// by the inferred changes of the functions until every function's inferred changes are a
+    /// subtype of its declared ones.
+    fn interpret_recursive_group(
+        &mut self,
+        members: Vec<(&'src str, Spanned<FnDef<'src, S::CS>>)>,
+        call_graph: &CallGraph<'src>,
+    ) -> Result<(), SpannedInterpreterError> {
+        let mut op_ids = Vec::new();
+        for (name, _) in &members {
+            let op_id = self.built_op_ctx.register_name(*name);
+            self.fns_to_op_ids.insert(name, op_id);
+            op_ids.push(op_id);
+        }
+
+        let mut declared = HashMap::new();
+        for (op_id, (name, (fn_def, fn_span))) in op_ids.iter().zip(&members) {
+            let mut builder = OperationBuilder::new(&self.built_op_ctx, *op_id);
+            let mut interpreter = FnInterpreter::new(&mut builder, &self.fns_to_op_ids, name);
+            interpreter.interpret_fn_header(fn_def)?;
+            let signature = builder
+                .expected_signature()
+                .change_context(InterpreterError::BuilderError.with_span(*fn_span))?;
+            declared.insert(*op_id, signature);
+        }
+
+        let lints_before = self.lints.len();
+        for _ in 0..MAX_RECURSIVE_GROUP_ITERATIONS {
+            // only the lints of the last iteration are reported
+            self.lints.truncate(lints_before);
+            let mut widened = false;
+            let mut user_ops = Vec::new();
+            for (op_id, (_, fn_def)) in op_ids.iter().zip(&members) {
+                let fn_span = fn_def.1;
+                let user_op = self.interpret_fn_def(*op_id, fn_def.clone(), &declared)?;
+                let declared_signature = declared.get_mut(op_id).unwrap();
+                if !user_op
+                    .signature
+                    .output
+                    .is_subtype_of(&declared_signature.output)
+                {
+                    declared_signature.output = merge_abstract_output_changes(
+                        &declared_signature.output,
+                        &user_op.signature.output,
+                    )
+                    .change_context(InterpreterError::BuilderError.with_span(fn_span))?;
+                    widened = true;
+                }
+                user_ops.push((*op_id, user_op));
+            }
+            if !widened {
+                // every function conforms to the signature the others were checked against
+                for (op_id, user_op) in user_ops {
+                    self.built_op_ctx.add_custom_operation(op_id, user_op);
+                }
+                return Ok(());
+            }
+        }
+
+        // report the call that closes a cycle through the first function of the group
+        let cycle = call_graph.find_cycle(members[0].0).unwrap();
+        let cycle_span = cycle.last().unwrap().1;
+        let cycle_names = std::iter::once(members[0].0)
+            .chain(cycle.iter().map(|(name, _)| *name))
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        Err(report!(
+            InterpreterError::UnresolvableCallCycle(format!(
+                "{cycle_names}, the signatures of its functions did not stabilize after {MAX_RECURSIVE_GROUP_ITERATIONS} iterations"
+            ))
+            .with_span(cycle_span)
+        ))
+    }
+
+    /// Builds the given function.
+    ///
+    /// `declared` contains the signatures of functions that are built at the same time, see
+    /// [`Self::interpret_recursive_group`].
     fn interpret_fn_def(
         &mut self,
         self_op_id: OperationId,
         fn_def: Spanned<FnDef<'src, S::CS>>,
+        declared: &HashMap<OperationId, OperationSignature<S>>,
     ) -> Result<UserDefinedOperation<S>, SpannedInterpreterError> {
         // use a OperationBuilder to interpret the function definition and build a user defined operation
 
         let mut builder = OperationBuilder::new(&self.built_op_ctx, self_op_id);
+        for (op_id, signature) in declared {
+            // our own signature is inferred, see interpret_recursive_group
+            if *op_id != self_op_id {
+                builder
+                    .declare_operation(*op_id, signature.clone())
+                    .change_context(InterpreterError::BuilderError.with_span(fn_def.1))?;
+            }
+        }
 
         let mut interpreter =
             FnInterpreter::new(&mut builder, &self.fns_to_op_ids, fn_def.0.name.0);
         let fn_span = fn_def.1;
         let res = interpreter.interpret_fn_def(fn_def);
-        // get the state maps before returning an error
+        // get the state maps and lints before returning an error
         self.state_map.extend(interpreter.state_map);
+        self.lints.extend(interpreter.lints);
         res?;
 
         builder
@@ -243,6 +437,9 @@ impl<'src, S: SemanticsWithCustomSyntax> Interpreter<'src, S> {
     }
 }
 
+/// How often the declared signatures of a group of mutually recursive functions may be widened.
+const MAX_RECURSIVE_GROUP_ITERATIONS: usize = 10;
+
 struct FnInterpreter<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> {
     builder: &'a mut OperationBuilder<'op_ctx, S>,
     self_name: &'src str,
@@ -252,6 +449,13 @@ struct FnInterpreter<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> {
     state_map: HashMap<String, IntermediateState<S>>,
     shape_query_counter: u64,
     current_path_diverged: bool,
+    /// Whether the current statement was already reported as unreachable.
+    in_unreachable_code: bool,
+    /// Every binding of a node or operation result, with its span and whether it is a parameter.
+    bindings: Vec<(&'src str, Span, bool)>,
+    /// The names of nodes and operation results that were used.
+    used_names: RefCell<HashSet<&'src str>>,
+    lints: Vec<(Lint, Span, String)>,
 }
 
 impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'op_ctx, S> {
@@ -269,21 +473,66 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
             state_map: HashMap::new(),
             shape_query_counter: 0,
             current_path_diverged: false,
+            in_unreachable_code: false,
+            bindings: Vec::new(),
+            used_names: RefCell::new(HashSet::new()),
+            lints: Vec::new(),
         }
     }
 
     fn interpret_fn_def(
         &mut self,
         (fn_def, _): Spanned<FnDef<'src, S::CS>>,
+    ) -> Result<(), SpannedInterpreterError> {
+        self.interpret_fn_header(&fn_def)?;
+
+        // then interpret the body
+        // TODO: we need an explicit "force build parameter" command that does the validation, because otherwise
+        //  we get a builder error when adding the first instruction if our parameter is invalid. That gives us a weird span and bad UX.
+        self.interpret_block(fn_def.body)?;
+        self.lint_unused_bindings();
+        Ok(())
+    }
+
+    fn lint(&mut self, lint: Lint, span: Span, message: impl Into<String>) {
+        self.lints.push((lint, span, message.into()));
+    }
+
+    fn lint_unused_bindings(&mut self) {
+        let used_names = self.used_names.borrow();
+        for &(name, span, is_param) in &self.bindings {
+            // like in Rust, a leading underscore marks a binding as intentionally unused
+            if name.starts_with('_') || used_names.contains(name) {
+                continue;
+            }
+            let (lint, message) = if is_param {
+                (
+                    Lint::UnusedParameter,
+                    format!("parameter `{name}` is never used"),
+                )
+            } else {
+                (
+                    Lint::UnusedNode,
+                    format!("`{name}` is bound but never used"),
+                )
+            };
+            self.lints.push((lint, span, message));
+        }
+    }
+
+    /// Interprets the parameters and the return signature of the function, but not its body.
+    fn interpret_fn_header(
+        &mut self,
+        fn_def: &FnDef<'src, S::CS>,
     ) -> Result<(), SpannedInterpreterError> {
         // interpret the parameter graph
         // explicit
-        for param in fn_def.explicit_params {
+        for param in fn_def.explicit_params.iter().cloned() {
             self.interpret_fn_node_param(true, param)?;
         }
 
         // implicit
-        for (param, param_span) in fn_def.implicit_params {
+        for (param, param_span) in fn_def.implicit_params.iter().cloned() {
             match param {
                 FnImplicitParam::Node(node_param) => {
                     self.interpret_fn_node_param(false, (node_param, param_span))?;
@@ -299,12 +548,14 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
                     self.builder
                         .expect_parameter_edge(src, dst, typ)
                         .change_context(InterpreterError::BuilderError.with_span(param_span))?;
+                    // the edge constrains which nodes can be passed, so its endpoints are used
+                    self.used_names.borrow_mut().extend([src, dst]);
                 }
             }
         }
 
         // then immediately register the return signature
-        for (return_sig, return_sig_span) in fn_def.return_signature {
+        for (return_sig, return_sig_span) in fn_def.return_signature.iter().cloned() {
             match return_sig {
                 FnImplicitParam::Node(node_sig) => {
                     let name = node_sig.name.0;
@@ -350,10 +601,11 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
             }
         }
 
-        // then interpret the body
-        // TODO: we need an explicit "force build parameter" command that does the validation, because otherwise
-        //  we get a builder error when adding the first instruction if our parameter is invalid. That gives us a weird span and bad UX.
-        self.interpret_block(fn_def.body)?;
+        if fn_def.is_query {
+            self.builder
+                .expect_self_query()
+                .change_context(InterpreterError::BuilderError.with_span(fn_def.name.1))?;
+        }
         Ok(())
     }
 
@@ -385,6 +637,7 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
         }
         self.single_node_aids
             .insert(name, AbstractNodeId::param(name));
+        self.bindings.push((name, param.name.1, true));
         Ok(())
     }
 
@@ -394,9 +647,20 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
     ) -> Result<(), SpannedInterpreterError> {
         // save and restore id mapping
         // let saved_single_node_aids = self.single_node_aids.clone();
+        let was_unreachable = self.in_unreachable_code;
         for stmt in body.statements {
+            // only the first unreachable statement is reported, not the ones after or inside it
+            if self.current_path_diverged && !self.in_unreachable_code {
+                self.lint(
+                    Lint::UnreachableCode,
+                    stmt.1,
+                    "unreachable statement, every path to it diverges",
+                );
+                self.in_unreachable_code = true;
+            }
             self.interpret_stmt(stmt)?;
         }
+        self.in_unreachable_code = was_unreachable;
         // restore the single node aids mapping
         // self.single_node_aids = saved_single_node_aids;
         Ok(())
@@ -424,12 +688,20 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
             Statement::If(if_stmt) => {
                 self.interpret_if_stmt(if_stmt)?;
             }
+            Statement::Match(match_stmt) => {
+                self.interpret_match_stmt(match_stmt)?;
+            }
             Statement::Return(return_stmt) => {
                 self.interpret_return(return_stmt)?;
             }
             Statement::Rename(rename_stmt) => {
                 self.interpret_rename(rename_stmt)?;
             }
+            Statement::Yield((value, yield_span)) => {
+                self.builder
+                    .yield_query_result(value)
+                    .change_context(InterpreterError::BuilderError.with_span(yield_span))?;
+            }
         }
         Ok(())
     }
@@ -534,6 +806,8 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
             .change_context(InterpreterError::BuilderError.with_span(rename_stmt_span))
             .attach_printable_lazy(|| "Failed to rename")?;
         self.single_node_aids.insert(new_name, new_aid);
+        self.bindings
+            .push((new_name, rename_stmt.new_name.1, false));
         Ok(())
     }
 
@@ -547,6 +821,18 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
         let initial_nodes = self.single_node_aids.clone();
         let initial_diverged = self.current_path_diverged;
 
+        // a shape query without branches still binds and marks nodes, and the same statements may
+        // refer to the nodes bound by a shape query in one branch but not in the other
+        let is_shape_query = matches!(if_stmt.cond.0, IfCond::Shape(_));
+        let both_empty = if_stmt.then_block.0.statements.is_empty()
+            && if_stmt.else_block.0.statements.is_empty();
+        if !is_shape_query && !both_empty && if_stmt.then_block.spanless_eq(&if_stmt.else_block) {
+            self.lint(
+                Lint::IdenticalBranches,
+                if_stmt.cond.1,
+                "both branches of this query are identical",
+            );
+        }
         let rename_instructions_then_branch = self.interpret_if_cond_and_start(if_stmt.cond)?;
 
         self.builder
@@ -583,6 +869,60 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
         Ok(())
     }
 
+    fn interpret_match_stmt(
+        &mut self,
+        (match_stmt, match_stmt_span): Spanned<MatchStmt<'src, S::CS>>,
+    ) -> Result<(), SpannedInterpreterError> {
+        let initial_nodes = self.single_node_aids.clone();
+        let initial_diverged = self.current_path_diverged;
+
+        // all arms bind their new nodes to the same marker, since only one of them is taken
+        let marker = self.get_new_shape_query_marker()?;
+        let marker = marker.as_str();
+        self.builder
+            .start_shape_match(marker)
+            .change_context(InterpreterError::BuilderError.with_span(match_stmt_span))
+            .attach_printable_lazy(|| {
+                format!("Failed to start shape match with marker {marker}")
+            })?;
+
+        // the single node aids and divergence at the end of every arm
+        let mut arm_results = vec![];
+        for (arm, arm_span) in match_stmt.arms {
+            self.builder
+                .start_match_arm()
+                .change_context(InterpreterError::BuilderError.with_span(arm_span))
+                .attach_printable_lazy(|| "Failed to start match arm")?;
+            let rename_instructions = self.interpret_shape_query_params(marker, arm.shape)?;
+            self.rename_many(rename_instructions)?;
+            self.interpret_block(arm.block)?;
+
+            let arm_aids = std::mem::replace(&mut self.single_node_aids, initial_nodes.clone());
+            let arm_diverged = std::mem::replace(&mut self.current_path_diverged, initial_diverged);
+            arm_results.push((arm_aids, arm_diverged));
+        }
+
+        self.builder
+            .enter_default_arm()
+            .change_context(InterpreterError::BuilderError.with_span(match_stmt.default_block.1))
+            .attach_printable_lazy(|| "Failed to enter default arm")?;
+        self.interpret_block(match_stmt.default_block)?;
+        self.builder
+            .end_query()
+            .change_context(InterpreterError::BuilderError.with_span(match_stmt_span))
+            .attach_printable_lazy(|| "Failed to end shape match")?;
+
+        for (arm_aids, arm_diverged) in arm_results {
+            (self.single_node_aids, self.current_path_diverged) = merge_node_aids(
+                &arm_aids,
+                arm_diverged,
+                &self.single_node_aids,
+                self.current_path_diverged,
+            );
+        }
+        Ok(())
+    }
+
     fn rename_many(
         &mut self,
         rename_instructions: HashMap<Spanned<&'src str>, AbstractNodeId>,
@@ -608,7 +948,7 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
         // starts either a builtin query or a shape query
         match cond {
             IfCond::Query((fn_call, fn_call_span)) => {
-                let query = self.query_name_to_builtin_query(fn_call.name, fn_call.macro_args)?;
+                let query = self.query_name_to_query_like(fn_call.name, fn_call.macro_args)?;
                 let args = fn_call
                     .args
                     .into_iter()
@@ -620,7 +960,7 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
                     })
                     .collect::<Result<Vec<_>, SpannedInterpreterError>>()?;
                 self.builder
-                    .start_query(query, args)
+                    .start_query_like(query, args)
                     .change_context(InterpreterError::BuilderError.with_span(fn_call_span))?;
                 Ok(HashMap::new())
             }
@@ -644,6 +984,18 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
             .attach_printable_lazy(|| {
                 format!("Failed to start shape query with marker {marker}")
             })?;
+        self.interpret_shape_query_params(marker, (shape_query_params, sqp_span))
+    }
+
+    /// Sends the skip markers and parameters of a started shape query, whose new nodes are bound
+    /// to `marker`.
+    ///
+    /// Returns the required rename instructions at the start of the then branch
+    fn interpret_shape_query_params(
+        &mut self,
+        marker: &str,
+        (shape_query_params, sqp_span): Spanned<ShapeQueryParams<'src, S::CS>>,
+    ) -> Result<HashMap<Spanned<&'src str>, AbstractNodeId>, SpannedInterpreterError> {
         // send the skip markers
         match shape_query_params.skip_markers {
             SkipMarkers::All => {
@@ -663,6 +1015,8 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
                 }
             }
         }
+        // the state before the query, to find expectations that can never be met
+        let state = self.builder.show_state().ok();
         // then interpret the shape query parameters
         let mut new_nodes_to_rename = HashMap::new();
         for (param, param_span) in shape_query_params.params {
@@ -679,6 +1033,19 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
                     // or a new one, in which case it must be a single.
 
                     if let Some(aid) = self.node_id_to_aid(node_id) {
+                        if state
+                            .as_ref()
+                            .and_then(|state| state.node_av_of_aid(&aid))
+                            .is_some_and(|av| disjoint::<S::NodeMatcher>(av, &param_type))
+                        {
+                            self.lint(
+                                Lint::UnmatchableShapeQuery,
+                                param_span,
+                                format!(
+                                    "`{node_id:?}` can never have the expected type, so this shape query never matches"
+                                ),
+                            );
+                        }
                         // issue an expected value change
                         self.builder
                             .expect_shape_node_change(aid, param_type)
@@ -692,34 +1059,103 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
                             .change_context(InterpreterError::BuilderError.with_span(param_span))?;
                         // we need to rename these as soon as we enter the then branch.
                         new_nodes_to_rename.insert((name, node_param.name.1), aid);
+                        self.bindings.push((name, node_param.name.1, false));
                     }
                 }
                 ShapeQueryParam::Edge(edge_param) => {
-                    let src = edge_param.src.0;
-                    let dst = edge_param.dst.0;
+                    let node_aid = |(node, span): Spanned<NodeId<'src>>| {
+                        self.node_id_to_aid(node)
+                            .or_else(|| {
+                                let name = node.single()?;
+                                // connecting a new node to the pattern uses it
+                                self.used_names.borrow_mut().insert(name);
+                                Some(AbstractNodeId::dynamic_output(marker, name))
+                            })
+                            .ok_or(report!(
+                                InterpreterError::NotFoundNodeId(format!("{node:?}"))
+                                    .with_span(span)
+                            ))
+                    };
 
-                    let src_aid = self
-                        .node_id_to_aid(src)
-                        .or_else(|| Some(AbstractNodeId::dynamic_output(marker, src.single()?)))
-                        .ok_or(report!(
-                            InterpreterError::NotFoundNodeId(format!("{src:?}"))
-                                .with_span(edge_param.src.1)
-                        ))?;
-                    let dst_aid = self
-                        .node_id_to_aid(dst)
-                        .or_else(|| Some(AbstractNodeId::dynamic_output(marker, dst.single()?)))
-                        .ok_or(report!(
-                            InterpreterError::NotFoundNodeId(format!("{dst:?}"))
-                                .with_span(edge_param.dst.1)
-                        ))?;
+                    let src_aid = node_aid(edge_param.src)?;
+                    let dst_aid = node_aid(edge_param.dst)?;
+                    let order = match edge_param.order {
+                        None => None,
+                        Some((ShapeEdgeOrderParam::FromStart(n), _)) => {
+                            Some(ShapeEdgeOrder::FromStart(n))
+                        }
+                        Some((ShapeEdgeOrderParam::FromEnd(n), _)) => {
+                            Some(ShapeEdgeOrder::FromEnd(n))
+                        }
+                        Some((ShapeEdgeOrderParam::After(sibling), _)) => {
+                            Some(ShapeEdgeOrder::after(node_aid(sibling)?))
+                        }
+                        Some((ShapeEdgeOrderParam::Before(sibling), _)) => {
+                            Some(ShapeEdgeOrder::before(node_aid(sibling)?))
+                        }
+                    };
 
                     let typ =
                         S::convert_edge_type(edge_param.edge_type.0.clone()).ok_or(report!(
                             InterpreterError::InvalidType(format!("{:?}", edge_param.edge_type.0))
                                 .with_span(edge_param.edge_type.1)
                         ))?;
+                    if state
+                        .as_ref()
+                        .and_then(|state| state.edge_av_of_aid(&src_aid, &dst_aid))
+                        .is_some_and(|av| disjoint::<S::EdgeMatcher>(av, &typ))
+                    {
+                        self.lint(
+                            Lint::UnmatchableShapeQuery,
+                            param_span,
+                            "this edge can never have the expected type, so this shape query never matches",
+                        );
+                    }
+                    match order {
+                        Some(order) => self
+                            .builder
+                            .expect_ordered_shape_edge(src_aid, dst_aid, typ, order),
+                        None => self.builder.expect_shape_edge(src_aid, dst_aid, typ),
+                    }
+                    .change_context(InterpreterError::BuilderError.with_span(param_span))?;
+                }
+                ShapeQueryParam::Path(path_param) => {
+                    let node_aid = |(node, span): Spanned<NodeId<'src>>| {
+                        self.node_id_to_aid(node)
+                            .or_else(|| {
+                                let name = node.single()?;
+                                self.used_names.borrow_mut().insert(name);
+                                Some(AbstractNodeId::dynamic_output(marker, name))
+                            })
+                            .ok_or(report!(
+                                InterpreterError::NotFoundNodeId(format!("{node:?}"))
+                                    .with_span(span)
+                            ))
+                    };
+
+                    let src_aid = node_aid(path_param.src)?;
+                    let dst_aid = node_aid(path_param.dst)?;
+                    let typ =
+                        S::convert_edge_type(path_param.edge_type.0.clone()).ok_or(report!(
+                            InterpreterError::InvalidType(format!("{:?}", path_param.edge_type.0))
+                                .with_span(path_param.edge_type.1)
+                        ))?;
+                    match path_param.marker {
+                        Some((path_marker, _)) => self.builder.expect_shape_path_marking(
+                            src_aid,
+                            dst_aid,
+                            typ,
+                            path_marker,
+                        ),
+                        None => self.builder.expect_shape_path(src_aid, dst_aid, typ),
+                    }
+                    .change_context(InterpreterError::BuilderError.with_span(param_span))?;
+                }
+                ShapeQueryParam::Negative(params) => {
+                    let condition =
+                        self.interpret_negative_shape_params(marker, params, &new_nodes_to_rename)?;
                     self.builder
-                        .expect_shape_edge(src_aid, dst_aid, typ)
+                        .expect_no_shape_pattern(condition)
                         .change_context(InterpreterError::BuilderError.with_span(param_span))?;
                 }
             }
@@ -727,6 +1163,108 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
         Ok(new_nodes_to_rename)
     }
 
+    /// Builds the negative condition of a `!(...)` shape query parameter.
+    ///
+    /// `shape_nodes` are the new nodes of the shape query so far, which the condition may refer to.
+    fn interpret_negative_shape_params(
+        &self,
+        marker: &str,
+        params: Vec<Spanned<ShapeQueryParam<'src, S::CS>>>,
+        shape_nodes: &HashMap<Spanned<&'src str>, AbstractNodeId>,
+    ) -> Result<NegativeShapeCondition<S, AbstractNodeId>, SpannedInterpreterError> {
+        let mut condition = NegativeShapeCondition::new();
+        let mut forbidden_nodes = HashMap::new();
+        for (param, param_span) in &params {
+            if let ShapeQueryParam::Node(node_param) = param {
+                let name = node_param.name.0.single().ok_or(report!(
+                    InterpreterError::Custom("forbidden nodes must have a single name")
+                        .with_span(node_param.name.1)
+                ))?;
+                if self.node_id_to_aid(node_param.name.0).is_some()
+                    || shape_nodes
+                        .keys()
+                        .any(|(shape_node, _)| *shape_node == name)
+                    || name == "_"
+                {
+                    return Err(report!(
+                        InterpreterError::Custom(
+                            "forbidden nodes must have a new name, existing nodes cannot be changed in negative shape conditions"
+                        )
+                        .with_span(*param_span)
+                    ));
+                }
+                let node_type =
+                    S::convert_node_type(node_param.node_type.0.clone()).ok_or(report!(
+                        InterpreterError::InvalidType(format!("{:?}", node_param.node_type.0))
+                            .with_span(node_param.node_type.1)
+                    ))?;
+                forbidden_nodes.insert(name, condition.add_forbidden_node(Some(node_type)));
+            }
+        }
+
+        for (param, param_span) in params {
+            match param {
+                ShapeQueryParam::Node(..) => {}
+                ShapeQueryParam::Edge(edge_param) => {
+                    if let Some((_, order_span)) = edge_param.order {
+                        return Err(report!(
+                            InterpreterError::Custom(
+                                "edge orders are not supported in negative shape conditions"
+                            )
+                            .with_span(order_span)
+                        ));
+                    }
+                    let mut node = |(node, span): Spanned<NodeId<'src>>| {
+                        if node.single() == Some("_") {
+                            // every `_` is a separate node of any type
+                            return Ok(condition.add_forbidden_node(None));
+                        }
+                        if let Some(forbidden) = node.single().and_then(|n| forbidden_nodes.get(n))
+                        {
+                            return Ok(*forbidden);
+                        }
+                        self.node_id_to_aid(node)
+                            .or_else(|| {
+                                let name = node.single()?;
+                                shape_nodes
+                                    .keys()
+                                    .any(|(shape_node, _)| *shape_node == name)
+                                    .then(|| AbstractNodeId::dynamic_output(marker, name))
+                            })
+                            .map(NegativeShapeNode::Existing)
+                            .ok_or(report!(
+                                InterpreterError::NotFoundNodeId(format!("{node:?}"))
+                                    .with_span(span)
+                            ))
+                    };
+                    let src = node(edge_param.src)?;
+                    let dst = node(edge_param.dst)?;
+                    let typ =
+                        S::convert_edge_type(edge_param.edge_type.0.clone()).ok_or(report!(
+                            InterpreterError::InvalidType(format!("{:?}", edge_param.edge_type.0))
+                                .with_span(edge_param.edge_type.1)
+                        ))?;
+                    condition.add_edge(src, dst, typ);
+                }
+                ShapeQueryParam::Path(..) => {
+                    return Err(report!(
+                        InterpreterError::Custom(
+                            "paths are not supported in negative shape conditions"
+                        )
+                        .with_span(param_span)
+                    ));
+                }
+                ShapeQueryParam::Negative(..) => {
+                    return Err(report!(
+                        InterpreterError::Custom("negative shape conditions cannot be nested")
+                            .with_span(param_span)
+                    ));
+                }
+            }
+        }
+        Ok(condition)
+    }
+
     fn get_new_shape_query_marker(&mut self) -> Result<String, SpannedInterpreterError> {
         let marker = format!("shape_query_{}", self.shape_query_counter);
         self.shape_query_counter += 1;
@@ -745,25 +1283,37 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
                 .change_context(InterpreterError::BuilderError.with_span(let_span))?;
             let new_aid = AbstractNodeId::named(result_name);
             self.single_node_aids.insert(result_name, new_aid);
+            self.bindings.push((result_name, let_stmt.ident.1, false));
         } else {
             let op_name = let_stmt.ident.0;
             let call_span = let_stmt.call.1;
             let (op_like, args) = self.call_expr_to_op_like(let_stmt.call)?;
 
             self.interpret_op_like(Some(op_name), op_like, args, call_span)?;
+            self.bindings.push((op_name, let_stmt.ident.1, false));
         }
         Ok(())
     }
 
-    fn query_name_to_builtin_query(
+    fn query_name_to_query_like(
         &self,
         (query_name, query_span): Spanned<&str>,
         args: Option<Spanned<MacroArgs>>,
-    ) -> Result<S::BuiltinQuery, SpannedInterpreterError> {
+    ) -> Result<BuilderQueryLike<S>, SpannedInterpreterError> {
         let args = args.map(|(args, _)| args);
-        S::find_builtin_query(query_name, args).ok_or(report!(
+        if let Some(query) = S::find_builtin_query(query_name, args) {
+            return Ok(BuilderQueryLike::Builtin(query));
+        }
+
+        if query_name == self.self_name {
+            return Ok(BuilderQueryLike::Recurse);
+        }
+
+        // otherwise must be a user defined query
+        let op_id = self.fn_names_to_op_ids.get(query_name).ok_or(report!(
             InterpreterError::NotFoundQuery(query_name.to_string()).with_span(query_span)
-        ))
+        ))?;
+        Ok(BuilderQueryLike::FromOperationId(*op_id))
     }
 
     fn op_name_to_op_like(
@@ -895,16 +1445,30 @@ impl<'src, 'a, 'op_ctx, S: SemanticsWithCustomSyntax> FnInterpreter<'src, 'a, 'o
         Ok(())
     }
 
-    fn node_id_to_aid(&self, node_id: NodeId) -> Option<AbstractNodeId> {
+    /// Returns the AID of the node, marking the node as used.
+    fn node_id_to_aid(&self, node_id: NodeId<'src>) -> Option<AbstractNodeId> {
         match node_id {
-            NodeId::Single(name) => self.single_node_aids.get(name).copied(),
+            NodeId::Single(name) => {
+                let aid = self.single_node_aids.get(name).copied()?;
+                self.used_names.borrow_mut().insert(name);
+                Some(aid)
+            }
             NodeId::Output((op_name, _), (node_name, _)) => {
+                self.used_names.borrow_mut().insert(op_name);
                 Some(AbstractNodeId::dynamic_output(op_name, node_name))
             }
         }
     }
 }
 
+/// Returns `true` if no value can be of both types.
+///
+/// This assumes that types that are not subtypes of one another have no common subtype, which
+/// holds if the types form a tree.
+fn disjoint<M: AbstractMatcher>(a: &M::Abstract, b: &M::Abstract) -> bool {
+    !M::matches(a, b) && !M::matches(b, a)
+}
+
 // merges entries only if they're the same in both maps
 /// Returns the merged single nodes and whether the merged paths diverged
 fn merge_node_aids<'a>(
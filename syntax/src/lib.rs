@@ -1,7 +1,13 @@
+pub mod call_graph;
 pub mod custom_syntax;
 pub mod interpreter;
+pub mod lints;
 
-use crate::interpreter::{InterpreterResult, interpret};
+use crate::interpreter::{
+    InterpreterResult, SpannedInterpreterError, interpret, interpret_with_library,
+    interpret_with_lints,
+};
+use crate::lints::{LintConfig, LintLevel, LintWarning};
 use ariadne::{Color, Label, Report, ReportKind, sources};
 use chumsky::input::SliceInput;
 use chumsky::{input::ValueInput, prelude::*};
@@ -27,6 +33,7 @@ pub enum Token<'src> {
     Str(&'src str),
     // Op(&'src str),
     Arrow,
+    FatArrow,
     // note: conflicts with call<-1>() ...
     // RevArrow
     ColonEq,
@@ -39,6 +46,7 @@ pub enum Token<'src> {
     If,
     Else,
     Shape,
+    Match,
     MacroArgs(&'src str),
     // NodeType(&'src str),
 }
@@ -51,6 +59,7 @@ impl fmt::Display for Token<'_> {
             Token::Str(s) => write!(f, "\"{s}\""),
             // Token::Op(op) => write!(f, "{}", op),
             Token::Arrow => write!(f, "->"),
+            Token::FatArrow => write!(f, "=>"),
             Token::ColonEq => write!(f, ":="),
             Token::Ctrl(c) => write!(f, "{c}"),
             Token::Ident(i) => write!(f, "{i}"),
@@ -61,6 +70,7 @@ impl fmt::Display for Token<'_> {
             Token::If => write!(f, "if"),
             Token::Else => write!(f, "else"),
             Token::Shape => write!(f, "shape"),
+            Token::Match => write!(f, "match"),
             Token::MacroArgs(s) => write!(f, "`{s}`"),
             // Token::NodeType(s) => write!(f, "{}", s),
         }
@@ -81,9 +91,10 @@ pub fn lexer<'src>()
         .boxed();
 
     // A parser for control characters (delimiters, semicolons, etc.)
-    let ctrl = one_of("-()[]{};,?:*=/<>\"'.").map(Token::Ctrl);
+    let ctrl = one_of("-()[]{};,?:*=/<>\"'.@!").map(Token::Ctrl);
 
     let arrow = just("->").to(Token::Arrow);
+    let fat_arrow = just("=>").to(Token::FatArrow);
     let colon_eq = just(":=").to(Token::ColonEq);
 
     let let_bang = just("let!").to(Token::LetBang);
@@ -96,6 +107,7 @@ pub fn lexer<'src>()
         "return" => Token::Return,
         "else" => Token::Else,
         "shape" => Token::Shape,
+        "match" => Token::Match,
         "true" => Token::Bool(true),
         "false" => Token::Bool(false),
         _ => Token::Ident(ident),
@@ -130,6 +142,7 @@ pub fn lexer<'src>()
     let token = let_bang
         .or(num)
         .or(arrow)
+        .or(fat_arrow)
         .or(colon_eq)
         .or(macro_args)
         .or(macro_args_opt2)
@@ -189,12 +202,46 @@ pub struct ShapeEdgeParam<'src, CS: CustomSyntax> {
     pub src: Spanned<NodeId<'src>>,
     pub dst: Spanned<NodeId<'src>>,
     pub edge_type: Spanned<CS::AbstractEdgeType>,
+    pub order: Option<Spanned<ShapeEdgeOrderParam<'src>>>,
+}
+
+/// A path of one or more edges, written as `src ->* dst: edge_type`, optionally followed by
+/// `marking "marker"` to mark all nodes of the found path.
+///
+/// Only `src` and `dst` are bound, since the number of nodes in between is not statically known.
+/// A node in between can be bound by splitting the path at it, e.g., `a ->* n: t, n -> b: t`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShapePathParam<'src, CS: CustomSyntax> {
+    pub src: Spanned<NodeId<'src>>,
+    pub dst: Spanned<NodeId<'src>>,
+    pub edge_type: Spanned<CS::AbstractEdgeType>,
+    pub marker: Option<Spanned<&'src str>>,
+}
+
+/// The position of a shape edge among the outgoing edges of its source node, written as
+/// `@ order` after the edge type.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShapeEdgeOrderParam<'src> {
+    /// `@ first`, or `@ n` for the `n`-th outgoing edge counting from `0`.
+    FromStart(usize),
+    /// `@ last`, or `@ -n` for the `n`-th outgoing edge counting backwards from `-1`.
+    FromEnd(usize),
+    /// `@ after sibling`: right after the edge from the same source to `sibling`.
+    After(Spanned<NodeId<'src>>),
+    /// `@ before sibling`: right before the edge from the same source to `sibling`.
+    Before(Spanned<NodeId<'src>>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum ShapeQueryParam<'src, CS: CustomSyntax> {
     Node(ShapeNodeParam<'src, CS>),
     Edge(ShapeEdgeParam<'src, CS>),
+    Path(ShapePathParam<'src, CS>),
+    /// `!(...)`: a pattern that must not be present.
+    ///
+    /// Node parameters declare forbidden nodes, and every `_` is a separate forbidden node of any type.
+    /// Edge orders are not supported.
+    Negative(Vec<Spanned<ShapeQueryParam<'src, CS>>>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -218,6 +265,21 @@ pub struct IfStmt<'src, CS: CustomSyntax> {
     pub else_block: Spanned<Block<'src, CS>>,
 }
 
+/// An arm of a [`MatchStmt`], i.e., `[...] => { ... }`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchArm<'src, CS: CustomSyntax> {
+    pub shape: Spanned<ShapeQueryParams<'src, CS>>,
+    pub block: Spanned<Block<'src, CS>>,
+}
+
+/// A `match shape { [...] => { ... }, _ => { ... } }` statement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchStmt<'src, CS: CustomSyntax> {
+    pub arms: Vec<Spanned<MatchArm<'src, CS>>>,
+    // if it doesn't exist, take empty span at the end of the arms
+    pub default_block: Spanned<Block<'src, CS>>,
+}
+
 #[derive(Clone, derive_more::Debug, PartialEq, Copy)]
 pub enum NodeId<'src> {
     #[debug("{_0}")]
@@ -275,8 +337,11 @@ pub enum Statement<'src, CS: CustomSyntax> {
     Let(Spanned<LetStmt<'src>>),
     FnCall(Spanned<FnCallExpr<'src>>),
     If(Spanned<IfStmt<'src, CS>>),
+    Match(Spanned<MatchStmt<'src, CS>>),
     Return(Spanned<ReturnStmt<'src, CS>>),
     Rename(Spanned<RenameStmt<'src>>),
+    /// `return true;` or `return false;` in a query.
+    Yield(Spanned<bool>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -308,13 +373,14 @@ pub struct FnDef<'src, CS: CustomSyntax> {
     pub explicit_params: Vec<Spanned<FnNodeParam<'src, CS>>>,
     pub implicit_params: Vec<Spanned<FnImplicitParam<'src, CS>>>,
     pub return_signature: Vec<Spanned<FnImplicitParam<'src, CS>>>,
+    /// Whether the function is a query, i.e., declared with `-> bool`.
+    pub is_query: bool,
     pub body: Spanned<Block<'src, CS>>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Program<'src, CS: CustomSyntax> {
-    // vec to preserve order. functions must be ordered according to their dependency order. no mutual recursion supported right now.
-    // wrapper functions first, then their dependencies.
+    // vec to preserve source order. functions may appear in any order, see `call_graph`.
     pub functions: Vec<(&'src str, Spanned<FnDef<'src, CS>>)>,
 }
 
@@ -423,6 +489,36 @@ where
         .boxed()
         .labelled("shape query node parameter");
 
+    let num = select! {
+        Token::Num(n) => n,
+    };
+    // the lexer only produces negative numbers if the minus sign is directly attached
+    let signed_num = just(Token::Ctrl('-'))
+        .or_not()
+        .then(num)
+        .map(|(minus, n)| if minus.is_some() { -n } else { n });
+
+    let shape_edge_order = choice((
+        just(Token::Ident("first")).to(ShapeEdgeOrderParam::FromStart(0)),
+        just(Token::Ident("last")).to(ShapeEdgeOrderParam::FromEnd(0)),
+        just(Token::Ident("after"))
+            .ignore_then(spanned_node_id.clone())
+            .map(ShapeEdgeOrderParam::After),
+        just(Token::Ident("before"))
+            .ignore_then(spanned_node_id.clone())
+            .map(ShapeEdgeOrderParam::Before),
+        signed_num.map(|n| {
+            if n < 0 {
+                ShapeEdgeOrderParam::FromEnd((-n - 1) as usize)
+            } else {
+                ShapeEdgeOrderParam::FromStart(n as usize)
+            }
+        }),
+    ))
+    .map_with(|order, e| (order, e.span()))
+    .labelled("edge order")
+    .boxed();
+
     let shape_edge_param = spanned_node_id
         .clone()
         .then_ignore(just(Token::Arrow))
@@ -433,22 +529,80 @@ where
                 .labelled("edge type")
                 .map_with(|s, e| (s, e.span())),
         )
+        .then(
+            just(Token::Ctrl('@'))
+                .ignore_then(shape_edge_order)
+                .or_not(),
+        )
         .map(
-            |(((src, src_span), (dst, dst_span)), (edge_type, edge_type_span))| ShapeEdgeParam {
-                src: (src, src_span),
-                dst: (dst, dst_span),
-                edge_type: (edge_type, edge_type_span),
+            |((((src, src_span), (dst, dst_span)), (edge_type, edge_type_span)), order)| {
+                ShapeEdgeParam {
+                    src: (src, src_span),
+                    dst: (dst, dst_span),
+                    edge_type: (edge_type, edge_type_span),
+                    order,
+                }
             },
         )
         .boxed()
         .labelled("shape query edge parameter");
 
-    let spanned_shape_param = shape_node_param
+    let shape_path_param = spanned_node_id
+        .clone()
+        .then_ignore(just(Token::Arrow))
+        .then_ignore(just(Token::Ctrl('*')))
+        .then(spanned_node_id.clone())
+        .then_ignore(just(Token::Ctrl(':')))
+        .then(
+            CS::get_edge_type_parser()
+                .labelled("edge type")
+                .map_with(|s, e| (s, e.span())),
+        )
+        .then(
+            just(Token::Ident("marking"))
+                .ignore_then(select! { Token::Str(s) => s }.labelled("marker"))
+                .map_with(|s, e| (s, e.span()))
+                .or_not(),
+        )
+        .map(
+            |((((src, src_span), (dst, dst_span)), (edge_type, edge_type_span)), marker)| {
+                ShapePathParam {
+                    src: (src, src_span),
+                    dst: (dst, dst_span),
+                    edge_type: (edge_type, edge_type_span),
+                    marker,
+                }
+            },
+        )
+        .boxed()
+        .labelled("shape query path parameter");
+
+    let spanned_positive_shape_param = shape_node_param
         .map(|node_param| ShapeQueryParam::Node(node_param))
+        .or(shape_path_param.map(ShapeQueryParam::Path))
         .or(shape_edge_param.map(|edge_param| ShapeQueryParam::Edge(edge_param)))
         .map_with(|s, e| (s, e.span()))
         .boxed();
 
+    let spanned_negative_shape_param = just(Token::Ctrl('!'))
+        .ignore_then(
+            spanned_positive_shape_param
+                .clone()
+                .separated_by(just(Token::Ctrl(',')))
+                .allow_trailing()
+                .at_least(1)
+                .collect::<Vec<_>>()
+                .delimited_by(just(Token::Ctrl('(')), just(Token::Ctrl(')'))),
+        )
+        .map(ShapeQueryParam::Negative)
+        .map_with(|s, e| (s, e.span()))
+        .labelled("negative shape query parameter")
+        .boxed();
+
+    let spanned_shape_param = spanned_negative_shape_param
+        .or(spanned_positive_shape_param)
+        .boxed();
+
     let shape_params = spanned_shape_param
         .separated_by(just(Token::Ctrl(',')))
         .allow_trailing()
@@ -511,8 +665,7 @@ where
         .boxed()
         .labelled("skipping markers");
 
-        let if_cond_shape = just(Token::Shape)
-            .ignore_then(just(Token::Ctrl('[')))
+        let bracketed_shape_params = just(Token::Ctrl('['))
             .ignore_then(shape_params.clone())
             .then_ignore(just(Token::Ctrl(']')))
             .then(optional_skipping_markers)
@@ -520,6 +673,10 @@ where
                 params.skip_markers = skip_markers;
                 params
             })
+            .boxed();
+
+        let if_cond_shape = just(Token::Shape)
+            .ignore_then(bracketed_shape_params.clone())
             .map_with(|params, e| (params, e.span()))
             .map(IfCond::Shape);
 
@@ -582,6 +739,55 @@ where
             .map_with(|if_stmt, e| (if_stmt, e.span()))
             .labelled("if statement");
 
+        let spanned_arm_block = block
+            .clone()
+            .delimited_by(just(Token::Ctrl('{')), just(Token::Ctrl('}')))
+            .map_with(|block, e| (block, e.span()))
+            .labelled("block")
+            .boxed();
+
+        let spanned_match_arm = bracketed_shape_params
+            .map_with(|params, e| (params, e.span()))
+            .then_ignore(just(Token::FatArrow))
+            .then(spanned_arm_block.clone())
+            .map_with(|(shape, block), e| (MatchArm { shape, block }, e.span()))
+            .labelled("match arm");
+
+        let default_match_arm = just(Token::Ident("_"))
+            .ignore_then(just(Token::FatArrow))
+            .ignore_then(spanned_arm_block)
+            .labelled("default match arm");
+
+        let spanned_match_stmt = just(Token::Match)
+            .ignore_then(just(Token::Shape))
+            .ignore_then(just(Token::Ctrl('{')))
+            .ignore_then(
+                spanned_match_arm
+                    .then_ignore(just(Token::Ctrl(',')).or_not())
+                    .repeated()
+                    .at_least(1)
+                    .collect::<Vec<_>>(),
+            )
+            .then(
+                default_match_arm
+                    .then_ignore(just(Token::Ctrl(',')).or_not())
+                    .or_not()
+                    .map_with(|opt, e| opt.unwrap_or((Block { statements: vec![] }, e.span()))),
+            )
+            .then_ignore(just(Token::Ctrl('}')))
+            .map_with(|(arms, default_block), e| {
+                (
+                    MatchStmt {
+                        arms,
+                        default_block,
+                    },
+                    e.span(),
+                )
+            })
+            .map(Statement::Match)
+            .map_with(|match_stmt, e| (match_stmt, e.span()))
+            .labelled("match statement");
+
         let fn_call_stmt = fn_call_expr
             .clone()
             .then_ignore(just(Token::Ctrl(';')))
@@ -666,6 +872,13 @@ where
             .map_with(|return_stmt, e| (return_stmt, e.span()))
             .labelled("return statement");
 
+        let spanned_yield_stmt = just(Token::Return)
+            .ignore_then(select! { Token::Bool(b) => b })
+            .then_ignore(just(Token::Ctrl(';')))
+            .map_with(|value, e| Statement::Yield((value, e.span())))
+            .map_with(|yield_stmt, e| (yield_stmt, e.span()))
+            .labelled("query result");
+
         let spanned_rename_stmt = ident_str
             .then_ignore(just(Token::ColonEq))
             .then(spanned_node_id.clone())
@@ -686,7 +899,9 @@ where
         let spanned_stmt = let_stmt
             .or(fn_call_stmt)
             .or(spanned_if_stmt)
+            .or(spanned_match_stmt)
             .or(spanned_return_stmt)
+            .or(spanned_yield_stmt)
             .or(spanned_rename_stmt)
             .labelled("statement")
             .boxed();
@@ -701,14 +916,20 @@ where
 
     let fn_return_signature = fn_implicit_params.clone();
 
-    let optional_fn_return_signature = (just(Token::Arrow)
-        .ignore_then(just(Token::Ctrl('(')))
-        .ignore_then(fn_return_signature)
-        .then_ignore(just(Token::Ctrl(')'))))
-    .or_not()
-    .map(|opt| opt.unwrap_or_default())
-    .boxed()
-    .labelled("function return signature");
+    // `-> bool` marks the function as a query
+    let optional_fn_return_signature = just(Token::Arrow)
+        .ignore_then(
+            just(Token::Ident("bool"))
+                .to((vec![], true))
+                .or(just(Token::Ctrl('('))
+                    .ignore_then(fn_return_signature)
+                    .then_ignore(just(Token::Ctrl(')')))
+                    .map(|return_signature| (return_signature, false))),
+        )
+        .or_not()
+        .map(|opt| opt.unwrap_or_default())
+        .boxed()
+        .labelled("function return signature");
 
     let fn_explicit_params = spanned_fn_explicit_param
         .separated_by(just(Token::Ctrl(',')))
@@ -735,13 +956,14 @@ where
         .then_ignore(just(Token::Ctrl('}')))
         .map(
             |(
-                (((spanned_name, explicit_params), implicit_params), return_signature),
+                (((spanned_name, explicit_params), implicit_params), (return_signature, is_query)),
                 spanned_body,
             )| FnDef {
                 name: spanned_name,
                 explicit_params,
                 implicit_params,
                 return_signature,
+                is_query,
                 body: spanned_body,
             },
         )
@@ -776,9 +998,8 @@ where
         .labelled("program")
 }
 
-/// Important syntax note: mutually recursive functions are not supported.
-/// Function definitions must be ordered in reverse C/C++ order, i.e.,
-/// if function `foo` calls `bar`, then `bar` must be defined after `foo` in the source.
+/// Functions may be defined in any order. Functions are built after the functions they call,
+/// and functions that call each other (transitively) are built together.
 // TODO: rework this function. terrible.
 pub fn parse_to_op_ctx_and_map<S: SemanticsWithCustomSyntax>(
     src: &str,
@@ -794,6 +1015,81 @@ pub fn parse_to_op_ctx_and_map<S: SemanticsWithCustomSyntax>(
 pub fn try_parse_to_op_ctx_and_map<'src, S: SemanticsWithCustomSyntax>(
     src: &'src str,
     color_enabled: bool,
+) -> InterpreterResult<'src, S, WithLineColSpans<String>> {
+    try_parse_with(src, color_enabled, interpret::<S>)
+}
+
+/// Like [`try_parse_to_op_ctx_and_map`], but the source may call the named user defined operations of `library`.
+///
+/// See [`interpreter::interpret_with_library`].
+pub fn try_parse_to_op_ctx_and_map_with_library<'src, S: SemanticsWithCustomSyntax>(
+    src: &'src str,
+    color_enabled: bool,
+    library: &'src OperationContext<S>,
+) -> InterpreterResult<'src, S, WithLineColSpans<String>> {
+    try_parse_with(src, color_enabled, |program| {
+        interpret_with_library::<S>(program, library)
+    })
+}
+
+/// Like [`try_parse_to_op_ctx_and_map`], but with the given lint levels, see [`interpreter::interpret_with_lints`].
+pub fn try_parse_to_op_ctx_and_map_with_lints<'src, S: SemanticsWithCustomSyntax>(
+    src: &'src str,
+    color_enabled: bool,
+    lints: &LintConfig,
+) -> InterpreterResult<'src, S, WithLineColSpans<String>> {
+    try_parse_with(src, color_enabled, |program| {
+        interpret_with_lints::<S>(program, lints)
+    })
+}
+
+/// Renders the lints reported by the interpreter, see [`InterpreterResult::warnings`].
+pub fn render_lint_warnings(
+    src: &str,
+    warnings: &[LintWarning],
+    color_enabled: bool,
+) -> WithLineColSpans<String> {
+    let filename = "input".to_string();
+    let mut output_buf = BufWriter::new(Vec::new());
+    let mut line_col_spans = Vec::new();
+    for warning in warnings {
+        line_col_spans.push(span_into_line_col_start_and_line_col_end(
+            &warning.span,
+            src,
+        ));
+        let (kind, color) = match warning.level {
+            LintLevel::Deny => (ReportKind::Error, Color::Red),
+            LintLevel::Allow | LintLevel::Warn => (ReportKind::Warning, Color::Yellow),
+        };
+        Report::build(kind, (filename.clone(), warning.span.into_range()))
+            .with_config(
+                ariadne::Config::new()
+                    .with_index_type(ariadne::IndexType::Byte)
+                    .with_color(color_enabled),
+            )
+            .with_message(&warning.message)
+            .with_label(
+                Label::new((filename.clone(), warning.span.into_range()))
+                    .with_message(format!("#{}", warning.lint))
+                    .with_color(color),
+            )
+            .finish()
+            .write(sources([(filename.clone(), src)]), &mut output_buf)
+            .unwrap();
+    }
+    WithLineColSpans {
+        value: String::from_utf8(output_buf.into_inner().unwrap()).unwrap(),
+        spans: line_col_spans,
+    }
+}
+
+fn try_parse_with<'src, S: SemanticsWithCustomSyntax>(
+    src: &'src str,
+    color_enabled: bool,
+    interpret: impl FnOnce(
+        Spanned<Program<'src, S::CS>>,
+    )
+        -> InterpreterResult<'src, S, error_stack::Report<SpannedInterpreterError>>,
 ) -> InterpreterResult<'src, S, WithLineColSpans<String>> {
     let filename = "input".to_string();
     let (tokens, errs) = lexer().parse(src).into_output_errors();
@@ -825,12 +1121,13 @@ pub fn try_parse_to_op_ctx_and_map<'src, S: SemanticsWithCustomSyntax>(
         // Note: if we wanted to also proceed with a error-recovered AST, this filter predicate needs to be changed, and the errors would still need
         // to be propagated somehow.
         if let Some((program, _file_span)) = ast.filter(|_| errs.len() + parse_errs.len() == 0) {
-            let res = interpret::<S>(program);
+            let res = interpret(program);
             match res.op_ctx_and_map {
                 Ok((op_ctx, fns_to_ids)) => {
                     return InterpreterResult {
                         op_ctx_and_map: Ok((op_ctx, fns_to_ids)),
                         state_map: res.state_map,
+                        warnings: res.warnings,
                     };
                 }
                 Err(e) => {
@@ -884,6 +1181,7 @@ pub fn try_parse_to_op_ctx_and_map<'src, S: SemanticsWithCustomSyntax>(
                     return InterpreterResult {
                         op_ctx_and_map: err,
                         state_map: res.state_map,
+                        warnings: res.warnings,
                     };
                 }
             }
@@ -936,6 +1234,7 @@ pub fn try_parse_to_op_ctx_and_map<'src, S: SemanticsWithCustomSyntax>(
             spans: line_col_spans,
         }),
         state_map: HashMap::new(),
+        warnings: Vec::new(),
     }
 }
 
@@ -0,0 +1,318 @@
+use crate::custom_syntax::CustomSyntax;
+use crate::{
+    Block, FnCallExpr, IfCond, IfStmt, LetStmt, MacroArgs, MatchArm, MatchStmt, NodeId, RenameStmt,
+    ReturnStmtMapping, ShapeEdgeOrderParam, ShapeQueryParam, ShapeQueryParams, Span, Spanned,
+    Statement,
+};
+use grabapl::operation::marker::SkipMarkers;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// A static check that the interpreter runs on every function, see [`LintConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A node that is bound, e.g., by `let!` or a shape query, but never used.
+    UnusedNode,
+    /// A parameter node that is never used in the function's body.
+    UnusedParameter,
+    /// A statement that is never executed, because every path to it diverges.
+    UnreachableCode,
+    /// A query whose two branches are identical, which makes the query pointless.
+    IdenticalBranches,
+    /// A shape query that can never match, because it expects a node or edge to have a type that
+    /// is disjoint from the type it is known to have.
+    UnmatchableShapeQuery,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 5] = [
+        Lint::UnusedNode,
+        Lint::UnusedParameter,
+        Lint::UnreachableCode,
+        Lint::IdenticalBranches,
+        Lint::UnmatchableShapeQuery,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedNode => "unused_node",
+            Lint::UnusedParameter => "unused_parameter",
+            Lint::UnreachableCode => "unreachable_code",
+            Lint::IdenticalBranches => "identical_branches",
+            Lint::UnmatchableShapeQuery => "unmatchable_shape_query",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LintLevel {
+    /// The lint is not reported.
+    Allow,
+    /// The lint is reported as a warning, but compilation succeeds.
+    #[default]
+    Warn,
+    /// The lint is reported, and compilation fails.
+    Deny,
+}
+
+/// The level of every lint. Lints are warnings by default.
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<Lint, LintLevel>,
+}
+
+impl LintConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a configuration where every lint has the given level.
+    pub fn all(level: LintLevel) -> Self {
+        let mut config = Self::new();
+        for lint in Lint::ALL {
+            config.set(lint, level);
+        }
+        config
+    }
+
+    pub fn set(&mut self, lint: Lint, level: LintLevel) -> &mut Self {
+        self.levels.insert(lint, level);
+        self
+    }
+
+    pub fn with(mut self, lint: Lint, level: LintLevel) -> Self {
+        self.set(lint, level);
+        self
+    }
+
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels.get(&lint).copied().unwrap_or_default()
+    }
+}
+
+/// A lint that was reported for a span of the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintWarning {
+    pub lint: Lint,
+    pub level: LintLevel,
+    pub span: Span,
+    pub message: String,
+}
+
+impl Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (#{})", self.message, self.lint)
+    }
+}
+
+/// Equality of syntax that ignores spans.
+///
+/// Two pieces of syntax are identical if they only differ in their positions in the source.
+pub(crate) trait SpanlessEq {
+    fn spanless_eq(&self, other: &Self) -> bool;
+}
+
+impl<T: SpanlessEq> SpanlessEq for Spanned<T> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.0.spanless_eq(&other.0)
+    }
+}
+
+impl<T: SpanlessEq> SpanlessEq for Vec<T> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a.spanless_eq(b))
+    }
+}
+
+impl<T: SpanlessEq> SpanlessEq for Option<T> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Some(a), Some(b)) => a.spanless_eq(b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Implements [`SpanlessEq`] with [`PartialEq`] for syntax that does not contain spans.
+macro_rules! spanless_eq_by_partial_eq {
+    ($($ty:ty),*) => {
+        $(
+            impl SpanlessEq for $ty {
+                fn spanless_eq(&self, other: &Self) -> bool {
+                    self == other
+                }
+            }
+        )*
+    };
+}
+
+spanless_eq_by_partial_eq!(&str, bool, MacroArgs<'_>, SkipMarkers);
+
+impl SpanlessEq for NodeId<'_> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (NodeId::Single(a), NodeId::Single(b)) => a == b,
+            (NodeId::Output(a_op, a_node), NodeId::Output(b_op, b_node)) => {
+                a_op.spanless_eq(b_op) && a_node.spanless_eq(b_node)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl SpanlessEq for FnCallExpr<'_> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.name.spanless_eq(&other.name)
+            && self.macro_args.spanless_eq(&other.macro_args)
+            && self.args.spanless_eq(&other.args)
+    }
+}
+
+impl SpanlessEq for LetStmt<'_> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.bang == other.bang
+            && self.ident.spanless_eq(&other.ident)
+            && self.call.spanless_eq(&other.call)
+    }
+}
+
+impl SpanlessEq for RenameStmt<'_> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.new_name.spanless_eq(&other.new_name) && self.src.spanless_eq(&other.src)
+    }
+}
+
+impl SpanlessEq for ShapeEdgeOrderParam<'_> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ShapeEdgeOrderParam::FromStart(a), ShapeEdgeOrderParam::FromStart(b))
+            | (ShapeEdgeOrderParam::FromEnd(a), ShapeEdgeOrderParam::FromEnd(b)) => a == b,
+            (ShapeEdgeOrderParam::After(a), ShapeEdgeOrderParam::After(b))
+            | (ShapeEdgeOrderParam::Before(a), ShapeEdgeOrderParam::Before(b)) => a.spanless_eq(b),
+            _ => false,
+        }
+    }
+}
+
+impl<CS: CustomSyntax> SpanlessEq for ShapeQueryParam<'_, CS> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ShapeQueryParam::Node(a), ShapeQueryParam::Node(b)) => {
+                a.name.spanless_eq(&b.name) && a.node_type.0 == b.node_type.0
+            }
+            (ShapeQueryParam::Edge(a), ShapeQueryParam::Edge(b)) => {
+                a.src.spanless_eq(&b.src)
+                    && a.dst.spanless_eq(&b.dst)
+                    && a.edge_type.0 == b.edge_type.0
+                    && a.order.spanless_eq(&b.order)
+            }
+            (ShapeQueryParam::Path(a), ShapeQueryParam::Path(b)) => {
+                a.src.spanless_eq(&b.src)
+                    && a.dst.spanless_eq(&b.dst)
+                    && a.edge_type.0 == b.edge_type.0
+                    && a.marker.spanless_eq(&b.marker)
+            }
+            (ShapeQueryParam::Negative(a), ShapeQueryParam::Negative(b)) => a.spanless_eq(b),
+            _ => false,
+        }
+    }
+}
+
+impl<CS: CustomSyntax> SpanlessEq for ShapeQueryParams<'_, CS> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.params.spanless_eq(&other.params) && self.skip_markers.spanless_eq(&other.skip_markers)
+    }
+}
+
+impl<CS: CustomSyntax> SpanlessEq for IfCond<'_, CS> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (IfCond::Query(a), IfCond::Query(b)) => a.spanless_eq(b),
+            (IfCond::Shape(a), IfCond::Shape(b)) => a.spanless_eq(b),
+            _ => false,
+        }
+    }
+}
+
+impl<CS: CustomSyntax> SpanlessEq for IfStmt<'_, CS> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.cond.spanless_eq(&other.cond)
+            && self.then_block.spanless_eq(&other.then_block)
+            && self.else_block.spanless_eq(&other.else_block)
+    }
+}
+
+impl<CS: CustomSyntax> SpanlessEq for MatchArm<'_, CS> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.shape.spanless_eq(&other.shape) && self.block.spanless_eq(&other.block)
+    }
+}
+
+impl<CS: CustomSyntax> SpanlessEq for MatchStmt<'_, CS> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.arms.spanless_eq(&other.arms) && self.default_block.spanless_eq(&other.default_block)
+    }
+}
+
+impl<CS: CustomSyntax> SpanlessEq for ReturnStmtMapping<'_, CS> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                ReturnStmtMapping::Node { ret_name, node },
+                ReturnStmtMapping::Node {
+                    ret_name: other_ret_name,
+                    node: other_node,
+                },
+            ) => ret_name.spanless_eq(other_ret_name) && node.spanless_eq(other_node),
+            (
+                ReturnStmtMapping::Edge {
+                    src,
+                    dst,
+                    edge_type,
+                },
+                ReturnStmtMapping::Edge {
+                    src: other_src,
+                    dst: other_dst,
+                    edge_type: other_edge_type,
+                },
+            ) => {
+                src.spanless_eq(other_src)
+                    && dst.spanless_eq(other_dst)
+                    && edge_type.0 == other_edge_type.0
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<CS: CustomSyntax> SpanlessEq for Statement<'_, CS> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Statement::Let(a), Statement::Let(b)) => a.spanless_eq(b),
+            (Statement::FnCall(a), Statement::FnCall(b)) => a.spanless_eq(b),
+            (Statement::If(a), Statement::If(b)) => a.spanless_eq(b),
+            (Statement::Match(a), Statement::Match(b)) => a.spanless_eq(b),
+            (Statement::Return(a), Statement::Return(b)) => a.0.mapping.spanless_eq(&b.0.mapping),
+            (Statement::Rename(a), Statement::Rename(b)) => a.spanless_eq(b),
+            (Statement::Yield(a), Statement::Yield(b)) => a.spanless_eq(b),
+            _ => false,
+        }
+    }
+}
+
+impl<CS: CustomSyntax> SpanlessEq for Block<'_, CS> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.statements.spanless_eq(&other.statements)
+    }
+}
@@ -5,8 +5,10 @@
 
 use ariadne::{Color, Label, Report, ReportKind, sources};
 use chumsky::prelude::*;
-use std::{env, fs};
+use std::{env, fs, process};
 
+use grabapl::operation::signature::compatibility::compare_contexts;
+use grabapl::prelude::OperationContext;
 use grabapl::semantics::example::ExampleSemantics;
 use grabapl_syntax::custom_syntax::example::MyCustomSyntax;
 use grabapl_syntax::interpreter::interpret;
@@ -15,6 +17,12 @@ use grabapl_syntax::*;
 fn main() {
     // println!("{:?}", ascii_ident_fixed::<&str, extra::Err<Rich<char>>>().map(|x: &str| x).parse("field1").unwrap());
 
+    let args = env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("compat") {
+        compat(&args[2..]);
+        return;
+    }
+
     let filename = env::args().nth(1).expect("Expected file argument");
     let src = fs::read_to_string(&filename).expect("Failed to read file");
 
@@ -38,9 +46,12 @@ fn main() {
             println!("Parsed: {program:#?}");
             println!("interpreting...");
 
-            let (op_ctx, fns_to_ids) = interpret::<ExampleSemantics>(program)
-                .op_ctx_and_map
-                .unwrap();
+            let result = interpret::<ExampleSemantics>(program);
+            eprint!(
+                "{}",
+                render_lint_warnings(&src, &result.warnings, true).value
+            );
+            let (op_ctx, fns_to_ids) = result.op_ctx_and_map.unwrap();
 
             let json = serde_json::to_string_pretty(&op_ctx)
                 .expect("Failed to serialize operation context to JSON");
@@ -80,6 +91,37 @@ fn main() {
         });
 }
 
+/// `compat <old> <new>`: reports, for every named operation, whether the version in `new` can
+/// replace the version in `old` without breaking its callers.
+///
+/// Both files are either source files or operation contexts serialized to JSON.
+/// Exits with code 1 if any operation breaks its callers.
+fn compat(args: &[String]) {
+    let [old, new] = args else {
+        eprintln!("usage: grabapl_syntax compat <old> <new>");
+        process::exit(2);
+    };
+    let report = compare_contexts(&load_op_ctx(old), &load_op_ctx(new));
+    print!("{report}");
+    if !report.is_backwards_compatible() {
+        process::exit(1);
+    }
+}
+
+fn load_op_ctx(filename: &str) -> OperationContext<ExampleSemantics> {
+    let src = fs::read_to_string(filename).expect("Failed to read file");
+    if filename.ends_with(".json") {
+        return serde_json::from_str(&src).expect("Failed to deserialize operation context");
+    }
+    match try_parse_to_op_ctx_and_map::<ExampleSemantics>(&src, true).op_ctx_and_map {
+        Ok((op_ctx, _)) => op_ctx,
+        Err(err) => {
+            eprintln!("{filename}:\n{}", err.value);
+            process::exit(2);
+        }
+    }
+}
+
 // use syntax::minirust::*;
 //
 // fn main() {
@@ -0,0 +1,181 @@
+use chumsky::prelude::*;
+use grabapl::prelude::*;
+use grabapl::semantics::example::{ExampleSemantics as TestSemantics, NodeValue};
+use grabapl_syntax::call_graph::CallGraph;
+use grabapl_syntax::custom_syntax::SemanticsWithCustomSyntax;
+use grabapl_syntax::{Program, Spanned, Token, lexer, program_parser};
+
+type CS = <TestSemantics as SemanticsWithCustomSyntax>::CS;
+
+fn parse_program<'src>(
+    src: &'src str,
+    tokens: &'src mut Vec<Spanned<Token<'src>>>,
+) -> Program<'src, CS> {
+    *tokens = lexer().parse(src).unwrap();
+    program_parser::<_, CS>()
+        .parse(
+            tokens
+                .as_slice()
+                .map((src.len()..src.len()).into(), |(t, s)| (t, s)),
+        )
+        .unwrap()
+        .0
+}
+
+#[test]
+fn processing_order_puts_callees_first() {
+    let src = stringify!(
+        fn leaf(x: int) {}
+
+        fn main(x: int) {
+            middle(x);
+            leaf(x);
+        }
+
+        fn middle(x: int) {
+            leaf(x);
+            add_node<int,0>();
+        }
+
+        fn unrelated(x: int) {}
+    );
+    let mut tokens = Vec::new();
+    let program = parse_program(src, &mut tokens);
+    let call_graph = CallGraph::new(&program);
+
+    // builtin operations are not part of the graph
+    assert_eq!(call_graph.callees("middle"), ["leaf"]);
+    assert_eq!(call_graph.callees("main"), ["middle", "leaf"]);
+    assert_eq!(
+        call_graph.processing_order(),
+        [
+            vec!["leaf"],
+            vec!["middle"],
+            vec!["main"],
+            vec!["unrelated"]
+        ]
+    );
+}
+
+#[test]
+fn processing_order_groups_call_cycles() {
+    let src = stringify!(
+        fn main(x: int) {
+            even(x);
+        }
+
+        fn odd(x: int) {
+            even(x);
+            helper(x);
+        }
+
+        fn helper(x: int) {}
+
+        fn even(x: int) {
+            if shape [y: int, x -> y: *] {
+                odd(y);
+            }
+        }
+
+        fn selfish(x: int) {
+            selfish(x);
+        }
+    );
+    let mut tokens = Vec::new();
+    let program = parse_program(src, &mut tokens);
+    let call_graph = CallGraph::new(&program);
+
+    assert_eq!(
+        call_graph.processing_order(),
+        [
+            vec!["helper"],
+            vec!["odd", "even"],
+            vec!["main"],
+            vec!["selfish"]
+        ]
+    );
+
+    let cycle = call_graph.find_cycle("odd").unwrap();
+    let names = cycle.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    assert_eq!(names, ["even", "odd"]);
+    let names = call_graph
+        .find_cycle("selfish")
+        .unwrap()
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["selfish"]);
+    assert!(call_graph.find_cycle("main").is_none());
+}
+
+#[test]
+fn functions_may_be_defined_before_their_callers() {
+    let src = stringify!(
+        fn add_child(p: int) -> (child: int) {
+            let! child = add_node<int,5>();
+            add_edge<"child">(p, child);
+            return (child: child);
+        }
+
+        fn add_grandchild(p: int) -> (grandchild: int) {
+            let! child = add_child(p);
+            let! grandchild = add_child(child);
+            return (grandchild: grandchild);
+        }
+    );
+    let (op_ctx, fn_map) = grabapl_syntax::parse_to_op_ctx_and_map::<TestSemantics>(src);
+
+    let mut g = TestSemantics::new_concrete_graph();
+    let p = g.add_node(NodeValue::Integer(0));
+    run_from_concrete(&mut g, &op_ctx, fn_map["add_grandchild"], &[p]).unwrap();
+    let (child, _) = g.out_edges(p).next().unwrap();
+    let (grandchild, _) = g.out_edges(child).next().unwrap();
+    assert_eq!(g.get_node_attr(grandchild), Some(&NodeValue::Integer(5)));
+}
+
+#[test]
+fn calls_to_undefined_functions_are_reported_at_the_call() {
+    let src = stringify!(
+        fn main(x: int) {
+            missing(x);
+        }
+    );
+    let res = grabapl_syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false);
+    let Err(err) = res.op_ctx_and_map else {
+        panic!("expected an error");
+    };
+    assert!(err.value.contains("missing"));
+}
+
+#[test]
+fn queries_used_as_conditions_are_callees() {
+    let src = stringify!(
+        fn main(x: int) {
+            if is_leaf(x) {
+                increment(x);
+            }
+        }
+
+        fn is_leaf(x: int) -> bool {
+            if shape [c: int, x -> c: *] {
+                return false;
+            } else {
+                return true;
+            }
+        }
+    );
+    let mut tokens = Vec::new();
+    let program = parse_program(src, &mut tokens);
+    let call_graph = CallGraph::new(&program);
+
+    assert_eq!(call_graph.callees("main"), ["is_leaf"]);
+    assert_eq!(
+        call_graph.processing_order(),
+        [vec!["is_leaf"], vec!["main"]]
+    );
+    assert!(
+        grabapl_syntax::try_parse_to_op_ctx_and_map::<TestSemantics>(src, false)
+            .op_ctx_and_map
+            .is_ok()
+    );
+}
@@ -0,0 +1,203 @@
+use grabapl::semantics::example::ExampleSemantics as TestSemantics;
+use grabapl_syntax::interpreter::InterpreterResult;
+use grabapl_syntax::lints::{Lint, LintConfig, LintLevel, LintWarning};
+use grabapl_syntax::{WithLineColSpans, try_parse_to_op_ctx_and_map_with_lints};
+
+fn compile<'src>(
+    src: &'src str,
+    lints: &LintConfig,
+) -> InterpreterResult<'src, TestSemantics, WithLineColSpans<String>> {
+    try_parse_to_op_ctx_and_map_with_lints::<TestSemantics>(src, false, lints)
+}
+
+/// Compiles `src` with the default lint levels, expecting it to succeed.
+fn warnings(src: &str) -> Vec<LintWarning> {
+    let res = compile(src, &LintConfig::new());
+    if let Err(err) = &res.op_ctx_and_map {
+        panic!("warnings must not fail compilation: {}", err.value);
+    }
+    res.warnings
+}
+
+fn lints(src: &str) -> Vec<Lint> {
+    warnings(src).into_iter().map(|w| w.lint).collect()
+}
+
+#[test]
+fn unused_nodes_and_parameters() {
+    let src = stringify!(
+        fn foo(used: int, unused: int, _ignored: int) {
+            let! child = add_node<int, 0>();
+            let! other = add_node<int, 0>();
+            add_edge<"child">(used, child);
+        }
+    );
+    let warnings = warnings(src);
+    let messages = warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "parameter `unused` is never used (#unused_parameter)",
+            "`other` is bound but never used (#unused_node)",
+        ]
+    );
+    assert_eq!(&src[warnings[0].span.into_range()], "unused");
+    assert_eq!(&src[warnings[1].span.into_range()], "other");
+}
+
+#[test]
+fn shape_query_nodes_are_used_by_the_pattern() {
+    let src = stringify!(
+        fn foo(p: int) {
+            if shape [child: int, p -> child: *] {
+                increment(p);
+            }
+            if shape [unused: int] {
+                increment(p);
+            }
+        }
+    );
+    let warnings = warnings(src);
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert_eq!(warnings[0].lint, Lint::UnusedNode);
+    assert_eq!(&src[warnings[0].span.into_range()], "unused");
+}
+
+#[test]
+fn parameters_used_by_the_context_are_used() {
+    assert_eq!(
+        lints(stringify!(
+            fn foo(x: int) [y: int, x -> y: *] {
+                increment(y);
+            }
+        )),
+        []
+    );
+}
+
+#[test]
+fn unreachable_code() {
+    let src = stringify!(
+        fn foo(p: int) {
+            diverge<"always">();
+            increment(p);
+            increment(p);
+        }
+    );
+    let warnings = warnings(src);
+    // only the first unreachable statement is reported
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].lint, Lint::UnreachableCode);
+    assert_eq!(&src[warnings[0].span.into_range()], "increment(p);");
+
+    // code after a branch that diverges is still reachable
+    assert_eq!(
+        lints(stringify!(
+            fn foo(p: int) {
+                if is_eq<0>(p) {
+                    diverge<"zero">();
+                }
+                increment(p);
+            }
+        )),
+        []
+    );
+}
+
+#[test]
+fn identical_branches() {
+    assert_eq!(
+        lints(stringify!(
+            fn foo(p: int) {
+                if is_eq<0>(p) {
+                    increment(p);
+                } else {
+                    increment(p);
+                }
+            }
+        )),
+        [Lint::IdenticalBranches]
+    );
+    assert_eq!(
+        lints(stringify!(
+            fn foo(p: int) {
+                if is_eq<0>(p) {
+                    increment(p);
+                } else {
+                    decrement(p);
+                }
+            }
+        )),
+        []
+    );
+
+    // a query without any branches, and shape queries that only bind or mark nodes
+    assert_eq!(
+        lints(stringify!(
+            fn foo(x: int) {
+                if is_eq<0>(x) {}
+                if shape [c: int, x -> c: *] {}
+                if shape [x ->* x: * marking "cycle"] {}
+            }
+        )),
+        []
+    );
+}
+
+#[test]
+fn unmatchable_shape_query() {
+    let src = stringify!(
+        fn foo(p: int, s: string) {
+            if shape [p: string] {
+                copy_value_from_to(p, s);
+            }
+            if shape [s: string] {
+                copy_value_from_to(s, p);
+            }
+        }
+    );
+    let warnings = warnings(src);
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert_eq!(warnings[0].lint, Lint::UnmatchableShapeQuery);
+    assert_eq!(&src[warnings[0].span.into_range()], "p: string");
+
+    // narrowing a node to a subtype may match
+    assert_eq!(
+        lints(stringify!(
+            fn foo(p: object) {
+                if shape [p: int] {
+                    increment(p);
+                }
+            }
+        )),
+        []
+    );
+}
+
+#[test]
+fn lint_levels() {
+    let src = stringify!(
+        fn foo(unused: int) {}
+    );
+
+    let allowed = compile(
+        src,
+        &LintConfig::new().with(Lint::UnusedParameter, LintLevel::Allow),
+    );
+    assert!(allowed.op_ctx_and_map.is_ok());
+    assert_eq!(allowed.warnings, []);
+
+    let denied = compile(src, &LintConfig::all(LintLevel::Deny));
+    let Err(err) = denied.op_ctx_and_map else {
+        panic!("expected a denied lint to fail compilation");
+    };
+    assert!(err.value.contains("parameter `unused` is never used"));
+    assert_eq!(denied.warnings.len(), 1);
+    assert_eq!(denied.warnings[0].level, LintLevel::Deny);
+
+    assert_eq!(
+        Lint::from_name("unused_parameter"),
+        Some(Lint::UnusedParameter)
+    );
+    assert_eq!(Lint::from_name("unknown"), None);
+}